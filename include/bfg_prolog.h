@@ -0,0 +1,48 @@
+/* The C interface of bfg-prolog, built with
+ *
+ *     cargo rustc --release --features ffi --crate-type cdylib
+ *
+ * Strings passed in are NUL-terminated UTF-8. Strings handed back belong to the
+ * machine or query they came from, and last until the next call on it. A query must
+ * be freed before the machine it runs on. While a query is live, the machine refuses
+ * to consult or start another query, giving -1 or NULL with the reason in
+ * bfg_machine_error, and bfg_machine_free leaves it alone.
+ */
+
+#ifndef BFG_PROLOG_H
+#define BFG_PROLOG_H
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef struct BfgMachine BfgMachine;
+typedef struct BfgQuery BfgQuery;
+
+BfgMachine *bfg_machine_new(void);
+void bfg_machine_free(BfgMachine *m);
+
+/* 0 on success, or -1 with the reason in bfg_machine_error */
+int bfg_consult_file(BfgMachine *m, const char *path);
+int bfg_consult_str(BfgMachine *m, const char *code);
+const char *bfg_machine_error(const BfgMachine *m);
+
+/* NULL, with the reason in bfg_machine_error, when the query does not parse or
+ * another query on the machine is live */
+BfgQuery *bfg_query_new(BfgMachine *m, const char *text);
+void bfg_query_free(BfgQuery *q);
+
+/* 1 for a solution, 0 when there are no more, -1 for an error in bfg_query_error */
+int bfg_query_next(BfgQuery *q);
+const char *bfg_query_error(const BfgQuery *q);
+
+/* the named variables of the query, and their values in the current solution */
+int bfg_query_binding_count(const BfgQuery *q);
+const char *bfg_query_binding_name(BfgQuery *q, int i);
+const char *bfg_query_binding(BfgQuery *q, const char *name);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
@@ -0,0 +1,186 @@
+use crate::ast::{Atom, Const, Number, Term};
+use crate::errors::{evaluation_error, indicator, instantiation_error, type_error};
+use std::convert::TryFrom;
+
+type Eval = Result<Number, Term>;
+
+// evaluate an arithmetic expression whose bindings have already been substituted
+pub(crate) fn eval(t: &Term) -> Eval {
+    match t {
+        Term::Number(n) => Ok(*n),
+        Term::Var(_) => Err(instantiation_error()),
+        Term::String(_) => Err(type_error("evaluable", t.clone())),
+        Term::Const(Const(name)) => match name.as_str() {
+            "pi" => Ok(Number::Float(std::f64::consts::PI)),
+            "e" => Ok(Number::Float(std::f64::consts::E)),
+            _ => Err(type_error("evaluable", indicator(name, 0))),
+        },
+        Term::Atom(Atom { name, args, .. }) => match &args[..] {
+            [x] => unary(&name.0, eval(x)?),
+            [x, y] => binary(&name.0, eval(x)?, eval(y)?),
+            _ => Err(type_error("evaluable", indicator(&name.0, args.len()))),
+        },
+    }
+}
+
+fn unary(name: &str, x: Number) -> Eval {
+    use Number::*;
+
+    match (name, x) {
+        ("-", Int(i)) => i.checked_neg().map(Int).ok_or_else(int_overflow),
+        ("-", Float(f)) => Ok(Float(-f)),
+        ("+", x) => Ok(x),
+        ("abs", Int(i)) => i.checked_abs().map(Int).ok_or_else(int_overflow),
+        ("abs", Float(f)) => Ok(Float(f.abs())),
+        ("sign", Int(i)) => Ok(Int(i.signum())),
+        ("sign", Float(f)) => Ok(Float(if f == 0.0 { 0.0 } else { f.signum() })),
+        ("\\", x) => Ok(Int(!integer(x)?)),
+        ("sqrt", x) if to_float(x) < 0.0 => Err(evaluation_error("undefined")),
+        ("sqrt", x) => float(to_float(x).sqrt()),
+        ("sin", x) => float(to_float(x).sin()),
+        ("cos", x) => float(to_float(x).cos()),
+        ("tan", x) => float(to_float(x).tan()),
+        ("asin", x) => float(to_float(x).asin()),
+        ("acos", x) => float(to_float(x).acos()),
+        ("atan", x) => float(to_float(x).atan()),
+        ("exp", x) => float(to_float(x).exp()),
+        ("log", x) if to_float(x) <= 0.0 => Err(evaluation_error("undefined")),
+        ("log", x) => float(to_float(x).ln()),
+        ("float", x) => float(to_float(x)),
+        // rounding an integer leaves it as it is
+        ("integer" | "round", Float(f)) => rounded(f.round()),
+        ("floor", Float(f)) => rounded(f.floor()),
+        ("ceiling", Float(f)) => rounded(f.ceil()),
+        ("truncate", Float(f)) => rounded(f.trunc()),
+        ("integer" | "round" | "floor" | "ceiling" | "truncate", Int(i)) => Ok(Int(i)),
+        ("float_integer_part", x) => float(to_float(x).trunc()),
+        ("float_fractional_part", x) => float(to_float(x).fract()),
+        _ => Err(type_error("evaluable", indicator(name, 1))),
+    }
+}
+
+fn binary(name: &str, x: Number, y: Number) -> Eval {
+    use Number::*;
+
+    match (name, x, y) {
+        ("+", Int(i), Int(j)) => i.checked_add(j).map(Int).ok_or_else(int_overflow),
+        ("-", Int(i), Int(j)) => i.checked_sub(j).map(Int).ok_or_else(int_overflow),
+        ("*", Int(i), Int(j)) => i.checked_mul(j).map(Int).ok_or_else(int_overflow),
+        ("+", x, y) => float(to_float(x) + to_float(y)),
+        ("-", x, y) => float(to_float(x) - to_float(y)),
+        ("*", x, y) => float(to_float(x) * to_float(y)),
+        ("/", Int(_), Int(0)) => Err(evaluation_error("zero_divisor")),
+        ("/", Int(i), Int(j)) if i.checked_rem(j) == Some(0) => {
+            i.checked_div(j).map(Int).ok_or_else(int_overflow)
+        }
+        ("/", _, y) if to_float(y) == 0.0 => Err(evaluation_error("zero_divisor")),
+        ("/", x, y) => float(to_float(x) / to_float(y)),
+        ("//", x, y) => {
+            let (i, j) = (integer(x)?, divisor(y)?);
+            i.checked_div(j).map(Int).ok_or_else(int_overflow)
+        }
+        ("rem", x, y) => {
+            let (i, j) = (integer(x)?, divisor(y)?);
+            Ok(Int(i.checked_rem(j).unwrap_or(0)))
+        }
+        ("mod", x, y) => {
+            let (i, j) = (integer(x)?, divisor(y)?);
+            let m = i.checked_rem(j).unwrap_or(0);
+            Ok(Int(if m != 0 && (m < 0) != (j < 0) {
+                m + j
+            } else {
+                m
+            }))
+        }
+        ("div", x, y) => {
+            let (i, j) = (integer(x)?, divisor(y)?);
+            let q = i.checked_div(j).ok_or_else(int_overflow)?;
+            Ok(Int(if q * j != i && (i < 0) != (j < 0) {
+                q - 1
+            } else {
+                q
+            }))
+        }
+        ("min", x, y) => Ok(if y.cmp_value(&x).is_lt() { y } else { x }),
+        ("max", x, y) => Ok(if y.cmp_value(&x).is_gt() { y } else { x }),
+        ("**", x, y) => float(to_float(x).powf(to_float(y))),
+        ("^", Int(i), Int(j)) => power(i, j),
+        ("^", x, y) => float(to_float(x).powf(to_float(y))),
+        (">>", x, y) => Ok(Int(integer(x)? >> integer(y)?.clamp(0, 63))),
+        ("<<", x, y) => {
+            let (i, j) = (integer(x)?, integer(y)?);
+            match u32::try_from(j).ok().and_then(|j| i.checked_shl(j)) {
+                Some(k) if k >> j == i => Ok(Int(k)),
+                _ => Err(int_overflow()),
+            }
+        }
+        ("/\\", x, y) => Ok(Int(integer(x)? & integer(y)?)),
+        ("\\/", x, y) => Ok(Int(integer(x)? | integer(y)?)),
+        ("xor", x, y) => Ok(Int(integer(x)? ^ integer(y)?)),
+        ("atan2" | "atan", x, y) if to_float(x) == 0.0 && to_float(y) == 0.0 => {
+            Err(evaluation_error("undefined"))
+        }
+        ("atan2" | "atan", x, y) => float(to_float(x).atan2(to_float(y))),
+        _ => Err(type_error("evaluable", indicator(name, 2))),
+    }
+}
+
+fn power(i: i64, j: i64) -> Eval {
+    match (i, j) {
+        (_, j) if j >= 0 => u32::try_from(j)
+            .ok()
+            .and_then(|j| i.checked_pow(j))
+            .map(Number::Int)
+            .ok_or_else(int_overflow),
+        (1, _) => Ok(Number::Int(1)),
+        (-1, j) => Ok(Number::Int(if j % 2 == 0 { 1 } else { -1 })),
+        (0, _) => Err(evaluation_error("zero_divisor")),
+        (i, _) => Err(type_error("float", Term::int(i))),
+    }
+}
+
+fn integer(x: Number) -> Result<i64, Term> {
+    match x {
+        Number::Int(i) => Ok(i),
+        Number::Float(_) => Err(type_error("integer", Term::Number(x))),
+    }
+}
+
+fn divisor(y: Number) -> Result<i64, Term> {
+    match integer(y)? {
+        0 => Err(evaluation_error("zero_divisor")),
+        j => Ok(j),
+    }
+}
+
+fn to_float(x: Number) -> f64 {
+    match x {
+        Number::Int(i) => i as f64,
+        Number::Float(f) => f,
+    }
+}
+
+// the integer a float rounded to a whole number stands for, if it has one
+fn rounded(f: f64) -> Eval {
+    if f.is_nan() {
+        Err(evaluation_error("undefined"))
+    } else if f < -(2f64.powi(63)) || f >= 2f64.powi(63) {
+        Err(int_overflow())
+    } else {
+        Ok(Number::Int(f as i64))
+    }
+}
+
+fn float(f: f64) -> Eval {
+    if f.is_nan() {
+        Err(evaluation_error("undefined"))
+    } else if f.is_infinite() {
+        Err(evaluation_error("float_overflow"))
+    } else {
+        Ok(Number::Float(f))
+    }
+}
+
+fn int_overflow() -> Term {
+    evaluation_error("int_overflow")
+}
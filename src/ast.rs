@@ -1,10 +1,24 @@
+use crate::dcg;
+use crate::lexer::{is_alnum, is_atom_start, is_symbol_char};
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
 use std::fmt::{Display, Formatter};
+use std::hash::{Hash, Hasher};
+use std::mem;
 
-#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[derive(Debug, Eq)]
 pub enum Term {
     Var(Var),
     Const(Const),
     Atom(Atom),
+    Number(Number),
+    String(String),
+}
+
+#[derive(Debug, Copy, Clone)]
+pub enum Number {
+    Int(i64),
+    Float(f64),
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
@@ -13,7 +27,7 @@ pub struct Var(pub String, pub usize);
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct Const(pub String);
 
-#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[derive(Debug, Eq)]
 pub struct Atom {
     pub name: Const,
     pub arity: Arity,
@@ -26,6 +40,12 @@ pub struct Assertion {
     pub clause: Clause,
 }
 
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Sentence {
+    Assertion(Assertion),
+    Directive(Term),
+}
+
 pub type Arity = usize;
 pub type Clause = Vec<Atom>;
 
@@ -33,6 +53,32 @@ impl Assertion {
     pub fn new(head: Atom, clause: Clause) -> Self {
         Assertion { head, clause }
     }
+
+    // variables that occur only once in the clause, other than those starting with `_`
+    pub fn singletons(&self) -> Vec<&Var> {
+        let mut vars: Vec<(&Var, usize)> = Vec::new();
+        let mut next: Vec<&Term> = Vec::new();
+
+        for a in Some(&self.head).into_iter().chain(self.clause.iter()).rev() {
+            next.extend(a.args.iter().rev());
+        }
+
+        while let Some(t) = next.pop() {
+            match t {
+                Term::Var(x) => match vars.iter_mut().find(|(y, _)| *y == x) {
+                    Some((_, n)) => *n += 1,
+                    None => vars.push((x, 1)),
+                },
+                Term::Atom(a) => next.extend(a.args.iter().rev()),
+                _ => (),
+            }
+        }
+
+        vars.into_iter()
+            .filter(|&(x, n)| n == 1 && !x.0.starts_with('_'))
+            .map(|(x, _)| x)
+            .collect()
+    }
 }
 
 impl Atom {
@@ -43,12 +89,55 @@ impl Atom {
             args,
         }
     }
+
+    // the name and arguments of the atom, which can't be moved out of it on their own
+    pub fn into_parts(mut self) -> (Const, Vec<Term>) {
+        let name = mem::replace(&mut self.name, Const(String::new()));
+        (name, mem::take(&mut self.args))
+    }
+
+    pub fn into_args(self) -> Vec<Term> {
+        self.into_parts().1
+    }
+}
+
+impl From<Atom> for Term {
+    fn from(a: Atom) -> Self {
+        if a.args.is_empty() {
+            Term::Const(a.into_parts().0)
+        } else {
+            Term::Atom(a)
+        }
+    }
+}
+
+// a clause as a term, with `true` as the body of a fact
+impl From<Assertion> for Term {
+    fn from(a: Assertion) -> Self {
+        let mut goals = a.clause.into_iter().rev().map(Term::from);
+        let body = match goals.next() {
+            None => Term::atom("true"),
+            Some(last) => goals.fold(last, |body, goal| Term::compound(",", vec![goal, body])),
+        };
+
+        Term::compound(":-", vec![a.head.into(), body])
+    }
 }
 
 impl Var {
     pub fn new(name: &str, n: usize) -> Self {
         Var(String::from(name), n)
     }
+
+    // every `_` in the source is a distinct variable, named after where it was read
+    pub fn anonymous(offset: usize) -> Self {
+        Var(format!("_G{}", offset), 0)
+    }
+
+    pub fn is_anonymous(&self) -> bool {
+        let Var(name, _) = self;
+        name.len() > 2 && name.starts_with("_G") && name[2..].bytes().all(|b| b.is_ascii_digit())
+    }
 }
 
 impl Const {
@@ -57,18 +146,616 @@ impl Const {
     }
 }
 
+impl Term {
+    pub fn var(name: &str) -> Self {
+        Term::Var(Var::new(name, 0))
+    }
+
+    pub fn atom(name: &str) -> Self {
+        Term::Const(Const::new(name))
+    }
+
+    pub fn int(i: i64) -> Self {
+        Term::Number(Number::Int(i))
+    }
+
+    pub fn float(x: f64) -> Self {
+        Term::Number(Number::Float(x))
+    }
+
+    pub fn string(text: &str) -> Self {
+        Term::String(String::from(text))
+    }
+
+    // a compound with no arguments is just its name, as the parser reads it
+    pub fn compound(name: &str, args: Vec<Term>) -> Self {
+        if args.is_empty() {
+            Term::atom(name)
+        } else {
+            Term::Atom(Atom::new(name, args))
+        }
+    }
+
+    pub fn list(items: Vec<Term>, tail: Term) -> Self {
+        items.into_iter().rev().fold(tail, |tail, item| {
+            Term::Atom(Atom::new(".", vec![item, tail]))
+        })
+    }
+
+    pub fn codes(text: &str) -> Self {
+        let codes = text.chars().map(|c| Term::Number(Number::Int(c as i64)));
+        Term::list(codes.collect(), Term::atom("[]"))
+    }
+
+    pub fn chars(text: &str) -> Self {
+        let chars = text.chars().map(|c| Term::Const(Const(c.to_string())));
+        Term::list(chars.collect(), Term::atom("[]"))
+    }
+
+    pub fn into_sentence(self) -> Option<Sentence> {
+        match self {
+            Term::Atom(a) if is_prefix_neck(&a.name, a.arity) => {
+                Some(Sentence::Directive(a.into_args().pop().unwrap()))
+            }
+            Term::Atom(a) if a.name.0 == "-->" && a.arity == 2 => {
+                let mut args = a.into_args();
+                let body = args.pop().unwrap();
+                let head = args.pop().unwrap();
+                dcg::translate(head, body)?
+                    .into_assertion()
+                    .map(Sentence::Assertion)
+            }
+            t => t.into_assertion().map(Sentence::Assertion),
+        }
+    }
+
+    pub fn into_assertion(self) -> Option<Assertion> {
+        match self {
+            Term::Atom(a) if a.name.0 == ":-" && a.arity == 2 => {
+                let mut args = a.into_args();
+                let body = args.pop().unwrap();
+                let head = args.pop().unwrap();
+
+                Some(Assertion::new(head.into_head()?, body.into_goals()?))
+            }
+            Term::Atom(Atom {
+                ref name, arity, ..
+            }) if is_prefix_neck(name, arity) => None,
+            t => Some(Assertion::new(t.into_head()?, vec![])),
+        }
+    }
+
+    // flatten a conjunction into a clause of goals, in execution order
+    // the goals of a toplevel query, which may be written either bare or as `?- Goal`
+    pub fn into_query(self) -> Option<Clause> {
+        let body = match self {
+            Term::Atom(a) if a.name.0 == "?-" && a.arity == 1 => a.into_args().pop().unwrap(),
+            t => t,
+        };
+
+        // a number or a string is left for call/1 to raise a type error on when the
+        // query runs, as it would for a goal only known then
+        body.flatten(|t| match t {
+            Term::Number(_) | Term::String(_) => Some(Atom::new("call", vec![t])),
+            t => t.into_goal(),
+        })
+    }
+
+    pub fn into_goals(self) -> Option<Clause> {
+        self.flatten(Term::into_goal)
+    }
+
+    fn flatten(self, into_goal: impl Fn(Term) -> Option<Atom>) -> Option<Clause> {
+        let mut goals = Vec::new();
+        let mut next = vec![self];
+
+        while let Some(t) = next.pop() {
+            match t {
+                Term::Atom(a) if a.name.0 == "," && a.arity == 2 => {
+                    let mut args = a.into_args();
+                    next.push(args.pop().unwrap());
+                    next.push(args.pop().unwrap());
+                }
+                t => goals.push(into_goal(t)?),
+            }
+        }
+
+        Some(goals)
+    }
+
+    pub fn into_goal(self) -> Option<Atom> {
+        match self {
+            Term::Var(v) => Some(Atom::new("call", vec![Term::Var(v)])),
+            t => t.into_callable(),
+        }
+    }
+
+    // the head of a clause, where `Module:Head` defines a predicate of the module, named
+    // after both, so that modules may each define a hook of the same name
+    pub fn into_head(self) -> Option<Atom> {
+        match self {
+            Term::Atom(a) if a.name.0 == ":" && a.arity == 2 => {
+                let mut args = a.into_args();
+                let head = args.pop().unwrap().into_callable()?;
+
+                match args.pop().unwrap() {
+                    Term::Const(Const(module)) => {
+                        let (name, args) = head.into_parts();
+                        Some(Atom::new(&qualified(&module, &name.0), args))
+                    }
+                    _ => None,
+                }
+            }
+            t => t.into_callable(),
+        }
+    }
+
+    pub fn into_callable(self) -> Option<Atom> {
+        match self {
+            Term::Atom(a) => Some(a),
+            Term::Const(Const(name)) => Some(Atom::new(&name, vec![])),
+            _ => None,
+        }
+    }
+
+    // the renaming of variables that turns this term into `other`, if they are variants
+    pub fn variant(&self, other: &Term) -> Option<HashMap<Var, Var>> {
+        let mut renaming = HashMap::new();
+        let mut renamed = HashSet::new();
+        let mut next = vec![(self, other)];
+
+        while let Some(pair) = next.pop() {
+            match pair {
+                (Term::Var(x), Term::Var(y)) => match renaming.get(x) {
+                    Some(z) if z == y => (),
+                    Some(_) => return None,
+                    None if renamed.insert(y.clone()) => {
+                        renaming.insert(x.clone(), y.clone());
+                    }
+                    None => return None,
+                },
+                (Term::Atom(a1), Term::Atom(a2)) if a1.name == a2.name && a1.arity == a2.arity => {
+                    next.extend(a1.args.iter().zip(a2.args.iter()));
+                }
+                (Term::Const(_), _) | (Term::Number(_), _) | (Term::String(_), _)
+                    if pair.0 == pair.1 => {}
+                _ => return None,
+            }
+        }
+
+        Some(renaming)
+    }
+
+    pub fn is_variant(&self, other: &Term) -> bool {
+        self.variant(other).is_some()
+    }
+
+    // whether `specific` is an instance of this term, without binding any of its variables
+    pub fn subsumes(&self, specific: &Term) -> bool {
+        let mut bindings: HashMap<&Var, &Term> = HashMap::new();
+        let mut next = vec![(self, specific)];
+
+        while let Some(pair) = next.pop() {
+            match pair {
+                (Term::Var(x), t) => match bindings.get(x) {
+                    Some(&bound) if bound == t => (),
+                    Some(_) => return false,
+                    None => {
+                        bindings.insert(x, t);
+                    }
+                },
+                (Term::Atom(a1), Term::Atom(a2)) if a1.name == a2.name && a1.arity == a2.arity => {
+                    next.extend(a1.args.iter().zip(a2.args.iter()));
+                }
+                (Term::Const(_), _) | (Term::Number(_), _) | (Term::String(_), _)
+                    if pair.0 == pair.1 => {}
+                _ => return false,
+            }
+        }
+
+        specific.variables().iter().all(|x| match bindings.get(x) {
+            None => true,
+            Some(Term::Var(y)) => *x == y,
+            Some(_) => false,
+        })
+    }
+
+    pub fn variables(&self) -> Vec<&Var> {
+        let mut vars = Vec::new();
+        let mut seen = HashSet::new();
+        let mut next = vec![self];
+
+        while let Some(t) = next.pop() {
+            match t {
+                Term::Var(x) if seen.insert(x) => vars.push(x),
+                Term::Atom(a) => next.extend(a.args.iter().rev()),
+                _ => (),
+            }
+        }
+
+        vars
+    }
+
+    pub(crate) fn list_items(&self) -> Option<(Vec<&Term>, &Term)> {
+        let mut items = Vec::new();
+        let mut t = self;
+
+        while let Term::Atom(Atom { name, args, .. }) = t {
+            if name.0 != "." || args.len() != 2 {
+                break;
+            }
+
+            items.push(&args[0]);
+            t = &args[1];
+        }
+
+        if items.is_empty() {
+            None
+        } else {
+            Some((items, t))
+        }
+    }
+}
+
+impl PartialEq for Number {
+    fn eq(&self, other: &Number) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Number {}
+
+impl PartialOrd for Number {
+    fn partial_cmp(&self, other: &Number) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+// numbers compare by value; a float precedes an integer of equal value
+impl Ord for Number {
+    fn cmp(&self, other: &Number) -> Ordering {
+        match (self, other) {
+            (Number::Int(x), Number::Int(y)) => x.cmp(y),
+            (Number::Float(x), Number::Float(y)) => x.total_cmp(y),
+            (Number::Int(x), Number::Float(y)) => {
+                cmp_float_int(*y, *x).reverse().then(Ordering::Greater)
+            }
+            (Number::Float(x), Number::Int(y)) => cmp_float_int(*x, *y).then(Ordering::Less),
+        }
+    }
+}
+
+impl Number {
+    // comparison by value alone, as the arithmetic comparison predicates do
+    pub fn cmp_value(&self, other: &Number) -> Ordering {
+        match (self, other) {
+            (Number::Int(x), Number::Int(y)) => x.cmp(y),
+            (Number::Float(x), Number::Float(y)) => x.partial_cmp(y).unwrap_or(Ordering::Less),
+            (Number::Int(x), Number::Float(y)) => cmp_float_int(*y, *x).reverse(),
+            (Number::Float(x), Number::Int(y)) => cmp_float_int(*x, *y),
+        }
+    }
+}
+
+// the name of a predicate defined by a module
+pub fn qualified(module: &str, name: &str) -> String {
+    format!("{}:{}", module, name)
+}
+
+// `:- Goal` and `?- Goal` are directives in a source file, not clauses
+fn is_prefix_neck(name: &Const, arity: Arity) -> bool {
+    arity == 1 && (name.0 == ":-" || name.0 == "?-")
+}
+
+// exact comparison, since converting large integers to floats loses precision
+fn cmp_float_int(x: f64, y: i64) -> Ordering {
+    const LIMIT: f64 = 9_223_372_036_854_775_808.0;
+
+    if x.is_nan() || x < -LIMIT {
+        return Ordering::Less;
+    }
+
+    if x >= LIMIT {
+        return Ordering::Greater;
+    }
+
+    let t = x.trunc();
+    (t as i64)
+        .cmp(&y)
+        .then_with(|| x.partial_cmp(&t).unwrap_or(Ordering::Equal))
+}
+
+impl Term {
+    // rank of each kind of term in the standard order:
+    // Var < Number < Atom < String < Compound
+    fn rank(&self) -> u8 {
+        match self {
+            Term::Var(_) => 0,
+            Term::Number(_) => 1,
+            Term::Const(_) => 2,
+            Term::String(_) => 3,
+            Term::Atom(_) => 4,
+        }
+    }
+}
+
+impl PartialOrd for Term {
+    fn partial_cmp(&self, other: &Term) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Term {
+    fn cmp(&self, other: &Term) -> Ordering {
+        let mut pairs = vec![(self, other)];
+
+        while let Some(pair) = pairs.pop() {
+            let ord = match pair {
+                (Term::Var(Var(x, n)), Term::Var(Var(y, m))) => n.cmp(m).then_with(|| x.cmp(y)),
+                (Term::Number(x), Term::Number(y)) => x.cmp(y),
+                (Term::Const(x), Term::Const(y)) => x.cmp(y),
+                (Term::String(x), Term::String(y)) => x.cmp(y),
+                (Term::Atom(a1), Term::Atom(a2)) => {
+                    let ord = a1.arity.cmp(&a2.arity).then_with(|| a1.name.cmp(&a2.name));
+
+                    if ord == Ordering::Equal {
+                        pairs.extend(a1.args.iter().zip(a2.args.iter()).rev());
+                        continue;
+                    }
+
+                    ord
+                }
+                (t1, t2) => t1.rank().cmp(&t2.rank()),
+            };
+
+            if ord != Ordering::Equal {
+                return ord;
+            }
+        }
+
+        Ordering::Equal
+    }
+}
+
+impl PartialOrd for Atom {
+    fn partial_cmp(&self, other: &Atom) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Atom {
+    fn cmp(&self, other: &Atom) -> Ordering {
+        self.arity
+            .cmp(&other.arity)
+            .then_with(|| self.name.cmp(&other.name))
+            .then_with(|| self.args.cmp(&other.args))
+    }
+}
+
+// clones, comparisons, hashes and drops of terms walk them with a stack of their own, so a long
+// list is gone through a cell at a time, rather than by recursing down its tail
+impl Clone for Term {
+    fn clone(&self) -> Self {
+        match self {
+            Term::Var(x) => Term::Var(x.clone()),
+            Term::Const(c) => Term::Const(c.clone()),
+            Term::Atom(a) => Term::Atom(a.clone()),
+            Term::Number(n) => Term::Number(*n),
+            Term::String(text) => Term::String(text.clone()),
+        }
+    }
+}
+
+impl Clone for Atom {
+    fn clone(&self) -> Self {
+        // the atoms being cloned, each with the clones of its arguments made so far
+        let mut stack = vec![(self, Vec::with_capacity(self.arity))];
+
+        loop {
+            let (a, args) = stack.last_mut().unwrap();
+            let a: &Atom = a;
+
+            if let Some(t) = a.args.get(args.len()) {
+                match t {
+                    Term::Atom(b) => stack.push((b, Vec::with_capacity(b.arity))),
+                    t => args.push(t.clone()),
+                }
+                continue;
+            }
+
+            let (a, args) = stack.pop().unwrap();
+            let clone = Atom {
+                name: a.name.clone(),
+                arity: a.arity,
+                args,
+            };
+
+            match stack.last_mut() {
+                Some((_, args)) => args.push(Term::Atom(clone)),
+                None => return clone,
+            }
+        }
+    }
+}
+
+impl PartialEq for Term {
+    fn eq(&self, other: &Term) -> bool {
+        let mut pairs = vec![(self, other)];
+
+        while let Some(pair) = pairs.pop() {
+            let eq = match pair {
+                (Term::Var(x), Term::Var(y)) => x == y,
+                (Term::Const(x), Term::Const(y)) => x == y,
+                (Term::Number(x), Term::Number(y)) => x == y,
+                (Term::String(x), Term::String(y)) => x == y,
+                (Term::Atom(a1), Term::Atom(a2)) => {
+                    if a1.name == a2.name && a1.args.len() == a2.args.len() {
+                        pairs.extend(a1.args.iter().zip(a2.args.iter()).rev());
+                        continue;
+                    }
+
+                    false
+                }
+                _ => false,
+            };
+
+            if !eq {
+                return false;
+            }
+        }
+
+        true
+    }
+}
+
+impl PartialEq for Atom {
+    fn eq(&self, other: &Atom) -> bool {
+        self.name == other.name && self.arity == other.arity && self.args == other.args
+    }
+}
+
+impl Hash for Term {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        let mut next = vec![self];
+
+        while let Some(t) = next.pop() {
+            mem::discriminant(t).hash(state);
+
+            match t {
+                Term::Var(x) => x.hash(state),
+                Term::Const(c) => c.hash(state),
+                Term::Number(n) => n.hash(state),
+                Term::String(text) => text.hash(state),
+                Term::Atom(a) => {
+                    a.name.hash(state);
+                    a.args.len().hash(state);
+                    next.extend(a.args.iter().rev());
+                }
+            }
+        }
+    }
+}
+
+impl Hash for Atom {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.name.hash(state);
+        self.arity.hash(state);
+        self.args.hash(state);
+    }
+}
+
+impl Drop for Atom {
+    fn drop(&mut self) {
+        let mut next = mem::take(&mut self.args);
+
+        while let Some(t) = next.pop() {
+            if let Term::Atom(mut a) = t {
+                next.append(&mut a.args);
+            }
+        }
+    }
+}
+
+impl Hash for Number {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        match self {
+            Number::Int(i) => i.hash(state),
+            Number::Float(x) => x.to_bits().hash(state),
+        }
+    }
+}
+
+impl Display for Number {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), std::fmt::Error> {
+        match self {
+            Number::Int(i) => write!(f, "{}", i),
+            Number::Float(x) if x.is_finite() => {
+                let s = format!("{:?}", x);
+
+                match s.find('e') {
+                    Some(i) if !s[..i].contains('.') => write!(f, "{}.0{}", &s[..i], &s[i..]),
+                    _ => write!(f, "{}", s),
+                }
+            }
+            Number::Float(x) => write!(f, "{}", x),
+        }
+    }
+}
+
+pub fn needs_quotes(name: &str) -> bool {
+    let mut chars = name.chars();
+
+    match chars.next() {
+        None => true,
+        Some(c) if is_atom_start(c) => !chars.all(is_alnum),
+        Some(c) if is_symbol_char(c) => !name.chars().all(is_symbol_char),
+        Some(_) => !["[]", "{}", "!", ";"].contains(&name),
+    }
+}
+
+pub fn fmt_name(f: &mut Formatter, name: &str) -> Result<(), std::fmt::Error> {
+    if needs_quotes(name) {
+        write!(f, "'")?;
+
+        for c in name.chars() {
+            match c {
+                '\'' => write!(f, "\\'")?,
+                '\\' => write!(f, "\\\\")?,
+                '\n' => write!(f, "\\n")?,
+                '\t' => write!(f, "\\t")?,
+                c => write!(f, "{}", c)?,
+            }
+        }
+
+        write!(f, "'")
+    } else {
+        write!(f, "{}", name)
+    }
+}
+
+pub fn fmt_string(f: &mut Formatter, text: &str) -> Result<(), std::fmt::Error> {
+    write!(f, "\"")?;
+
+    for c in text.chars() {
+        match c {
+            '"' => write!(f, "\\\"")?,
+            '\\' => write!(f, "\\\\")?,
+            '\n' => write!(f, "\\n")?,
+            '\t' => write!(f, "\\t")?,
+            c => write!(f, "{}", c)?,
+        }
+    }
+
+    write!(f, "\"")
+}
+
 impl Display for Term {
     fn fmt(&self, f: &mut Formatter) -> Result<(), std::fmt::Error> {
+        if let Some((items, tail)) = self.list_items() {
+            write!(f, "[{}", items[0])?;
+
+            for item in &items[1..] {
+                write!(f, ", {}", item)?;
+            }
+
+            return match tail {
+                Term::Const(Const(nil)) if nil == "[]" => write!(f, "]"),
+                tail => write!(f, "|{}]", tail),
+            };
+        }
+
         match self {
             Term::Var(Var(name, n)) if *n == 0 => Ok(write!(f, "{}", name)?),
             Term::Var(Var(name, n)) => Ok(write!(f, "{}{}", name, n)?),
-            Term::Const(Const(a)) => Ok(write!(f, "{}", a)?),
+            Term::Const(Const(a)) => fmt_name(f, a),
+            Term::Number(n) => write!(f, "{}", n),
+            Term::String(text) => fmt_string(f, text),
             Term::Atom(Atom {
                 name: Const(name),
                 args,
                 ..
             }) => match args.last() {
-                None => Ok(write!(f, "{}", &name)?),
+                None => fmt_name(f, name),
                 Some(last) => {
                     let init = &args[..args.len() - 1];
                     let mut args = String::new();
@@ -79,7 +766,8 @@ impl Display for Term {
 
                     args.push_str(&format!("{})", last));
 
-                    Ok(write!(f, "{}({}", &name, args)?)
+                    fmt_name(f, name)?;
+                    Ok(write!(f, "({}", args)?)
                 }
             },
         }
@@ -103,3 +791,116 @@ impl Display for Atom {
         Ok(write!(f, "{}", Term::Atom(self.clone()))?)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn int(i: i64) -> Term {
+        Term::Number(Number::Int(i))
+    }
+
+    fn float(x: f64) -> Term {
+        Term::Number(Number::Float(x))
+    }
+
+    fn variant(t1: &str, t2: &str) -> bool {
+        crate::term!(t1).is_variant(&crate::term!(t2))
+    }
+
+    fn subsumes(t1: &str, t2: &str) -> bool {
+        crate::term!(t1).subsumes(&crate::term!(t2))
+    }
+
+    fn assert_ascending(terms: &[Term]) {
+        for (i, t1) in terms.iter().enumerate() {
+            for (j, t2) in terms.iter().enumerate() {
+                assert_eq!(t1.cmp(t2), i.cmp(&j), "comparing {} with {}", t1, t2);
+            }
+        }
+    }
+
+    #[test]
+    fn test_standard_order_1_succeeds() {
+        assert_ascending(&[
+            Term::Var(Var::new("Y", 0)),
+            Term::Var(Var::new("X", 1)),
+            float(-1.5),
+            int(-1),
+            float(1.0),
+            int(1),
+            float(1.5),
+            int(2),
+            Term::Const(Const::new("[]")),
+            Term::Const(Const::new("a")),
+            Term::Const(Const::new("b")),
+            Term::Atom(Atom::new("z", vec![int(1)])),
+            Term::Atom(Atom::new("a", vec![int(1), int(2)])),
+            Term::Atom(Atom::new("a", vec![int(2), int(1)])),
+            Term::Atom(Atom::new("b", vec![int(0), int(0)])),
+        ]);
+    }
+
+    #[test]
+    fn test_standard_order_2_succeeds() {
+        let big = i64::MAX;
+
+        assert_ascending(&[
+            float(f64::NEG_INFINITY),
+            int(i64::MIN),
+            int(big - 1),
+            float(big as f64),
+            float(f64::INFINITY),
+        ]);
+        assert_ascending(&[float(-0.5), int(0), float(0.5)]);
+        assert_ascending(&[float(-1.0), int(-1), float(-0.5)]);
+    }
+
+    #[test]
+    fn test_standard_order_3_succeeds() {
+        assert_ne!(int(1), float(1.0));
+        assert_eq!(float(2.5), float(2.5));
+
+        let mut terms = [
+            Term::Const(Const::new("a")),
+            int(1),
+            Term::Var(Var::new("X", 0)),
+            float(1.0),
+            Term::Atom(Atom::new("f", vec![Term::Var(Var::new("X", 0))])),
+        ];
+        terms.sort();
+
+        assert_eq!(
+            terms.iter().map(|t| t.to_string()).collect::<Vec<_>>(),
+            ["X", "1.0", "1", "a", "f(X)"]
+        );
+    }
+
+    #[test]
+    fn test_variant_1_succeeds() {
+        assert!(variant("f(X, Y, X)", "f(A, B, A)"));
+        assert!(variant("g(X, [a|T])", "g(Y, [a|X])"));
+        assert!(!variant("f(X, Y)", "f(A, A)"));
+        assert!(!variant("f(X, X)", "f(A, B)"));
+        assert!(!variant("f(X, a)", "f(A, b)"));
+        assert!(!variant("f(X)", "f(a)"));
+
+        let renaming = crate::term!("p(X, Y)")
+            .variant(&crate::term!("p(Y, Z)"))
+            .unwrap();
+        assert_eq!(renaming[&Var::new("X", 0)], Var::new("Y", 0));
+        assert_eq!(renaming[&Var::new("Y", 0)], Var::new("Z", 0));
+    }
+
+    #[test]
+    fn test_subsumes_1_succeeds() {
+        assert!(subsumes("f(X, Y)", "f(Z, Z)"));
+        assert!(subsumes("f(X, a)", "f(b(c), a)"));
+        assert!(subsumes("X", "f(Y)"));
+        assert!(!subsumes("f(Z, Z)", "f(X, Y)"));
+        assert!(!subsumes("X", "f(X)"));
+        assert!(!subsumes("f(X, X)", "f(a, b)"));
+        assert!(subsumes("g(X)", "g(1.0)"));
+        assert!(!subsumes("g(1)", "g(1.0)"));
+    }
+}
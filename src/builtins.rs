@@ -0,0 +1,1871 @@
+use crate::arith::eval;
+use crate::ast::{qualified, Arity, Assertion, Atom, Const, Number, Term, Var};
+use crate::database::{DatabaseError, Key, Segment};
+use crate::dcg::translate_body;
+use crate::debugger::Spypoint;
+use crate::engine::{Engine, Step};
+use crate::errors::{
+    self, domain_error, existence_error, indicator, instantiation_error, permission_error,
+    representation_error, syntax_error, type_error, uninstantiation_error,
+};
+use crate::flags::FLAGS;
+use crate::format::format;
+use crate::json::JsonError;
+use crate::machine::{Machine, Query};
+use crate::ops::OpType;
+use crate::profiler::Profiler;
+use crate::random::Random;
+use crate::reader::{parse_number, ReadTerm, Reader};
+use crate::stream::{Buffer, StreamKind};
+use crate::writer::Writer;
+use crate::{map_vars, rename_term};
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::mem;
+
+// a builtin runs directly against the query, failing by returning false, or raising
+// the formal part of an ISO error
+pub(crate) type Builtin = fn(&mut Machine, &mut Query, &[Term]) -> Result<bool, Term>;
+
+pub(crate) const BUILTINS: &[(&str, Arity, Builtin)] = &[
+    ("true", 0, succeed),
+    ("fail", 0, fail),
+    ("false", 0, fail),
+    ("halt", 0, halt),
+    ("garbage_collect", 0, |m, q, _| {
+        m.collect_garbage(q);
+        Ok(true)
+    }),
+    (",", 2, conjunction),
+    (";", 2, disjunction),
+    ("->", 2, |_, q, args| {
+        let height = q.choicepoints();
+        if_then(q, &args[0], &args[1], height);
+        Ok(true)
+    }),
+    // a cut only reaches here when it is called as a goal of its own, when it cuts
+    // nothing; cuts in clause bodies become $cut/1
+    ("!", 0, succeed),
+    ("$cut", 1, |_, q, args| {
+        if let Term::Number(Number::Int(n)) = args[0] {
+            q.cut(n as usize);
+        }
+        Ok(true)
+    }),
+    ("=", 2, unify),
+    ("catch", 3, |_, q, args| {
+        q.push_catch(args[0].clone(), args[1].clone(), args[2].clone());
+        Ok(true)
+    }),
+    ("throw", 1, |_, q, args| {
+        match q.env.substitute_term(&args[0]) {
+            Term::Var(_) => Err(Term::compound(
+                "error",
+                vec![instantiation_error(), q.fresh_var()],
+            )),
+            ball => Err(ball),
+        }
+    }),
+    ("call", 1, call),
+    ("call", 2, call),
+    ("call", 3, call),
+    ("call", 4, call),
+    ("call", 5, call),
+    ("call", 6, call),
+    ("call", 7, call),
+    ("call", 8, call),
+    (":", 2, qualified_call),
+    ("\\+", 1, not_provable),
+    ("engine_create", 3, |m, q, args| {
+        // the engine runs on a copy of the template and goal, sharing no variables
+        let pair = Term::compound("-", vec![args[0].clone(), args[1].clone()]);
+        let (template, goal) = match rename_term(&q.env.substitute_term(&pair)) {
+            Term::Atom(a) => {
+                let mut args = a.into_args();
+                (args.remove(0), args.remove(0))
+            }
+            _ => unreachable!(),
+        };
+
+        let id = m.next_engine;
+        m.next_engine += 1;
+        m.engines.insert(id, Engine::new(template, goal));
+
+        let handle = Term::compound("$engine", vec![Term::int(id as i64)]);
+        Ok(q.unify(&args[2], &handle))
+    }),
+    ("engine_next", 2, |m, q, args| {
+        engine_next(m, q, &args[0], &args[1])
+    }),
+    ("engine_post", 2, |m, q, args| {
+        engine_post(m, q, &args[0], &args[1])?;
+        Ok(true)
+    }),
+    ("engine_post", 3, |m, q, args| {
+        engine_post(m, q, &args[0], &args[1])?;
+        engine_next(m, q, &args[0], &args[2])
+    }),
+    ("engine_fetch", 1, |m, q, args| match m.posted.take() {
+        Some(t) => {
+            let t = q.rename(&t);
+            Ok(q.unify(&args[0], &t))
+        }
+        None => Err(existence_error("term", Term::atom("posted"))),
+    }),
+    ("engine_destroy", 1, |m, q, args| {
+        let id = engine(m, q, &args[0])?;
+        m.engines.remove(&id);
+        Ok(true)
+    }),
+    // forall(Cond, Action) holds if Action holds for every solution of Cond, and binds
+    // nothing either way
+    ("forall", 2, |m, q, args| {
+        let action = Term::compound("\\+", vec![args[1].clone()]);
+        let counterexample = Term::compound(",", vec![args[0].clone(), action]);
+        not_provable(m, q, &[counterexample])
+    }),
+    ("phrase", 2, |_, q, args| {
+        let rest = Term::atom("[]");
+        phrase(q, &args[0], &args[1], &rest)
+    }),
+    ("phrase", 3, |_, q, args| {
+        phrase(q, &args[0], &args[1], &args[2])
+    }),
+    ("findall", 3, findall),
+    ("$bag_add", 2, |_, q, args| {
+        let item = rename_term(&q.env.substitute_term(&args[1]));
+        q.add_to_bag(bag(&args[0]), item);
+        Ok(true)
+    }),
+    ("$bag", 2, |_, q, args| {
+        let items = q.close_bag(bag(&args[0]));
+        let items = items.iter().map(|item| q.rename(item)).collect();
+        Ok(q.unify(&args[1], &Term::list(items, Term::atom("[]"))))
+    }),
+    ("bagof", 3, |_, q, args| bagof(q, args, "bag")),
+    ("setof", 3, |_, q, args| bagof(q, args, "set")),
+    ("$bagof", 4, bags),
+    ("^", 2, |_, q, args| {
+        q.push_call(args[1].clone());
+        Ok(true)
+    }),
+    ("aggregate_all", 3, aggregate_all),
+    ("$aggregate_all", 3, |_, q, args| {
+        aggregate(q, args).or_else(|formal| {
+            let context = vec![indicator("aggregate_all", 3), q.fresh_var()];
+            let error = vec![formal, Term::compound("context", context)];
+            q.exception = Some(Term::compound("error", error));
+            Ok(false)
+        })
+    }),
+    ("is", 2, is),
+    ("=:=", 2, |_, q, args| {
+        compare(q, args, |o| o == Ordering::Equal)
+    }),
+    ("=\\=", 2, |_, q, args| {
+        compare(q, args, |o| o != Ordering::Equal)
+    }),
+    ("<", 2, |_, q, args| {
+        compare(q, args, |o| o == Ordering::Less)
+    }),
+    (">", 2, |_, q, args| {
+        compare(q, args, |o| o == Ordering::Greater)
+    }),
+    ("=<", 2, |_, q, args| {
+        compare(q, args, |o| o != Ordering::Greater)
+    }),
+    (">=", 2, |_, q, args| {
+        compare(q, args, |o| o != Ordering::Less)
+    }),
+    ("==", 2, |_, q, args| {
+        order(q, args, |o| o == Ordering::Equal)
+    }),
+    ("\\==", 2, |_, q, args| {
+        order(q, args, |o| o != Ordering::Equal)
+    }),
+    ("@<", 2, |_, q, args| {
+        order(q, args, |o| o == Ordering::Less)
+    }),
+    ("@>", 2, |_, q, args| {
+        order(q, args, |o| o == Ordering::Greater)
+    }),
+    ("@=<", 2, |_, q, args| {
+        order(q, args, |o| o != Ordering::Greater)
+    }),
+    ("@>=", 2, |_, q, args| {
+        order(q, args, |o| o != Ordering::Less)
+    }),
+    ("compare", 3, compare_order),
+    ("random", 1, |m, q, args| {
+        let x = Term::Number(Number::Float(m.random.float()));
+        Ok(q.unify(&args[0], &x))
+    }),
+    ("random_between", 3, |m, q, args| {
+        let (low, high) = (integer(q, &args[0])?, integer(q, &args[1])?);
+        if low > high {
+            return Ok(false);
+        }
+
+        let x = Term::int(m.random.between(low, high));
+        Ok(q.unify(&args[2], &x))
+    }),
+    ("random_member", 2, |m, q, args| {
+        let items = list(q, &args[1])?;
+        if items.is_empty() {
+            return Ok(false);
+        }
+
+        let i = m.random.below(items.len() as u64) as usize;
+        Ok(q.unify(&args[0], &items[i]))
+    }),
+    ("random_permutation", 2, |m, q, args| {
+        let mut items = list(q, &args[0])?;
+        partial_list(q, &args[1])?;
+        m.random.shuffle(&mut items);
+        Ok(q.unify(&args[1], &Term::list(items, Term::atom("[]"))))
+    }),
+    ("set_random", 1, set_random),
+    ("write", 1, |m, q, args| {
+        let text = Writer::new(&m.ops).write(&q.env.substitute_term(&args[0]));
+        output(m, &text)
+    }),
+    ("print", 1, |m, q, args| {
+        let text = portrayed(m, q, &q.env.substitute_term(&args[0]))?;
+        output(m, &text)
+    }),
+    ("writeln", 1, |m, q, args| {
+        let text = Writer::new(&m.ops).write(&q.env.substitute_term(&args[0]));
+        output(m, &format!("{}\n", text))
+    }),
+    ("writeq", 1, |m, q, args| {
+        let text = Writer::new(&m.ops)
+            .quoted(true)
+            .write(&q.env.substitute_term(&args[0]));
+        output(m, &text)
+    }),
+    ("write_canonical", 1, |m, q, args| {
+        let text = Writer::new(&m.ops)
+            .quoted(true)
+            .ignore_ops(true)
+            .write(&q.env.substitute_term(&args[0]));
+        output(m, &text)
+    }),
+    ("nl", 0, |m, _, _| output(m, "\n")),
+    ("format", 1, |m, q, args| {
+        format_output(m, q, &args[0], &Term::atom("[]"))
+    }),
+    ("format", 2, |m, q, args| {
+        format_output(m, q, &args[0], &args[1])
+    }),
+    ("read", 1, |m, q, args| read_term(m, q, &args[0], &[])),
+    ("read_term", 2, |m, q, args| {
+        let options = list(q, &args[1])?;
+        read_term(m, q, &args[0], &options)
+    }),
+    ("json_read", 2, json_read),
+    ("json_write", 2, |m, q, args| {
+        stream(q, &args[0], "user_output")?;
+        let t = q.env.substitute_term(&args[1]);
+        let json = t.to_json().map_err(|e| match e {
+            JsonError::NotJson(Term::Var(_)) => instantiation_error(),
+            JsonError::NotJson(culprit) => type_error("json_term", culprit),
+            _ => unreachable!(),
+        })?;
+        output(m, &json)
+    }),
+    ("set_prolog_flag", 2, |m, q, args| {
+        let flag = q.env.deref(&args[0]);
+        m.flags.set(&flag, &q.env.substitute_term(&args[1]))?;
+        Ok(true)
+    }),
+    ("current_prolog_flag", 2, current_prolog_flag),
+    ("op", 3, op),
+    ("var", 1, |_, q, args| {
+        type_test(q, args, |t| matches!(t, Term::Var(_)))
+    }),
+    ("nonvar", 1, |_, q, args| {
+        type_test(q, args, |t| !matches!(t, Term::Var(_)))
+    }),
+    ("atom", 1, |_, q, args| {
+        type_test(q, args, |t| matches!(t, Term::Const(_)))
+    }),
+    ("number", 1, |_, q, args| {
+        type_test(q, args, |t| matches!(t, Term::Number(_)))
+    }),
+    ("integer", 1, |_, q, args| {
+        type_test(q, args, |t| matches!(t, Term::Number(Number::Int(_))))
+    }),
+    ("float", 1, |_, q, args| {
+        type_test(q, args, |t| matches!(t, Term::Number(Number::Float(_))))
+    }),
+    ("atomic", 1, |_, q, args| {
+        type_test(q, args, |t| {
+            matches!(t, Term::Const(_) | Term::Number(_) | Term::String(_))
+        })
+    }),
+    ("string", 1, |_, q, args| {
+        type_test(q, args, |t| matches!(t, Term::String(_)))
+    }),
+    ("compound", 1, |_, q, args| {
+        type_test(q, args, |t| matches!(t, Term::Atom(_)))
+    }),
+    ("callable", 1, |_, q, args| {
+        type_test(q, args, |t| matches!(t, Term::Const(_) | Term::Atom(_)))
+    }),
+    ("ground", 1, |_, q, args| {
+        Ok(q.env.substitute_term(&args[0]).variables().is_empty())
+    }),
+    ("term_variables", 2, |_, q, args| {
+        let t = q.env.substitute_term(&args[0]);
+        let vars = t.variables().into_iter().cloned().map(Term::Var);
+        Ok(q.unify(&args[1], &Term::list(vars.collect(), Term::atom("[]"))))
+    }),
+    ("is_list", 1, |_, q, args| Ok(list(q, &args[0]).is_ok())),
+    ("sort", 2, |_, q, args| {
+        let mut items = sortable(q, args)?;
+        items.sort();
+        items.dedup();
+        Ok(q.unify(&args[1], &Term::list(items, Term::atom("[]"))))
+    }),
+    ("msort", 2, |_, q, args| {
+        let mut items = sortable(q, args)?;
+        items.sort();
+        Ok(q.unify(&args[1], &Term::list(items, Term::atom("[]"))))
+    }),
+    ("keysort", 2, keysort),
+    ("sort", 4, sort),
+    ("between", 3, between),
+    ("numlist", 3, |_, q, args| {
+        let (low, high) = (integer(q, &args[0])?, integer(q, &args[1])?);
+        partial_list(q, &args[2])?;
+        if low > high {
+            return Ok(false);
+        }
+
+        let items = (low..=high).map(Term::int).collect();
+        Ok(q.unify(&args[2], &Term::list(items, Term::atom("[]"))))
+    }),
+    ("number_codes", 2, |_, q, args| {
+        number_text(q, args, Text::Codes)
+    }),
+    ("number_chars", 2, |_, q, args| {
+        number_text(q, args, Text::Chars)
+    }),
+    ("atom_number", 2, atom_number),
+    ("char_code", 2, char_code),
+    ("string_concat", 3, string_concat),
+    ("string_chars", 2, |_, q, args| {
+        string_list(q, args, Text::Chars)
+    }),
+    ("string_codes", 2, |_, q, args| {
+        string_list(q, args, Text::Codes)
+    }),
+    ("string_to_atom", 2, string_to_atom),
+    ("number_string", 2, number_string),
+    ("split_string", 4, split_string),
+    ("functor", 3, functor),
+    ("arg", 3, arg),
+    ("=..", 2, univ),
+    ("assert", 1, |m, q, args| assert(m, q, args, false)),
+    ("asserta", 1, |m, q, args| assert(m, q, args, true)),
+    ("assertz", 1, |m, q, args| assert(m, q, args, false)),
+    ("dynamic", 1, dynamic),
+    ("retract", 1, retract),
+    ("$retract", 2, retract_next),
+    ("retractall", 1, retractall),
+    ("clause", 2, clause),
+    ("current_predicate", 1, current_predicate),
+    ("abolish", 1, |m, q, args| {
+        let key = predicate_indicator(&q.env.substitute_term(&args[0]))?;
+        m.db.abolish(&key).map_err(modify_error)?;
+        Ok(true)
+    }),
+    ("nb_setval", 2, |m, q, args| {
+        let name = atom(q, &args[0])?;
+        let value = rename_term(&q.env.substitute_term(&args[1]));
+        q.env.remove_global(&name);
+        m.globals.insert(name, value);
+        Ok(true)
+    }),
+    ("b_setval", 2, |_, q, args| {
+        let name = atom(q, &args[0])?;
+        q.env.set_global(&name, args[1].clone());
+        Ok(true)
+    }),
+    ("nb_getval", 2, getval),
+    ("b_getval", 2, getval),
+    ("listing", 0, |m, _, _| {
+        let listing = m.listing();
+        output(m, &listing)
+    }),
+    ("listing", 1, listing),
+    ("profile", 1, |m, q, args| {
+        // the profile is taken until the goal first succeeds, fails or raises an error
+        m.profiler = Some(Profiler::default());
+        let stop = Term::atom("$profile_stop");
+        let ball = q.fresh_var();
+        let rethrow = Term::compound("throw", vec![ball.clone()]);
+
+        q.push_alternative(Term::compound(",", vec![stop.clone(), Term::atom("fail")]));
+        q.push_goal(stop.clone());
+        q.push_catch(
+            args[0].clone(),
+            ball,
+            Term::compound(",", vec![stop, rethrow]),
+        );
+        Ok(true)
+    }),
+    ("$profile_stop", 0, |m, _, _| match m.profiler.take() {
+        Some(profiler) => {
+            let profile = profiler.finish();
+            let report = profile.to_string();
+            m.profile = Some(profile);
+            output(m, &report)
+        }
+        None => Ok(true),
+    }),
+    ("trace", 0, |m, _, _| {
+        m.debugger.trace();
+        Ok(true)
+    }),
+    ("notrace", 0, |m, _, _| {
+        m.debugger.notrace();
+        Ok(true)
+    }),
+    ("spy", 1, |m, q, args| {
+        let spypoint = spypoint(q, &args[0])?;
+        m.debugger.spy(spypoint);
+        Ok(true)
+    }),
+    ("nospy", 1, |m, q, args| {
+        let spypoint = spypoint(q, &args[0])?;
+        m.debugger.nospy(&spypoint);
+        Ok(true)
+    }),
+    ("attvar", 1, |_, q, args| match q.env.deref(&args[0]) {
+        Term::Var(x) => Ok(q.env.is_attributed(&x)),
+        _ => Ok(false),
+    }),
+    ("put_attr", 3, |_, q, args| {
+        let (x, module) = attribute(q, args)?;
+        q.env.put_attr(x, &module, args[2].clone());
+        Ok(true)
+    }),
+    ("get_attr", 3, |_, q, args| {
+        let (x, module) = attribute(q, args)?;
+        match q.env.get_attr(&x, &module).cloned() {
+            Some(value) => Ok(q.unify(&args[2], &value)),
+            None => Ok(false),
+        }
+    }),
+    ("del_attr", 2, |_, q, args| {
+        if let Term::Var(x) = q.env.deref(&args[0]) {
+            q.env.del_attr(&x, &atom(q, &args[1])?);
+        }
+        Ok(true)
+    }),
+    ("freeze", 2, |_, q, args| match q.env.deref(&args[0]) {
+        Term::Var(x) => {
+            suspend(q, x, "freeze", args[1].clone());
+            Ok(true)
+        }
+        _ => {
+            q.push_goal(args[1].clone());
+            Ok(true)
+        }
+    }),
+    ("dif", 2, dif),
+    ("when", 2, |_, q, args| {
+        let done = q.fresh_var();
+        when(q, &[done, args[0].clone(), args[1].clone()])
+    }),
+    ("$when", 3, |_, q, args| when(q, args)),
+    ("$foreign_redo", 2, |m, q, args| {
+        // the error of a foreign predicate already has its own context
+        match m.redo_foreign(q, &args[0], &args[1]) {
+            Ok(found) => Ok(found),
+            Err(ball) => {
+                q.exception = Some(ball);
+                Ok(false)
+            }
+        }
+    }),
+];
+
+fn succeed(_: &mut Machine, _: &mut Query, _: &[Term]) -> Result<bool, Term> {
+    Ok(true)
+}
+
+fn fail(_: &mut Machine, _: &mut Query, _: &[Term]) -> Result<bool, Term> {
+    Ok(false)
+}
+
+fn halt(_: &mut Machine, q: &mut Query, _: &[Term]) -> Result<bool, Term> {
+    q.exception = Some(errors::halt());
+    Ok(false)
+}
+
+fn conjunction(_: &mut Machine, q: &mut Query, args: &[Term]) -> Result<bool, Term> {
+    q.push_goal(args[1].clone());
+    q.push_goal(args[0].clone());
+    Ok(true)
+}
+
+// try the left branch, leaving the right one to backtrack into; with `->` on the left,
+// the right branch runs only if the condition has no solution
+fn disjunction(_: &mut Machine, q: &mut Query, args: &[Term]) -> Result<bool, Term> {
+    q.push_alternative(args[1].clone());
+
+    match q.env.deref(&args[0]) {
+        Term::Atom(Atom {
+            ref name, ref args, ..
+        }) if name.0 == "->" && args.len() == 2 => {
+            // the choicepoint just left is the one the condition cuts back past
+            let height = q.choicepoints() - 1;
+            if_then(q, &args[0], &args[1], height);
+        }
+        left => q.push_goal(left),
+    }
+
+    Ok(true)
+}
+
+// run the then branch on the first solution of the condition, cutting back to a number
+// of choicepoints so as to keep no others
+fn if_then(q: &mut Query, cond: &Term, then: &Term, height: usize) {
+    q.push_goal(then.clone());
+    q.push_goal(Term::compound("$cut", vec![Term::int(height as i64)]));
+    q.push_call(cond.clone());
+}
+
+// call a goal with extra arguments added to the end of its own
+fn call(_: &mut Machine, q: &mut Query, args: &[Term]) -> Result<bool, Term> {
+    let extra = args[1..].iter().cloned();
+
+    let goal = match q.env.deref(&args[0]) {
+        Term::Var(_) => return Err(instantiation_error()),
+        Term::Const(Const(name)) => Term::compound(&name, extra.collect()),
+        Term::Atom(a) => {
+            let (name, args) = a.into_parts();
+            Term::compound(&name.0, args.into_iter().chain(extra).collect())
+        }
+        t => return Err(type_error("callable", t)),
+    };
+
+    q.push_call(goal);
+    Ok(true)
+}
+
+// negation as failure, as `(Goal -> fail ; true)`: the goal runs within the query, on
+// its budget, and none of its bindings survive; errors pass through untouched
+fn not_provable(_: &mut Machine, q: &mut Query, args: &[Term]) -> Result<bool, Term> {
+    let height = q.choicepoints();
+    q.push_alternative(Term::atom("true"));
+    if_then(q, &args[0], &Term::atom("fail"), height);
+    Ok(true)
+}
+
+// the number of a bag of findall/3 from the term it is passed as
+fn bag(t: &Term) -> usize {
+    match t {
+        Term::Number(Number::Int(n)) => *n as usize,
+        _ => unreachable!(),
+    }
+}
+
+// the number of an engine from its handle; an engine is out of reach while it runs
+fn engine(m: &Machine, q: &Query, t: &Term) -> Result<usize, Term> {
+    match q.env.deref(t) {
+        Term::Var(_) => Err(instantiation_error()),
+        Term::Atom(Atom {
+            ref name, ref args, ..
+        }) if name.0 == "$engine" && args.len() == 1 => match &args[0] {
+            Term::Number(Number::Int(id)) if m.engines.contains_key(&(*id as usize)) => {
+                Ok(*id as usize)
+            }
+            _ => Err(existence_error("engine", q.env.substitute_term(t))),
+        },
+        t => Err(type_error("engine", t)),
+    }
+}
+
+// run an engine to its next solution, raising in the caller the error it raised
+fn engine_next(m: &mut Machine, q: &mut Query, e: &Term, t: &Term) -> Result<bool, Term> {
+    let id = engine(m, q, e)?;
+    let mut running = m.engines.remove(&id).unwrap();
+    let result = running.next_within(m, q);
+    m.engines.insert(id, running);
+
+    match result {
+        Ok(Step::Answer(answer)) => {
+            let answer = q.rename(&rename_term(&answer));
+            Ok(q.unify(t, &answer))
+        }
+        // the query pauses with the engine, to call this again once it is resumed
+        Ok(Step::Paused) => {
+            q.push_goal(Term::compound("engine_next", vec![e.clone(), t.clone()]));
+            Ok(true)
+        }
+        Ok(Step::Done) => Ok(false),
+        Err(ball) => {
+            q.exception = Some(ball);
+            Ok(false)
+        }
+    }
+}
+
+fn engine_post(m: &mut Machine, q: &mut Query, e: &Term, t: &Term) -> Result<(), Term> {
+    let id = engine(m, q, e)?;
+    let t = rename_term(&q.env.substitute_term(t));
+
+    if m.engines.get_mut(&id).unwrap().post(t) {
+        Ok(())
+    } else {
+        let handle = q.env.substitute_term(e);
+        Err(permission_error("post_to", "engine", handle))
+    }
+}
+
+// parse a list with a grammar body, leaving the given rest of it
+fn phrase(q: &mut Query, body: &Term, list: &Term, rest: &Term) -> Result<bool, Term> {
+    let body = match q.env.deref(body) {
+        Term::Var(_) => return Err(instantiation_error()),
+        body => body,
+    };
+    partial_list(q, list)?;
+    partial_list(q, rest)?;
+
+    let goal = translate_body(body.clone(), list.clone(), rest.clone(), &mut || {
+        q.fresh_var()
+    })
+    .ok_or_else(|| type_error("callable", body))?;
+    q.push_call(goal);
+    Ok(true)
+}
+
+// the instances of a template for every solution of a goal, as a list; the goal runs
+// within the query, each solution adding to a bag that is taken once there are no more
+fn findall(_: &mut Machine, q: &mut Query, args: &[Term]) -> Result<bool, Term> {
+    partial_list(q, &args[2])?;
+
+    let bag = Term::int(q.open_bag() as i64);
+    q.push_alternative(Term::compound("$bag", vec![bag.clone(), args[2].clone()]));
+    q.push_goal(Term::atom("fail"));
+    q.push_goal(Term::compound("$bag_add", vec![bag, args[0].clone()]));
+    q.push_call(args[1].clone());
+    Ok(true)
+}
+
+// count, sum(Expr), max(Expr), min(Expr), bag(Template) or set(Template) over all the
+// solutions of a goal; max and min fail when there are none
+fn aggregate_all(_: &mut Machine, q: &mut Query, args: &[Term]) -> Result<bool, Term> {
+    let (spec, template) = match q.env.deref(&args[0]) {
+        Term::Var(_) => return Err(instantiation_error()),
+        Term::Const(Const(ref name)) if name == "count" => (name.clone(), Term::atom("x")),
+        Term::Atom(Atom {
+            ref name, ref args, ..
+        }) if args.len() == 1 && ["sum", "max", "min", "bag", "set"].contains(&name.0.as_str()) => {
+            (name.0.clone(), args[0].clone())
+        }
+        spec => return Err(domain_error("aggregate_spec", spec)),
+    };
+
+    let items = q.fresh_var();
+    let aggregate = vec![Term::atom(&spec), items.clone(), args[2].clone()];
+    q.push_goal(Term::compound("$aggregate_all", aggregate));
+    q.push_goal(Term::compound(
+        "findall",
+        vec![template, args[1].clone(), items],
+    ));
+    Ok(true)
+}
+
+// the solutions of a goal for each binding of its free variables, those neither in the
+// template nor bound by `V^` in front of it: findall/3 collects each with the binding of
+// the free variables as its witness, then $bagof groups them by witness
+fn bagof(q: &mut Query, args: &[Term], kind: &str) -> Result<bool, Term> {
+    partial_list(q, &args[2])?;
+
+    let template = q.env.substitute_term(&args[0]);
+    let mut goal = q.env.substitute_term(&args[1]);
+    let mut bound: Vec<Var> = template.variables().into_iter().cloned().collect();
+
+    loop {
+        match goal {
+            Term::Var(_) => return Err(instantiation_error()),
+            Term::Atom(a) if a.name.0 == "^" && a.arity == 2 => {
+                let mut args = a.into_args();
+                goal = args.pop().unwrap();
+                bound.extend(args[0].variables().into_iter().cloned());
+            }
+            _ => break,
+        }
+    }
+
+    let free = goal.variables().into_iter().filter(|x| !bound.contains(x));
+    let witness = Term::list(free.cloned().map(Term::Var).collect(), Term::atom("[]"));
+    let pair = Term::compound("-", vec![witness.clone(), template]);
+
+    let pairs = q.fresh_var();
+    let grouped = vec![Term::atom(kind), witness, pairs.clone(), args[2].clone()];
+    q.push_goal(Term::compound("$bagof", grouped));
+    q.push_goal(Term::compound("findall", vec![pair, goal, pairs]));
+    Ok(true)
+}
+
+// the bags of bagof/3 or sets of setof/3, one for each witness in the standard order,
+// left to be unified with the witness in turn; there are none without any solutions
+fn bags(_: &mut Machine, q: &mut Query, args: &[Term]) -> Result<bool, Term> {
+    let set = atom(q, &args[0])? == "set";
+    let mut pairs = Vec::new();
+
+    for item in list(q, &args[2])? {
+        if let Term::Atom(a) = q.env.substitute_term(&item) {
+            let mut pair = a.into_args();
+            let template = pair.pop().unwrap();
+            pairs.push((pair.pop().unwrap(), template));
+        }
+    }
+
+    pairs.sort_by(|(w1, _), (w2, _)| w1.cmp(w2));
+    let mut groups: Vec<(Term, Vec<Term>)> = Vec::new();
+
+    for (witness, template) in pairs {
+        match groups.iter_mut().find(|(w, _)| w.is_variant(&witness)) {
+            Some((_, items)) => items.push(template),
+            None => groups.push((witness, vec![template])),
+        }
+    }
+
+    let values = groups.into_iter().map(|(witness, mut items)| {
+        if set {
+            items.sort();
+            items.dedup();
+        }
+        Term::compound("-", vec![witness, Term::list(items, Term::atom("[]"))])
+    });
+
+    let values = values.collect();
+    let pair = Term::compound("-", vec![args[1].clone(), args[3].clone()]);
+    Ok(alternatives(q, &pair, values))
+}
+
+// the aggregate of the items aggregate_all/3 found, whose errors are its own
+fn aggregate(q: &mut Query, args: &[Term]) -> Result<bool, Term> {
+    let spec = atom(q, &args[0])?;
+    let mut items = list(q, &args[1])?;
+
+    let result = match &*spec {
+        "count" => Term::int(items.len() as i64),
+        "bag" => Term::list(items, Term::atom("[]")),
+        "set" => {
+            items.sort();
+            items.dedup();
+            Term::list(items, Term::atom("[]"))
+        }
+        "sum" => {
+            let mut sum = Number::Int(0);
+            for item in items {
+                sum = eval(&Term::compound("+", vec![Term::Number(sum), item]))?;
+            }
+            Term::Number(sum)
+        }
+        _ => {
+            let mut extreme: Option<Number> = None;
+            for item in items {
+                let value = eval(&item)?;
+                let better = match extreme {
+                    None => true,
+                    Some(x) if spec == "max" => value.cmp_value(&x).is_gt(),
+                    Some(x) => value.cmp_value(&x).is_lt(),
+                };
+
+                if better {
+                    extreme = Some(value);
+                }
+            }
+
+            match extreme {
+                Some(x) => Term::Number(x),
+                None => return Ok(false),
+            }
+        }
+    };
+
+    Ok(q.unify(&args[2], &result))
+}
+
+// call a goal of a module, that is the predicate named after both
+fn qualified_call(_: &mut Machine, q: &mut Query, args: &[Term]) -> Result<bool, Term> {
+    let module = atom(q, &args[0])?;
+
+    let goal = match q.env.deref(&args[1]) {
+        Term::Var(_) => return Err(instantiation_error()),
+        Term::Const(Const(name)) => Term::atom(&qualified(&module, &name)),
+        Term::Atom(a) => {
+            let (name, args) = a.into_parts();
+            Term::compound(&qualified(&module, &name.0), args)
+        }
+        t => return Err(type_error("callable", t)),
+    };
+
+    q.push_goal(goal);
+    Ok(true)
+}
+
+fn unify(_: &mut Machine, q: &mut Query, args: &[Term]) -> Result<bool, Term> {
+    Ok(q.unify(&args[0], &args[1]))
+}
+
+fn is(_: &mut Machine, q: &mut Query, args: &[Term]) -> Result<bool, Term> {
+    let value = eval(&q.env.substitute_term(&args[1]))?;
+    Ok(q.unify(&args[0], &Term::Number(value)))
+}
+
+fn compare<F: Fn(Ordering) -> bool>(q: &mut Query, args: &[Term], f: F) -> Result<bool, Term> {
+    let x = eval(&q.env.substitute_term(&args[0]))?;
+    let y = eval(&q.env.substitute_term(&args[1]))?;
+    Ok(f(x.cmp_value(&y)))
+}
+
+// compare terms in the standard order, without binding their variables
+fn order<F: Fn(Ordering) -> bool>(q: &mut Query, args: &[Term], f: F) -> Result<bool, Term> {
+    Ok(f(q.env.compare_terms(&args[0], &args[1])))
+}
+
+fn compare_order(_: &mut Machine, q: &mut Query, args: &[Term]) -> Result<bool, Term> {
+    match q.env.deref(&args[0]) {
+        Term::Var(_) => (),
+        Term::Const(Const(ref name)) if name == "<" || name == "=" || name == ">" => (),
+        Term::Const(name) => return Err(domain_error("order", Term::Const(name))),
+        t => return Err(type_error("atom", t)),
+    }
+
+    let order = match q.env.compare_terms(&args[1], &args[2]) {
+        Ordering::Less => "<",
+        Ordering::Equal => "=",
+        Ordering::Greater => ">",
+    };
+
+    Ok(q.unify(&args[0], &Term::atom(order)))
+}
+
+fn output(m: &mut Machine, text: &str) -> Result<bool, Term> {
+    if m.current_output.kind() == StreamKind::Binary {
+        let culprit = Term::atom("user_output");
+        return Err(permission_error("output", "binary_stream", culprit));
+    }
+
+    m.current_output
+        .write(text.as_bytes())
+        .and_then(|_| m.current_output.flush())
+        .map_err(|_| permission_error("output", "stream", Term::atom("user_output")))?;
+    Ok(true)
+}
+
+// the arguments of format/2 are a list, or a single term standing for a list of one
+fn format_output(m: &mut Machine, q: &mut Query, text: &Term, args: &Term) -> Result<bool, Term> {
+    let directives = any_text(q, text)?.ok_or_else(instantiation_error)?;
+    let args = match list(q, args) {
+        Ok(items) => items,
+        Err(_) => vec![args.clone()],
+    };
+    let args = args.iter().map(|t| q.env.substitute_term(t)).collect();
+
+    let text = if portrays(m) {
+        let ops = m.ops.clone();
+        format(&ops, &directives, args, Some(&mut |t| portrayed(m, q, t)))?
+    } else {
+        format(&m.ops, &directives, args, None)?
+    };
+    output(m, &text)
+}
+
+fn portrays(m: &Machine) -> bool {
+    m.db.segment(&Key::new("portray", 1)).is_some()
+}
+
+// the text print/1 writes for a term: what portray/1 writes for it, if the program
+// defines portray/1 and it succeeds, and the term written as by writeq/1 otherwise
+fn portrayed(m: &mut Machine, q: &mut Query, t: &Term) -> Result<String, Term> {
+    if portrays(m) {
+        let buffer = Buffer::new();
+        let output = mem::replace(&mut m.current_output, Box::new(buffer.clone()));
+        let mut portray = Query::new(vec![Atom::new("portray", vec![rename_term(t)])]);
+        let solved = m.solve_within(q, &mut portray);
+        m.current_output = output;
+
+        if solved? {
+            return Ok(buffer.text());
+        }
+    }
+
+    Ok(Writer::new(&m.ops).quoted(true).write(t))
+}
+
+// read the next term from the input, with the variables, variable_names and singletons
+// options of read_term/2
+fn read_term(m: &mut Machine, q: &mut Query, t: &Term, options: &[Term]) -> Result<bool, Term> {
+    for option in options {
+        match q.env.deref(option) {
+            Term::Var(_) => return Err(instantiation_error()),
+            Term::Atom(Atom {
+                ref name, arity: 1, ..
+            }) if ["variables", "variable_names", "singletons"].contains(&&*name.0) => {}
+            option => return Err(domain_error("read_option", option)),
+        }
+    }
+
+    if m.current_input.kind() == StreamKind::Binary {
+        let culprit = Term::atom("user_input");
+        return Err(permission_error("input", "binary_stream", culprit));
+    }
+
+    let text = m
+        .read_text()
+        .map_err(|_| permission_error("input", "stream", Term::atom("user_input")))?;
+
+    let read = match text {
+        Some(text) => Reader::new(&m.ops)
+            .double_quotes(m.flags.double_quotes)
+            .read_term(&text)
+            .map_err(|e| syntax_error(&e.to_string()))?,
+        None => ReadTerm {
+            term: Term::atom("end_of_file"),
+            variable_names: Vec::new(),
+            singletons: Vec::new(),
+        },
+    };
+
+    // the variables read are new to the query, whatever their names
+    let mut fresh: HashMap<Var, Term> = HashMap::new();
+    let term = map_vars(&read.term, &mut |x| {
+        fresh
+            .entry(x.clone())
+            .or_insert_with(|| q.fresh_var())
+            .clone()
+    });
+    let named = |vars: &[(String, Var)]| {
+        let vars = vars
+            .iter()
+            .map(|(name, x)| Term::compound("=", vec![Term::atom(name), fresh[x].clone()]));
+        Term::list(vars.collect(), Term::atom("[]"))
+    };
+
+    let mut unified = q.unify(t, &term);
+    for option in options {
+        if let Term::Atom(Atom {
+            ref name, ref args, ..
+        }) = q.env.deref(option)
+        {
+            let value = match &*name.0 {
+                "variables" => {
+                    let vars = term.variables().into_iter().cloned().map(Term::Var);
+                    Term::list(vars.collect(), Term::atom("[]"))
+                }
+                "variable_names" => named(&read.variable_names),
+                _ => named(&read.singletons),
+            };
+
+            unified = unified && q.unify(&args[0], &value);
+        }
+    }
+
+    Ok(unified)
+}
+
+// read the next JSON value from the input as a term, or `end_of_file` at its end
+fn json_read(m: &mut Machine, q: &mut Query, args: &[Term]) -> Result<bool, Term> {
+    stream(q, &args[0], "user_input")?;
+
+    if m.current_input.kind() == StreamKind::Binary {
+        let culprit = Term::atom("user_input");
+        return Err(permission_error("input", "binary_stream", culprit));
+    }
+
+    let read = m
+        .read_json()
+        .map_err(|_| permission_error("input", "stream", Term::atom("user_input")))?;
+
+    match read.map_err(|e| syntax_error(&e.to_string()))? {
+        Some(t) => Ok(q.unify(&args[1], &t)),
+        None => Ok(q.unify(&args[1], &Term::atom("end_of_file"))),
+    }
+}
+
+// the stream argument of a builtin, which can only name the current stream it uses
+fn stream(q: &Query, t: &Term, alias: &str) -> Result<(), Term> {
+    match q.env.deref(t) {
+        Term::Var(_) => Err(instantiation_error()),
+        Term::Const(Const(name)) if name == alias => Ok(()),
+        t @ Term::Const(_) => Err(existence_error("stream", t)),
+        t => Err(domain_error("stream_or_alias", t)),
+    }
+}
+
+// the variable and module of put_attr/3 and get_attr/3
+fn attribute(q: &Query, args: &[Term]) -> Result<(Var, String), Term> {
+    match q.env.deref(&args[0]) {
+        Term::Var(x) => Ok((x, atom(q, &args[1])?)),
+        t => Err(uninstantiation_error(t)),
+    }
+}
+
+// leave a goal to run once a variable is bound, after those already waiting on it
+fn suspend(q: &mut Query, x: Var, module: &str, goal: Term) {
+    let goal = match q.env.get_attr(&x, module) {
+        Some(waiting) => Term::compound(",", vec![waiting.clone(), goal]),
+        None => goal,
+    };
+
+    q.env.put_attr(x, module, goal);
+}
+
+// succeed if two terms can never be equal, fail if they are, or check again whenever a
+// variable that unifying them would bind gets bound
+fn dif(_: &mut Machine, q: &mut Query, args: &[Term]) -> Result<bool, Term> {
+    let vars = match q.env.unifier(&args[0], &args[1]) {
+        Some(vars) => vars,
+        None => return Ok(true),
+    };
+
+    let goal = Term::compound("dif", args.to_vec());
+    for x in &vars {
+        suspend(q, x.clone(), "dif", goal.clone());
+    }
+
+    Ok(!vars.is_empty())
+}
+
+// run a goal once a condition holds, unless it has already run, as recorded by binding
+// the first argument; until then wait on a variable the condition depends on
+fn when(q: &mut Query, args: &[Term]) -> Result<bool, Term> {
+    if let Term::Var(_) = q.env.deref(&args[0]) {
+        match when_condition(q, &args[1])? {
+            None => {
+                q.push_goal(args[2].clone());
+                return Ok(q.unify(&args[0], &Term::atom("true")));
+            }
+            Some(vars) => {
+                let goal = Term::compound("$when", args.to_vec());
+                for x in vars {
+                    suspend(q, x, "when", goal.clone());
+                }
+            }
+        }
+    }
+
+    Ok(true)
+}
+
+// nothing if a condition of when/2 holds, or the variables to wait on until it might
+fn when_condition(q: &mut Query, condition: &Term) -> Result<Option<Vec<Var>>, Term> {
+    let (name, args) = match q.env.deref(condition) {
+        Term::Var(_) => return Err(instantiation_error()),
+        Term::Atom(a) => {
+            let (name, args) = a.into_parts();
+            (name.0, args)
+        }
+        t => return Err(domain_error("when_condition", t)),
+    };
+
+    match (&*name, &args[..]) {
+        ("nonvar", [t]) => match q.env.deref(t) {
+            Term::Var(x) => Ok(Some(vec![x])),
+            _ => Ok(None),
+        },
+        ("ground", [t]) => {
+            let t = q.env.substitute_term(t);
+            Ok(t.variables().first().map(|&x| vec![x.clone()]))
+        }
+        ("?=", [t1, t2]) => match q.env.unifier(t1, t2) {
+            Some(vars) if !vars.is_empty() => Ok(Some(vars)),
+            _ => Ok(None),
+        },
+        (",", [c1, c2]) => match when_condition(q, c1)? {
+            None => when_condition(q, c2),
+            waiting => Ok(waiting),
+        },
+        (";", [c1, c2]) => match (when_condition(q, c1)?, when_condition(q, c2)?) {
+            (Some(mut vars), Some(more)) => {
+                vars.extend(more);
+                Ok(Some(vars))
+            }
+            _ => Ok(None),
+        },
+        _ => Err(domain_error(
+            "when_condition",
+            q.env.substitute_term(condition),
+        )),
+    }
+}
+
+// the value of a global variable, as b_setval/2 last set it if that has not been undone,
+// or else as nb_setval/2 did, in a copy of its own
+fn getval(m: &mut Machine, q: &mut Query, args: &[Term]) -> Result<bool, Term> {
+    let name = atom(q, &args[0])?;
+
+    let value = match (q.env.get_global(&name), m.globals.get(&name)) {
+        (Some(value), _) => value.clone(),
+        (None, Some(value)) => q.rename(value),
+        (None, None) => return Err(existence_error("variable", Term::atom(&name))),
+    };
+
+    Ok(q.unify(&args[1], &value))
+}
+
+fn atom(q: &Query, t: &Term) -> Result<String, Term> {
+    match q.env.deref(t) {
+        Term::Var(_) => Err(instantiation_error()),
+        Term::Const(Const(name)) => Ok(name),
+        t => Err(type_error("atom", t)),
+    }
+}
+
+fn type_test<F: Fn(&Term) -> bool>(q: &mut Query, args: &[Term], f: F) -> Result<bool, Term> {
+    Ok(f(&q.env.deref(&args[0])))
+}
+
+// the items of a list to sort, in the standard order of their bound values
+fn sortable(q: &mut Query, args: &[Term]) -> Result<Vec<Term>, Term> {
+    let items = list(q, &args[0])?;
+    partial_list(q, &args[1])?;
+    Ok(items.iter().map(|t| q.env.substitute_term(t)).collect())
+}
+
+// seed(N) makes the random numbers that follow the same every time, and seed(random)
+// seeds them from the clock again
+fn set_random(m: &mut Machine, q: &mut Query, args: &[Term]) -> Result<bool, Term> {
+    let option = q.env.substitute_term(&args[0]);
+
+    m.random = match &option {
+        Term::Var(_) => return Err(instantiation_error()),
+        Term::Atom(Atom { name, args, .. }) if name.0 == "seed" && args.len() == 1 => {
+            match &args[0] {
+                Term::Var(_) => return Err(instantiation_error()),
+                Term::Number(Number::Int(seed)) => Random::seeded(*seed as u64),
+                Term::Const(Const(name)) if name == "random" => Random::default(),
+                t => return Err(type_error("integer", t.clone())),
+            }
+        }
+        _ => return Err(domain_error("set_random_option", option)),
+    };
+
+    Ok(true)
+}
+
+// sort(Key, Order, List, Sorted) stably sorts on an argument of each item, or on the
+// whole item if Key is 0, in the order given by @< or @> and keeping the items with
+// equal keys for @=< and @>=, or only the first of them otherwise
+fn sort(_: &mut Machine, q: &mut Query, args: &[Term]) -> Result<bool, Term> {
+    let key = match integer(q, &args[0])? {
+        key if key < 0 => return Err(domain_error("not_less_than_zero", Term::int(key))),
+        key => key as usize,
+    };
+    let (descending, dedup) = match q.env.deref(&args[1]) {
+        Term::Var(_) => return Err(instantiation_error()),
+        Term::Const(Const(ref order)) if order == "@<" => (false, true),
+        Term::Const(Const(ref order)) if order == "@=<" => (false, false),
+        Term::Const(Const(ref order)) if order == "@>" => (true, true),
+        Term::Const(Const(ref order)) if order == "@>=" => (true, false),
+        t => return Err(domain_error("order", t)),
+    };
+
+    let mut keyed = Vec::new();
+    for item in sortable(q, &args[2..])? {
+        let k = match &item {
+            _ if key == 0 => item.clone(),
+            Term::Var(_) => return Err(instantiation_error()),
+            Term::Atom(a) if key <= a.args.len() => a.args[key - 1].clone(),
+            Term::Atom(_) => return Err(existence_error("key", Term::int(key as i64))),
+            t => return Err(type_error("compound", t.clone())),
+        };
+        keyed.push((k, item));
+    }
+
+    keyed.sort_by(|(k1, _), (k2, _)| if descending { k2.cmp(k1) } else { k1.cmp(k2) });
+    if dedup {
+        keyed.dedup_by(|(k2, _), (k1, _)| k1 == k2);
+    }
+
+    let items = keyed.into_iter().map(|(_, item)| item).collect();
+    Ok(q.unify(&args[3], &Term::list(items, Term::atom("[]"))))
+}
+
+// between(Low, High, X) gives the integers from Low to High one at a time, leaving the
+// rest to be found on backtracking; High may be inf or infinite
+fn between(_: &mut Machine, q: &mut Query, args: &[Term]) -> Result<bool, Term> {
+    let low = integer(q, &args[0])?;
+    let high = match q.env.deref(&args[1]) {
+        Term::Const(Const(ref name)) if name == "inf" || name == "infinite" => i64::MAX,
+        _ => integer(q, &args[1])?,
+    };
+
+    match q.env.deref(&args[2]) {
+        Term::Var(_) if low > high => Ok(false),
+        Term::Var(_) => {
+            if low < high {
+                let rest = vec![Term::int(low + 1), args[1].clone(), args[2].clone()];
+                q.push_alternative(Term::compound("between", rest));
+            }
+
+            Ok(q.unify(&args[2], &Term::int(low)))
+        }
+        Term::Number(Number::Int(x)) => Ok(low <= x && x <= high),
+        t => Err(type_error("integer", t)),
+    }
+}
+
+// a stable sort of Key-Value pairs on their keys alone
+fn keysort(_: &mut Machine, q: &mut Query, args: &[Term]) -> Result<bool, Term> {
+    let mut pairs = Vec::new();
+
+    for item in sortable(q, args)? {
+        match item {
+            Term::Var(_) => return Err(instantiation_error()),
+            Term::Atom(Atom {
+                ref name, ref args, ..
+            }) if name.0 == "-" && args.len() == 2 => pairs.push((args[0].clone(), item)),
+            t => return Err(type_error("pair", t)),
+        }
+    }
+
+    pairs.sort_by(|(k1, _), (k2, _)| k1.cmp(k2));
+    let items = pairs.into_iter().map(|(_, pair)| pair).collect();
+    Ok(q.unify(&args[1], &Term::list(items, Term::atom("[]"))))
+}
+
+#[derive(Copy, Clone)]
+enum Text {
+    Codes,
+    Chars,
+}
+
+// the text of a list of codes or chars, or nothing while it is not fully instantiated
+fn text(q: &Query, t: &Term, kind: Text) -> Result<Option<String>, Term> {
+    let items = match list(q, t) {
+        Ok(items) => items,
+        Err(_) => {
+            partial_list(q, t)?;
+            return Ok(None);
+        }
+    };
+
+    let mut text = String::new();
+    for item in items {
+        match (q.env.deref(&item), kind) {
+            (Term::Var(_), _) => return Ok(None),
+            (Term::Number(Number::Int(i)), Text::Codes) => {
+                match u32::try_from(i).ok().and_then(std::char::from_u32) {
+                    Some(c) => text.push(c),
+                    None => return Err(representation_error("character_code")),
+                }
+            }
+            (Term::Const(Const(ref c)), Text::Chars) if c.chars().count() == 1 => text.push_str(c),
+            (t, Text::Codes) => return Err(type_error("integer", t)),
+            (t, Text::Chars) => return Err(type_error("character", t)),
+        }
+    }
+
+    Ok(Some(text))
+}
+
+fn number_text(q: &mut Query, args: &[Term], kind: Text) -> Result<bool, Term> {
+    if let Some(text) = text(q, &args[1], kind)? {
+        return match parse_number(&text) {
+            Some(n) => Ok(q.unify(&args[0], &Term::Number(n))),
+            None => Err(syntax_error("illegal_number")),
+        };
+    }
+
+    let text = match q.env.deref(&args[0]) {
+        Term::Var(_) => return Err(instantiation_error()),
+        Term::Number(n) => n.to_string(),
+        t => return Err(type_error("number", t)),
+    };
+
+    let list = match kind {
+        Text::Codes => Term::codes(&text),
+        Text::Chars => Term::chars(&text),
+    };
+    Ok(q.unify(&args[1], &list))
+}
+
+fn atom_number(_: &mut Machine, q: &mut Query, args: &[Term]) -> Result<bool, Term> {
+    match q.env.deref(&args[0]) {
+        Term::Var(_) => match q.env.deref(&args[1]) {
+            Term::Var(_) => Err(instantiation_error()),
+            Term::Number(n) => Ok(q.unify(&args[0], &Term::atom(&n.to_string()))),
+            t => Err(type_error("number", t)),
+        },
+        Term::Const(Const(name)) => match parse_number(&name) {
+            Some(n) => Ok(q.unify(&args[1], &Term::Number(n))),
+            None => Ok(false),
+        },
+        t => Err(type_error("atom", t)),
+    }
+}
+
+fn char_code(_: &mut Machine, q: &mut Query, args: &[Term]) -> Result<bool, Term> {
+    match q.env.deref(&args[0]) {
+        Term::Const(Const(ref c)) if c.chars().count() == 1 => {
+            let code = c.chars().next().unwrap() as i64;
+            Ok(q.unify(&args[1], &Term::int(code)))
+        }
+        Term::Var(_) => match q.env.deref(&args[1]) {
+            Term::Var(_) => Err(instantiation_error()),
+            Term::Number(Number::Int(i)) => {
+                match u32::try_from(i).ok().and_then(std::char::from_u32) {
+                    Some(c) => Ok(q.unify(&args[0], &Term::atom(&c.to_string()))),
+                    None => Err(representation_error("character_code")),
+                }
+            }
+            t => Err(type_error("integer", t)),
+        },
+        t => Err(type_error("character", t)),
+    }
+}
+
+// the text of an atom, string, number, or list of codes or chars, or nothing while it is
+// not instantiated enough to tell
+fn any_text(q: &Query, t: &Term) -> Result<Option<String>, Term> {
+    match q.env.deref(t) {
+        Term::Var(_) => Ok(None),
+        Term::String(text) => Ok(Some(text)),
+        Term::Number(n) => Ok(Some(n.to_string())),
+        Term::Const(Const(name)) if name != "[]" => Ok(Some(name)),
+        t => {
+            let first = list(q, &t)
+                .ok()
+                .and_then(|items| items.first().map(|item| q.env.deref(item)));
+            let kind = match first {
+                Some(Term::Number(_)) => Text::Codes,
+                _ => Text::Chars,
+            };
+
+            text(q, &t, kind)
+        }
+    }
+}
+
+// join two texts into a string, or split a string in every way that yields the parts
+// that are given
+fn string_concat(_: &mut Machine, q: &mut Query, args: &[Term]) -> Result<bool, Term> {
+    let (left, right) = (any_text(q, &args[0])?, any_text(q, &args[1])?);
+
+    if let (Some(left), Some(right)) = (&left, &right) {
+        return Ok(q.unify(&args[2], &Term::string(&format!("{}{}", left, right))));
+    }
+
+    let text = match any_text(q, &args[2])? {
+        Some(text) => text,
+        None => return Err(instantiation_error()),
+    };
+
+    match (left, right) {
+        (Some(left), _) => match text.strip_prefix(&left) {
+            Some(rest) => Ok(q.unify(&args[1], &Term::string(rest))),
+            None => Ok(false),
+        },
+        (_, Some(right)) => match text.strip_suffix(&right) {
+            Some(rest) => Ok(q.unify(&args[0], &Term::string(rest))),
+            None => Ok(false),
+        },
+        (None, None) => {
+            let pair = Term::compound("-", vec![args[0].clone(), args[1].clone()]);
+            let splits = text
+                .char_indices()
+                .map(|(i, _)| i)
+                .chain(Some(text.len()))
+                .map(|i| {
+                    let (left, right) = text.split_at(i);
+                    Term::compound("-", vec![Term::string(left), Term::string(right)])
+                });
+
+            Ok(alternatives(q, &pair, splits.collect()))
+        }
+    }
+}
+
+// the chars or codes of a string, or the string made of them
+fn string_list(q: &mut Query, args: &[Term], kind: Text) -> Result<bool, Term> {
+    if let Some(text) = any_text(q, &args[0])? {
+        let list = match kind {
+            Text::Codes => Term::codes(&text),
+            Text::Chars => Term::chars(&text),
+        };
+
+        return Ok(q.unify(&args[1], &list));
+    }
+
+    match text(q, &args[1], kind)? {
+        Some(text) => Ok(q.unify(&args[0], &Term::String(text))),
+        None => Err(instantiation_error()),
+    }
+}
+
+fn string_to_atom(_: &mut Machine, q: &mut Query, args: &[Term]) -> Result<bool, Term> {
+    if let Some(text) = any_text(q, &args[0])? {
+        return Ok(q.unify(&args[1], &Term::atom(&text)));
+    }
+
+    match any_text(q, &args[1])? {
+        Some(text) => Ok(q.unify(&args[0], &Term::String(text))),
+        None => Err(instantiation_error()),
+    }
+}
+
+fn number_string(_: &mut Machine, q: &mut Query, args: &[Term]) -> Result<bool, Term> {
+    if let Some(text) = any_text(q, &args[1])? {
+        return match parse_number(&text) {
+            Some(n) => Ok(q.unify(&args[0], &Term::Number(n))),
+            None => Err(syntax_error("illegal_number")),
+        };
+    }
+
+    match q.env.deref(&args[0]) {
+        Term::Var(_) => Err(instantiation_error()),
+        Term::Number(n) => Ok(q.unify(&args[1], &Term::string(&n.to_string()))),
+        t => Err(type_error("number", t)),
+    }
+}
+
+// split a text at each of the separator characters, then strip the padding characters
+// from both ends of every field
+fn split_string(_: &mut Machine, q: &mut Query, args: &[Term]) -> Result<bool, Term> {
+    let mut texts = Vec::new();
+    for t in &args[..3] {
+        match any_text(q, t)? {
+            Some(text) => texts.push(text),
+            None => return Err(instantiation_error()),
+        }
+    }
+
+    let (text, separators, pad) = (&texts[0], &texts[1], &texts[2]);
+    let fields = text
+        .split(|c| separators.contains(c))
+        .map(|field| Term::string(field.trim_matches(|c| pad.contains(c))));
+
+    Ok(q.unify(&args[3], &Term::list(fields.collect(), Term::atom("[]"))))
+}
+
+fn functor(_: &mut Machine, q: &mut Query, args: &[Term]) -> Result<bool, Term> {
+    match q.env.deref(&args[0]) {
+        Term::Var(_) => {
+            let name = q.env.deref(&args[1]);
+            let arity = integer(q, &args[2])?;
+
+            let t = match (name, arity) {
+                (Term::Var(_), _) => return Err(instantiation_error()),
+                (_, n) if n < 0 => return Err(domain_error("not_less_than_zero", Term::int(n))),
+                (Term::Atom(a), _) => return Err(type_error("atomic", Term::Atom(a))),
+                (name, 0) => name,
+                (Term::Const(Const(name)), n) => {
+                    let args = (0..n).map(|_| q.fresh_var()).collect();
+                    Term::compound(&name, args)
+                }
+                (name, _) => return Err(type_error("atom", name)),
+            };
+
+            Ok(q.unify(&args[0], &t))
+        }
+        Term::Atom(Atom {
+            ref name, arity, ..
+        }) => Ok(q.unify(&args[1], &Term::Const(name.clone()))
+            && q.unify(&args[2], &Term::int(arity as i64))),
+        t => Ok(q.unify(&args[1], &t) && q.unify(&args[2], &Term::int(0))),
+    }
+}
+
+fn arg(_: &mut Machine, q: &mut Query, args: &[Term]) -> Result<bool, Term> {
+    let n = integer(q, &args[0])?;
+
+    match q.env.deref(&args[1]) {
+        Term::Var(_) => Err(instantiation_error()),
+        Term::Atom(Atom {
+            args: ref items, ..
+        }) => match n {
+            n if n < 0 => Err(domain_error("not_less_than_zero", Term::int(n))),
+            n if n == 0 || n as usize > items.len() => Ok(false),
+            n => Ok(q.unify(&args[2], &items[n as usize - 1])),
+        },
+        t => Err(type_error("compound", t)),
+    }
+}
+
+fn univ(_: &mut Machine, q: &mut Query, args: &[Term]) -> Result<bool, Term> {
+    match q.env.deref(&args[0]) {
+        Term::Var(_) => {
+            let mut items = list(q, &args[1])?;
+            if items.is_empty() {
+                return Err(domain_error("non_empty_list", Term::atom("[]")));
+            }
+
+            let rest = items.split_off(1);
+            let t = match (q.env.deref(&items[0]), rest.len()) {
+                (Term::Var(_), _) => return Err(instantiation_error()),
+                (Term::Atom(a), _) => return Err(type_error("atomic", Term::Atom(a))),
+                (name, 0) => name,
+                (Term::Const(Const(name)), _) => Term::compound(&name, rest),
+                (name, _) => return Err(type_error("atom", name)),
+            };
+
+            Ok(q.unify(&args[0], &t))
+        }
+        Term::Atom(a) => {
+            let (name, items) = a.into_parts();
+            let list = Term::list(vec![Term::Const(name)], Term::list(items, Term::atom("[]")));
+            Ok(q.unify(&args[1], &list))
+        }
+        t => Ok(q.unify(&args[1], &Term::list(vec![t], Term::atom("[]")))),
+    }
+}
+
+fn assert(m: &mut Machine, q: &mut Query, args: &[Term], front: bool) -> Result<bool, Term> {
+    let assertion = assertion(q, &args[0])?;
+    m.db.assert(assertion, front).map_err(modify_error)?;
+    Ok(true)
+}
+
+fn retract(m: &mut Machine, q: &mut Query, args: &[Term]) -> Result<bool, Term> {
+    let clause = match q.env.substitute_term(&args[0]) {
+        Term::Atom(a) if a.name.0 == ":-" && a.arity == 2 => Term::compound(":-", a.into_args()),
+        head => Term::compound(":-", vec![head, Term::atom("true")]),
+    };
+
+    let key = callable_key(head(&clause))?;
+    m.db.modifiable(&key).map_err(modify_error)?;
+
+    // the clauses to try are fixed when retract is called, as they are for a call
+    let clauses =
+        m.db.clauses(&key)
+            .iter()
+            .map(|c| Term::from(Assertion::clone(c)))
+            .collect();
+    retract_next(m, q, &[clause, Term::list(clauses, Term::atom("[]"))])
+}
+
+// remove the first of the remaining clauses that unifies with the given one, leaving the
+// rest to be tried on backtracking
+fn retract_next(m: &mut Machine, q: &mut Query, args: &[Term]) -> Result<bool, Term> {
+    let mut clauses = list(q, &args[1])?;
+    if clauses.is_empty() {
+        return Ok(false);
+    }
+
+    let rest = clauses.split_off(1);
+    if !rest.is_empty() {
+        let rest = Term::list(rest, Term::atom("[]"));
+        q.push_alternative(Term::compound("$retract", vec![args[0].clone(), rest]));
+    }
+
+    let clause = clauses.pop().unwrap();
+    let renamed = q.rename(&clause);
+    let key = callable_key(head(&clause))?;
+
+    Ok(q.unify(&args[0], &renamed) && m.db.retract(&key, &clause))
+}
+
+fn clause(m: &mut Machine, q: &mut Query, args: &[Term]) -> Result<bool, Term> {
+    let key = callable_key(&q.env.deref(&args[0]))?;
+    if let Term::Number(_) = q.env.deref(&args[1]) {
+        return Err(type_error("callable", q.env.deref(&args[1])));
+    }
+
+    if let Some(Segment::System) = m.db.segment(&key) {
+        let culprit = indicator(&key.0, key.1);
+        return Err(permission_error("access", "private_procedure", culprit));
+    }
+
+    let clause = Term::compound(":-", args.to_vec());
+    let clauses: Vec<Term> =
+        m.db.clauses(&key)
+            .iter()
+            .map(|c| Term::from(Assertion::clone(c)))
+            .collect();
+    let renamed = clauses.iter().map(|c| q.rename(c)).collect();
+
+    Ok(alternatives(q, &clause, renamed))
+}
+
+// enumerate the indicators of the predicates defined by programs
+fn current_predicate(m: &mut Machine, q: &mut Query, args: &[Term]) -> Result<bool, Term> {
+    match q.env.substitute_term(&args[0]) {
+        Term::Var(_) => (),
+        Term::Atom(Atom {
+            ref name, ref args, ..
+        }) if name.0 == "/"
+            && args.len() == 2
+            && matches!(args[0], Term::Var(_) | Term::Const(_))
+            && matches!(args[1], Term::Var(_) | Term::Number(Number::Int(_))) => {}
+        t => return Err(type_error("predicate_indicator", t)),
+    }
+
+    let mut keys: Vec<&Key> =
+        m.db.predicates()
+            .filter(|(_, p)| {
+                !matches!(p.segment, Segment::System | Segment::Library)
+                    && (p.dynamic || !p.clauses.is_empty())
+            })
+            .map(|(key, _)| key)
+            .collect();
+    keys.sort();
+
+    let indicators = keys.into_iter().map(|key| indicator(&key.0, key.1));
+    Ok(alternatives(q, &args[0], indicators.collect()))
+}
+
+fn current_prolog_flag(m: &mut Machine, q: &mut Query, args: &[Term]) -> Result<bool, Term> {
+    let flags = match q.env.deref(&args[0]) {
+        Term::Var(_) => FLAGS.to_vec(),
+        Term::Const(Const(ref name)) if FLAGS.contains(&name.as_str()) => {
+            FLAGS.iter().copied().filter(|&flag| flag == name).collect()
+        }
+        Term::Const(name) => return Err(domain_error("prolog_flag", Term::Const(name))),
+        t => return Err(type_error("atom", t)),
+    };
+
+    let pair = Term::compound("-", args.to_vec());
+    let values = flags.into_iter().map(|flag| {
+        let value = m.flags.get(flag).unwrap();
+        Term::compound("-", vec![Term::atom(flag), value])
+    });
+
+    Ok(alternatives(q, &pair, values.collect()))
+}
+
+// define, redefine or, with priority 0, remove operators; names are checked before any
+// is changed, so a bad one in a list leaves the table as it was
+fn op(m: &mut Machine, q: &mut Query, args: &[Term]) -> Result<bool, Term> {
+    let priority = integer(q, &args[0])?;
+    if !(0..=1200).contains(&priority) {
+        return Err(domain_error("operator_priority", Term::int(priority)));
+    }
+
+    let specifier = atom(q, &args[1])?;
+    let op_type = OpType::from_name(&specifier)
+        .ok_or_else(|| domain_error("operator_specifier", Term::atom(&specifier)))?;
+
+    let names = match q.env.deref(&args[2]) {
+        Term::Const(Const(name)) if name != "[]" => vec![name],
+        _ => list(q, &args[2])?
+            .iter()
+            .map(|t| atom(q, t))
+            .collect::<Result<_, _>>()?,
+    };
+
+    for name in &names {
+        let infix = !op_type.is_prefix() && !op_type.is_postfix();
+        let clash = if infix {
+            m.ops.postfix(name)
+        } else if op_type.is_postfix() {
+            m.ops.infix(name)
+        } else {
+            None
+        };
+
+        if name == "," || (name == "|" && (!infix || (1..1001).contains(&priority))) {
+            return Err(permission_error("modify", "operator", Term::atom(name)));
+        }
+        if name == "[]" || name == "{}" || (priority > 0 && clash.is_some()) {
+            return Err(permission_error("create", "operator", Term::atom(name)));
+        }
+    }
+
+    for name in names {
+        m.ops.add(priority as usize, op_type, &name);
+    }
+
+    Ok(true)
+}
+
+// unify a term with each of some values in turn, the rest being tried on backtracking
+fn alternatives(q: &mut Query, t: &Term, values: Vec<Term>) -> bool {
+    let mut values = values.into_iter();
+
+    match values.next() {
+        Some(first) => {
+            for value in values.rev() {
+                q.push_alternative(Term::compound("=", vec![t.clone(), value]));
+            }
+
+            q.unify(t, &first)
+        }
+        None => false,
+    }
+}
+
+fn retractall(m: &mut Machine, q: &mut Query, args: &[Term]) -> Result<bool, Term> {
+    let goal = q.env.substitute_term(&args[0]);
+    let key = callable_key(&goal)?;
+    m.db.modifiable(&key).map_err(modify_error)?;
+
+    let clauses: Vec<Term> =
+        m.db.clauses(&key)
+            .iter()
+            .map(|c| Term::from(Assertion::clone(c)))
+            .collect();
+    for clause in clauses {
+        let renamed = q.rename(&clause);
+
+        if q.env.unifier(&goal, head(&renamed)).is_some() {
+            m.db.retract(&key, &clause);
+        }
+    }
+
+    m.db.dynamic(key).map_err(modify_error)?;
+    Ok(true)
+}
+
+fn head(clause: &Term) -> &Term {
+    match clause {
+        Term::Atom(Atom { name, args, .. }) if name.0 == ":-" && args.len() == 2 => &args[0],
+        t => t,
+    }
+}
+
+fn callable_key(head: &Term) -> Result<Key, Term> {
+    match head {
+        Term::Var(_) => Err(instantiation_error()),
+        Term::Atom(a) => Ok(Key::of(a)),
+        Term::Const(Const(name)) => Ok(Key::new(name, 0)),
+        t => Err(type_error("callable", t.clone())),
+    }
+}
+
+// declare predicates dynamic, given one indicator, a conjunction or a list of them
+fn dynamic(m: &mut Machine, q: &mut Query, args: &[Term]) -> Result<bool, Term> {
+    let mut next = vec![q.env.substitute_term(&args[0])];
+
+    while let Some(t) = next.pop() {
+        match t {
+            Term::Atom(a) if a.name.0 == "," || a.name.0 == "." => {
+                next.extend(a.into_args().into_iter().rev())
+            }
+            Term::Const(Const(ref name)) if name == "[]" => (),
+            t => {
+                m.db.dynamic(predicate_indicator(&t)?)
+                    .map_err(modify_error)?;
+            }
+        }
+    }
+
+    Ok(true)
+}
+
+// a clause to store, with its variables renamed apart from those of the query
+fn assertion(q: &Query, t: &Term) -> Result<Assertion, Term> {
+    let t = rename_term(&q.env.substitute_term(t));
+
+    match &t {
+        Term::Var(_) => return Err(instantiation_error()),
+        Term::Atom(Atom { name, args, .. }) if name.0 == ":-" && args.len() == 2 => {
+            if let Term::Var(_) = args[0] {
+                return Err(instantiation_error());
+            }
+        }
+        _ => (),
+    }
+
+    t.clone()
+        .into_assertion()
+        .ok_or_else(|| type_error("callable", t))
+}
+
+fn predicate_indicator(t: &Term) -> Result<Key, Term> {
+    match t {
+        Term::Var(_) => Err(instantiation_error()),
+        Term::Atom(Atom { name, args, .. }) if name.0 == "/" && args.len() == 2 => {
+            match (&args[0], &args[1]) {
+                (Term::Var(_), _) | (_, Term::Var(_)) => Err(instantiation_error()),
+                (Term::Const(Const(name)), Term::Number(Number::Int(n))) if *n >= 0 => {
+                    Ok(Key::new(name, *n as usize))
+                }
+                (Term::Const(_), Term::Number(Number::Int(n))) => {
+                    Err(domain_error("not_less_than_zero", Term::int(*n)))
+                }
+                (Term::Const(_), n) => Err(type_error("integer", n.clone())),
+                (name, _) => Err(type_error("atom", name.clone())),
+            }
+        }
+        t => Err(type_error("predicate_indicator", t.clone())),
+    }
+}
+
+// show the predicates of a name, or the one of a `Name/Arity`
+fn listing(m: &mut Machine, q: &mut Query, args: &[Term]) -> Result<bool, Term> {
+    let mut keys = match q.env.substitute_term(&args[0]) {
+        Term::Const(Const(name)) => {
+            m.db.predicates()
+                .filter(|(key, _)| key.0 == name)
+                .map(|(key, _)| key.clone())
+                .collect()
+        }
+        t => vec![predicate_indicator(&t)?],
+    };
+    keys.sort();
+
+    let listing: String = keys.iter().map(|key| m.listing_of(key)).collect();
+    output(m, &listing)
+}
+
+// the predicate of spy/1 and nospy/1, as `Name/Arity`, or a name for every arity
+fn spypoint(q: &Query, t: &Term) -> Result<Spypoint, Term> {
+    match q.env.substitute_term(t) {
+        Term::Const(Const(name)) => Ok(Spypoint(name, None)),
+        t => {
+            let Key(name, arity) = predicate_indicator(&t)?;
+            Ok(Spypoint(name, Some(arity)))
+        }
+    }
+}
+
+fn modify_error(e: DatabaseError) -> Term {
+    let DatabaseError::PermissionError(Key(name, arity)) = e;
+    permission_error("modify", "static_procedure", indicator(&name, arity))
+}
+
+fn integer(q: &Query, t: &Term) -> Result<i64, Term> {
+    match q.env.deref(t) {
+        Term::Var(_) => Err(instantiation_error()),
+        Term::Number(Number::Int(i)) => Ok(i),
+        t => Err(type_error("integer", t)),
+    }
+}
+
+// check that a term could still be unified with a list
+fn partial_list(q: &Query, t: &Term) -> Result<(), Term> {
+    let mut tail = q.env.deref(t);
+
+    loop {
+        match tail {
+            Term::Var(_) => return Ok(()),
+            Term::Const(Const(ref name)) if name == "[]" => return Ok(()),
+            Term::Atom(a) if a.name.0 == "." && a.arity == 2 => {
+                let mut args = a.into_args();
+                tail = q.env.deref_owned(args.pop().unwrap());
+            }
+            _ => return Err(type_error("list", q.env.substitute_term(t))),
+        }
+    }
+}
+
+// the items of a proper list, following bindings of the tail as far as they go
+fn list(q: &Query, t: &Term) -> Result<Vec<Term>, Term> {
+    let mut items = Vec::new();
+    let mut tail = q.env.deref(t);
+
+    loop {
+        match tail {
+            Term::Var(_) => return Err(instantiation_error()),
+            Term::Const(Const(ref name)) if name == "[]" => return Ok(items),
+            Term::Atom(a) if a.name.0 == "." && a.arity == 2 => {
+                let mut args = a.into_args();
+                tail = q.env.deref_owned(args.pop().unwrap());
+                items.push(args.pop().unwrap());
+            }
+            _ => return Err(type_error("list", q.env.substitute_term(t))),
+        }
+    }
+}
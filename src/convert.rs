@@ -0,0 +1,319 @@
+use crate::ast::{Const, Number, Term, Var};
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::hash::Hash;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+// a Rust value that can be read back from a term, as the binding of a query variable
+pub trait FromTerm: Sized {
+    fn from_term(t: &Term) -> Result<Self, ConversionError>;
+}
+
+// a Rust value that can be given to prolog as a term: lists for sequences, `K-V` pairs
+// for tuples and maps, and a fresh variable for `None`
+pub trait ToTerm {
+    fn to_term(&self) -> Term;
+}
+
+// fresh variables made for `None`, which no two values may share
+static FRESH: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConversionError {
+    // the query has no variable of this name
+    NoVariable(String),
+    // the term is not of the kind expected, e.g. an unbound variable where an integer is
+    Type { expected: &'static str, found: Term },
+}
+
+impl ConversionError {
+    fn type_error(expected: &'static str, found: &Term) -> Self {
+        ConversionError::Type {
+            expected,
+            found: found.clone(),
+        }
+    }
+}
+
+impl FromTerm for Term {
+    fn from_term(t: &Term) -> Result<Self, ConversionError> {
+        Ok(t.clone())
+    }
+}
+
+impl FromTerm for i64 {
+    fn from_term(t: &Term) -> Result<Self, ConversionError> {
+        match t {
+            Term::Number(Number::Int(i)) => Ok(*i),
+            _ => Err(ConversionError::type_error("integer", t)),
+        }
+    }
+}
+
+// integers are read as the nearest float
+impl FromTerm for f64 {
+    fn from_term(t: &Term) -> Result<Self, ConversionError> {
+        match t {
+            Term::Number(Number::Float(x)) => Ok(*x),
+            Term::Number(Number::Int(i)) => Ok(*i as f64),
+            _ => Err(ConversionError::type_error("number", t)),
+        }
+    }
+}
+
+// the text of an atom or a string
+impl FromTerm for String {
+    fn from_term(t: &Term) -> Result<Self, ConversionError> {
+        match t {
+            Term::Const(Const(name)) => Ok(name.clone()),
+            Term::String(text) => Ok(text.clone()),
+            _ => Err(ConversionError::type_error("text", t)),
+        }
+    }
+}
+
+// the atoms `true` and `false`
+impl FromTerm for bool {
+    fn from_term(t: &Term) -> Result<Self, ConversionError> {
+        match t {
+            Term::Const(Const(name)) if name == "true" => Ok(true),
+            Term::Const(Const(name)) if name == "false" => Ok(false),
+            _ => Err(ConversionError::type_error("boolean", t)),
+        }
+    }
+}
+
+// the items of a proper list, each read in turn
+impl<T: FromTerm> FromTerm for Vec<T> {
+    fn from_term(t: &Term) -> Result<Self, ConversionError> {
+        if *t == Term::atom("[]") {
+            return Ok(Vec::new());
+        }
+
+        match t.list_items() {
+            Some((items, tail)) if *tail == Term::atom("[]") => {
+                items.into_iter().map(T::from_term).collect()
+            }
+            _ => Err(ConversionError::type_error("list", t)),
+        }
+    }
+}
+
+// an unbound variable is `None`
+impl<T: FromTerm> FromTerm for Option<T> {
+    fn from_term(t: &Term) -> Result<Self, ConversionError> {
+        match t {
+            Term::Var(_) => Ok(None),
+            t => T::from_term(t).map(Some),
+        }
+    }
+}
+
+impl<A: FromTerm, B: FromTerm> FromTerm for (A, B) {
+    fn from_term(t: &Term) -> Result<Self, ConversionError> {
+        match pair(t) {
+            Some((a, b)) => Ok((A::from_term(a)?, B::from_term(b)?)),
+            None => Err(ConversionError::type_error("pair", t)),
+        }
+    }
+}
+
+// `A-B-C`, which reads as `(A-B)-C`
+impl<A: FromTerm, B: FromTerm, C: FromTerm> FromTerm for (A, B, C) {
+    fn from_term(t: &Term) -> Result<Self, ConversionError> {
+        match pair(t).and_then(|(ab, c)| Some((pair(ab)?, c))) {
+            Some(((a, b), c)) => Ok((A::from_term(a)?, B::from_term(b)?, C::from_term(c)?)),
+            None => Err(ConversionError::type_error("pair", t)),
+        }
+    }
+}
+
+// a list of `Key-Value` pairs; a key given twice keeps its last value
+impl<K: FromTerm + Eq + Hash, V: FromTerm> FromTerm for HashMap<K, V> {
+    fn from_term(t: &Term) -> Result<Self, ConversionError> {
+        Ok(Vec::<(K, V)>::from_term(t)?.into_iter().collect())
+    }
+}
+
+fn pair(t: &Term) -> Option<(&Term, &Term)> {
+    match t {
+        Term::Atom(a) if a.name.0 == "-" && a.args.len() == 2 => Some((&a.args[0], &a.args[1])),
+        _ => None,
+    }
+}
+
+impl<T: ToTerm + ?Sized> ToTerm for &T {
+    fn to_term(&self) -> Term {
+        (**self).to_term()
+    }
+}
+
+impl ToTerm for Term {
+    fn to_term(&self) -> Term {
+        self.clone()
+    }
+}
+
+impl ToTerm for i64 {
+    fn to_term(&self) -> Term {
+        Term::int(*self)
+    }
+}
+
+impl ToTerm for f64 {
+    fn to_term(&self) -> Term {
+        Term::float(*self)
+    }
+}
+
+impl ToTerm for bool {
+    fn to_term(&self) -> Term {
+        Term::atom(if *self { "true" } else { "false" })
+    }
+}
+
+impl ToTerm for str {
+    fn to_term(&self) -> Term {
+        Term::string(self)
+    }
+}
+
+impl ToTerm for String {
+    fn to_term(&self) -> Term {
+        Term::string(self)
+    }
+}
+
+impl<T: ToTerm> ToTerm for [T] {
+    fn to_term(&self) -> Term {
+        Term::list(self.iter().map(T::to_term).collect(), Term::atom("[]"))
+    }
+}
+
+impl<T: ToTerm> ToTerm for Vec<T> {
+    fn to_term(&self) -> Term {
+        self[..].to_term()
+    }
+}
+
+impl<T: ToTerm> ToTerm for Option<T> {
+    fn to_term(&self) -> Term {
+        match self {
+            Some(x) => x.to_term(),
+            None => {
+                let n = FRESH.fetch_add(1, Ordering::Relaxed);
+                Term::Var(Var::new(&format!("_R{}", n), 0))
+            }
+        }
+    }
+}
+
+impl<A: ToTerm, B: ToTerm> ToTerm for (A, B) {
+    fn to_term(&self) -> Term {
+        Term::compound("-", vec![self.0.to_term(), self.1.to_term()])
+    }
+}
+
+impl<A: ToTerm, B: ToTerm, C: ToTerm> ToTerm for (A, B, C) {
+    fn to_term(&self) -> Term {
+        let ab = Term::compound("-", vec![self.0.to_term(), self.1.to_term()]);
+        Term::compound("-", vec![ab, self.2.to_term()])
+    }
+}
+
+// the pairs are listed in the map's own order
+impl<K: ToTerm, V: ToTerm> ToTerm for HashMap<K, V> {
+    fn to_term(&self) -> Term {
+        let pairs = self
+            .iter()
+            .map(|(k, v)| Term::compound("-", vec![k.to_term(), v.to_term()]))
+            .collect();
+        Term::list(pairs, Term::atom("[]"))
+    }
+}
+
+impl Display for ConversionError {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), std::fmt::Error> {
+        match self {
+            ConversionError::NoVariable(name) => write!(f, "no variable {} in the query", name),
+            ConversionError::Type { expected, found } => {
+                write!(f, "expected {}, found {}", expected, found)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::term;
+
+    #[test]
+    fn test_from_term_1_succeeds() {
+        assert_eq!(i64::from_term(&term!("-3")), Ok(-3));
+        assert_eq!(f64::from_term(&term!("2")), Ok(2.0));
+        assert_eq!(f64::from_term(&term!("2.5")), Ok(2.5));
+        assert_eq!(String::from_term(&term!("'a b'")), Ok(String::from("a b")));
+        assert_eq!(String::from_term(&Term::string("s")), Ok(String::from("s")));
+        assert_eq!(bool::from_term(&term!("false")), Ok(false));
+        assert_eq!(Vec::<i64>::from_term(&term!("[]")), Ok(vec![]));
+        assert_eq!(
+            Vec::<Vec<i64>>::from_term(&term!("[[1, 2], [3]]")),
+            Ok(vec![vec![1, 2], vec![3]])
+        );
+        assert_eq!(Term::from_term(&term!("f(X)")), Ok(term!("f(X)")));
+    }
+
+    #[test]
+    fn test_from_term_2_succeeds() {
+        assert_eq!(Option::<i64>::from_term(&term!("X")), Ok(None));
+        assert_eq!(Option::<i64>::from_term(&term!("1")), Ok(Some(1)));
+        assert_eq!(
+            <(String, i64)>::from_term(&term!("a-1")),
+            Ok((String::from("a"), 1))
+        );
+        assert_eq!(
+            <(i64, i64, bool)>::from_term(&term!("1-2-true")),
+            Ok((1, 2, true))
+        );
+
+        let map = HashMap::<String, i64>::from_term(&term!("[a-1, b-2, a-3]")).unwrap();
+        assert_eq!(map.len(), 2);
+        assert_eq!(map["a"], 3);
+    }
+
+    #[test]
+    fn test_to_term_1_succeeds() {
+        assert_eq!(3.to_term(), term!("3"));
+        assert_eq!(true.to_term(), term!("true"));
+        assert_eq!("text".to_term(), Term::string("text"));
+        assert_eq!(vec![(1, 2.5)].to_term(), term!("[1-2.5]"));
+        assert_eq!((1, 2, 3).to_term(), term!("1-2-3"));
+        assert_eq!(vec![Some(1)].to_term(), term!("[1]"));
+        assert!(matches!(None::<i64>.to_term(), Term::Var(_)));
+        assert_ne!(None::<i64>.to_term(), None::<i64>.to_term());
+
+        let map: HashMap<_, _> = vec![(String::from("k"), vec![1, 2])].into_iter().collect();
+        assert_eq!(HashMap::from_term(&map.to_term()), Ok(map));
+    }
+
+    #[test]
+    fn test_from_term_1_fails() {
+        assert_eq!(
+            i64::from_term(&term!("1.0")),
+            Err(ConversionError::Type {
+                expected: "integer",
+                found: term!("1.0")
+            })
+        );
+        assert!(bool::from_term(&term!("yes")).is_err());
+        assert!(String::from_term(&term!("f(a)")).is_err());
+        assert!(Vec::<i64>::from_term(&term!("[1|T]")).is_err());
+        assert_eq!(
+            Vec::<i64>::from_term(&term!("[1, a]"))
+                .unwrap_err()
+                .to_string(),
+            "expected integer, found a"
+        );
+    }
+}
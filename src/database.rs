@@ -0,0 +1,622 @@
+use crate::ast::{fmt_name, Arity, Assertion, Atom, Number, Term};
+use crate::builtins::BUILTINS;
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::sync::{Arc, OnceLock};
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Key(pub String, pub Arity);
+
+// where a predicate was defined: builtins may never be redefined, library predicates
+// may be overridden by user code, and query code is discarded between queries
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Segment {
+    System,
+    Library,
+    User,
+    Query,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Predicate {
+    pub segment: Segment,
+    pub file: Option<String>,
+    pub dynamic: bool,
+    pub clauses: Vec<Arc<Assertion>>,
+    index: Index,
+}
+
+// predicates with at least this many clauses have their clauses indexed on their first
+// argument when they are first called
+const INDEXED: usize = 8;
+
+// the clauses of a predicate by what their first argument is, built when first needed
+// and dropped whenever the clauses change; clauses with a variable or a float as their
+// first argument may match any goal, so they are kept apart
+#[derive(Debug, Clone, Default)]
+struct Index(OnceLock<Buckets>);
+
+#[derive(Debug, Clone, Default)]
+struct Buckets {
+    keyed: HashMap<ArgKey, Vec<usize>>,
+    open: Vec<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum ArgKey {
+    Functor(Key),
+    Int(i64),
+    String(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DatabaseError {
+    PermissionError(Key),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Warning {
+    Redefined(Key, Segment, Option<String>),
+    Singletons(Key, Vec<String>, usize),
+    // a directive of a program, at a line, that failed or raised an error when loaded
+    GoalFailed(Term, usize),
+    GoalRaised(Term, Term, usize),
+}
+
+// clones of a database share the clauses of each predicate until one of them changes it,
+// so the machines of a query pool can share one program
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Database {
+    predicates: HashMap<Key, Arc<Predicate>>,
+}
+
+impl Key {
+    pub fn new(name: &str, arity: Arity) -> Self {
+        Key(String::from(name), arity)
+    }
+
+    pub fn of(a: &Atom) -> Self {
+        Key(a.name.0.clone(), a.arity)
+    }
+}
+
+impl Database {
+    pub fn new() -> Self {
+        let mut db = Database::default();
+
+        for &(name, arity, _) in BUILTINS {
+            db.add_system(Key::new(name, arity));
+        }
+
+        db
+    }
+
+    // a predicate defined outside of prolog, which no clauses may be added to
+    pub fn add_system(&mut self, key: Key) {
+        self.predicates.insert(
+            key,
+            Arc::new(Predicate {
+                segment: Segment::System,
+                file: None,
+                dynamic: false,
+                clauses: Vec::new(),
+                index: Index::default(),
+            }),
+        );
+    }
+
+    // (re)load the predicates defined by a file: every predicate is replaced as a whole,
+    // and nothing is changed unless the entire file can be loaded
+    pub fn consult(
+        &mut self,
+        segment: Segment,
+        file: &str,
+        source: Vec<Assertion>,
+    ) -> Result<Vec<Warning>, DatabaseError> {
+        let mut keys = Vec::new();
+        let mut predicates: HashMap<Key, Predicate> = HashMap::new();
+
+        for assertion in source {
+            let key = Key::of(&assertion.head);
+
+            if let Some(Segment::System) = self.segment(&key) {
+                return Err(DatabaseError::PermissionError(key));
+            }
+
+            let predicate = predicates.entry(key.clone()).or_insert_with(|| {
+                keys.push(key);
+                Predicate {
+                    segment,
+                    file: Some(String::from(file)),
+                    dynamic: false,
+                    clauses: Vec::new(),
+                    index: Index::default(),
+                }
+            });
+
+            predicate.clauses.push(Arc::new(assertion));
+        }
+
+        let mut warnings = Vec::new();
+
+        for key in keys {
+            let predicate = predicates.remove(&key).unwrap();
+
+            if let Some(old) = self.predicates.get(&key) {
+                if old.segment != segment || old.file != predicate.file {
+                    warnings.push(Warning::Redefined(
+                        key.clone(),
+                        old.segment,
+                        old.file.clone(),
+                    ));
+                }
+            }
+
+            self.predicates.insert(key, Arc::new(predicate));
+        }
+
+        Ok(warnings)
+    }
+
+    pub fn add(
+        &mut self,
+        segment: Segment,
+        assertion: Assertion,
+    ) -> Result<Option<Warning>, DatabaseError> {
+        let key = Key::of(&assertion.head);
+        let mut warning = None;
+
+        match self.predicates.get_mut(&key) {
+            Some(p) if p.segment == Segment::System => {
+                return Err(DatabaseError::PermissionError(key))
+            }
+            Some(p) if p.segment == segment => {
+                Arc::make_mut(p).clauses_mut().push(Arc::new(assertion));
+                return Ok(None);
+            }
+            Some(p) => warning = Some(Warning::Redefined(key.clone(), p.segment, p.file.clone())),
+            None => (),
+        }
+
+        self.predicates.insert(
+            key,
+            Arc::new(Predicate {
+                segment,
+                file: None,
+                dynamic: false,
+                clauses: vec![Arc::new(assertion)],
+                index: Index::default(),
+            }),
+        );
+
+        Ok(warning)
+    }
+
+    // add a clause at runtime, before or after the existing ones; predicates loaded from
+    // source may only be changed this way once they are declared dynamic
+    pub fn assert(&mut self, assertion: Assertion, front: bool) -> Result<(), DatabaseError> {
+        let key = Key::of(&assertion.head);
+        self.modifiable(&key)?;
+
+        let p = self.dynamic(key)?;
+        if front {
+            p.clauses_mut().insert(0, Arc::new(assertion));
+        } else {
+            p.clauses_mut().push(Arc::new(assertion));
+        }
+
+        Ok(())
+    }
+
+    // remove the first clause of a predicate that is equal to the given clause term, if it
+    // is still there
+    pub fn retract(&mut self, key: &Key, clause: &Term) -> bool {
+        match self.predicates.get_mut(key) {
+            Some(p) if p.dynamic => match p
+                .clauses
+                .iter()
+                .position(|c| Term::from(Assertion::clone(c)) == *clause)
+            {
+                Some(i) => {
+                    Arc::make_mut(p).clauses_mut().remove(i);
+                    true
+                }
+                None => false,
+            },
+            _ => false,
+        }
+    }
+
+    pub fn abolish(&mut self, key: &Key) -> Result<(), DatabaseError> {
+        self.modifiable(key)?;
+        self.predicates.remove(key);
+        Ok(())
+    }
+
+    // whether the clauses of a predicate may be changed at runtime: builtins never may,
+    // and predicates loaded from source only once they are declared dynamic
+    pub fn modifiable(&self, key: &Key) -> Result<(), DatabaseError> {
+        match self.predicates.get(key) {
+            Some(p) if p.segment == Segment::System || (!p.dynamic && !p.clauses.is_empty()) => {
+                Err(DatabaseError::PermissionError(key.clone()))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    // declare a predicate dynamic, defining it with no clauses if it is not defined yet
+    pub fn dynamic(&mut self, key: Key) -> Result<&mut Predicate, DatabaseError> {
+        if let Some(Segment::System) = self.segment(&key) {
+            return Err(DatabaseError::PermissionError(key));
+        }
+
+        let p = self.predicates.entry(key).or_insert_with(|| {
+            Arc::new(Predicate {
+                segment: Segment::User,
+                file: None,
+                dynamic: false,
+                clauses: Vec::new(),
+                index: Index::default(),
+            })
+        });
+        let p = Arc::make_mut(p);
+        p.dynamic = true;
+        p.index = Index::default();
+
+        Ok(p)
+    }
+
+    pub fn clauses(&self, key: &Key) -> &[Arc<Assertion>] {
+        match self.predicates.get(key) {
+            Some(p) => &p.clauses,
+            None => &[],
+        }
+    }
+
+    // the clauses of the predicate of a goal that may match it, in order; a goal whose
+    // first argument is bound to a constant or a structure only gets the clauses whose
+    // first argument could unify with it, found without going through the others; the
+    // clauses are shared with the database, so that calls don't copy them
+    pub fn matching<'a>(&'a self, goal: &Atom, first: Option<&Term>) -> Vec<&'a Arc<Assertion>> {
+        let p = match self.predicates.get(&Key::of(goal)) {
+            Some(p) => p,
+            None => return Vec::new(),
+        };
+
+        let key = match first.and_then(ArgKey::of) {
+            Some(key) if p.clauses.len() >= INDEXED => key,
+            _ => return p.clauses.iter().collect(),
+        };
+
+        let buckets = p.index.0.get_or_init(|| Buckets::new(&p.clauses));
+        let keyed = buckets.keyed.get(&key).map_or(&[][..], |k| &k[..]);
+        let (mut i, mut j) = (0, 0);
+        let mut clauses = Vec::with_capacity(keyed.len() + buckets.open.len());
+
+        // both lists of clauses are in order, so they are merged
+        while i < keyed.len() || j < buckets.open.len() {
+            if j == buckets.open.len() || (i < keyed.len() && keyed[i] < buckets.open[j]) {
+                clauses.push(&p.clauses[keyed[i]]);
+                i += 1;
+            } else {
+                clauses.push(&p.clauses[buckets.open[j]]);
+                j += 1;
+            }
+        }
+
+        clauses
+    }
+
+    pub fn predicates(&self) -> impl Iterator<Item = (&Key, &Predicate)> {
+        self.predicates.iter().map(|(key, p)| (key, &**p))
+    }
+
+    pub fn predicate(&self, key: &Key) -> Option<&Predicate> {
+        self.predicates.get(key).map(|p| &**p)
+    }
+
+    pub fn segment(&self, key: &Key) -> Option<Segment> {
+        self.predicates.get(key).map(|p| p.segment)
+    }
+
+    pub fn clear(&mut self, segment: Segment) {
+        self.predicates.retain(|_, p| p.segment != segment);
+    }
+}
+
+impl Predicate {
+    fn clauses_mut(&mut self) -> &mut Vec<Arc<Assertion>> {
+        self.index = Index::default();
+        &mut self.clauses
+    }
+}
+
+// the index is only ever built from the clauses, so it has no part in comparing predicates
+impl PartialEq for Index {
+    fn eq(&self, _: &Index) -> bool {
+        true
+    }
+}
+
+impl Eq for Index {}
+
+impl Buckets {
+    fn new(clauses: &[Arc<Assertion>]) -> Self {
+        let mut buckets = Buckets::default();
+
+        for (i, clause) in clauses.iter().enumerate() {
+            match clause.head.args.first().and_then(ArgKey::of) {
+                Some(key) => buckets.keyed.entry(key).or_default().push(i),
+                None => buckets.open.push(i),
+            }
+        }
+
+        buckets
+    }
+}
+
+impl ArgKey {
+    fn of(t: &Term) -> Option<Self> {
+        match t {
+            Term::Const(c) => Some(ArgKey::Functor(Key::new(&c.0, 0))),
+            Term::Atom(a) => Some(ArgKey::Functor(Key::of(a))),
+            Term::Number(Number::Int(i)) => Some(ArgKey::Int(*i)),
+            Term::String(text) => Some(ArgKey::String(text.clone())),
+            Term::Var(_) | Term::Number(Number::Float(_)) => None,
+        }
+    }
+}
+
+impl Display for Key {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), std::fmt::Error> {
+        fmt_name(f, &self.0)?;
+        write!(f, "/{}", self.1)
+    }
+}
+
+impl Display for Warning {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), std::fmt::Error> {
+        match self {
+            Warning::Redefined(key, Segment::Library, _) => {
+                write!(f, "redefined library predicate {}", key)
+            }
+            Warning::Redefined(key, _, Some(file)) => {
+                write!(
+                    f,
+                    "redefined predicate {}, previously loaded from {}",
+                    key, file
+                )
+            }
+            Warning::Redefined(key, _, None) => write!(f, "redefined predicate {}", key),
+            Warning::Singletons(key, vars, line) => write!(
+                f,
+                "singleton variables [{}] in clause of {} at line {}",
+                vars.join(", "),
+                key,
+                line
+            ),
+            Warning::GoalFailed(goal, line) => {
+                write!(f, "goal (directive) failed at line {}: {}", line, goal)
+            }
+            Warning::GoalRaised(goal, ball, line) => write!(
+                f,
+                "goal (directive) raised {} at line {}: {}",
+                ball, line, goal
+            ),
+        }
+    }
+}
+
+impl Display for DatabaseError {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), std::fmt::Error> {
+        match self {
+            DatabaseError::PermissionError(key) => {
+                write!(f, "no permission to modify static procedure {}", key)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::Const;
+
+    fn fact(name: &str, arg: &str) -> Assertion {
+        Assertion::new(Atom::new(name, vec![Term::Const(Const::new(arg))]), vec![])
+    }
+
+    fn clauses(db: &Database, name: &str) -> Vec<Assertion> {
+        let clauses = db.clauses(&Key::new(name, 1)).iter();
+        clauses.map(|c| Assertion::clone(c)).collect()
+    }
+
+    #[test]
+    fn test_segments_1_succeeds() {
+        let mut db = Database::new();
+        let warnings = db
+            .consult(Segment::User, "a.pl", vec![fact("p", "a"), fact("p", "b")])
+            .unwrap();
+
+        assert!(warnings.is_empty());
+        assert_eq!(db.clauses(&Key::new("p", 1)).len(), 2);
+        assert_eq!(db.segment(&Key::new("p", 1)), Some(Segment::User));
+    }
+
+    #[test]
+    fn test_segments_1_fails() {
+        let mut db = Database::new();
+        let halt = Assertion::new(Atom::new("halt", vec![]), vec![]);
+
+        assert_eq!(
+            db.add(Segment::User, halt),
+            Err(DatabaseError::PermissionError(Key::new("halt", 0)))
+        );
+    }
+
+    #[test]
+    fn test_segments_2_succeeds() {
+        let mut db = Database::new();
+        db.add(Segment::Library, fact("p", "a")).unwrap();
+
+        assert_eq!(
+            db.add(Segment::User, fact("p", "b")),
+            Ok(Some(Warning::Redefined(
+                Key::new("p", 1),
+                Segment::Library,
+                None
+            )))
+        );
+        assert_eq!(clauses(&db, "p"), &[fact("p", "b")][..]);
+    }
+
+    #[test]
+    fn test_segments_3_succeeds() {
+        let mut db = Database::new();
+        db.add(Segment::User, fact("p", "a")).unwrap();
+        db.add(Segment::Query, fact("q", "a")).unwrap();
+        db.clear(Segment::Query);
+
+        assert_eq!(db.clauses(&Key::new("p", 1)).len(), 1);
+        assert!(db.clauses(&Key::new("q", 1)).is_empty());
+        assert_eq!(db.segment(&Key::new("halt", 0)), Some(Segment::System));
+    }
+
+    #[test]
+    fn test_dynamic_1_succeeds() {
+        let mut db = Database::new();
+        db.assert(fact("p", "b"), false).unwrap();
+        db.assert(fact("p", "c"), false).unwrap();
+        db.assert(fact("p", "a"), true).unwrap();
+
+        assert_eq!(
+            clauses(&db, "p"),
+            &[fact("p", "a"), fact("p", "b"), fact("p", "c")][..]
+        );
+    }
+
+    #[test]
+    fn test_dynamic_1_fails() {
+        let mut db = Database::new();
+        db.consult(Segment::User, "a.pl", vec![fact("p", "a")])
+            .unwrap();
+
+        assert_eq!(
+            db.assert(fact("p", "b"), false),
+            Err(DatabaseError::PermissionError(Key::new("p", 1)))
+        );
+        assert_eq!(
+            db.assert(Assertion::new(Atom::new("halt", vec![]), vec![]), true),
+            Err(DatabaseError::PermissionError(Key::new("halt", 0)))
+        );
+    }
+
+    #[test]
+    fn test_dynamic_2_succeeds() {
+        let mut db = Database::new();
+        db.assert(fact("p", "a"), false).unwrap();
+        db.assert(fact("p", "b"), false).unwrap();
+
+        let key = Key::new("p", 1);
+        assert!(db.retract(&key, &fact("p", "a").into()));
+        assert!(!db.retract(&key, &fact("p", "a").into()));
+        assert_eq!(clauses(&db, "p"), &[fact("p", "b")][..]);
+
+        db.abolish(&Key::new("p", 1)).unwrap();
+        assert_eq!(db.segment(&Key::new("p", 1)), None);
+    }
+
+    #[test]
+    fn test_redefinition_1_succeeds() {
+        let mut db = Database::new();
+        db.consult(Segment::User, "a.pl", vec![fact("p", "a"), fact("q", "a")])
+            .unwrap();
+
+        let warnings = db
+            .consult(Segment::User, "a.pl", vec![fact("p", "b")])
+            .unwrap();
+
+        assert!(warnings.is_empty());
+        assert_eq!(clauses(&db, "p"), &[fact("p", "b")][..]);
+        assert_eq!(clauses(&db, "q"), &[fact("q", "a")][..]);
+    }
+
+    #[test]
+    fn test_redefinition_2_succeeds() {
+        let mut db = Database::new();
+        db.consult(Segment::User, "a.pl", vec![fact("p", "a")])
+            .unwrap();
+
+        let warnings = db
+            .consult(Segment::User, "b.pl", vec![fact("p", "b"), fact("p", "c")])
+            .unwrap();
+
+        assert_eq!(
+            warnings,
+            vec![Warning::Redefined(
+                Key::new("p", 1),
+                Segment::User,
+                Some(String::from("a.pl"))
+            )]
+        );
+        assert_eq!(clauses(&db, "p"), &[fact("p", "b"), fact("p", "c")][..]);
+    }
+
+    #[test]
+    fn test_redefinition_2_fails() {
+        let mut db = Database::new();
+        db.consult(Segment::User, "a.pl", vec![fact("p", "a")])
+            .unwrap();
+
+        let halt = Assertion::new(Atom::new("halt", vec![]), vec![]);
+        let result = db.consult(Segment::User, "b.pl", vec![fact("p", "b"), halt]);
+
+        assert_eq!(
+            result,
+            Err(DatabaseError::PermissionError(Key::new("halt", 0)))
+        );
+        assert_eq!(clauses(&db, "p"), &[fact("p", "a")][..]);
+    }
+
+    #[test]
+    fn test_matching_1_succeeds() {
+        let mut db = Database::new();
+        let mut source: Vec<_> = (0..10).map(|i| fact("p", &format!("c{}", i))).collect();
+        let open = Assertion::new(Atom::new("p", vec![Term::var("X")]), vec![]);
+        source.insert(4, open.clone());
+        db.consult(Segment::User, "a.pl", source).unwrap();
+
+        let goal = Atom::new("p", vec![Term::var("Y")]);
+        let c7 = Term::Const(Const::new("c7"));
+        let expected = [Arc::new(open.clone()), Arc::new(fact("p", "c7"))];
+        assert_eq!(
+            db.matching(&goal, Some(&c7)),
+            expected.iter().collect::<Vec<_>>()
+        );
+        assert_eq!(db.matching(&goal, Some(&Term::var("Y"))).len(), 11);
+
+        db.dynamic(Key::new("p", 1)).unwrap();
+        db.assert(fact("p", "c7"), true).unwrap();
+        assert_eq!(db.matching(&goal, Some(&c7)).len(), 3);
+
+        db.retract(&Key::new("p", 1), &Term::from(open));
+        let c0 = Term::Const(Const::new("c0"));
+        assert_eq!(
+            db.matching(&goal, Some(&c0)),
+            vec![&Arc::new(fact("p", "c0"))]
+        );
+    }
+
+    #[test]
+    fn test_matching_1_fails() {
+        let mut db = Database::new();
+        let source = (0..10).map(|i| fact("p", &format!("c{}", i))).collect();
+        db.consult(Segment::User, "a.pl", source).unwrap();
+
+        let goal = Atom::new("p", vec![Term::var("Y")]);
+        let f = Term::compound("c1", vec![Term::int(1)]);
+        assert!(db.matching(&goal, Some(&f)).is_empty());
+        assert!(db.matching(&goal, Some(&Term::int(1))).is_empty());
+        assert!(db.matching(&Atom::new("q", vec![]), None).is_empty());
+    }
+}
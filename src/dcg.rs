@@ -0,0 +1,98 @@
+use crate::ast::{Atom, Const, Term, Var};
+
+// the clause a grammar rule `Head --> Body` stands for, with a pair of arguments for the
+// list before and after the phrase added to the head and every nonterminal of the body
+pub fn translate(head: Term, body: Term) -> Option<Term> {
+    let mut n = 0;
+    let mut fresh = || {
+        n += 1;
+        Term::Var(Var::new(&format!("_S{}", n), 0))
+    };
+
+    let (s0, s) = (fresh(), fresh());
+    let head = nonterminal(head, s0.clone(), s.clone())?;
+    let body = translate_body(body, s0, s, &mut fresh)?;
+
+    Some(Term::compound(":-", vec![head, body]))
+}
+
+// the goal that parses a grammar body from the list `s0`, leaving `s`, or nothing if
+// the body is not callable
+pub(crate) fn translate_body<F: FnMut() -> Term>(
+    body: Term,
+    s0: Term,
+    s: Term,
+    fresh: &mut F,
+) -> Option<Term> {
+    if let Some((items, tail)) = body.list_items() {
+        if *tail != Term::atom("[]") {
+            return None;
+        }
+
+        let items = items.into_iter().cloned().collect();
+        return Some(Term::compound("=", vec![s0, Term::list(items, s)]));
+    }
+
+    let goal = match body {
+        Term::Var(_) => Term::compound("phrase", vec![body, s0, s]),
+        Term::String(text) => {
+            let codes = text.chars().map(|c| Term::int(c as i64)).collect();
+            Term::compound("=", vec![s0, Term::list(codes, s)])
+        }
+        Term::Const(Const(ref name)) if name == "[]" => Term::compound("=", vec![s0, s]),
+        Term::Const(Const(ref name)) if name == "!" => conjunction(body.clone(), s0, s),
+        Term::Atom(a) => {
+            let (name, mut args) = a.into_parts();
+            match (&*name.0, args.len()) {
+                (",", 2) => {
+                    let mid = fresh();
+                    let right = translate_body(args.pop().unwrap(), mid.clone(), s, fresh)?;
+                    let left = translate_body(args.pop().unwrap(), s0, mid, fresh)?;
+                    Term::compound(",", vec![left, right])
+                }
+                (";", 2) | ("|", 2) => {
+                    let right = translate_body(args.pop().unwrap(), s0.clone(), s.clone(), fresh)?;
+                    let left = translate_body(args.pop().unwrap(), s0, s, fresh)?;
+                    Term::compound(";", vec![left, right])
+                }
+                ("->", 2) => {
+                    let mid = fresh();
+                    let then = translate_body(args.pop().unwrap(), mid.clone(), s, fresh)?;
+                    let cond = translate_body(args.pop().unwrap(), s0, mid, fresh)?;
+                    Term::compound("->", vec![cond, then])
+                }
+                ("\\+", 1) => {
+                    let rest = fresh();
+                    let goal = translate_body(args.pop().unwrap(), s0.clone(), rest, fresh)?;
+                    conjunction(Term::compound("\\+", vec![goal]), s0, s)
+                }
+                ("{}", 1) => conjunction(args.pop().unwrap(), s0, s),
+                ("call", _) => {
+                    args.extend(vec![s0, s]);
+                    Term::compound("call", args)
+                }
+                _ => nonterminal(Term::Atom(Atom::new(&name.0, args)), s0, s)?,
+            }
+        }
+        body => nonterminal(body, s0, s)?,
+    };
+
+    Some(goal)
+}
+
+fn nonterminal(t: Term, s0: Term, s: Term) -> Option<Term> {
+    match t {
+        Term::Const(Const(name)) => Some(Term::compound(&name, vec![s0, s])),
+        Term::Atom(a) => {
+            let (name, mut args) = a.into_parts();
+            args.extend(vec![s0, s]);
+            Some(Term::compound(&name.0, args))
+        }
+        _ => None,
+    }
+}
+
+// a goal that runs where the list is left as it is
+fn conjunction(goal: Term, s0: Term, s: Term) -> Term {
+    Term::compound(",", vec![goal, Term::compound("=", vec![s0, s])])
+}
@@ -0,0 +1,154 @@
+use crate::ast::{Arity, Term};
+use crate::ports::Port;
+
+// the debugger of a machine, driven by trace/0, notrace/0, spy/1 and nospy/1: while it
+// is on, the machine stops at the ports of the goals it asks for, shows the goal, and
+// reads a command for what to do next
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Debugger {
+    mode: Mode,
+    spypoints: Vec<Spypoint>,
+}
+
+// where to stop next: nowhere, at spy points only, at every port, or only at the ports
+// of goals no deeper than a skipped one
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+enum Mode {
+    #[default]
+    Off,
+    Leap,
+    Creep,
+    Skip(usize),
+}
+
+// a predicate to stop at, by its name and arity, or by its name alone for every arity
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Spypoint(pub String, pub Option<Arity>);
+
+// what to do with the goal on leaving a port
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) enum Action {
+    Continue,
+    Fail,
+}
+
+pub(crate) const HELP: &str =
+    "c: creep, s: skip, l: leap, f: fail, n: nodebug (an empty line creeps)\n";
+
+impl Debugger {
+    pub(crate) fn is_on(&self) -> bool {
+        self.mode != Mode::Off
+    }
+
+    pub(crate) fn trace(&mut self) {
+        self.mode = Mode::Creep;
+    }
+
+    // stop tracing, though spy points are still stopped at
+    pub(crate) fn notrace(&mut self) {
+        self.mode = if self.spypoints.is_empty() {
+            Mode::Off
+        } else {
+            Mode::Leap
+        };
+    }
+
+    pub(crate) fn spy(&mut self, spypoint: Spypoint) {
+        if !self.spypoints.contains(&spypoint) {
+            self.spypoints.push(spypoint);
+        }
+
+        if self.mode == Mode::Off {
+            self.mode = Mode::Leap;
+        }
+    }
+
+    // a spy point by name alone removes those on the predicates of every arity
+    pub(crate) fn nospy(&mut self, spypoint: &Spypoint) {
+        self.spypoints
+            .retain(|s| s.0 != spypoint.0 || (spypoint.1.is_some() && s.1 != spypoint.1));
+
+        if self.spypoints.is_empty() && self.mode == Mode::Leap {
+            self.mode = Mode::Off;
+        }
+    }
+
+    pub(crate) fn stops_at(&self, goal: &Term, depth: usize) -> bool {
+        match self.mode {
+            Mode::Off => false,
+            Mode::Creep => true,
+            Mode::Leap => self.is_spied(goal),
+            Mode::Skip(skipped) => depth <= skipped || self.is_spied(goal),
+        }
+    }
+
+    fn is_spied(&self, goal: &Term) -> bool {
+        let (name, arity) = match goal {
+            Term::Atom(a) => (&a.name.0, a.arity),
+            Term::Const(c) => (&c.0, 0),
+            _ => return false,
+        };
+
+        self.spypoints
+            .iter()
+            .any(|s| s.0 == *name && s.1.is_none_or(|n| n == arity))
+    }
+
+    // follow a command given at a port, or give nothing if there is no such command; at
+    // the end of the input the debugger leaps
+    pub(crate) fn command(&mut self, port: Port, depth: usize, command: &str) -> Option<Action> {
+        match command {
+            "" | "c" => self.mode = Mode::Creep,
+            "s" if port == Port::Call || port == Port::Redo => self.mode = Mode::Skip(depth),
+            "s" => self.mode = Mode::Creep,
+            "l" => self.mode = Mode::Leap,
+            "n" => self.mode = Mode::Off,
+            "f" => {
+                self.mode = Mode::Creep;
+                return Some(Action::Fail);
+            }
+            _ => return None,
+        }
+
+        Some(Action::Continue)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::term;
+
+    #[test]
+    fn test_debugger_1_succeeds() {
+        let mut debugger = Debugger::default();
+        assert!(!debugger.stops_at(&term!("p(a)"), 0));
+
+        debugger.spy(Spypoint(String::from("p"), None));
+        debugger.spy(Spypoint(String::from("q"), Some(0)));
+        assert!(debugger.stops_at(&term!("p(a)"), 3));
+        assert!(debugger.stops_at(&term!("q"), 3));
+        assert!(!debugger.stops_at(&term!("q(a)"), 3));
+
+        assert_eq!(debugger.command(Port::Call, 2, "s"), Some(Action::Continue));
+        assert!(debugger.stops_at(&term!("r"), 2));
+        assert!(!debugger.stops_at(&term!("r"), 3));
+        assert!(debugger.stops_at(&term!("p(b)"), 3));
+
+        debugger.nospy(&Spypoint(String::from("p"), None));
+        debugger.nospy(&Spypoint(String::from("q"), Some(0)));
+        debugger.notrace();
+        assert!(!debugger.is_on());
+    }
+
+    #[test]
+    fn test_debugger_1_fails() {
+        let mut debugger = Debugger::default();
+        debugger.trace();
+
+        assert_eq!(debugger.command(Port::Exit, 0, "x"), None);
+        assert_eq!(debugger.command(Port::Exit, 0, "f"), Some(Action::Fail));
+        assert_eq!(debugger.command(Port::Exit, 0, "n"), Some(Action::Continue));
+        assert!(!debugger.is_on());
+    }
+}
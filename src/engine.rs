@@ -0,0 +1,100 @@
+use crate::ast::Term;
+use crate::machine::{Machine, Query, Run};
+use std::mem;
+
+// a goal whose solutions are pulled one at a time, each as the value of a template,
+// with the machine left free to do other work in between; engine_create/3 and
+// engine_next/2 drive one from prolog, and embedders may hold one of their own. A term
+// posted to an engine is fetched by the goal running in it with engine_fetch/1
+pub struct Engine {
+    query: Query,
+    template: Term,
+    posted: Option<Term>,
+    done: bool,
+}
+
+// where a run of an engine stopped: at an answer, paused before finding one, or with
+// no more answers
+#[derive(Debug, Clone, PartialEq)]
+pub enum Step {
+    Answer(Term),
+    Paused,
+    Done,
+}
+
+impl Engine {
+    pub fn new(template: Term, goal: Term) -> Self {
+        let mut query = Query::new(Vec::new());
+        query.push_call(goal);
+
+        Engine {
+            query,
+            template,
+            posted: None,
+            done: false,
+        }
+    }
+
+    // the template as bound by the next solution of the goal, nothing once there are no
+    // more, or the error the goal raised; an engine that failed or raised stays done. An
+    // engine paused by run_for carries on from where it stopped
+    pub fn next(&mut self, machine: &mut Machine) -> Result<Option<Term>, Term> {
+        match self.run(machine, |m, query| m.run(query, None))? {
+            Step::Answer(t) => Ok(Some(t)),
+            _ => Ok(None),
+        }
+    }
+
+    // run the goal only until it has called a number of goals more, so that a host can
+    // share its time out between prolog and other work; the engine pauses there, to be
+    // carried on by another run_for or by next
+    pub fn run_for(&mut self, machine: &mut Machine, inferences: u64) -> Result<Step, Term> {
+        self.run(machine, |m, query| m.run(query, Some(inferences)))
+    }
+
+    // the next answer for engine_next/2, found on the budget of the query calling it
+    pub(crate) fn next_within(
+        &mut self,
+        machine: &mut Machine,
+        q: &mut Query,
+    ) -> Result<Step, Term> {
+        self.run(machine, |m, query| m.run_within(q, query))
+    }
+
+    fn run<F>(&mut self, machine: &mut Machine, run: F) -> Result<Step, Term>
+    where
+        F: FnOnce(&mut Machine, &mut Query) -> Result<Run, Term>,
+    {
+        if self.done {
+            return Ok(Step::Done);
+        }
+
+        // a term posted and not fetched is kept for the next run
+        let posted = mem::replace(&mut machine.posted, self.posted.take());
+        let result = run(machine, &mut self.query);
+        self.posted = mem::replace(&mut machine.posted, posted);
+
+        match result {
+            Ok(Run::Solution) => Ok(Step::Answer(self.query.env.substitute_term(&self.template))),
+            Ok(Run::Paused) => Ok(Step::Paused),
+            Ok(Run::Failed) => {
+                self.done = true;
+                Ok(Step::Done)
+            }
+            Err(ball) => {
+                self.done = true;
+                Err(ball)
+            }
+        }
+    }
+
+    // give the engine a term for its goal to fetch, unless it has one it has not fetched
+    pub fn post(&mut self, t: Term) -> bool {
+        if self.posted.is_some() {
+            return false;
+        }
+
+        self.posted = Some(t);
+        true
+    }
+}
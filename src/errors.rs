@@ -0,0 +1,61 @@
+use crate::ast::Term;
+
+// the formal part of the ISO error terms raised by builtins; the machine adds the
+// context of the builtin that raised them
+
+pub(crate) fn instantiation_error() -> Term {
+    Term::atom("instantiation_error")
+}
+
+pub(crate) fn uninstantiation_error(culprit: Term) -> Term {
+    Term::compound("uninstantiation_error", vec![culprit])
+}
+
+pub(crate) fn type_error(kind: &str, culprit: Term) -> Term {
+    Term::compound("type_error", vec![Term::atom(kind), culprit])
+}
+
+pub(crate) fn domain_error(domain: &str, culprit: Term) -> Term {
+    Term::compound("domain_error", vec![Term::atom(domain), culprit])
+}
+
+pub(crate) fn permission_error(action: &str, kind: &str, culprit: Term) -> Term {
+    Term::compound(
+        "permission_error",
+        vec![Term::atom(action), Term::atom(kind), culprit],
+    )
+}
+
+pub(crate) fn existence_error(kind: &str, culprit: Term) -> Term {
+    Term::compound("existence_error", vec![Term::atom(kind), culprit])
+}
+
+pub(crate) fn representation_error(what: &str) -> Term {
+    Term::compound("representation_error", vec![Term::atom(what)])
+}
+
+pub(crate) fn resource_error(resource: &str) -> Term {
+    Term::compound("resource_error", vec![Term::atom(resource)])
+}
+
+pub(crate) fn syntax_error(message: &str) -> Term {
+    Term::compound("syntax_error", vec![Term::atom(message)])
+}
+
+pub(crate) fn evaluation_error(error: &str) -> Term {
+    Term::compound("evaluation_error", vec![Term::atom(error)])
+}
+
+// what halt/0 raises: it unwinds the query past every catch/3, and leaves it to whoever
+// runs the machine to stop
+pub(crate) fn halt() -> Term {
+    Term::atom("$halt")
+}
+
+pub(crate) fn is_halt(ball: &Term) -> bool {
+    *ball == halt()
+}
+
+pub(crate) fn indicator(name: &str, arity: usize) -> Term {
+    Term::compound("/", vec![Term::atom(name), Term::int(arity as i64)])
+}
@@ -0,0 +1,362 @@
+// a C interface to the machine, declared in include/bfg_prolog.h; build it as a library
+// with `cargo rustc --release --features ffi --crate-type cdylib`
+//
+// every function takes pointers made by this module, or NUL-terminated UTF-8 strings,
+// and a query must be freed before the machine it runs on; the strings handed back
+// belong to the machine or query they came from, and last until the next call on it.
+// A live query borrows its machine, so until it is freed the machine refuses to
+// consult, start another query or be freed, and only its error may be read
+#![allow(clippy::missing_safety_doc)]
+
+use crate::ast::Term;
+use crate::machine::{Machine, Solution, Solutions};
+use crate::ops::OpTable;
+use crate::writer::Writer;
+use std::cell::Cell;
+use std::ffi::{CStr, CString};
+use std::os::raw::{c_char, c_int};
+use std::ptr;
+use std::rc::Rc;
+
+pub struct BfgMachine {
+    machine: Machine,
+    error: Option<CString>,
+    // whether a query borrows the machine, shared with the query
+    live: Rc<Cell<bool>>,
+}
+
+pub struct BfgQuery {
+    // borrows the machine it was made on, which the caller keeps alive
+    solutions: Solutions<'static>,
+    live: Rc<Cell<bool>>,
+    solution: Option<Solution>,
+    value: Option<CString>,
+    error: Option<CString>,
+}
+
+#[no_mangle]
+pub extern "C" fn bfg_machine_new() -> *mut BfgMachine {
+    Box::into_raw(Box::new(BfgMachine {
+        machine: Machine::new(),
+        error: None,
+        live: Rc::new(Cell::new(false)),
+    }))
+}
+
+// free a machine, unless a query on it is still live
+#[no_mangle]
+pub unsafe extern "C" fn bfg_machine_free(m: *mut BfgMachine) {
+    if !m.is_null() && !(*m).live.get() {
+        drop(Box::from_raw(m));
+    }
+}
+
+// load a program from a file, giving 0, or -1 with the reason in bfg_machine_error
+#[no_mangle]
+pub unsafe extern "C" fn bfg_consult_file(m: *mut BfgMachine, path: *const c_char) -> c_int {
+    consult(m, path, |machine, path| machine.consult_file(path))
+}
+
+// load a program from its text, as bfg_consult_file does
+#[no_mangle]
+pub unsafe extern "C" fn bfg_consult_str(m: *mut BfgMachine, code: *const c_char) -> c_int {
+    consult(m, code, |machine, code| machine.consult_str(code))
+}
+
+// why the last consult or query on the machine failed, or NULL; only the error is read,
+// since a live query may hold the rest of the machine
+#[no_mangle]
+pub unsafe extern "C" fn bfg_machine_error(m: *const BfgMachine) -> *const c_char {
+    if m.is_null() {
+        ptr::null()
+    } else {
+        text_ptr(&(*m).error)
+    }
+}
+
+// read a query, ended by `.`, giving NULL with the reason in bfg_machine_error when it
+// does not parse, or another query on the machine is still live
+#[no_mangle]
+pub unsafe extern "C" fn bfg_query_new(m: *mut BfgMachine, text: *const c_char) -> *mut BfgQuery {
+    let (m, text) = match (idle(m), text_arg(text)) {
+        (Some(m), Some(text)) => (m, text),
+        _ => return ptr::null_mut(),
+    };
+
+    let machine: &'static mut Machine = &mut *(&mut m.machine as *mut Machine);
+    match machine.solve(text) {
+        Ok(solutions) => {
+            m.error = None;
+            m.live.set(true);
+            Box::into_raw(Box::new(BfgQuery {
+                solutions,
+                live: m.live.clone(),
+                solution: None,
+                value: None,
+                error: None,
+            }))
+        }
+        Err(e) => {
+            m.error = c_text(&e.to_string());
+            ptr::null_mut()
+        }
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn bfg_query_free(q: *mut BfgQuery) {
+    if !q.is_null() {
+        let q = Box::from_raw(q);
+        let live = q.live.clone();
+        drop(q);
+        live.set(false);
+    }
+}
+
+// find the next solution, giving 1, or 0 when there are no more, or -1 when the query
+// raised an error, described by bfg_query_error
+#[no_mangle]
+pub unsafe extern "C" fn bfg_query_next(q: *mut BfgQuery) -> c_int {
+    let q = match q.as_mut() {
+        Some(q) => q,
+        None => return -1,
+    };
+
+    q.value = None;
+    q.solution = q.solutions.next();
+
+    match (&q.solution, q.solutions.exception()) {
+        (Some(_), _) => 1,
+        (None, None) if q.solutions.halted() => {
+            q.error = c_text("halt");
+            -1
+        }
+        (None, None) => 0,
+        (None, Some(ball)) => {
+            q.error = c_text(&write(ball));
+            -1
+        }
+    }
+}
+
+// the error that ended the query, or NULL
+#[no_mangle]
+pub unsafe extern "C" fn bfg_query_error(q: *const BfgQuery) -> *const c_char {
+    match q.as_ref() {
+        Some(q) => text_ptr(&q.error),
+        None => ptr::null(),
+    }
+}
+
+// the number of named variables of the query, bound in each solution
+#[no_mangle]
+pub unsafe extern "C" fn bfg_query_binding_count(q: *const BfgQuery) -> c_int {
+    match q.as_ref().and_then(|q| q.solution.as_ref()) {
+        Some(solution) => solution.bindings().len() as c_int,
+        None => 0,
+    }
+}
+
+// the name of a variable of the query, by its place in the query
+#[no_mangle]
+pub unsafe extern "C" fn bfg_query_binding_name(q: *mut BfgQuery, i: c_int) -> *const c_char {
+    binding(q, |solution| {
+        let (name, _) = solution.bindings().get(i as usize)?;
+        Some(name.clone())
+    })
+}
+
+// the value of a variable of the query in the current solution, written as writeq/1
+// does, or NULL if the query has no such variable
+#[no_mangle]
+pub unsafe extern "C" fn bfg_query_binding(q: *mut BfgQuery, name: *const c_char) -> *const c_char {
+    let name = match text_arg(name) {
+        Some(name) => name,
+        None => return ptr::null(),
+    };
+
+    binding(q, |solution| solution.term(name).map(write))
+}
+
+unsafe fn consult<F, T>(m: *mut BfgMachine, text: *const c_char, f: F) -> c_int
+where
+    F: FnOnce(&mut Machine, &str) -> Result<T, crate::WamError>,
+{
+    let (m, text) = match (idle(m), text_arg(text)) {
+        (Some(m), Some(text)) => (m, text),
+        _ => return -1,
+    };
+
+    match f(&mut m.machine, text) {
+        Ok(_) => {
+            m.error = None;
+            0
+        }
+        Err(e) => {
+            m.error = c_text(&e.to_string());
+            -1
+        }
+    }
+}
+
+// the machine, unless a live query borrows it, when the reason is left as its error;
+// only the error is touched then, so as not to alias the query's borrow
+unsafe fn idle<'a>(m: *mut BfgMachine) -> Option<&'a mut BfgMachine> {
+    if m.is_null() {
+        return None;
+    }
+
+    if (*m).live.get() {
+        (*m).error = c_text("a query on the machine is still live");
+        return None;
+    }
+
+    m.as_mut()
+}
+
+unsafe fn binding<F>(q: *mut BfgQuery, f: F) -> *const c_char
+where
+    F: FnOnce(&Solution) -> Option<String>,
+{
+    let q = match q.as_mut() {
+        Some(q) => q,
+        None => return ptr::null(),
+    };
+
+    q.value = q.solution.as_ref().and_then(f).and_then(|s| c_text(&s));
+    text_ptr(&q.value)
+}
+
+unsafe fn text_arg<'a>(text: *const c_char) -> Option<&'a str> {
+    if text.is_null() {
+        None
+    } else {
+        CStr::from_ptr(text).to_str().ok()
+    }
+}
+
+// text with a NUL in it cannot be handed to C, and is dropped
+fn c_text(text: &str) -> Option<CString> {
+    CString::new(text).ok()
+}
+
+fn text_ptr(text: &Option<CString>) -> *const c_char {
+    text.as_ref().map_or(ptr::null(), |s| s.as_ptr())
+}
+
+fn write(t: &Term) -> String {
+    Writer::new(&OpTable::default()).quoted(true).write(t)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(s: *const c_char) -> Option<String> {
+        unsafe { text_arg(s).map(String::from) }
+    }
+
+    #[test]
+    fn test_ffi_1_succeeds() {
+        let code = CString::new("p(1, 'a b').\np(2, [x]).").unwrap();
+        let query = CString::new("p(X, Y).").unwrap();
+        let x = CString::new("X").unwrap();
+        let y = CString::new("Y").unwrap();
+
+        unsafe {
+            let m = bfg_machine_new();
+            assert_eq!(bfg_consult_str(m, code.as_ptr()), 0);
+
+            let q = bfg_query_new(m, query.as_ptr());
+            assert_eq!(bfg_query_next(q), 1);
+            assert_eq!(bfg_query_binding_count(q), 2);
+            assert_eq!(text(bfg_query_binding_name(q, 1)), Some(String::from("Y")));
+            assert_eq!(
+                text(bfg_query_binding(q, x.as_ptr())),
+                Some(String::from("1"))
+            );
+            assert_eq!(
+                text(bfg_query_binding(q, y.as_ptr())),
+                Some(String::from("'a b'"))
+            );
+            assert_eq!(bfg_query_next(q), 1);
+            assert_eq!(
+                text(bfg_query_binding(q, y.as_ptr())),
+                Some(String::from("[x]"))
+            );
+            assert_eq!(bfg_query_next(q), 0);
+            bfg_query_free(q);
+            bfg_machine_free(m);
+        }
+    }
+
+    #[test]
+    fn test_ffi_1_fails() {
+        let bad = CString::new("p(").unwrap();
+        let raises = CString::new("X is foo + 1.").unwrap();
+        let path = CString::new("missing.pl").unwrap();
+
+        unsafe {
+            let m = bfg_machine_new();
+            assert_eq!(bfg_consult_file(m, path.as_ptr()), -1);
+            assert!(text(bfg_machine_error(m)).is_some());
+            assert!(bfg_query_new(m, bad.as_ptr()).is_null());
+            assert!(text(bfg_machine_error(m))
+                .unwrap()
+                .starts_with("syntax error"));
+
+            let q = bfg_query_new(m, raises.as_ptr());
+            assert_eq!(bfg_query_next(q), -1);
+            assert!(text(bfg_query_error(q))
+                .unwrap()
+                .starts_with("error(type_error(evaluable,foo/0),"));
+            bfg_query_free(q);
+            bfg_machine_free(m);
+            assert_eq!(bfg_query_next(ptr::null_mut()), -1);
+        }
+    }
+
+    #[test]
+    fn test_ffi_2_fails() {
+        let code = CString::new("p(1).").unwrap();
+        let query = CString::new("X = 1.").unwrap();
+        let p = CString::new("p(X).").unwrap();
+
+        unsafe {
+            let m = bfg_machine_new();
+            let q = bfg_query_new(m, query.as_ptr());
+            assert!(!q.is_null());
+
+            // the machine refuses anything that would alias the live query's borrow
+            assert_eq!(bfg_consult_str(m, code.as_ptr()), -1);
+            assert_eq!(
+                text(bfg_machine_error(m)),
+                Some(String::from("a query on the machine is still live"))
+            );
+            assert!(bfg_query_new(m, query.as_ptr()).is_null());
+            bfg_machine_free(m);
+            assert_eq!(bfg_query_next(q), 1);
+
+            bfg_query_free(q);
+            assert_eq!(bfg_consult_str(m, code.as_ptr()), 0);
+            let q = bfg_query_new(m, p.as_ptr());
+            assert_eq!(bfg_query_next(q), 1);
+            bfg_query_free(q);
+            bfg_machine_free(m);
+        }
+    }
+
+    // the header declares every function exported here
+    #[test]
+    fn test_header_1_succeeds() {
+        let header = include_str!("../include/bfg_prolog.h");
+        let source = include_str!("ffi.rs");
+
+        for line in source.lines() {
+            if let Some(i) = line.find("extern \"C\" fn ") {
+                let name = &line[i + 14..line.find('(').unwrap()];
+                assert!(header.contains(&format!("{}(", name)), "{}", name);
+            }
+        }
+    }
+}
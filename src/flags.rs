@@ -0,0 +1,135 @@
+use crate::ast::{Const, Term};
+use crate::errors::{domain_error, instantiation_error, permission_error, type_error};
+use crate::lexer::DoubleQuotes;
+
+// what to do when calling a predicate that has not been defined
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum Unknown {
+    #[default]
+    Error,
+    Warning,
+    Fail,
+}
+
+// terms are never cyclic here, so a binding that would make one either fails or raises
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum OccursCheck {
+    #[default]
+    True,
+    Error,
+}
+
+// the prolog flags that can be read and changed with current_prolog_flag/2 and
+// set_prolog_flag/2, next to the read-only ones describing integers
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub struct Flags {
+    pub double_quotes: DoubleQuotes,
+    pub unknown: Unknown,
+    pub occurs_check: OccursCheck,
+}
+
+pub const FLAGS: &[&str] = &[
+    "bounded",
+    "double_quotes",
+    "max_integer",
+    "min_integer",
+    "occurs_check",
+    "unknown",
+];
+
+impl Flags {
+    pub fn get(&self, flag: &str) -> Option<Term> {
+        let value = match flag {
+            "bounded" => Term::atom("true"),
+            "max_integer" => Term::int(i64::MAX),
+            "min_integer" => Term::int(i64::MIN),
+            "double_quotes" => Term::atom(match self.double_quotes {
+                DoubleQuotes::Codes => "codes",
+                DoubleQuotes::Chars => "chars",
+                DoubleQuotes::Atom => "atom",
+                DoubleQuotes::String => "string",
+            }),
+            "unknown" => Term::atom(match self.unknown {
+                Unknown::Error => "error",
+                Unknown::Warning => "warning",
+                Unknown::Fail => "fail",
+            }),
+            "occurs_check" => Term::atom(match self.occurs_check {
+                OccursCheck::True => "true",
+                OccursCheck::Error => "error",
+            }),
+            _ => return None,
+        };
+
+        Some(value)
+    }
+
+    // change a flag, failing with the formal part of the error set_prolog_flag/2 raises
+    pub fn set(&mut self, flag: &Term, value: &Term) -> Result<(), Term> {
+        let name = match flag {
+            Term::Var(_) => return Err(instantiation_error()),
+            Term::Const(Const(name)) => name,
+            t => return Err(type_error("atom", t.clone())),
+        };
+
+        let atom = match value {
+            Term::Var(_) => return Err(instantiation_error()),
+            Term::Const(Const(atom)) => atom.as_str(),
+            _ => "",
+        };
+
+        match (name.as_str(), atom) {
+            ("double_quotes", "codes") => self.double_quotes = DoubleQuotes::Codes,
+            ("double_quotes", "chars") => self.double_quotes = DoubleQuotes::Chars,
+            ("double_quotes", "atom") => self.double_quotes = DoubleQuotes::Atom,
+            ("double_quotes", "string") => self.double_quotes = DoubleQuotes::String,
+            ("unknown", "error") => self.unknown = Unknown::Error,
+            ("unknown", "warning") => self.unknown = Unknown::Warning,
+            ("unknown", "fail") => self.unknown = Unknown::Fail,
+            ("occurs_check", "true") => self.occurs_check = OccursCheck::True,
+            ("occurs_check", "error") => self.occurs_check = OccursCheck::Error,
+            ("bounded", _) | ("max_integer", _) | ("min_integer", _) => {
+                return Err(permission_error("modify", "flag", flag.clone()))
+            }
+            (name, _) if FLAGS.contains(&name) => {
+                let culprit = Term::compound("+", vec![flag.clone(), value.clone()]);
+                return Err(domain_error("flag_value", culprit));
+            }
+            _ => return Err(domain_error("prolog_flag", flag.clone())),
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_flags_1_succeeds() {
+        let mut flags = Flags::default();
+        flags
+            .set(&Term::atom("double_quotes"), &Term::atom("atom"))
+            .unwrap();
+
+        assert_eq!(flags.double_quotes, DoubleQuotes::Atom);
+        assert_eq!(flags.get("double_quotes"), Some(Term::atom("atom")));
+        assert_eq!(flags.get("unknown"), Some(Term::atom("error")));
+        assert_eq!(flags.get("missing"), None);
+    }
+
+    #[test]
+    fn test_flags_1_fails() {
+        let mut flags = Flags::default();
+
+        assert!(flags
+            .set(&Term::atom("bounded"), &Term::atom("false"))
+            .is_err());
+        assert!(flags
+            .set(&Term::atom("unknown"), &Term::atom("maybe"))
+            .is_err());
+        assert!(flags.set(&Term::atom("missing"), &Term::int(1)).is_err());
+        assert_eq!(flags, Flags::default());
+    }
+}
@@ -0,0 +1,67 @@
+use crate::ast::Term;
+use crate::convert::{ConversionError, FromTerm, ToTerm};
+use crate::errors::{instantiation_error, type_error};
+use crate::machine::{Machine, Query};
+use std::sync::Arc;
+
+// a predicate written in Rust: it is given the arguments of a call, and reports the
+// outcome or the formal part of an error to raise; it is shared by the machines of a
+// query pool, so it may be called from any thread
+pub type ForeignPredicate =
+    Arc<dyn Fn(&mut Machine, &mut Args) -> Result<Foreign, Term> + Send + Sync>;
+
+// what a call to a foreign predicate came to
+#[derive(Debug, Clone, PartialEq)]
+pub enum Foreign {
+    Fail,
+    Succeed,
+    // succeeded, and is called again on backtracking with this as its state
+    Redo(Term),
+}
+
+// the arguments of a call to a foreign predicate, and the bindings it can make to them
+pub struct Args<'q> {
+    query: &'q mut Query,
+    args: &'q [Term],
+    state: Option<Term>,
+}
+
+impl<'q> Args<'q> {
+    pub(crate) fn new(query: &'q mut Query, args: &'q [Term], state: Option<Term>) -> Self {
+        Args { query, args, state }
+    }
+
+    pub fn len(&self) -> usize {
+        self.args.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.args.is_empty()
+    }
+
+    // an argument with the bindings made so far applied to it
+    pub fn get(&self, i: usize) -> Term {
+        self.query.env.substitute_term(&self.args[i])
+    }
+
+    // an argument as a Rust value, or the error to raise when it is not one
+    pub fn read<T: FromTerm>(&self, i: usize) -> Result<T, Term> {
+        T::from_term(&self.get(i)).map_err(|e| match e {
+            ConversionError::Type { expected, found } if !matches!(found, Term::Var(_)) => {
+                type_error(expected, found)
+            }
+            _ => instantiation_error(),
+        })
+    }
+
+    // bind an argument to a term, or a Rust value as one, reporting whether they unify
+    pub fn unify<T: ToTerm>(&mut self, i: usize, value: T) -> bool {
+        self.query.unify(&self.args[i], &value.to_term())
+    }
+
+    // what the predicate returned with `Foreign::Redo` the last time it succeeded, when
+    // it is being backtracked into
+    pub fn state(&self) -> Option<&Term> {
+        self.state.as_ref()
+    }
+}
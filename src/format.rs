@@ -0,0 +1,438 @@
+use crate::ast::{Atom, Const, Number, Term};
+use crate::errors::{instantiation_error, type_error};
+use crate::ops::OpTable;
+use crate::writer::Writer;
+use std::collections::VecDeque;
+use std::convert::TryFrom;
+
+// how ~p writes a term
+pub(crate) type Print<'a> = dyn FnMut(&Term) -> Result<String, Term> + 'a;
+
+// the text of format/2: each directive of the form `~[N]c` takes its arguments from
+// the front of the list, where N is a number, `*` to take it from the list too, or a
+// backquoted character; ~t, ~| and ~+ line text up in columns. ~p writes a term as
+// print/1 would, if given how, and as ~q does otherwise
+pub(crate) fn format<'a>(
+    ops: &'a OpTable,
+    directives: &str,
+    args: Vec<Term>,
+    print: Option<&'a mut Print<'a>>,
+) -> Result<String, Term> {
+    let mut f = Formatter {
+        ops,
+        print,
+        args: args.into(),
+        done: String::new(),
+        pending: String::new(),
+        fills: Vec::new(),
+        stop: 0,
+    };
+    let mut chars = directives.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '~' {
+            f.pending.push(c);
+            continue;
+        }
+
+        let mut c = chars.next();
+        let n = match c {
+            Some('*') => {
+                c = chars.next();
+                match f.next_arg()? {
+                    Term::Number(Number::Int(n)) if n >= 0 => Some(n as usize),
+                    Term::Var(_) => return Err(instantiation_error()),
+                    t => return Err(type_error("integer", t)),
+                }
+            }
+            Some('`') => {
+                let fill = chars.next();
+                c = chars.next();
+                fill.map(|fill| fill as usize)
+            }
+            Some(d) if d.is_ascii_digit() => {
+                let mut n: usize = 0;
+                while let Some(d) = c.and_then(|c| c.to_digit(10)) {
+                    n = n.saturating_mul(10).saturating_add(d as usize);
+                    c = chars.next();
+                }
+                Some(n)
+            }
+            _ => None,
+        };
+
+        let directive = c.ok_or_else(|| error("truncated directive"))?;
+        f.directive(n, directive)?;
+    }
+
+    if !f.args.is_empty() {
+        return Err(error("too many arguments"));
+    }
+
+    f.done.push_str(&f.pending);
+    Ok(f.done)
+}
+
+struct Formatter<'a> {
+    ops: &'a OpTable,
+    print: Option<&'a mut Print<'a>>,
+    args: VecDeque<Term>,
+    // the text before the last column stop, and the text after it with the places to
+    // fill it up to the next stop, if there are any
+    done: String,
+    pending: String,
+    fills: Vec<(usize, char)>,
+    // the column of the last column stop
+    stop: usize,
+}
+
+impl Formatter<'_> {
+    fn next_arg(&mut self) -> Result<Term, Term> {
+        self.args
+            .pop_front()
+            .ok_or_else(|| error("not enough arguments"))
+    }
+
+    fn directive(&mut self, n: Option<usize>, directive: char) -> Result<(), Term> {
+        match directive {
+            '~' => self.pending.push('~'),
+            'n' => self.pending.push_str(&"\n".repeat(n.unwrap_or(1))),
+            'a' => match self.next_arg()? {
+                Term::Var(_) => return Err(instantiation_error()),
+                t @ Term::Atom(_) => return Err(type_error("atomic", t)),
+                t => self.write(&t, false),
+            },
+            'w' => {
+                let t = self.next_arg()?;
+                self.write(&t, false);
+            }
+            'p' if self.print.is_some() => {
+                let t = self.next_arg()?;
+                let text = (self.print.as_mut().unwrap())(&t)?;
+                self.pending.push_str(&text);
+            }
+            'p' | 'q' => {
+                let t = self.next_arg()?;
+                self.write(&t, true);
+            }
+            'i' => {
+                self.next_arg()?;
+            }
+            'c' => {
+                let c = match self.next_arg()? {
+                    Term::Number(Number::Int(i)) => u32::try_from(i).ok().and_then(char::from_u32),
+                    Term::Var(_) => return Err(instantiation_error()),
+                    t => return Err(type_error("integer", t)),
+                };
+                let c = c.ok_or_else(|| error("not a character code"))?;
+                self.pending.extend(std::iter::repeat_n(c, n.unwrap_or(1)));
+            }
+            's' => {
+                let t = self.next_arg()?;
+                let text = text(&t).ok_or_else(|| type_error("text", t))?;
+                self.pending.push_str(&text);
+            }
+            'd' | 'D' => {
+                let i = self.integer()?;
+                let text = grouped(i, n.unwrap_or(0), directive == 'D');
+                self.pending.push_str(&text);
+            }
+            'f' | 'e' | 'g' => {
+                let x = match self.next_arg()? {
+                    Term::Number(Number::Int(i)) => i as f64,
+                    Term::Number(Number::Float(x)) => x,
+                    Term::Var(_) => return Err(instantiation_error()),
+                    t => return Err(type_error("number", t)),
+                };
+                let precision = n.unwrap_or(6);
+                let text = match directive {
+                    'f' => format!("{:.*}", precision, x),
+                    'e' => exponent(x, precision),
+                    _ => general(x, precision),
+                };
+                self.pending.push_str(&text);
+            }
+            'r' | 'R' => {
+                let radix = match n {
+                    Some(radix) if (2..=36).contains(&radix) => radix as u32,
+                    _ => return Err(error("radix must be from 2 to 36")),
+                };
+                let i = self.integer()?;
+                let text = radixed(i, radix, directive == 'R');
+                self.pending.push_str(&text);
+            }
+            't' => {
+                let fill = n.and_then(|n| char::from_u32(n as u32)).unwrap_or(' ');
+                self.fills.push((self.pending.len(), fill));
+            }
+            '|' => self.column(n),
+            '+' => {
+                let stop = if self.pending.contains('\n') {
+                    0
+                } else {
+                    self.stop
+                };
+                self.column(Some(stop + n.unwrap_or(8)));
+            }
+            c => return Err(error(&format!("unknown directive ~{}", c))),
+        }
+
+        Ok(())
+    }
+
+    fn write(&mut self, t: &Term, quoted: bool) {
+        let text = Writer::new(self.ops).quoted(quoted).write(t);
+        self.pending.push_str(&text);
+    }
+
+    fn integer(&mut self) -> Result<i64, Term> {
+        match self.next_arg()? {
+            Term::Number(Number::Int(i)) => Ok(i),
+            Term::Var(_) => Err(instantiation_error()),
+            t => Err(type_error("integer", t)),
+        }
+    }
+
+    // end a column at the given column, or where the text is now: the text since the
+    // last stop is padded at its fill points, or after it if it has none
+    fn column(&mut self, at: Option<usize>) {
+        let (start, width) = match (self.pending.rfind('\n'), self.done.rfind('\n')) {
+            (Some(i), _) => (0, self.pending[i + 1..].chars().count()),
+            (None, Some(i)) => (
+                self.done[i + 1..].chars().count(),
+                self.pending.chars().count(),
+            ),
+            (None, None) => (self.done.chars().count(), self.pending.chars().count()),
+        };
+        let at = at.unwrap_or(start + width);
+
+        if at > start + width {
+            let pad = at - start - width;
+            if self.fills.is_empty() {
+                self.fills.push((self.pending.len(), ' '));
+            }
+
+            // the padding is shared out between the fill points, the last ones taking
+            // what is left over
+            let n = self.fills.len();
+            for (i, &(at, fill)) in self.fills.iter().enumerate().rev() {
+                let share = pad / n + usize::from(i >= n - pad % n);
+                let padding: String = std::iter::repeat_n(fill, share).collect();
+                self.pending.insert_str(at, &padding);
+            }
+        }
+
+        self.done.push_str(&self.pending);
+        self.pending.clear();
+        self.fills.clear();
+        self.stop = at.max(start + width);
+    }
+}
+
+fn error(message: &str) -> Term {
+    Term::compound("format", vec![Term::atom(message)])
+}
+
+// the text of a string, an atom, or a list of codes or characters
+fn text(t: &Term) -> Option<String> {
+    let mut text = String::new();
+    let mut tail = t;
+
+    loop {
+        match tail {
+            Term::String(s) => return Some(s.clone()),
+            Term::Const(Const(name)) if name == "[]" => return Some(text),
+            Term::Atom(Atom { name, args, .. }) if name.0 == "." && args.len() == 2 => {
+                match &args[0] {
+                    Term::Number(Number::Int(i)) => {
+                        text.push(u32::try_from(*i).ok().and_then(char::from_u32)?)
+                    }
+                    Term::Const(Const(c)) if c.chars().count() == 1 => text.push_str(c),
+                    _ => return None,
+                }
+                tail = &args[1];
+            }
+            _ => return None,
+        }
+    }
+}
+
+// an integer with a decimal point put in before its last digits, and with its whole part
+// in groups of three digits if asked for
+fn grouped(i: i64, decimals: usize, commas: bool) -> String {
+    let digits = i.unsigned_abs().to_string();
+    let digits = format!("{:0>width$}", digits, width = decimals + 1);
+    let (whole, fraction) = digits.split_at(digits.len() - decimals);
+
+    let mut text = String::new();
+    if i < 0 {
+        text.push('-');
+    }
+
+    for (n, c) in whole.chars().enumerate() {
+        if commas && n > 0 && (whole.len() - n) % 3 == 0 {
+            text.push(',');
+        }
+        text.push(c);
+    }
+
+    if decimals > 0 {
+        text.push('.');
+        text.push_str(fraction);
+    }
+
+    text
+}
+
+fn radixed(i: i64, radix: u32, upper: bool) -> String {
+    let mut digits = Vec::new();
+    let mut n = i.unsigned_abs();
+
+    loop {
+        let d = std::char::from_digit((n % radix as u64) as u32, radix).unwrap();
+        digits.push(if upper { d.to_ascii_uppercase() } else { d });
+        n /= radix as u64;
+        if n == 0 {
+            break;
+        }
+    }
+
+    if i < 0 {
+        digits.push('-');
+    }
+
+    digits.iter().rev().collect()
+}
+
+// a float in exponent notation as C writes it, e.g. 1.500000e+02
+fn exponent(x: f64, precision: usize) -> String {
+    let text = format!("{:.*e}", precision, x);
+    match text.split_once('e') {
+        Some((mantissa, exp)) => {
+            let exp: i32 = exp.parse().unwrap_or(0);
+            let sign = if exp < 0 { '-' } else { '+' };
+            format!("{}e{}{:02}", mantissa, sign, exp.abs())
+        }
+        None => text,
+    }
+}
+
+// a float in the shorter of fixed and exponent notation, with trailing zeros dropped,
+// as C's %g writes it
+fn general(x: f64, precision: usize) -> String {
+    if !x.is_finite() {
+        return x.to_string();
+    }
+
+    let precision = precision.max(1);
+    let exp = match format!("{:.*e}", precision - 1, x).split_once('e') {
+        Some((_, exp)) => exp.parse().unwrap_or(0),
+        None => 0,
+    };
+
+    let text = if exp < -4 || exp >= precision as i32 {
+        exponent(x, precision - 1)
+    } else {
+        format!("{:.*}", (precision as i32 - 1 - exp) as usize, x)
+    };
+
+    match text.split_once('e') {
+        Some((mantissa, exp)) if mantissa.contains('.') => {
+            format!(
+                "{}e{}",
+                mantissa.trim_end_matches('0').trim_end_matches('.'),
+                exp
+            )
+        }
+        None if text.contains('.') => {
+            String::from(text.trim_end_matches('0').trim_end_matches('.'))
+        }
+        _ => text,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn format_ok(directives: &str, args: Vec<Term>) -> String {
+        format(&OpTable::default(), directives, args, None).unwrap()
+    }
+
+    #[test]
+    fn test_format_1_succeeds() {
+        assert_eq!(
+            format_ok(
+                "~d|~2d|~D",
+                vec![Term::int(-42), Term::int(5), Term::int(1234567)]
+            ),
+            "-42|0.05|1,234,567"
+        );
+        assert_eq!(format_ok("~2D", vec![Term::int(123456789)]), "1,234,567.89");
+        assert_eq!(
+            format_ok(
+                "~8r ~16R ~2r",
+                vec![Term::int(64), Term::int(255), Term::int(-5)]
+            ),
+            "100 FF -101"
+        );
+        assert_eq!(
+            format_ok(
+                "~2f ~e ~3e",
+                vec![Term::int(3), Term::float(150.0), Term::float(0.00123)]
+            ),
+            "3.00 1.500000e+02 1.230e-03"
+        );
+        assert_eq!(
+            format_ok(
+                "~g ~g ~3g",
+                vec![Term::float(0.5), Term::float(1e-5), Term::float(1234.0)]
+            ),
+            "0.5 1e-05 1.23e+03"
+        );
+        assert_eq!(format_ok("[~w~t~6|]", vec![Term::atom("ab")]), "[ab   ]");
+        assert_eq!(format_ok("[~t~w~6|]", vec![Term::atom("ab")]), "[   ab]");
+        assert_eq!(
+            format_ok("~`-t~8|~n~w~t~3|", vec![Term::int(1)]),
+            "--------\n1  "
+        );
+        assert_eq!(
+            format_ok("~w~t~4+~w~t~4+|", vec![Term::atom("a"), Term::atom("b")]),
+            "a   b   |"
+        );
+        assert_eq!(
+            format_ok(
+                "~a~n~*c~~",
+                vec![Term::atom("x"), Term::int(3), Term::int('y' as i64)]
+            ),
+            "x\nyyy~"
+        );
+    }
+
+    #[test]
+    fn test_format_1_fails() {
+        let ops = OpTable::default();
+        let error = |message| Err(Term::compound("format", vec![Term::atom(message)]));
+
+        assert_eq!(
+            format(&ops, "~w ~w", vec![Term::atom("a")], None),
+            error("not enough arguments")
+        );
+        assert_eq!(
+            format(&ops, "~w", vec![Term::atom("a"), Term::atom("b")], None),
+            error("too many arguments")
+        );
+        assert_eq!(
+            format(&ops, "~r", vec![Term::int(1)], None),
+            error("radix must be from 2 to 36")
+        );
+        assert_eq!(
+            format(&ops, "~d", vec![Term::float(1.0)], None),
+            Err(type_error("integer", Term::float(1.0)))
+        );
+        assert_eq!(
+            format(&ops, "~", vec![], None),
+            error("truncated directive")
+        );
+    }
+}
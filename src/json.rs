@@ -0,0 +1,467 @@
+use crate::ast::{Const, Number, Term};
+use std::fmt::{Display, Formatter, Write};
+
+// JSON as terms: an object is `json([Key=Value, ...])` with atoms for keys, an array is
+// a list, a string is a string, and true, false and null are those atoms; writing a
+// term back also takes `Key-Value` pairs, and other atoms as strings
+#[derive(Debug, Clone, PartialEq)]
+pub enum JsonError {
+    // the text is not JSON, from this byte on
+    Syntax(usize),
+    // the text ended in the middle of a value
+    EndOfInput,
+    // the term, or this part of it, has no JSON form
+    NotJson(Term),
+}
+
+impl Term {
+    // the term for the JSON value that is the whole of the text, bar layout around it
+    pub fn from_json(text: &str) -> Result<Term, JsonError> {
+        match read_json(text, true)? {
+            Some((t, end)) => match skip_layout(text, end) {
+                end if end == text.len() => Ok(t),
+                end => Err(JsonError::Syntax(end)),
+            },
+            None => Err(JsonError::EndOfInput),
+        }
+    }
+
+    pub fn to_json(&self) -> Result<String, JsonError> {
+        let mut json = String::new();
+        write_json(&mut json, self)?;
+        Ok(json)
+    }
+}
+
+// the first JSON value in a text, and where it ends; nothing when the text ends first,
+// unless it is `at_end` of the input, as a number running to the end may go on
+pub(crate) fn read_json(text: &str, at_end: bool) -> Result<Option<(Term, usize)>, JsonError> {
+    let mut parser = Parser {
+        text: text.as_bytes(),
+        pos: 0,
+        at_end,
+    };
+
+    match parser.value() {
+        Ok(t) => Ok(Some((t, parser.pos))),
+        Err(JsonError::EndOfInput) if !at_end => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+struct Parser<'a> {
+    text: &'a [u8],
+    pos: usize,
+    at_end: bool,
+}
+
+impl<'a> Parser<'a> {
+    fn value(&mut self) -> Result<Term, JsonError> {
+        self.pos = skip_layout_bytes(self.text, self.pos);
+
+        match self.peek()? {
+            b'{' => self.object(),
+            b'[' => self.array(),
+            b'"' => Ok(Term::string(&self.string()?)),
+            b't' => self.literal("true"),
+            b'f' => self.literal("false"),
+            b'n' => self.literal("null"),
+            b'-' | b'0'..=b'9' => self.number(),
+            _ => Err(JsonError::Syntax(self.pos)),
+        }
+    }
+
+    fn object(&mut self) -> Result<Term, JsonError> {
+        let mut pairs = Vec::new();
+        self.pos += 1;
+
+        if self.next_is(b'}')? {
+            return Ok(Term::compound("json", vec![Term::atom("[]")]));
+        }
+
+        loop {
+            self.pos = skip_layout_bytes(self.text, self.pos);
+            if self.peek()? != b'"' {
+                return Err(JsonError::Syntax(self.pos));
+            }
+
+            let key = Term::atom(&self.string()?);
+            self.expect(b':')?;
+            let value = self.value()?;
+            pairs.push(Term::compound("=", vec![key, value]));
+
+            if self.close(b'}')? {
+                let pairs = Term::list(pairs, Term::atom("[]"));
+                return Ok(Term::compound("json", vec![pairs]));
+            }
+        }
+    }
+
+    fn array(&mut self) -> Result<Term, JsonError> {
+        let mut items = Vec::new();
+        self.pos += 1;
+
+        if self.next_is(b']')? {
+            return Ok(Term::atom("[]"));
+        }
+
+        loop {
+            items.push(self.value()?);
+
+            if self.close(b']')? {
+                return Ok(Term::list(items, Term::atom("[]")));
+            }
+        }
+    }
+
+    // after an item, whether the object or array ends, or another item follows
+    fn close(&mut self, end: u8) -> Result<bool, JsonError> {
+        if self.next_is(end)? {
+            Ok(true)
+        } else {
+            self.expect(b',').map(|_| false)
+        }
+    }
+
+    fn string(&mut self) -> Result<String, JsonError> {
+        let mut bytes = Vec::new();
+        self.pos += 1;
+
+        loop {
+            let b = self.peek()?;
+            self.pos += 1;
+
+            match b {
+                b'"' => break,
+                b'\\' => {
+                    let escape = self.peek()?;
+                    self.pos += 1;
+
+                    let c = match escape {
+                        b'"' => '"',
+                        b'\\' => '\\',
+                        b'/' => '/',
+                        b'b' => '\u{8}',
+                        b'f' => '\u{c}',
+                        b'n' => '\n',
+                        b'r' => '\r',
+                        b't' => '\t',
+                        b'u' => self.unicode()?,
+                        _ => return Err(JsonError::Syntax(self.pos - 1)),
+                    };
+
+                    let mut buf = [0; 4];
+                    bytes.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+                }
+                b if b < 0x20 => return Err(JsonError::Syntax(self.pos - 1)),
+                b => bytes.push(b),
+            }
+        }
+
+        // the text came from a str, and escapes only add whole characters
+        Ok(String::from_utf8(bytes).unwrap())
+    }
+
+    // the character of a `\uXXXX` escape, or of two making a surrogate pair
+    fn unicode(&mut self) -> Result<char, JsonError> {
+        let start = self.pos - 2;
+        let high = self.hex()?;
+
+        let code = if (0xd800..0xdc00).contains(&high) {
+            if self.peek()? != b'\\' {
+                return Err(JsonError::Syntax(start));
+            }
+            self.pos += 1;
+            if self.peek()? != b'u' {
+                return Err(JsonError::Syntax(start));
+            }
+            self.pos += 1;
+
+            let low = self.hex()?;
+            if !(0xdc00..0xe000).contains(&low) {
+                return Err(JsonError::Syntax(start));
+            }
+            0x10000 + ((high - 0xd800) << 10) + (low - 0xdc00)
+        } else {
+            high
+        };
+
+        std::char::from_u32(code).ok_or(JsonError::Syntax(start))
+    }
+
+    fn hex(&mut self) -> Result<u32, JsonError> {
+        let mut code = 0;
+
+        for _ in 0..4 {
+            let digit = (self.peek()? as char)
+                .to_digit(16)
+                .ok_or(JsonError::Syntax(self.pos))?;
+            code = code * 16 + digit;
+            self.pos += 1;
+        }
+
+        Ok(code)
+    }
+
+    fn number(&mut self) -> Result<Term, JsonError> {
+        let start = self.pos;
+        let mut float = false;
+
+        if self.peek()? == b'-' {
+            self.pos += 1;
+        }
+
+        match self.peek()? {
+            b'0' => self.pos += 1,
+            b'1'..=b'9' => self.digits()?,
+            _ => return Err(JsonError::Syntax(self.pos)),
+        }
+
+        if self.maybe_peek()? == Some(b'.') {
+            float = true;
+            self.pos += 1;
+            self.required_digits()?;
+        }
+
+        if let Some(b'e') | Some(b'E') = self.maybe_peek()? {
+            float = true;
+            self.pos += 1;
+            if let b'+' | b'-' = self.peek()? {
+                self.pos += 1;
+            }
+            self.required_digits()?;
+        }
+
+        // the digits are ASCII, and make a number rust reads
+        let text = std::str::from_utf8(&self.text[start..self.pos]).unwrap();
+        match text.parse::<i64>() {
+            Ok(i) if !float => Ok(Term::int(i)),
+            _ => Ok(Term::float(text.parse().unwrap())),
+        }
+    }
+
+    fn required_digits(&mut self) -> Result<(), JsonError> {
+        match self.peek()? {
+            b'0'..=b'9' => self.digits(),
+            _ => Err(JsonError::Syntax(self.pos)),
+        }
+    }
+
+    fn digits(&mut self) -> Result<(), JsonError> {
+        while let Some(b'0'..=b'9') = self.maybe_peek()? {
+            self.pos += 1;
+        }
+        Ok(())
+    }
+
+    fn literal(&mut self, name: &str) -> Result<Term, JsonError> {
+        for &b in name.as_bytes() {
+            if self.peek()? != b {
+                return Err(JsonError::Syntax(self.pos));
+            }
+            self.pos += 1;
+        }
+
+        Ok(Term::atom(name))
+    }
+
+    // skip layout, and take the byte if it is the one given
+    fn next_is(&mut self, b: u8) -> Result<bool, JsonError> {
+        self.pos = skip_layout_bytes(self.text, self.pos);
+
+        if self.peek()? == b {
+            self.pos += 1;
+            Ok(true)
+        } else {
+            Ok(false)
+        }
+    }
+
+    fn expect(&mut self, b: u8) -> Result<(), JsonError> {
+        if self.next_is(b)? {
+            Ok(())
+        } else {
+            Err(JsonError::Syntax(self.pos))
+        }
+    }
+
+    fn peek(&self) -> Result<u8, JsonError> {
+        self.text
+            .get(self.pos)
+            .copied()
+            .ok_or(JsonError::EndOfInput)
+    }
+
+    // the next byte, where the end of the text may end a number
+    fn maybe_peek(&self) -> Result<Option<u8>, JsonError> {
+        match self.text.get(self.pos) {
+            None if !self.at_end => Err(JsonError::EndOfInput),
+            b => Ok(b.copied()),
+        }
+    }
+}
+
+fn skip_layout(text: &str, pos: usize) -> usize {
+    skip_layout_bytes(text.as_bytes(), pos)
+}
+
+fn skip_layout_bytes(text: &[u8], mut pos: usize) -> usize {
+    while let Some(b' ') | Some(b'\t') | Some(b'\n') | Some(b'\r') = text.get(pos) {
+        pos += 1;
+    }
+    pos
+}
+
+fn write_json(json: &mut String, t: &Term) -> Result<(), JsonError> {
+    match t {
+        Term::Number(Number::Int(i)) => write!(json, "{}", i).unwrap(),
+        Term::Number(Number::Float(x)) if x.is_finite() => {
+            write!(json, "{}", Number::Float(*x)).unwrap()
+        }
+        Term::Const(Const(name)) if name == "[]" => json.push_str("[]"),
+        Term::Const(Const(name)) if ["true", "false", "null"].contains(&&**name) => {
+            json.push_str(name)
+        }
+        Term::Const(Const(name)) | Term::String(name) => write_string(json, name),
+        Term::Atom(a) if a.name.0 == "json" && a.args.len() == 1 => {
+            let pairs = match &a.args[0] {
+                Term::Const(Const(nil)) if nil == "[]" => Vec::new(),
+                pairs => proper_list(pairs).ok_or_else(|| JsonError::NotJson(t.clone()))?,
+            };
+
+            json.push('{');
+            for (i, pair) in pairs.into_iter().enumerate() {
+                if i > 0 {
+                    json.push(',');
+                }
+
+                let (key, value) = match pair {
+                    Term::Atom(p) if ["=", "-"].contains(&&*p.name.0) && p.args.len() == 2 => {
+                        (&p.args[0], &p.args[1])
+                    }
+                    pair => return Err(JsonError::NotJson(pair.clone())),
+                };
+
+                match key {
+                    Term::Const(Const(key)) | Term::String(key) => write_string(json, key),
+                    key => return Err(JsonError::NotJson(key.clone())),
+                }
+                json.push(':');
+                write_json(json, value)?;
+            }
+            json.push('}');
+        }
+        Term::Atom(a) if a.name.0 == "." && a.args.len() == 2 => {
+            let items = proper_list(t).ok_or_else(|| JsonError::NotJson(t.clone()))?;
+
+            json.push('[');
+            for (i, item) in items.into_iter().enumerate() {
+                if i > 0 {
+                    json.push(',');
+                }
+                write_json(json, item)?;
+            }
+            json.push(']');
+        }
+        t => return Err(JsonError::NotJson(t.clone())),
+    }
+
+    Ok(())
+}
+
+fn proper_list(t: &Term) -> Option<Vec<&Term>> {
+    match t.list_items()? {
+        (items, Term::Const(Const(nil))) if nil == "[]" => Some(items),
+        _ => None,
+    }
+}
+
+fn write_string(json: &mut String, text: &str) {
+    json.push('"');
+
+    for c in text.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            c if (c as u32) < 0x20 => write!(json, "\\u{:04x}", c as u32).unwrap(),
+            c => json.push(c),
+        }
+    }
+
+    json.push('"');
+}
+
+impl Display for JsonError {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), std::fmt::Error> {
+        match self {
+            JsonError::Syntax(pos) => write!(f, "invalid JSON at byte {}", pos),
+            JsonError::EndOfInput => write!(f, "JSON ended in the middle of a value"),
+            JsonError::NotJson(t) => write!(f, "{} has no JSON form", t),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::term;
+
+    #[test]
+    fn test_json_1_succeeds() {
+        let t =
+            Term::from_json(r#" {"a": [1, -2.5e1, "x\n\u00e9\ud83d\ude00"], "b": {}, "c": null} "#)
+                .unwrap();
+        let expected = Term::compound(
+            "json",
+            vec![Term::list(
+                vec![
+                    Term::compound(
+                        "=",
+                        vec![
+                            Term::atom("a"),
+                            Term::list(
+                                vec![Term::int(1), Term::float(-25.0), Term::string("x\né😀")],
+                                Term::atom("[]"),
+                            ),
+                        ],
+                    ),
+                    term!("b = json([])"),
+                    term!("c = null"),
+                ],
+                Term::atom("[]"),
+            )],
+        );
+        assert_eq!(t, expected);
+        assert_eq!(
+            t.to_json().unwrap(),
+            "{\"a\":[1,-25.0,\"x\\né😀\"],\"b\":{},\"c\":null}"
+        );
+        assert_eq!(Term::from_json(&t.to_json().unwrap()), Ok(t));
+
+        assert_eq!(
+            term!("json([k-v, 'K'=[]])").to_json().unwrap(),
+            "{\"k\":\"v\",\"K\":[]}"
+        );
+        assert_eq!(read_json("12", false), Ok(None));
+        assert_eq!(read_json("12 ", false), Ok(Some((Term::int(12), 2))));
+        assert_eq!(read_json("[tr", false), Ok(None));
+    }
+
+    #[test]
+    fn test_json_1_fails() {
+        assert_eq!(Term::from_json("[1,]"), Err(JsonError::Syntax(3)));
+        assert_eq!(Term::from_json("{\"a\" 1}"), Err(JsonError::Syntax(5)));
+        assert_eq!(Term::from_json("01"), Err(JsonError::Syntax(1)));
+        assert_eq!(Term::from_json("[1, 2"), Err(JsonError::EndOfInput));
+        assert_eq!(Term::from_json("\"\\ud800\""), Err(JsonError::Syntax(1)));
+        assert_eq!(
+            term!("[f(x)]").to_json(),
+            Err(JsonError::NotJson(term!("f(x)")))
+        );
+        assert!(term!("[1|T]").to_json().is_err());
+        assert!(Term::float(f64::NAN).to_json().is_err());
+    }
+}
@@ -0,0 +1,510 @@
+use lalrpop_util::ParseError as LalrpopError;
+use std::fmt::{Display, Formatter};
+use std::iter::Peekable;
+use std::str::CharIndices;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Tok {
+    Name(String),
+    Functor(String),
+    Var(String),
+    Anonymous,
+    // the digits of an integer, whose sign if any is a token of its own; the largest is
+    // only in range as the magnitude of the smallest negative integer
+    Int(u64),
+    Float(f64),
+    Codes(String),
+    Chars(String),
+    Str(String),
+    OpenParen,
+    CloseParen,
+    OpenList,
+    CloseList,
+    OpenCurly,
+    CloseCurly,
+    Comma,
+    Bar,
+    End,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SyntaxError {
+    UnexpectedChar(char, usize),
+    UnterminatedQuoted(usize),
+    UnterminatedComment(usize),
+    UndefinedEscape(char, usize),
+    IntegerOverflow(usize),
+    OperatorExpected(usize),
+    OperatorClash(usize),
+    UnexpectedEnd(usize),
+    NotCallable(usize),
+}
+
+pub type Spanned = Result<(usize, Tok, usize), SyntaxError>;
+
+// a syntax error located in the text it was read from, for reporting to the user
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    pub line: usize,
+    pub column: usize,
+    pub lexeme: Option<String>,
+    pub expected: Vec<String>,
+    pub cause: Option<SyntaxError>,
+}
+
+// what a double-quoted string denotes, as with the double_quotes flag
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub enum DoubleQuotes {
+    #[default]
+    Codes,
+    Chars,
+    Atom,
+    String,
+}
+
+pub struct Lexer<'input> {
+    input: &'input str,
+    chars: Peekable<CharIndices<'input>>,
+    double_quotes: DoubleQuotes,
+}
+
+const SYMBOL_CHARS: &str = "+-*/\\^<>=~:.?@#&$";
+
+pub fn is_symbol_char(c: char) -> bool {
+    SYMBOL_CHARS.contains(c)
+}
+
+// letters are not limited to ASCII: caseless letters start atoms, like lowercase ones
+pub fn is_alnum(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+pub fn is_atom_start(c: char) -> bool {
+    c.is_alphabetic() && !c.is_uppercase()
+}
+
+pub fn is_var_start(c: char) -> bool {
+    c.is_uppercase() || c == '_'
+}
+
+impl<'input> Lexer<'input> {
+    pub fn new(input: &'input str) -> Self {
+        Lexer {
+            input,
+            chars: input.char_indices().peekable(),
+            double_quotes: DoubleQuotes::default(),
+        }
+    }
+
+    pub fn double_quotes(mut self, double_quotes: DoubleQuotes) -> Self {
+        self.double_quotes = double_quotes;
+        self
+    }
+
+    fn peek_char(&mut self) -> Option<char> {
+        self.chars.peek().map(|&(_, c)| c)
+    }
+
+    fn offset(&mut self) -> usize {
+        match self.chars.peek() {
+            Some(&(i, _)) => i,
+            None => self.input.len(),
+        }
+    }
+
+    fn take_while<F: Fn(char) -> bool>(&mut self, start: usize, f: F) -> &'input str {
+        while let Some(c) = self.peek_char() {
+            if !f(c) {
+                break;
+            }
+
+            self.chars.next();
+        }
+
+        let end = self.offset();
+        &self.input[start..end]
+    }
+
+    // block comments do not nest, as in ISO: the first `*/` closes the comment
+    fn skip_layout(&mut self) -> Result<(), SyntaxError> {
+        while let Some(c) = self.peek_char() {
+            if c.is_whitespace() {
+                self.chars.next();
+            } else if c == '%' {
+                let start = self.offset();
+                self.take_while(start, |c| c != '\n');
+            } else if c == '/' && self.input[self.offset()..].starts_with("/*") {
+                let start = self.offset();
+
+                match self.input[start + 2..].find("*/") {
+                    None => return Err(SyntaxError::UnterminatedComment(start)),
+                    Some(i) => {
+                        let end = start + 2 + i + 2;
+                        while self.offset() < end {
+                            self.chars.next();
+                        }
+                    }
+                }
+            } else {
+                break;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn name_or_functor(&mut self, start: usize, name: String) -> Spanned {
+        if self.peek_char() == Some('(') {
+            self.chars.next();
+            let end = self.offset();
+            return Ok((start, Tok::Functor(name), end));
+        }
+
+        let end = self.offset();
+        Ok((start, Tok::Name(name), end))
+    }
+
+    fn number(&mut self, start: usize) -> Spanned {
+        let digits = self.take_while(start, |c| c.is_ascii_digit());
+
+        if digits == "0" && self.peek_char() == Some('\'') {
+            self.chars.next();
+            let code = self.char_code(start)?;
+            return Ok((start, Tok::Int(code as u64), self.offset()));
+        }
+
+        let mut lookahead = self.chars.clone();
+        let is_float = match (lookahead.next(), lookahead.next()) {
+            (Some((_, '.')), Some((_, c))) => c.is_ascii_digit(),
+            _ => false,
+        };
+
+        if is_float {
+            self.chars.next();
+            let frac_start = self.offset();
+            self.take_while(frac_start, |c| c.is_ascii_digit());
+            self.exponent();
+
+            let end = self.offset();
+            let value = self.input[start..end].parse().unwrap();
+            return Ok((start, Tok::Float(value), end));
+        }
+
+        let end = self.offset();
+        match self.input[start..end].parse() {
+            Ok(value) if value <= i64::MIN.unsigned_abs() => Ok((start, Tok::Int(value), end)),
+            _ => Err(SyntaxError::IntegerOverflow(start)),
+        }
+    }
+
+    // the character after `0'`, where a quote may be written doubled and escapes apply
+    fn char_code(&mut self, start: usize) -> Result<char, SyntaxError> {
+        match self.chars.next() {
+            None => Err(SyntaxError::UnexpectedEnd(start)),
+            Some((_, '\'')) => {
+                if self.peek_char() == Some('\'') {
+                    self.chars.next();
+                }
+
+                Ok('\'')
+            }
+            Some((i, '\\')) => match self.escape(i)? {
+                Some(c) => Ok(c),
+                None => Err(SyntaxError::UndefinedEscape('\n', i)),
+            },
+            Some((_, c)) => Ok(c),
+        }
+    }
+
+    fn exponent(&mut self) {
+        let mut lookahead = self.chars.clone();
+
+        match lookahead.next() {
+            Some((_, 'e')) | Some((_, 'E')) => (),
+            _ => return,
+        }
+
+        let digits = match lookahead.next() {
+            Some((_, '+')) | Some((_, '-')) => lookahead.next(),
+            c => c,
+        };
+
+        if let Some((_, c)) = digits {
+            if c.is_ascii_digit() {
+                self.chars.next();
+                if let Some('+') | Some('-') = self.peek_char() {
+                    self.chars.next();
+                }
+
+                let exp_start = self.offset();
+                self.take_while(exp_start, |c| c.is_ascii_digit());
+            }
+        }
+    }
+
+    fn quoted(&mut self, start: usize, quote: char) -> Result<String, SyntaxError> {
+        let mut text = String::new();
+
+        loop {
+            match self.chars.next() {
+                None => return Err(SyntaxError::UnterminatedQuoted(start)),
+                Some((_, c)) if c == quote => {
+                    if self.peek_char() == Some(quote) {
+                        self.chars.next();
+                        text.push(quote);
+                    } else {
+                        return Ok(text);
+                    }
+                }
+                Some((i, '\\')) => {
+                    if let Some(c) = self.escape(i)? {
+                        text.push(c);
+                    }
+                }
+                Some((_, c)) => text.push(c),
+            }
+        }
+    }
+
+    fn escape(&mut self, start: usize) -> Result<Option<char>, SyntaxError> {
+        let c = match self.chars.next() {
+            None => return Err(SyntaxError::UnterminatedQuoted(start)),
+            Some((_, c)) => c,
+        };
+
+        let escaped = match c {
+            'n' => '\n',
+            't' => '\t',
+            'r' => '\r',
+            'a' => '\x07',
+            'b' => '\x08',
+            'f' => '\x0c',
+            'v' => '\x0b',
+            'e' => '\x1b',
+            's' => ' ',
+            '0'..='7' => return self.numeric_escape(start, c, 8).map(Some),
+            'x' => return self.numeric_escape(start, '0', 16).map(Some),
+            '\n' => return Ok(None),
+            '\\' | '\'' | '"' | '`' => c,
+            _ => return Err(SyntaxError::UndefinedEscape(c, start)),
+        };
+
+        Ok(Some(escaped))
+    }
+
+    fn numeric_escape(
+        &mut self,
+        start: usize,
+        first: char,
+        radix: u32,
+    ) -> Result<char, SyntaxError> {
+        let mut digits = String::new();
+        digits.push(first);
+
+        loop {
+            match self.chars.next() {
+                Some((_, '\\')) => break,
+                Some((_, c)) if c.is_digit(radix) => digits.push(c),
+                Some((_, c)) => return Err(SyntaxError::UndefinedEscape(c, start)),
+                None => return Err(SyntaxError::UnterminatedQuoted(start)),
+            }
+        }
+
+        u32::from_str_radix(&digits, radix)
+            .ok()
+            .and_then(std::char::from_u32)
+            .ok_or(SyntaxError::UndefinedEscape(first, start))
+    }
+
+    fn symbol(&mut self, start: usize) -> Spanned {
+        let text = self.take_while(start, is_symbol_char);
+
+        if text == "." {
+            match self.peek_char() {
+                None | Some('%') => return Ok((start, Tok::End, start + 1)),
+                Some(c) if c.is_whitespace() => return Ok((start, Tok::End, start + 1)),
+                _ => (),
+            }
+        }
+
+        self.name_or_functor(start, String::from(text))
+    }
+}
+
+impl<'input> Iterator for Lexer<'input> {
+    type Item = Spanned;
+
+    fn next(&mut self) -> Option<Spanned> {
+        if let Err(e) = self.skip_layout() {
+            return Some(Err(e));
+        }
+
+        let (start, c) = self.chars.next()?;
+        let end = start + c.len_utf8();
+
+        let tok = match c {
+            '(' => Ok((start, Tok::OpenParen, end)),
+            ')' => Ok((start, Tok::CloseParen, end)),
+            '[' => Ok((start, Tok::OpenList, end)),
+            ']' => Ok((start, Tok::CloseList, end)),
+            '{' => Ok((start, Tok::OpenCurly, end)),
+            '}' => Ok((start, Tok::CloseCurly, end)),
+            ',' => Ok((start, Tok::Comma, end)),
+            '|' => Ok((start, Tok::Bar, end)),
+            '!' | ';' => self.name_or_functor(start, c.to_string()),
+            '\'' => match self.quoted(start, '\'') {
+                Ok(name) => self.name_or_functor(start, name),
+                Err(e) => Err(e),
+            },
+            '"' => self.quoted(start, '"').map(|text| {
+                let tok = match self.double_quotes {
+                    DoubleQuotes::Codes => Tok::Codes(text),
+                    DoubleQuotes::Chars => Tok::Chars(text),
+                    DoubleQuotes::Atom => Tok::Name(text),
+                    DoubleQuotes::String => Tok::Str(text),
+                };
+
+                (start, tok, self.offset())
+            }),
+            c if c.is_ascii_digit() => self.number(start),
+            c if is_var_start(c) => {
+                let name = self.take_while(start, is_alnum);
+                let tok = match name {
+                    "_" => Tok::Anonymous,
+                    name => Tok::Var(String::from(name)),
+                };
+                Ok((start, tok, self.offset()))
+            }
+            c if is_atom_start(c) => {
+                let name = self.take_while(start, is_alnum);
+                self.name_or_functor(start, String::from(name))
+            }
+            c if is_symbol_char(c) => self.symbol(start),
+            c => Err(SyntaxError::UnexpectedChar(c, start)),
+        };
+
+        Some(tok)
+    }
+}
+
+impl SyntaxError {
+    pub fn position(&self) -> usize {
+        match *self {
+            SyntaxError::UnexpectedChar(_, i)
+            | SyntaxError::UnterminatedQuoted(i)
+            | SyntaxError::UnterminatedComment(i)
+            | SyntaxError::UndefinedEscape(_, i)
+            | SyntaxError::IntegerOverflow(i)
+            | SyntaxError::OperatorExpected(i)
+            | SyntaxError::OperatorClash(i)
+            | SyntaxError::UnexpectedEnd(i)
+            | SyntaxError::NotCallable(i) => i,
+        }
+    }
+}
+
+impl ParseError {
+    pub fn new(input: &str, error: LalrpopError<usize, Tok, SyntaxError>) -> Self {
+        let (position, lexeme, expected, cause) = match error {
+            LalrpopError::InvalidToken { location } => (location, None, Vec::new(), None),
+            LalrpopError::UnrecognizedEOF { location, expected } => {
+                (location, None, expected, None)
+            }
+            LalrpopError::UnrecognizedToken {
+                token: (l, _, r),
+                expected,
+            } => (l, Some(String::from(&input[l..r])), expected, None),
+            LalrpopError::ExtraToken { token: (l, _, r) } => {
+                (l, Some(String::from(&input[l..r])), Vec::new(), None)
+            }
+            LalrpopError::User { error } => (error.position(), None, Vec::new(), Some(error)),
+        };
+
+        let (line, column) = line_column(input, position);
+
+        let mut names: Vec<String> = Vec::new();
+        for name in expected.iter().map(|t| expected_name(t)) {
+            if !names.contains(&name) {
+                names.push(name);
+            }
+        }
+
+        ParseError {
+            line,
+            column,
+            lexeme,
+            expected: names,
+            cause,
+        }
+    }
+
+    // the error in a part of some input, placed where that part starts in the whole
+    pub(crate) fn offset(mut self, input: &str, start: usize) -> Self {
+        let (line, column) = line_column(input, start);
+        if self.line == 1 {
+            self.column += column - 1;
+        }
+        self.line += line - 1;
+        self
+    }
+}
+
+// 1-based line and column of a byte offset into the input
+pub fn line_column(input: &str, position: usize) -> (usize, usize) {
+    let before = &input[..position.min(input.len())];
+    let line = before.matches('\n').count() + 1;
+    let column = before.chars().rev().take_while(|&c| c != '\n').count() + 1;
+
+    (line, column)
+}
+
+// the grammar's names for terminals are either quoted punctuation or token kinds
+fn expected_name(terminal: &str) -> String {
+    match terminal.trim_matches('"') {
+        "end" => String::from("`.`"),
+        "Name" => String::from("atom"),
+        "Functor" => String::from("functor"),
+        "Var" | "_" => String::from("variable"),
+        "Int" | "Float" => String::from("number"),
+        "Codes" | "Chars" | "Str" => String::from("string"),
+        punct => format!("`{}`", punct),
+    }
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), std::fmt::Error> {
+        write!(
+            f,
+            "syntax error at line {}, column {}: ",
+            self.line, self.column
+        )?;
+
+        match (&self.cause, &self.lexeme) {
+            (Some(cause), _) => write!(f, "{}", cause)?,
+            (None, Some(lexeme)) => write!(f, "unexpected `{}`", lexeme)?,
+            (None, None) => write!(f, "unexpected end of input")?,
+        }
+
+        if !self.expected.is_empty() {
+            write!(f, ", expected {}", self.expected.join(", "))?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Display for SyntaxError {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), std::fmt::Error> {
+        match self {
+            SyntaxError::UnexpectedChar(c, _) => write!(f, "unexpected character `{}`", c),
+            SyntaxError::UnterminatedQuoted(_) => write!(f, "unterminated quoted text"),
+            SyntaxError::UnterminatedComment(_) => write!(f, "unterminated block comment"),
+            SyntaxError::UndefinedEscape(c, _) => write!(f, "undefined escape sequence `\\{}`", c),
+            SyntaxError::IntegerOverflow(_) => write!(f, "integer literal is out of range"),
+            SyntaxError::OperatorExpected(_) => write!(f, "operator expected"),
+            SyntaxError::OperatorClash(_) => write!(f, "operator priority clash"),
+            SyntaxError::UnexpectedEnd(_) => write!(f, "unexpected end of term"),
+            SyntaxError::NotCallable(_) => write!(f, "clause is not callable"),
+        }
+    }
+}
@@ -1,45 +1,102 @@
+mod arith;
 pub mod ast;
-
-use self::ast::{Assertion, Atom, Clause, Const, Term, Var};
+mod builtins;
+pub mod convert;
+pub mod database;
+pub mod dcg;
+mod debugger;
+pub mod engine;
+mod errors;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod flags;
+pub mod foreign;
+mod format;
+pub mod json;
+pub mod lexer;
+pub mod library;
+pub mod machine;
+pub mod ops;
+pub mod pool;
+pub mod ports;
+pub mod profiler;
+mod random;
+pub mod reader;
+#[cfg(feature = "serde")]
+mod serialize;
+pub mod stream;
+pub mod writer;
+
+use self::ast::{Assertion, Atom, Clause, Const, Sentence, Term, Var};
+use self::database::{DatabaseError, Key, Warning};
+use self::errors::{is_halt, type_error};
+use self::lexer::{line_column, ParseError};
+use self::machine::{Machine, Query};
+use self::writer::Writer;
 use lalrpop_util::lalrpop_mod;
-use std::collections::HashMap;
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
 use std::fmt::{Display, Formatter};
-use std::io::Write;
+use std::hash::Hash;
+use std::io::{self, BufRead, Write};
+
+lalrpop_mod!(#[allow(clippy::all, unused_parens)] pub parser);
+
+// read a term from Prolog text with the standard operators, panicking on syntax errors
+#[macro_export]
+macro_rules! term {
+    ($text:expr) => {
+        $crate::parser::TermParser::new()
+            .parse(
+                &$crate::ops::OpTable::default(),
+                $crate::lexer::Lexer::new($text),
+            )
+            .unwrap_or_else(|e| panic!("{}", $crate::lexer::ParseError::new($text, e)))
+    };
+}
 
-lalrpop_mod!(pub parser);
+// the bindings of variables, the attributes of those still unbound, by module, the
+// global variables set by b_setval/2, and the trail of changes made to them, which
+// backtracking undoes
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Environment(
+    HashMap<Var, Term>,
+    HashMap<Var, Vec<(String, Term)>>,
+    HashMap<String, Term>,
+    Trail,
+);
+
+// what each change to an environment replaced, the latest last; a mark is the count of
+// changes made, including those forgotten once nothing could backtrack past them
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+struct Trail {
+    changes: Vec<Change>,
+    forgotten: usize,
+}
 
 #[derive(Debug, Clone, PartialEq, Eq)]
-pub struct Environment(HashMap<Var, Term>);
+enum Change {
+    Binding(Var, Option<Term>),
+    Attributes(Var, Option<Vec<(String, Term)>>),
+    Global(String, Option<Term>),
+}
 pub type KnowledgeBase = Vec<Assertion>;
 pub type Assertions = Vec<Assertion>;
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Clone)]
 enum UnifyErr {
     NoUnify,
-}
-
-#[derive(Debug, Copy, Clone)]
-enum SolveErr {
-    NoSolution,
-}
-
-#[derive(Debug, Clone)]
-enum Solution {
-    Answer(String),
-    Choicepoint(String, Vec<Choicepoint>),
-}
-
-#[derive(Debug, Clone)]
-struct Choicepoint {
-    assertions: KnowledgeBase,
-    environment: Environment,
-    clause: Clause,
-    depth: usize,
+    // binding the variable to the term would make it cyclic
+    Cyclic(Var, Term),
 }
 
 impl Display for Environment {
     fn fmt(&self, f: &mut Formatter) -> Result<(), std::fmt::Error> {
-        let mut env: Vec<_> = self.0.iter().filter(|(Var(_, n), _)| *n == 0).collect();
+        let mut env: Vec<_> = self
+            .0
+            .iter()
+            .filter(|(x, _)| x.1 == 0 && !x.is_anonymous())
+            .collect();
         env.sort();
         let mut response = String::from("\n");
         let last = env.last().cloned();
@@ -60,79 +117,155 @@ impl Display for Environment {
 }
 
 impl Environment {
-    fn new() -> Self {
-        Environment(HashMap::new())
+    pub(crate) fn new() -> Self {
+        Environment(
+            HashMap::new(),
+            HashMap::new(),
+            HashMap::new(),
+            Trail::default(),
+        )
     }
 
     fn insert(&mut self, x: Var, t: Term) {
-        self.0.insert(x, t);
+        let old = self.0.insert(x.clone(), t);
+        self.3.changes.push(Change::Binding(x, old));
+    }
+
+    fn save_attributes(&mut self, x: &Var) {
+        let old = self.1.get(x).cloned();
+        self.3.changes.push(Change::Attributes(x.clone(), old));
     }
 
-    fn lookup(&self, x: &Var) -> Term {
-        match self.0.get(x) {
-            Some(t) => t.clone(),
-            None => Term::Var(x.clone()),
+    // where the trail is now, to undo the changes made after it on backtracking
+    pub(crate) fn mark(&self) -> usize {
+        self.3.forgotten + self.3.changes.len()
+    }
+
+    pub(crate) fn undo_to(&mut self, mark: usize) {
+        while self.mark() > mark {
+            match self.3.changes.pop().unwrap() {
+                Change::Binding(x, old) => restore(&mut self.0, x, old),
+                Change::Attributes(x, old) => restore(&mut self.1, x, old),
+                Change::Global(name, old) => restore(&mut self.2, name, old),
+            }
         }
     }
 
-    fn substitute_term(&self, t: &Term) -> Term {
-        if let Term::Const(_) = t {
-            return t.clone();
+    // drop the changes made before a mark, once there is nothing that could backtrack
+    // to before it
+    pub(crate) fn forget_before(&mut self, mark: usize) {
+        let n = mark
+            .saturating_sub(self.3.forgotten)
+            .min(self.3.changes.len());
+        self.3.changes.drain(..n);
+        self.3.forgotten += n;
+    }
+
+    // follow the bindings of a variable until reaching a value or an unbound variable
+    pub(crate) fn deref(&self, t: &Term) -> Term {
+        self.deref_owned(t.clone())
+    }
+
+    // as deref, for a term that is given up, so that one which is not a variable is not
+    // cloned, as when walking down a list
+    pub(crate) fn deref_owned(&self, mut t: Term) -> Term {
+        while let Term::Var(x) = &t {
+            match self.0.get(x) {
+                Some(bound) => t = bound.clone(),
+                None => break,
+            }
         }
 
+        t
+    }
+
+    // point every variable on the chain of bindings from a variable straight at where the
+    // chain ends, so it is walked once; the trail has the chain as it was, for
+    // backtracking to restore
+    fn shorten(&mut self, t: &Term) {
+        let mut chain = Vec::new();
         let mut t = t.clone();
-        let mut temp = t;
 
-        loop {
-            match temp {
-                Term::Var(x) => {
-                    t = self.lookup(&x);
+        while let Term::Var(x) = t {
+            match self.0.get(&x) {
+                Some(bound) => {
+                    t = bound.clone();
+                    chain.push(x);
+                }
+                None => {
+                    t = Term::Var(x);
+                    break;
+                }
+            }
+        }
 
-                    if Term::Var(x) == t {
-                        return t;
-                    }
+        if chain.len() > 1 {
+            for x in chain {
+                self.insert(x, t.clone());
+            }
+        }
+    }
 
-                    temp = t;
-                }
-                Term::Atom(mut a) => {
-                    let mut next_atoms = Vec::new();
-                    self.substitute_atom(&mut a, &mut next_atoms);
+    // the term with every bound variable in it replaced by its value; the subterms still
+    // to be gone through are kept on a stack rather than recursed into, so terms built up
+    // over long chains of bindings, like long lists, cannot overflow the stack
+    pub(crate) fn substitute_term(&self, t: &Term) -> Term {
+        let mut t = self.deref(t);
 
-                    while let Some(a) = next_atoms.pop() {
-                        self.substitute_atom(a, &mut next_atoms);
-                    }
+        if let Term::Atom(ref mut a) = t {
+            let mut next_atoms = vec![a];
 
-                    return Term::Atom(a);
-                }
-                Term::Const(_) => return temp,
+            while let Some(a) = next_atoms.pop() {
+                self.substitute_atom(a, &mut next_atoms);
             }
         }
+
+        t
+    }
+
+    // standard order of terms as bound in this environment, for compare/3 and sorting
+    pub fn compare_terms(&self, t1: &Term, t2: &Term) -> Ordering {
+        self.substitute_term(t1).cmp(&self.substitute_term(t2))
     }
 
     fn substitute_atom<'a>(&self, a: &'a mut Atom, next: &mut Vec<&'a mut Atom>) {
         for arg in &mut a.args {
-            match arg {
-                ref t @ Term::Var(_) => {
-                    *arg = self.substitute_term(*t);
-                }
-                Term::Atom(ref mut a) => next.push(a),
-                _ => (),
+            if let Term::Var(_) = arg {
+                *arg = self.deref(arg);
+            }
+
+            if let Term::Atom(a) = arg {
+                next.push(a);
             }
         }
     }
 
-    fn unify_terms(self, t1: &Term, t2: &Term) -> Result<Self, UnifyErr> {
+    // bind the variables of two terms so they are equal; what a failed unification
+    // bound before failing is left for the caller to undo
+    pub(crate) fn unify(&mut self, t1: &Term, t2: &Term) -> Result<(), UnifyErr> {
+        self.shorten(t1);
+        self.shorten(t2);
+
         match (self.substitute_term(t1), self.substitute_term(t2)) {
-            (ref t1, ref t2) if t1 == t2 => Ok(self),
+            (ref t1, ref t2) if t1 == t2 => Ok(()),
+            // of two variables the younger, from the deeper clause, is bound to the older,
+            // which is the one that tends to stay around
+            (Term::Var(x), Term::Var(y)) => {
+                if x.1 < y.1 {
+                    self.insert(y, Term::Var(x));
+                } else {
+                    self.insert(x, Term::Var(y));
+                }
+
+                Ok(())
+            }
             (Term::Var(y), t) | (t, Term::Var(y)) => {
                 if occurs(&y, &t) {
-                    return Err(UnifyErr::NoUnify);
+                    return Err(UnifyErr::Cyclic(y, t));
                 }
 
-                let mut env = self;
-                env.insert(y, t);
-
-                Ok(env)
+                self.insert(y, t);
+                Ok(())
             }
             (
                 Term::Atom(Atom {
@@ -147,177 +280,233 @@ impl Environment {
                 }),
             ) if c1 == c2 => {
                 let mut next_atoms = Vec::new();
-                let mut env = self.unify_list_level(ts1, ts2, &mut next_atoms)?;
+                self.unify_list_level(ts1, ts2, &mut next_atoms)?;
 
                 while let Some((a1, a2)) = next_atoms.pop() {
                     if a1.name != a2.name {
                         return Err(UnifyErr::NoUnify);
                     }
 
-                    let next_env = env.unify_list_level(&a1.args, &a2.args, &mut next_atoms)?;
-                    env = next_env;
+                    self.unify_list_level(&a1.args, &a2.args, &mut next_atoms)?;
                 }
 
-                Ok(env)
+                Ok(())
             }
             _ => Err(UnifyErr::NoUnify),
         }
     }
 
     fn unify_list_level<'a>(
-        self,
+        &mut self,
         l1: &'a [Term],
         l2: &'a [Term],
         next_atoms: &mut Vec<(&'a Atom, &'a Atom)>,
-    ) -> Result<Environment, UnifyErr> {
+    ) -> Result<(), UnifyErr> {
         if l1.len() != l2.len() {
             return Err(UnifyErr::NoUnify);
         }
 
-        let terms = l1.iter().zip(l2.iter());
-        let mut env = self;
-
-        for (t1, t2) in terms {
+        for (t1, t2) in l1.iter().zip(l2.iter()) {
             if let (Term::Atom(ref a1), Term::Atom(ref a2)) = (t1, t2) {
                 next_atoms.push((a1, a2));
             } else {
-                env = env.unify_terms(t1, t2)?;
+                self.unify(t1, t2)?;
             }
         }
 
-        Ok(env)
+        Ok(())
     }
 
-    fn unify_lists(&self, l1: &[Term], l2: &[Term]) -> Result<Self, UnifyErr> {
+    fn unify_lists(&mut self, l1: &[Term], l2: &[Term]) -> Result<(), UnifyErr> {
         if l1.len() != l2.len() {
             return Err(UnifyErr::NoUnify);
         }
 
-        l1.iter()
-            .zip(l2.iter())
-            .fold(Ok(self.clone()), |env, (t1, t2)| env?.unify_terms(t1, t2))
+        for (t1, t2) in l1.iter().zip(l2.iter()) {
+            self.unify(t1, t2)?;
+        }
+
+        Ok(())
     }
 
-    fn unify_atoms(&self, a1: &Atom, a2: &Atom) -> Result<Self, UnifyErr> {
-        if a1.name == a2.name {
-            return self.unify_lists(&a1.args, &a2.args);
-        }
+    // the variables unifying two terms would bind, along with the variables they would be
+    // bound to, or nothing if the terms don't unify; the bindings are undone again
+    pub(crate) fn unifier(&mut self, t1: &Term, t2: &Term) -> Option<Vec<Var>> {
+        let mark = self.mark();
+        let vars = self.unify(t1, t2).ok().map(|()| self.bound_since(mark));
+        self.undo_to(mark);
+        vars
+    }
 
-        Err(UnifyErr::NoUnify)
+    pub(crate) fn get_attr(&self, x: &Var, module: &str) -> Option<&Term> {
+        let attributes = self.1.get(x)?;
+        attributes
+            .iter()
+            .find(|(m, _)| m == module)
+            .map(|(_, value)| value)
     }
 
-    fn reduce_atom(
-        &self,
-        n: usize,
-        a: &Atom,
-        asrl: &[Assertion],
-    ) -> Option<(KnowledgeBase, Environment, Clause)> {
-        let mut asrl = asrl.to_vec();
-
-        while let Some(Assertion {
-            head: ref b,
-            clause: ref lst,
-        }) = asrl.pop()
-        {
-            let next_env = self.unify_atoms(a, &renumber_atom(n, b));
-
-            match next_env {
-                Ok(next_env) => {
-                    return Some((
-                        asrl,
-                        next_env,
-                        lst.iter().map(|a| renumber_atom(n, a)).collect(),
-                    ));
-                }
-                Err(UnifyErr::NoUnify) => {
-                    continue;
-                }
+    pub(crate) fn put_attr(&mut self, x: Var, module: &str, value: Term) {
+        self.save_attributes(&x);
+        let attributes = self.1.entry(x).or_default();
+
+        match attributes.iter_mut().find(|(m, _)| m == module) {
+            Some((_, old)) => *old = value,
+            None => attributes.push((String::from(module), value)),
+        }
+    }
+
+    pub(crate) fn del_attr(&mut self, x: &Var, module: &str) {
+        if self.1.contains_key(x) {
+            self.save_attributes(x);
+        }
+
+        if let Some(attributes) = self.1.get_mut(x) {
+            attributes.retain(|(m, _)| m != module);
+
+            if attributes.is_empty() {
+                self.1.remove(x);
             }
         }
+    }
+
+    pub(crate) fn is_attributed(&self, x: &Var) -> bool {
+        self.1.contains_key(x)
+    }
+
+    // take the attributes off the attributed variables that have since been bound
+    pub(crate) fn take_bound_attributes(&mut self) -> Vec<(Var, Vec<(String, Term)>)> {
+        let mut bound: Vec<Var> = self
+            .1
+            .keys()
+            .filter(|x| self.0.contains_key(x))
+            .cloned()
+            .collect();
+        bound.sort();
+
+        bound
+            .into_iter()
+            .map(|x| {
+                self.save_attributes(&x);
+                let attributes = self.1.remove(&x).unwrap();
+                (x, attributes)
+            })
+            .collect()
+    }
+
+    pub(crate) fn set_attributes(&mut self, x: Var, attributes: Vec<(String, Term)>) {
+        self.save_attributes(&x);
+        self.1.insert(x, attributes);
+    }
 
-        None
+    pub(crate) fn get_global(&self, name: &str) -> Option<&Term> {
+        self.2.get(name)
     }
 
-    fn solve(
-        self,
-        mut ch: Vec<Choicepoint>,
-        kb: &[Assertion],
-        asrl: &[Assertion],
-        mut c: Clause,
-        mut n: usize,
-    ) -> Result<Solution, SolveErr> {
-        let mut env = self;
-        let mut asrl = asrl;
-        let mut next_asrl = Some(asrl.to_vec());
+    pub(crate) fn set_global(&mut self, name: &str, value: Term) {
+        let old = self.2.insert(String::from(name), value);
+        self.3.changes.push(Change::Global(String::from(name), old));
+    }
+
+    pub(crate) fn remove_global(&mut self, name: &str) {
+        let old = self.2.remove(name);
+        self.3.changes.push(Change::Global(String::from(name), old));
+    }
 
-        while let Some(a) = c.pop() {
-            let Atom {
-                name: Const(ref atom_name),
-                arity,
-                ..
-            } = a;
+    // the variables bound since a mark that were unbound before it, along with the
+    // variables they are bound to
+    fn bound_since(&self, mark: usize) -> Vec<Var> {
+        let mut vars = Vec::new();
 
-            if atom_name == "halt" && arity == 0 {
-                std::process::exit(0);
+        for change in &self.3.changes[mark - self.3.forgotten..] {
+            if let Change::Binding(x, None) = change {
+                vars.push(x.clone());
+                if let Some(Term::Var(y)) = self.0.get(x) {
+                    vars.push(y.clone());
+                }
             }
+        }
+
+        vars.sort();
+        vars.dedup();
+        vars
+    }
 
-            asrl = match next_asrl {
-                None => kb,
-                Some(ref assertions) => assertions,
-            };
-
-            match env.reduce_atom(n, &a, asrl) {
-                None => match ch.pop() {
-                    None => return Err(SolveErr::NoSolution),
-                    Some(Choicepoint {
-                        assertions: ch_asrl,
-                        environment: next_env,
-                        clause: gs,
-                        depth: next_n,
-                    }) => {
-                        env = next_env;
-                        next_asrl = Some(ch_asrl);
-                        c = gs;
-                        n = next_n;
+    pub(crate) fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    // drop the bindings that can no longer be reached from the given variables, nor from
+    // the variables of the query itself or what backtracking would put back, returning
+    // how many were dropped
+    pub(crate) fn collect_garbage(&mut self, roots: Vec<Var>) -> usize {
+        let mut reachable = HashSet::new();
+        let mut next: Vec<Var> = self.0.keys().filter(|x| x.1 == 0).cloned().collect();
+        next.extend(roots);
+
+        for change in &self.3.changes {
+            match change {
+                Change::Binding(_, Some(t)) | Change::Global(_, Some(t)) => {
+                    next.extend(t.variables().into_iter().cloned())
+                }
+                Change::Attributes(x, Some(attributes)) => {
+                    next.push(x.clone());
+                    for (_, value) in attributes {
+                        next.extend(value.variables().into_iter().cloned());
                     }
-                },
-                Some((ch_asrl, next_env, mut d)) => {
-                    let mut ch_clause = c.clone();
-                    ch_clause.push(a);
-
-                    let mut ch_buffer = vec![Choicepoint {
-                        assertions: ch_asrl,
-                        environment: env,
-                        clause: ch_clause,
-                        depth: n,
-                    }];
-
-                    ch_buffer.extend_from_slice(&ch);
-                    d.extend_from_slice(&c);
-
-                    env = next_env;
-                    ch = ch_buffer;
-                    next_asrl = None;
-                    c = d;
-                    n += 1;
                 }
+                _ => (),
+            }
+        }
+
+        for (x, attributes) in &self.1 {
+            next.push(x.clone());
+            for (_, value) in attributes {
+                next.extend(value.variables().into_iter().cloned());
             }
         }
 
-        Ok(match (&env.to_string()[..], &ch[..]) {
-            (answer, []) => Solution::Answer(String::from(answer)),
-            (answer, _) => {
-                let answer = if answer == "Yes" { "Yes " } else { answer };
-                Solution::Choicepoint(String::from(answer), ch)
+        for value in self.2.values() {
+            next.extend(value.variables().into_iter().cloned());
+        }
+
+        while let Some(x) = next.pop() {
+            if let Some(t) = self.0.get(&x) {
+                next.extend(
+                    t.variables()
+                        .into_iter()
+                        .filter(|y| !reachable.contains(*y))
+                        .cloned(),
+                );
             }
-        })
+            reachable.insert(x);
+        }
+
+        let before = self.0.len();
+        self.0.retain(|x, _| reachable.contains(x));
+        before - self.0.len()
+    }
+
+    pub(crate) fn unify_atoms(&mut self, a1: &Atom, a2: &Atom) -> Result<(), UnifyErr> {
+        if a1.name == a2.name {
+            return self.unify_lists(&a1.args, &a2.args);
+        }
+
+        Err(UnifyErr::NoUnify)
     }
 }
 
+fn restore<K: Eq + Hash, V>(map: &mut HashMap<K, V>, key: K, old: Option<V>) {
+    match old {
+        Some(value) => map.insert(key, value),
+        None => map.remove(&key),
+    };
+}
+
 fn occurs(x: &Var, t: &Term) -> bool {
     match t {
         Term::Var(y) => x == y,
-        Term::Const(_) => false,
+        Term::Const(_) | Term::Number(_) | Term::String(_) => false,
         Term::Atom(a) => occurs_atom(x, a),
     }
 }
@@ -338,15 +527,45 @@ fn occurs_atom(x: &Var, a: &Atom) -> bool {
     false
 }
 
-fn renumber_term(n: usize, t: &Term) -> Term {
+// give the variables of a term fresh names, so that variables which only differed in
+// depth stay apart once the term is stored as a clause and renumbered again
+pub(crate) fn rename_term(t: &Term) -> Term {
+    let vars = t.variables();
+    let index: HashMap<&Var, usize> = vars.iter().enumerate().map(|(i, &x)| (x, i)).collect();
+
+    map_vars(t, &mut |x| {
+        Term::Var(Var::new(&format!("_V{}", index[x]), 0))
+    })
+}
+
+// replace every variable of a term by what it maps to, from left to right
+pub(crate) fn map_vars<F: FnMut(&Var) -> Term>(t: &Term, f: &mut F) -> Term {
+    let mut t = t.clone();
+    let mut next = vec![&mut t];
+
+    while let Some(t) = next.pop() {
+        match t {
+            Term::Var(x) => {
+                let mapped = f(x);
+                *t = mapped;
+            }
+            Term::Atom(a) => next.extend(a.args.iter_mut().rev()),
+            _ => (),
+        }
+    }
+
+    t
+}
+
+pub(crate) fn renumber_term(n: usize, t: &Term) -> Term {
     match t {
         Term::Var(Var(x, _)) => Term::Var(Var(x.clone(), n)),
-        c @ Term::Const(_) => c.clone(),
+        c @ Term::Const(_) | c @ Term::Number(_) | c @ Term::String(_) => c.clone(),
         Term::Atom(a) => Term::Atom(renumber_atom(n, a)),
     }
 }
 
-fn renumber_atom(n: usize, a: &Atom) -> Atom {
+pub(crate) fn renumber_atom(n: usize, a: &Atom) -> Atom {
     let mut a = a.clone();
     let mut next_atoms = Vec::new();
     renumber_atom_level(n, &mut a, &mut next_atoms);
@@ -362,7 +581,7 @@ fn renumber_atom_level<'a>(n: usize, a: &'a mut Atom, next: &mut Vec<&'a mut Ato
     for arg in &mut a.args {
         match arg {
             ref t @ Term::Var(_) => {
-                *arg = renumber_term(n, *t);
+                *arg = renumber_term(n, t);
             }
             Term::Atom(ref mut a) => next.push(a),
             _ => (),
@@ -370,71 +589,263 @@ fn renumber_atom_level<'a>(n: usize, a: &'a mut Atom, next: &mut Vec<&'a mut Ato
     }
 }
 
-fn continue_search(kb: &[Assertion], mut ch: Vec<Choicepoint>) -> Result<Solution, SolveErr> {
-    match ch.pop() {
-        None => Err(SolveErr::NoSolution),
-        Some(Choicepoint {
-            assertions: asrl,
-            environment: env,
-            clause: gs,
-            depth: n,
-        }) => env.solve(ch, kb, &asrl, gs, n),
+// what can go wrong for a caller of the machine; panics are left for broken invariants
+#[derive(Debug)]
+pub enum WamError {
+    Parse(ParseError),
+    // a clause that could not be added to the database
+    Compile(DatabaseError),
+    // an error term the goal raised and did not catch
+    Execution(Term),
+    // reading or writing outside the machine failed
+    Resource(io::Error),
+    // a goal called halt/0
+    Halt,
+}
+
+impl From<ParseError> for WamError {
+    fn from(e: ParseError) -> Self {
+        WamError::Parse(e)
+    }
+}
+
+impl From<DatabaseError> for WamError {
+    fn from(e: DatabaseError) -> Self {
+        WamError::Compile(e)
+    }
+}
+
+impl From<io::Error> for WamError {
+    fn from(e: io::Error) -> Self {
+        WamError::Resource(e)
+    }
+}
+
+impl Display for WamError {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), std::fmt::Error> {
+        match self {
+            WamError::Parse(e) => write!(f, "{}", e),
+            WamError::Compile(e) => write!(f, "{}", e),
+            WamError::Execution(ball) => write!(f, "uncaught exception: {}", ball),
+            WamError::Resource(e) => write!(f, "{}", e),
+            WamError::Halt => write!(f, "halt"),
+        }
+    }
+}
+
+impl std::error::Error for WamError {}
+
+// the error for a ball a goal raised and did not catch
+pub(crate) fn uncaught(ball: Term) -> WamError {
+    if is_halt(&ball) {
+        WamError::Halt
+    } else {
+        WamError::Execution(ball)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Initialization {
+    Load(Term),
+    Main(Term),
+}
+
+// warn about the clauses of a program that mention a variable only once, which is
+// usually a misspelling
+pub fn singleton_warnings(source: &str, program: &[(usize, Sentence)]) -> Vec<Warning> {
+    let mut warnings = Vec::new();
+
+    for (position, sentence) in program {
+        if let Sentence::Assertion(assertion) = sentence {
+            let vars = assertion.singletons();
+
+            if !vars.is_empty() {
+                let (line, _) = line_column(source, *position);
+                let vars = vars.iter().map(|x| x.to_string()).collect();
+                warnings.push(Warning::Singletons(Key::of(&assertion.head), vars, line));
+            }
+        }
+    }
+
+    warnings
+}
+
+// separate the directives of a loaded program from its clauses, keeping them in source
+// order; `initialization/1` goals and plain directives run once the program is loaded
+pub fn initialization_goals(
+    program: Vec<(usize, Sentence)>,
+) -> (KnowledgeBase, Vec<Initialization>) {
+    let mut kb = Vec::new();
+    let mut goals = Vec::new();
+
+    for (_, sentence) in program {
+        match sentence {
+            Sentence::Assertion(assertion) => kb.push(assertion),
+            Sentence::Directive(goal) => goals.push(initialization_directive(goal)),
+        }
+    }
+
+    (kb, goals)
+}
+
+pub(crate) fn initialization_directive(goal: Term) -> Initialization {
+    if let Term::Atom(Atom { name, args, .. }) = &goal {
+        if name.0 == "initialization" {
+            match &args[..] {
+                [goal] => return Initialization::Load(goal.clone()),
+                [goal, Term::Const(Const(when))] if when == "main" => {
+                    return Initialization::Main(goal.clone())
+                }
+                _ => (),
+            }
+        }
+    }
+
+    Initialization::Load(goal)
+}
+
+// run a goal for its side effects, reporting only whether it succeeded
+pub fn run_goal(machine: &mut Machine, goal: Term) -> Result<bool, WamError> {
+    match goal.clone().into_goals() {
+        None => Err(WamError::Execution(type_error("callable", goal))),
+        Some(mut c) => {
+            c.reverse();
+            machine.next_solution(&mut Query::new(c)).map_err(uncaught)
+        }
+    }
+}
+
+// answer a query at the toplevel: show each solution, and after one that may have
+// others, wait for `;` to ask for the next or for `.` or a blank line to stop, which
+// ends the answer with `.`; anything else gets a reminder of the actions once, and
+// stops the query the second time, so that a query typed after it is not taken for an
+// action. A query that calls halt/0 gives WamError::Halt
+pub fn answer_query<R: BufRead, W: Write>(
+    machine: &mut Machine,
+    c: Clause,
+    input: &mut R,
+    output: &mut W,
+) -> Result<(), WamError> {
+    let ops = machine.ops.clone();
+    let mut solutions = machine.solutions(c);
+
+    while let Some(solution) = solutions.next() {
+        let answer = solution.answer(&ops);
+
+        if !solutions.has_more() {
+            writeln!(output, "{}.", answer)?;
+            return Ok(());
+        }
+
+        write!(output, "{} ", answer)?;
+        output.flush()?;
+        let mut reminded = false;
+
+        loop {
+            let mut line = String::new();
+            input.read_line(&mut line)?;
+
+            match line.trim() {
+                ";" => break,
+                "" | "." => {
+                    writeln!(output, ".")?;
+                    return Ok(());
+                }
+                _ if reminded => {
+                    writeln!(output, ".")?;
+                    return Ok(());
+                }
+                action => {
+                    write!(output, "unknown action {} (; for more, . to stop) ", action)?;
+                    output.flush()?;
+                    reminded = true;
+                }
+            }
+        }
+    }
+
+    if solutions.halted() {
+        return Err(WamError::Halt);
+    }
+
+    match solutions.exception() {
+        Some(ball) => {
+            let error = Writer::new(&ops).quoted(true).write(ball);
+            writeln!(output, "Error: {}", error)?;
+        }
+        None => writeln!(output, "false.")?,
     }
+
+    Ok(())
 }
 
-pub fn solve_toplevel(interactive: bool, kb: &[Assertion], c: Clause) -> Vec<String> {
-    let env = Environment::new();
-    let asrl = kb;
-    let mut s = env.solve(Vec::new(), kb, asrl, c, 1);
+pub fn solve_toplevel(interactive: bool, machine: &mut Machine, c: Clause) -> Vec<String> {
+    let mut query = Query::new(c);
     let mut answers = Vec::new();
     let mut found = false;
 
     loop {
-        match s {
-            Err(SolveErr::NoSolution) if found => break,
-            Err(SolveErr::NoSolution) => {
-                println!("\nNo.");
+        match machine.next_solution(&mut query) {
+            Ok(true) => found = true,
+            Ok(false) => break,
+            Err(ball) if is_halt(&ball) => return answers,
+            Err(ball) => {
+                let error = format!(
+                    "Error: {}",
+                    Writer::new(&machine.ops).quoted(true).write(&ball)
+                );
+                println!("\n{}", error);
                 if !interactive {
-                    answers.push(String::from("No"))
+                    answers.push(error)
                 }
-                break;
+                return answers;
             }
-            Ok(Solution::Choicepoint(answer, ch)) => {
-                found = true;
+        }
 
-                print!("{}", answer);
-                if !interactive {
-                    answers.push(answer)
-                }
+        let answer = query.env.to_string();
 
-                std::io::stdout().flush().expect("Could not flush stdout");
+        if !query.has_alternatives() {
+            println!("\n{}.", answer);
+            if !interactive {
+                answers.push(answer)
+            }
+            return answers;
+        }
 
-                if interactive {
-                    let mut input_buffer = String::new();
-                    std::io::stdin()
-                        .read_line(&mut input_buffer)
-                        .expect("error reading input");
+        let answer = if answer == "Yes" {
+            String::from("Yes ")
+        } else {
+            answer
+        };
 
-                    match &input_buffer[..] {
-                        ";\r\n" | ";\n" => {
-                            s = continue_search(kb, ch);
-                        }
-                        _ => break,
-                    }
-                } else {
-                    s = continue_search(kb, ch);
-                }
+        print!("{}", answer);
+        if !interactive {
+            answers.push(answer)
+        }
+
+        // the answers are only shown, so losing one to a broken terminal is not an error
+        let _ = io::stdout().flush();
+
+        if interactive {
+            let mut input_buffer = String::new();
+            if io::stdin().read_line(&mut input_buffer).is_err() {
+                return answers;
             }
-            Ok(Solution::Answer(answer)) => {
-                println!("\n{}.", answer);
-                if !interactive {
-                    answers.push(answer)
-                }
-                break;
+
+            match &input_buffer[..] {
+                ";\r\n" | ";\n" => (),
+                _ => return answers,
             }
         }
     }
 
+    if !found {
+        println!("\nNo.");
+        if !interactive {
+            answers.push(String::from("No"))
+        }
+    }
+
     answers
 }
 
@@ -442,6 +853,21 @@ pub fn solve_toplevel(interactive: bool, kb: &[Assertion], c: Clause) -> Vec<Str
 mod tests {
     use super::*;
 
+    fn unify_terms(t1: &Term, t2: &Term) -> Result<Environment, UnifyErr> {
+        let mut env = Environment::new();
+        env.unify(t1, t2).map(|()| env)
+    }
+
+    fn unify_atoms(a1: &Atom, a2: &Atom) -> Result<Environment, UnifyErr> {
+        let mut env = Environment::new();
+        env.unify_atoms(a1, a2).map(|()| env)
+    }
+
+    fn unify_lists(l1: &[Term], l2: &[Term]) -> Result<Environment, UnifyErr> {
+        let mut env = Environment::new();
+        env.unify_lists(l1, l2).map(|()| env)
+    }
+
     fn unification_result(env: &Environment, results: &mut [(Var, Term)]) {
         let mut env: Vec<_> = env.0.iter().map(|(v, t)| (v.clone(), t.clone())).collect();
         env.sort();
@@ -449,6 +875,50 @@ mod tests {
         assert_eq!(env, results);
     }
 
+    #[test]
+    fn test_substitute_term_1_succeeds() {
+        // a list built one binding at a time, as a recursive predicate builds it
+        let mut env = Environment::new();
+        for i in 0..20000 {
+            let tail = Term::Var(Var(String::from("T"), i + 1));
+            let cell = Term::compound(".", vec![Term::int(i as i64), tail]);
+            env.insert(Var(String::from("T"), i), cell);
+        }
+
+        let mut list = env.substitute_term(&Term::Var(Var(String::from("T"), 0)));
+        let mut n = 0;
+
+        while let Term::Atom(mut a) = list {
+            assert_eq!(a.args[0], Term::int(n));
+            list = a.args.pop().unwrap();
+            n += 1;
+        }
+
+        assert_eq!(n, 20000);
+        assert_eq!(list, Term::Var(Var(String::from("T"), 20000)));
+    }
+
+    #[test]
+    fn test_compare_terms_1_succeeds() {
+        let x = Term::var("X");
+        let y = Term::var("Y");
+        let env = unify_terms(&x, &Term::compound("f", vec![Term::int(1)])).unwrap();
+
+        assert_eq!(env.compare_terms(&x, &y), Ordering::Greater);
+        assert_eq!(env.compare_terms(&x, &Term::atom("z")), Ordering::Greater);
+        assert_eq!(
+            env.compare_terms(&x, &Term::compound("f", vec![Term::float(1.0)])),
+            Ordering::Greater
+        );
+        assert_eq!(
+            env.compare_terms(
+                &Term::compound("g", vec![x.clone()]),
+                &Term::compound("g", vec![x])
+            ),
+            Ordering::Equal
+        );
+    }
+
     #[test]
     fn test_unify_1_succeeds() {
         let x = Term::Atom(Atom::new(
@@ -466,7 +936,7 @@ mod tests {
             ))],
         ));
 
-        let env = Environment::new().unify_terms(&x, &f);
+        let env = unify_terms(&x, &f);
         unification_result(
             &env.unwrap(),
             &mut [(Var::new("X", 0), Term::Const(Const::new("z")))],
@@ -491,7 +961,7 @@ mod tests {
             ))],
         ));
 
-        let env = Environment::new().unify_terms(&x, &f);
+        let env = unify_terms(&x, &f);
         env.unwrap();
     }
 
@@ -506,7 +976,7 @@ mod tests {
             ))],
         ));
 
-        let env = Environment::new().unify_terms(&f, &x);
+        let env = unify_terms(&f, &x);
         unification_result(&env.unwrap(), &mut [(Var::new("X", 0), f)]);
     }
 
@@ -515,7 +985,7 @@ mod tests {
         let x = Term::Var(Var::new("X", 0));
         let y = Term::Var(Var::new("Y", 0));
 
-        let env = Environment::new().unify_terms(&x, &y);
+        let env = unify_terms(&x, &y);
         unification_result(&env.unwrap(), &mut [(Var::new("X", 0), y)]);
     }
 
@@ -524,7 +994,7 @@ mod tests {
         let x1 = Term::Var(Var::new("X", 0));
         let x2 = Term::Var(Var::new("X", 0));
 
-        let env = Environment::new().unify_terms(&x1, &x2);
+        let env = unify_terms(&x1, &x2);
         unification_result(&env.unwrap(), &mut []);
     }
 
@@ -533,7 +1003,7 @@ mod tests {
         let a1 = Term::Const(Const::new("a"));
         let a2 = Term::Const(Const::new("a"));
 
-        let env = Environment::new().unify_terms(&a1, &a2);
+        let env = unify_terms(&a1, &a2);
         unification_result(&env.unwrap(), &mut []);
     }
 
@@ -543,7 +1013,7 @@ mod tests {
         let a1 = Term::Const(Const::new("a"));
         let a2 = Term::Const(Const::new("b"));
 
-        let env = Environment::new().unify_terms(&a1, &a2);
+        let env = unify_terms(&a1, &a2);
         env.unwrap();
     }
 
@@ -564,7 +1034,7 @@ mod tests {
             ))],
         ));
 
-        let env = Environment::new().unify_terms(&x, &f);
+        let env = unify_terms(&x, &f);
         unification_result(
             &env.unwrap(),
             &mut [
@@ -602,7 +1072,7 @@ mod tests {
             ],
         ));
 
-        let env = Environment::new().unify_terms(&p1, &p2);
+        let env = unify_terms(&p1, &p2);
         unification_result(
             &env.unwrap(),
             &mut [
@@ -652,7 +1122,7 @@ mod tests {
             ],
         ));
 
-        let env = Environment::new().unify_terms(&p1, &p2);
+        let env = unify_terms(&p1, &p2);
         env.unwrap();
     }
 
@@ -679,7 +1149,7 @@ mod tests {
             ],
         ));
 
-        let env = Environment::new().unify_terms(&f1, &f2);
+        let env = unify_terms(&f1, &f2);
         unification_result(
             &env.unwrap(),
             &mut [
@@ -721,7 +1191,7 @@ mod tests {
             ],
         );
 
-        let env = Environment::new().unify_atoms(&f1, &f2);
+        let env = unify_atoms(&f1, &f2);
         unification_result(
             &env.unwrap(),
             &mut [
@@ -764,7 +1234,7 @@ mod tests {
             ],
         );
 
-        let env = Environment::new().unify_atoms(&f1, &f2);
+        let env = unify_atoms(&f1, &f2);
         env.unwrap();
     }
 
@@ -772,7 +1242,7 @@ mod tests {
     fn test_unify_10_succeeds() {
         let l1 = vec![Term::Atom(Atom::new("a", vec![]))];
         let l2 = vec![Term::Var(Var::new("X", 1))];
-        let env = Environment::new().unify_lists(&l1, &l2);
+        let env = unify_lists(&l1, &l2);
 
         unification_result(
             &env.unwrap(),
@@ -788,7 +1258,7 @@ mod tests {
             Term::Atom(Atom::new("a", vec![])),
         ];
         let l2 = vec![Term::Var(Var::new("X", 0))];
-        let env = Environment::new().unify_lists(&l1, &l2);
+        let env = unify_lists(&l1, &l2);
 
         env.unwrap();
     }
@@ -798,7 +1268,7 @@ mod tests {
     fn test_unify_11_fails() {
         let l1 = vec![Term::Atom(Atom::new("a", vec![]))];
         let l2 = vec![Term::Atom(Atom::new("b", vec![]))];
-        let env = Environment::new().unify_lists(&l1, &l2);
+        let env = unify_lists(&l1, &l2);
 
         env.unwrap();
     }
@@ -819,7 +1289,7 @@ mod tests {
             Term::Atom(Atom::new("a", vec![Term::Var(Var::new("X", 0))])),
             Term::Atom(Atom::new("b", vec![])),
         ];
-        let env = Environment::new().unify_lists(&l1, &l2);
+        let env = unify_lists(&l1, &l2);
 
         unification_result(
             &env.unwrap(),
@@ -847,11 +1317,63 @@ mod tests {
             Term::Atom(Atom::new("a", vec![Term::Var(Var::new("X", 0))])),
             Term::Atom(Atom::new("b", vec![])),
         ];
-        let env = Environment::new().unify_lists(&l1, &l2);
+        let env = unify_lists(&l1, &l2);
 
         env.unwrap();
     }
 
+    #[test]
+    fn test_trail_1_succeeds() {
+        let x = Var::new("X", 0);
+        let y = Var::new("Y", 1);
+        let mut env = unify_terms(&Term::Var(x.clone()), &Term::var("Z")).unwrap();
+        env.put_attr(y.clone(), "m", Term::atom("a"));
+        env.set_global("g", Term::atom("a"));
+        let before = env.clone();
+
+        let mark = env.mark();
+        env.unify(&Term::Var(y.clone()), &Term::int(1)).unwrap();
+        env.take_bound_attributes();
+        env.unify(&Term::var("Z"), &Term::atom("b")).unwrap();
+        env.set_global("g", Term::atom("b"));
+        env.set_global("h", Term::atom("b"));
+        env.undo_to(mark);
+
+        assert_eq!((&env.0, &env.1, &env.2), (&before.0, &before.1, &before.2));
+        assert_eq!(env.get_attr(&y, "m"), Some(&Term::atom("a")));
+
+        // what was done before the oldest mark still held is never undone
+        env.unify(&Term::var("Z"), &Term::atom("b")).unwrap();
+        let mark = env.mark();
+        env.unify(&Term::Var(y), &Term::int(1)).unwrap();
+        env.forget_before(mark);
+        assert_eq!(env.3.changes.len(), 1);
+        env.undo_to(mark);
+        assert_eq!(env.substitute_term(&Term::Var(x)), Term::atom("b"));
+    }
+
+    #[test]
+    fn test_trail_1_fails() {
+        let x = Term::var("X");
+        let mut env = unify_terms(&x, &Term::compound("f", vec![Term::var("Y")])).unwrap();
+        let before = env.0.clone();
+
+        // a unification that fails part way has its bindings undone by the caller
+        let mark = env.mark();
+        let g = Term::compound("g", vec![Term::var("Y"), Term::atom("a")]);
+        let h = Term::compound("g", vec![Term::atom("b"), Term::atom("c")]);
+        assert!(env.unify(&g, &h).is_err());
+        assert_ne!(env.0, before);
+
+        env.undo_to(mark);
+        assert_eq!(env.0, before);
+        assert_eq!(
+            env.unifier(&x, &Term::compound("f", vec![Term::int(1)])),
+            Some(vec![Var::new("Y", 0)])
+        );
+        assert_eq!(env.0, before);
+    }
+
     #[test]
     fn test_occurs_1_succeeds() {
         let v = Var::new("X", 0);
@@ -0,0 +1,55 @@
+use crate::ast::Sentence;
+use crate::database::{Database, Segment};
+use crate::lexer::Lexer;
+use crate::ops::OpTable;
+use crate::parser::ProgramParser;
+
+// prolog libraries shipped inside the crate and loaded into every machine; user
+// programs may still define predicates of the same name
+pub const LIBRARIES: &[(&str, &str)] = &[
+    ("lists", include_str!("library/lists.pl")),
+    ("apply", include_str!("library/apply.pl")),
+    ("assoc", include_str!("library/assoc.pl")),
+    ("pairs", include_str!("library/pairs.pl")),
+    ("coroutining", include_str!("library/coroutining.pl")),
+];
+
+pub(crate) fn load(db: &mut Database, ops: &OpTable) {
+    for &(name, code) in LIBRARIES {
+        let program = ProgramParser::new()
+            .parse(ops, Lexer::new(code))
+            .unwrap_or_else(|e| panic!("library({}) does not parse: {:?}", name, e));
+
+        let assertions = program
+            .into_iter()
+            .filter_map(|(_, sentence)| match sentence {
+                Sentence::Assertion(assertion) => Some(assertion),
+                Sentence::Directive(_) => None,
+            })
+            .collect();
+
+        db.consult(Segment::Library, &format!("library({})", name), assertions)
+            .unwrap_or_else(|e| panic!("library({}) does not load: {}", name, e));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::Key;
+
+    #[test]
+    fn test_libraries_1_succeeds() {
+        let mut db = Database::new();
+        load(&mut db, &OpTable::default());
+
+        assert_eq!(db.segment(&Key::new("append", 3)), Some(Segment::Library));
+        assert_eq!(db.segment(&Key::new("exclude", 3)), Some(Segment::Library));
+        assert_eq!(db.clauses(&Key::new("member", 2)).len(), 2);
+        assert_eq!(db.segment(&Key::new("maplist", 7)), Some(Segment::Library));
+        assert_eq!(
+            db.segment(&Key::new("get_assoc", 3)),
+            Some(Segment::Library)
+        );
+    }
+}
@@ -0,0 +1,55 @@
+% meta-predicates applying a goal to the elements of lists
+
+maplist(_, []).
+maplist(G, [X1|Xs1]) :-
+    call(G, X1),
+    maplist(G, Xs1).
+
+maplist(_, [], []).
+maplist(G, [X1|Xs1], [X2|Xs2]) :-
+    call(G, X1, X2),
+    maplist(G, Xs1, Xs2).
+
+maplist(_, [], [], []).
+maplist(G, [X1|Xs1], [X2|Xs2], [X3|Xs3]) :-
+    call(G, X1, X2, X3),
+    maplist(G, Xs1, Xs2, Xs3).
+
+maplist(_, [], [], [], []).
+maplist(G, [X1|Xs1], [X2|Xs2], [X3|Xs3], [X4|Xs4]) :-
+    call(G, X1, X2, X3, X4),
+    maplist(G, Xs1, Xs2, Xs3, Xs4).
+
+maplist(_, [], [], [], [], []).
+maplist(G, [X1|Xs1], [X2|Xs2], [X3|Xs3], [X4|Xs4], [X5|Xs5]) :-
+    call(G, X1, X2, X3, X4, X5),
+    maplist(G, Xs1, Xs2, Xs3, Xs4, Xs5).
+
+maplist(_, [], [], [], [], [], []).
+maplist(G, [X1|Xs1], [X2|Xs2], [X3|Xs3], [X4|Xs4], [X5|Xs5], [X6|Xs6]) :-
+    call(G, X1, X2, X3, X4, X5, X6),
+    maplist(G, Xs1, Xs2, Xs3, Xs4, Xs5, Xs6).
+
+foldl(G, Xs, V0, V) :-
+    '$foldl'(Xs, G, V0, V).
+
+'$foldl'([], _, V, V).
+'$foldl'([X|Xs], G, V0, V) :-
+    call(G, X, V0, V1),
+    '$foldl'(Xs, G, V1, V).
+
+foldl(G, Xs, Ys, V0, V) :-
+    '$foldl'(Xs, Ys, G, V0, V).
+
+'$foldl'([], [], _, V, V).
+'$foldl'([X|Xs], [Y|Ys], G, V0, V) :-
+    call(G, X, Y, V0, V1),
+    '$foldl'(Xs, Ys, G, V1, V).
+
+foldl(G, Xs, Ys, Zs, V0, V) :-
+    '$foldl'(Xs, Ys, Zs, G, V0, V).
+
+'$foldl'([], [], [], _, V, V).
+'$foldl'([X|Xs], [Y|Ys], [Z|Zs], G, V0, V) :-
+    call(G, X, Y, Z, V0, V1),
+    '$foldl'(Xs, Ys, Zs, G, V1, V).
@@ -0,0 +1,89 @@
+% association lists as AVL trees: t for the empty tree, t(K,V,Balance,L,R) otherwise,
+% where the balance compares the height of the left subtree with the right one
+
+empty_assoc(t).
+
+get_assoc(Key, t(K,V,_,L,R), Value) :-
+    compare(Order, Key, K),
+    '$get_assoc'(Order, Key, V, L, R, Value).
+
+'$get_assoc'(=, _, V, _, _, V).
+'$get_assoc'(<, Key, _, L, _, V) :-
+    get_assoc(Key, L, V).
+'$get_assoc'(>, Key, _, _, R, V) :-
+    get_assoc(Key, R, V).
+
+put_assoc(Key, A0, Value, A) :-
+    '$put_assoc'(A0, Key, Value, A, _).
+
+'$put_assoc'(t, Key, Value, t(Key,Value,=,t,t), yes).
+'$put_assoc'(t(K,V,B,L,R), Key, Value, A, Grown) :-
+    compare(Order, Key, K),
+    '$put_assoc'(Order, t(K,V,B,L,R), Key, Value, A, Grown).
+
+'$put_assoc'(=, t(K,_,B,L,R), _, Value, t(K,Value,B,L,R), no).
+'$put_assoc'(<, t(K,V,B,L0,R), Key, Value, A, Grown) :-
+    '$put_assoc'(L0, Key, Value, L, LeftGrown),
+    '$left_grown'(LeftGrown, K, V, B, L, R, A, Grown).
+'$put_assoc'(>, t(K,V,B,L,R0), Key, Value, A, Grown) :-
+    '$put_assoc'(R0, Key, Value, R, RightGrown),
+    '$right_grown'(RightGrown, K, V, B, L, R, A, Grown).
+
+'$left_grown'(no, K, V, B, L, R, t(K,V,B,L,R), no).
+'$left_grown'(yes, K, V, <, L, R, t(K,V,=,L,R), no).
+'$left_grown'(yes, K, V, =, L, R, t(K,V,>,L,R), yes).
+'$left_grown'(yes, K, V, >, L, R, A, no) :-
+    '$rotate_right'(L, K, V, R, A).
+
+'$right_grown'(no, K, V, B, L, R, t(K,V,B,L,R), no).
+'$right_grown'(yes, K, V, >, L, R, t(K,V,=,L,R), no).
+'$right_grown'(yes, K, V, =, L, R, t(K,V,<,L,R), yes).
+'$right_grown'(yes, K, V, <, L, R, A, no) :-
+    '$rotate_left'(R, K, V, L, A).
+
+'$rotate_right'(t(LK,LV,>,LL,LR), K, V, R, t(LK,LV,=,LL,t(K,V,=,LR,R))).
+'$rotate_right'(t(LK,LV,<,LL,t(XK,XV,XB,XL,XR)), K, V, R, A) :-
+    '$rebalanced'(XB, B1, B2),
+    A = t(XK,XV,=,t(LK,LV,B1,LL,XL),t(K,V,B2,XR,R)).
+
+'$rotate_left'(t(RK,RV,<,RL,RR), K, V, L, t(RK,RV,=,t(K,V,=,L,RL),RR)).
+'$rotate_left'(t(RK,RV,>,t(XK,XV,XB,XL,XR),RR), K, V, L, A) :-
+    '$rebalanced'(XB, B1, B2),
+    A = t(XK,XV,=,t(K,V,B1,L,XL),t(RK,RV,B2,XR,RR)).
+
+% the balance of the two nodes moved below the middle one by a double rotation
+'$rebalanced'(>, =, <).
+'$rebalanced'(=, =, =).
+'$rebalanced'(<, >, =).
+
+list_to_assoc(Pairs, A) :-
+    '$list_to_assoc'(Pairs, t, A).
+
+'$list_to_assoc'([], A, A).
+'$list_to_assoc'([K-V|Pairs], A0, A) :-
+    put_assoc(K, A0, V, A1),
+    '$list_to_assoc'(Pairs, A1, A).
+
+assoc_to_list(A, Pairs) :-
+    '$assoc_to_list'(A, Pairs, []).
+
+'$assoc_to_list'(t, Pairs, Pairs).
+'$assoc_to_list'(t(K,V,_,L,R), Pairs0, Pairs) :-
+    '$assoc_to_list'(L, Pairs0, [K-V|Pairs1]),
+    '$assoc_to_list'(R, Pairs1, Pairs).
+
+assoc_to_keys(A, Keys) :-
+    '$assoc_to_keys'(A, Keys, []).
+
+'$assoc_to_keys'(t, Keys, Keys).
+'$assoc_to_keys'(t(K,_,_,L,R), Keys0, Keys) :-
+    '$assoc_to_keys'(L, Keys0, [K|Keys1]),
+    '$assoc_to_keys'(R, Keys1, Keys).
+
+assoc_to_values(A, Values) :-
+    '$assoc_to_values'(A, Values, []).
+
+'$assoc_to_values'(t, Values, Values).
+'$assoc_to_values'(t(_,V,_,L,R), Values0, Values) :-
+    '$assoc_to_values'(L, Values0, [V|Values1]),
+    '$assoc_to_values'(R, Values1, Values).
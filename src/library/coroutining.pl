@@ -0,0 +1,15 @@
+% the hooks run when a variable with the attributes of freeze/2, dif/2 or when/2 is
+% bound; each attribute holds the goals waiting on the variable
+
+freeze:attr_unify_hook(Goal, Y) :-
+    nonvar(Y),
+    call(Goal).
+freeze:attr_unify_hook(Goal, Y) :-
+    var(Y),
+    freeze(Y, Goal).
+
+dif:attr_unify_hook(Goal, _) :-
+    call(Goal).
+
+when:attr_unify_hook(Goal, _) :-
+    call(Goal).
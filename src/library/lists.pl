@@ -0,0 +1,98 @@
+% list predicates bundled with the machine
+
+append([], Ys, Ys).
+append([X|Xs], Ys, [X|Zs]) :-
+    append(Xs, Ys, Zs).
+
+member(X, [X|_]).
+member(X, [_|Xs]) :-
+    member(X, Xs).
+
+memberchk(X, [X|_]).
+memberchk(X, [Y|Ys]) :-
+    \+ X = Y,
+    memberchk(X, Ys).
+
+reverse(Xs, Ys) :-
+    '$reverse'(Xs, [], Ys).
+
+'$reverse'([], Ys, Ys).
+'$reverse'([X|Xs], Acc, Ys) :-
+    '$reverse'(Xs, [X|Acc], Ys).
+
+nth0(N, Xs, X) :-
+    '$nth'(Xs, 0, N, X).
+
+nth1(N, Xs, X) :-
+    '$nth'(Xs, 1, N, X).
+
+'$nth'([X|_], N, N, X).
+'$nth'([_|Xs], N0, N, X) :-
+    N1 is N0 + 1,
+    '$nth'(Xs, N1, N, X).
+
+sum_list(Xs, Sum) :-
+    '$sum_list'(Xs, 0, Sum).
+
+'$sum_list'([], Sum, Sum).
+'$sum_list'([X|Xs], Sum0, Sum) :-
+    Sum1 is Sum0 + X,
+    '$sum_list'(Xs, Sum1, Sum).
+
+max_list([X|Xs], Max) :-
+    '$max_list'(Xs, X, Max).
+
+'$max_list'([], Max, Max).
+'$max_list'([X|Xs], Max0, Max) :-
+    Max1 is max(Max0, X),
+    '$max_list'(Xs, Max1, Max).
+
+min_list([X|Xs], Min) :-
+    '$min_list'(Xs, X, Min).
+
+'$min_list'([], Min, Min).
+'$min_list'([X|Xs], Min0, Min) :-
+    Min1 is min(Min0, X),
+    '$min_list'(Xs, Min1, Min).
+
+last([X], X).
+last([_|Xs], X) :-
+    last(Xs, X).
+
+select(X, [X|Xs], Xs).
+select(X, [Y|Xs], [Y|Ys]) :-
+    select(X, Xs, Ys).
+
+delete([], _, []).
+delete([X|Xs], Y, Zs) :-
+    \+ \+ X = Y,
+    delete(Xs, Y, Zs).
+delete([X|Xs], Y, [X|Zs]) :-
+    \+ X = Y,
+    delete(Xs, Y, Zs).
+
+include(_, [], []).
+include(P, [X|Xs], Ys) :-
+    (   call(P, X)
+    ->  Ys = [X|Ys1]
+    ;   Ys = Ys1
+    ),
+    include(P, Xs, Ys1).
+
+exclude(_, [], []).
+exclude(P, [X|Xs], Ys) :-
+    (   call(P, X)
+    ->  Ys = Ys1
+    ;   Ys = [X|Ys1]
+    ),
+    exclude(P, Xs, Ys1).
+
+partition(_, [], [], []).
+partition(P, [X|Xs], Is, Es) :-
+    (   call(P, X)
+    ->  Is = [X|Is1],
+        Es = Es1
+    ;   Is = Is1,
+        Es = [X|Es1]
+    ),
+    partition(P, Xs, Is1, Es1).
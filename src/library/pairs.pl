@@ -0,0 +1,13 @@
+% operations on lists of Key-Value pairs, as produced by keysort/2
+
+pairs_keys_values([], [], []).
+pairs_keys_values([K-V|Pairs], [K|Keys], [V|Values]) :-
+    pairs_keys_values(Pairs, Keys, Values).
+
+pairs_keys([], []).
+pairs_keys([K-_|Pairs], [K|Keys]) :-
+    pairs_keys(Pairs, Keys).
+
+pairs_values([], []).
+pairs_values([_-V|Pairs], [V|Values]) :-
+    pairs_values(Pairs, Values).
@@ -0,0 +1,1437 @@
+use crate::ast::{Arity, Assertion, Atom, Clause, Const, Sentence, Term, Var};
+use crate::builtins::{self, Builtin};
+use crate::convert::{ConversionError, FromTerm, ToTerm};
+use crate::database::{Database, Key, Segment, Warning};
+use crate::debugger::{self, Action, Debugger};
+use crate::engine::Engine;
+use crate::errors::{indicator, instantiation_error, is_halt, resource_error, type_error};
+use crate::flags::{Flags, OccursCheck, Unknown};
+use crate::foreign::{Args, Foreign, ForeignPredicate};
+use crate::json::{self, JsonError};
+use crate::lexer::{line_column, DoubleQuotes, Lexer, ParseError};
+use crate::library;
+use crate::ops::OpTable;
+use crate::parser::{ClauseParser, LocatedParser};
+use crate::ports::{Port, PortListener};
+use crate::profiler::{Profile, Profiler};
+use crate::random::Random;
+use crate::reader::{is_layout, term_end};
+use crate::stream::{InputStream, OutputStream, Stdin, Stdout, Stream};
+use crate::writer::Writer;
+use crate::{
+    initialization_directive, renumber_atom, renumber_term, run_goal, singleton_warnings,
+    Environment, Initialization, UnifyErr, WamError,
+};
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::marker::PhantomData;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+// a database with the operators and builtins used to read and run goals against it
+pub struct Machine {
+    pub db: Database,
+    pub ops: OpTable,
+    pub flags: Flags,
+    pub gc: GcPolicy,
+    pub gc_stats: GcStats,
+    pub budget: Budget,
+    // the streams read/1 and write/1 and friends use
+    pub(crate) current_output: Box<dyn Stream>,
+    pub(crate) current_input: Box<dyn Stream>,
+    // text taken from the current input but not yet read as a term
+    pending: String,
+    builtins: HashMap<Key, Builtin>,
+    foreign: HashMap<Key, ForeignPredicate>,
+    listener: Option<Box<dyn PortListener>>,
+    // the global variables set by nb_setval/2, kept across backtracking and queries
+    pub(crate) globals: HashMap<String, Term>,
+    pub(crate) debugger: Debugger,
+    // the profile profile/1 is taking, and the last one it took
+    pub(crate) profiler: Option<Profiler>,
+    pub(crate) profile: Option<Profile>,
+    pub(crate) random: Random,
+    // the engines made by engine_create/3, by number, except one that is running, and
+    // the term posted to the one running now
+    pub(crate) engines: HashMap<usize, Engine>,
+    pub(crate) next_engine: usize,
+    pub(crate) posted: Option<Term>,
+}
+
+// when to drop the bindings a running query can no longer reach: every time it has made
+// `threshold` new ones since the last collection, or only on garbage_collect/0 if unset
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub struct GcPolicy {
+    pub threshold: Option<usize>,
+}
+
+// how much a query may do before it is stopped with a resource error: how many goals
+// it may call, counting those of the engines it runs, and for how long it may run, not
+// counting the time between asking for its solutions or slices; unlimited if unset.
+// Goals called while handling the error are over budget too, so a query that catches
+// it still stops
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub struct Budget {
+    pub inferences: Option<u64>,
+    pub time: Option<Duration>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub struct GcStats {
+    pub collections: usize,
+    pub freed: usize,
+    // the bindings left after the last collection
+    pub live: usize,
+}
+
+// the configuration of a machine to be built, for embedders that want it set before
+// the machine runs anything
+#[derive(Default)]
+pub struct MachineBuilder {
+    flags: Flags,
+    gc: GcPolicy,
+    budget: Budget,
+    output: Option<Box<dyn Write + Send>>,
+    input: Option<Box<dyn BufRead + Send>>,
+}
+
+// what loading a program left to report, the goals of `initialization(Goal)` it ran
+// once loaded, and the goal it asks to be run as the main program by
+// `initialization(Goal, main)`, if any
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Consulted {
+    pub warnings: Vec<Warning>,
+    pub initialized: Vec<Term>,
+    pub main: Option<Term>,
+}
+
+// the solutions of a query, each found on demand by backtracking into the one before;
+// an error the query does not catch ends them, and is kept
+pub struct Solutions<'m> {
+    machine: &'m mut Machine,
+    query: Query,
+    vars: Vec<Var>,
+    exception: Option<Term>,
+}
+
+// the values a template takes in the solutions of a goal, each converted to a Rust value
+// as it is found; only the template is copied out of each solution
+pub struct SolutionsOf<'m, T> {
+    solutions: Solutions<'m>,
+    template: Term,
+    values: PhantomData<T>,
+}
+
+// the values of the named variables of a query in one of its solutions, in the order
+// the variables first appear in the query
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Solution {
+    bindings: Vec<(String, Term)>,
+}
+
+// where running a query stopped
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) enum Run {
+    Solution,
+    Failed,
+    Paused,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) enum Goal {
+    Call(Term),
+    // marks the end of the goal of a catch/3, which is running while this is on the stack
+    Catch(Box<Catch>),
+    // marks the end of a goal a port listener was told of the call of, and so should be
+    // told of the exit of
+    Exit(Box<Frame>),
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct Frame {
+    id: usize,
+    goal: Term,
+    depth: usize,
+    choicepoints: usize,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct Catch {
+    catcher: Term,
+    recovery: Term,
+    // the mark of the trail to undo the bindings back to, and the number of choicepoints
+    // to cut back to, when an error is caught
+    trail: usize,
+    choicepoints: usize,
+}
+
+// what to try on backtracking
+#[derive(Debug, Clone)]
+enum Alternative {
+    Clauses(Atom, Vec<Arc<Assertion>>),
+    Goal(Term),
+}
+
+#[derive(Debug, Clone)]
+struct Choicepoint {
+    alternative: Alternative,
+    // the mark of the trail to undo the bindings back to
+    trail: usize,
+    goals: Vec<Goal>,
+}
+
+// the state of a running query: goals still to prove (the next one last), the
+// bindings made so far, and where to resume on backtracking
+#[derive(Debug, Clone)]
+pub(crate) struct Query {
+    pub(crate) env: Environment,
+    goals: Vec<Goal>,
+    choicepoints: Vec<Choicepoint>,
+    depth: usize,
+    started: bool,
+    occurs_check: OccursCheck,
+    // an error raised while unifying or in a nested query, to be thrown once the current
+    // goal is done
+    pub(crate) exception: Option<Term>,
+    collected: usize,
+    inferences: u64,
+    deadline: Option<Instant>,
+    time_left: Option<Duration>,
+    frames: usize,
+    // the count of goals called at which to pause, and whether the query is paused there
+    pause: Option<u64>,
+    paused: bool,
+    // the solutions findall/3 has found so far, for each call still running; those of a
+    // call an error ends are only dropped with the query
+    bags: HashMap<usize, Vec<Term>>,
+    next_bag: usize,
+}
+
+impl Default for Machine {
+    fn default() -> Self {
+        Machine::new()
+    }
+}
+
+impl Machine {
+    pub fn new() -> Self {
+        let mut machine = Machine {
+            db: Database::new(),
+            ops: OpTable::default(),
+            flags: Flags::default(),
+            gc: GcPolicy::default(),
+            gc_stats: GcStats::default(),
+            budget: Budget::default(),
+            current_output: Box::new(Stdout),
+            current_input: Box::new(Stdin::default()),
+            pending: String::new(),
+            builtins: builtins::BUILTINS
+                .iter()
+                .map(|&(name, arity, f)| (Key::new(name, arity), f))
+                .collect(),
+            foreign: HashMap::new(),
+            listener: None,
+            globals: HashMap::new(),
+            debugger: Debugger::default(),
+            profiler: None,
+            profile: None,
+            random: Random::default(),
+            engines: HashMap::new(),
+            next_engine: 0,
+            posted: None,
+        };
+
+        library::load(&mut machine.db, &machine.ops);
+        machine
+    }
+
+    // a machine with the program, operators, flags and foreign predicates of this one,
+    // sharing its clauses until either changes them; it reads and writes standard I/O
+    pub fn fork(&self) -> Machine {
+        Machine {
+            db: self.db.clone(),
+            ops: self.ops.clone(),
+            flags: self.flags,
+            gc: self.gc,
+            gc_stats: GcStats::default(),
+            budget: self.budget,
+            current_output: Box::new(Stdout),
+            current_input: Box::new(Stdin::default()),
+            pending: String::new(),
+            builtins: self.builtins.clone(),
+            foreign: self.foreign.clone(),
+            listener: None,
+            globals: self.globals.clone(),
+            debugger: Debugger::default(),
+            profiler: None,
+            profile: None,
+            random: Random::default(),
+            engines: HashMap::new(),
+            next_engine: 0,
+            posted: None,
+        }
+    }
+
+    // an engine for a goal, whose answers are the goal as each solution binds it; it can
+    // be run a number of goals at a time with Engine::run_for
+    pub fn start(&self, goal: Term) -> Engine {
+        Engine::new(goal.clone(), goal)
+    }
+
+    // what the last call of profile/1 found
+    pub fn profile(&self) -> Option<&Profile> {
+        self.profile.as_ref()
+    }
+
+    // the clauses of the predicates of the user's program, as listing/0 shows them
+    pub fn listing(&self) -> String {
+        let mut keys: Vec<_> = self
+            .db
+            .predicates()
+            .filter(|(_, p)| p.segment == Segment::User)
+            .map(|(key, _)| key)
+            .collect();
+        keys.sort();
+
+        keys.into_iter().map(|key| self.listing_of(key)).collect()
+    }
+
+    // the clauses of a predicate, after its declaration if it is dynamic, and a blank
+    // line; nothing if there is no such predicate
+    pub fn listing_of(&self, key: &Key) -> String {
+        let predicate = match self.db.predicate(key) {
+            Some(predicate) => predicate,
+            None => return String::new(),
+        };
+
+        let mut listing = String::new();
+        if predicate.dynamic {
+            listing.push_str(&format!(":- dynamic {}.\n\n", key));
+        }
+
+        let writer = Writer::new(&self.ops).quoted(true);
+        for clause in &predicate.clauses {
+            listing.push_str(&writer.clause(clause));
+        }
+
+        listing.push('\n');
+        listing
+    }
+
+    // who to tell of the goals passing through the ports of the box model, if anyone
+    pub fn set_port_listener(&mut self, listener: Option<Box<dyn PortListener>>) {
+        self.listener = listener;
+    }
+
+    pub fn builder() -> MachineBuilder {
+        MachineBuilder::default()
+    }
+
+    // define a predicate by a Rust closure; like a builtin, it cannot then be redefined
+    // by clauses
+    pub fn register<F>(&mut self, name: &str, arity: Arity, f: F)
+    where
+        F: Fn(&mut Machine, &mut Args) -> Result<Foreign, Term> + Send + Sync + 'static,
+    {
+        let key = Key::new(name, arity);
+        self.db.add_system(key.clone());
+        self.foreign.insert(key, Arc::new(f));
+    }
+
+    // where the output of write/1 and friends goes, standard output by default
+    pub fn set_output(&mut self, output: Box<dyn Write + Send>) {
+        self.set_current_output(Box::new(OutputStream::new(output)));
+    }
+
+    // where read/1 and friends take their input from, standard input by default
+    pub fn set_input(&mut self, input: Box<dyn BufRead + Send>) {
+        self.set_current_input(Box::new(InputStream::new(input)));
+    }
+
+    pub fn set_current_output(&mut self, output: Box<dyn Stream>) {
+        self.current_output = output;
+    }
+
+    pub fn set_current_input(&mut self, input: Box<dyn Stream>) {
+        self.current_input = input;
+        self.pending.clear();
+    }
+
+    // load a program from a file, replacing the predicates it defines
+    pub fn consult_file(&mut self, path: &str) -> Result<Consulted, WamError> {
+        let code = fs::read_to_string(path)?;
+        self.consult(path, &code)
+    }
+
+    // load a program from its text, as if it were the file `user`
+    pub fn consult_str(&mut self, code: &str) -> Result<Consulted, WamError> {
+        self.consult("user", code)
+    }
+
+    // add the clauses of a program, then run its directives in source order; a program
+    // that does not parse or load changes nothing, while directives that fail or raise
+    // an error are only warned about. op/3 directives run as they are read instead, so
+    // that the operators they define hold for the clauses after them
+    fn consult(&mut self, file: &str, code: &str) -> Result<Consulted, WamError> {
+        let ops = self.ops.clone();
+        let mut warnings = Vec::new();
+
+        let program = match self.read_program(code, &mut warnings) {
+            Ok(program) => program,
+            Err(e) => {
+                self.ops = ops;
+                return Err(e);
+            }
+        };
+
+        let mut assertions = Vec::new();
+        let mut directives = Vec::new();
+        warnings.splice(0..0, singleton_warnings(code, &program));
+
+        for (position, sentence) in program {
+            match sentence {
+                Sentence::Assertion(assertion) => assertions.push(assertion),
+                Sentence::Directive(goal) => {
+                    let (line, _) = line_column(code, position);
+                    let initialization = is_initialization(&goal);
+                    directives.push((line, initialization, initialization_directive(goal)));
+                }
+            }
+        }
+
+        match self.db.consult(Segment::User, file, assertions) {
+            Ok(redefined) => warnings.extend(redefined),
+            Err(e) => {
+                self.ops = ops;
+                return Err(e.into());
+            }
+        }
+
+        let mut initialized = Vec::new();
+        let mut main = None;
+
+        for (line, initialization, directive) in directives {
+            match directive {
+                Initialization::Load(goal) => {
+                    self.directive(goal.clone(), line, &mut warnings)?;
+                    if initialization {
+                        initialized.push(goal);
+                    }
+                }
+                Initialization::Main(goal) => main = Some(goal),
+            }
+        }
+
+        Ok(Consulted {
+            warnings,
+            initialized,
+            main,
+        })
+    }
+
+    // the clauses and directives of a program, read one at a time with the operators
+    // defined so far, running op/3 directives as they are met
+    fn read_program(
+        &mut self,
+        code: &str,
+        warnings: &mut Vec<Warning>,
+    ) -> Result<Vec<(usize, Sentence)>, WamError> {
+        let mut program = Vec::new();
+        let mut start = 0;
+
+        while !is_layout(&code[start..]) {
+            let end = term_end(&code[start..]).map_or(code.len(), |end| start + end);
+            let text = &code[start..end];
+            let lexer = Lexer::new(text).double_quotes(self.flags.double_quotes);
+            let (position, sentence) = LocatedParser::new()
+                .parse(&self.ops, lexer)
+                .map_err(|e| ParseError::new(text, e).offset(code, start))?;
+            let position = start + position;
+
+            match sentence {
+                Sentence::Directive(goal) if is_op_directive(&goal) => {
+                    let (line, _) = line_column(code, position);
+                    self.directive(goal, line, warnings)?;
+                }
+                sentence => program.push((position, sentence)),
+            }
+
+            start = end;
+        }
+
+        Ok(program)
+    }
+
+    // run a directive of a program, warning if it fails or raises an error
+    fn directive(
+        &mut self,
+        goal: Term,
+        line: usize,
+        warnings: &mut Vec<Warning>,
+    ) -> Result<(), WamError> {
+        match run_goal(self, goal.clone()) {
+            Ok(true) => (),
+            Ok(false) => warnings.push(Warning::GoalFailed(goal, line)),
+            Err(WamError::Execution(ball)) => warnings.push(Warning::GoalRaised(goal, ball, line)),
+            Err(e) => return Err(e),
+        }
+
+        Ok(())
+    }
+
+    // read a query, ended by `.`, to enumerate its solutions lazily
+    pub fn solve(&mut self, text: &str) -> Result<Solutions<'_>, WamError> {
+        let lexer = Lexer::new(text).double_quotes(self.flags.double_quotes);
+        let goals = ClauseParser::new()
+            .parse(&self.ops, lexer)
+            .map_err(|e| ParseError::new(text, e))?;
+
+        Ok(self.solutions(goals))
+    }
+
+    // enumerate the solutions of a goal built as a term, which needs no parsing
+    pub fn solve_term(&mut self, goal: Term) -> Result<Solutions<'_>, WamError> {
+        match goal.clone().into_goals() {
+            Some(mut goals) => {
+                goals.reverse();
+                Ok(self.solutions(goals))
+            }
+            None => Err(WamError::Execution(type_error("callable", goal))),
+        }
+    }
+
+    // enumerate the solutions of a call to a predicate with arguments given as Rust values
+    pub fn solve_call<T: ToTerm>(
+        &mut self,
+        name: &str,
+        args: &[T],
+    ) -> Result<Solutions<'_>, WamError> {
+        let args = args.iter().map(T::to_term).collect();
+        self.solve_term(Term::compound(name, args))
+    }
+
+    // enumerate the values of a template in the solutions of a goal, e.g. the numbers
+    // `X` takes in `between(1, inf, X)`, without keeping any but the current one
+    pub fn iter_solutions_of<T: FromTerm>(
+        &mut self,
+        template: Term,
+        goal: Term,
+    ) -> Result<SolutionsOf<'_, T>, WamError> {
+        let mut solutions = self.solve_term(goal)?;
+        solutions.vars.clear();
+
+        Ok(SolutionsOf {
+            solutions,
+            template,
+            values: PhantomData,
+        })
+    }
+
+    pub(crate) fn solutions(&mut self, goals: Clause) -> Solutions<'_> {
+        let mut vars = Vec::new();
+        for goal in goals.iter().rev() {
+            for x in Term::from(goal.clone()).variables() {
+                if !x.is_anonymous() && !vars.contains(x) {
+                    vars.push(x.clone());
+                }
+            }
+        }
+
+        Solutions {
+            machine: self,
+            query: Query::new(goals),
+            vars,
+            exception: None,
+        }
+    }
+
+    // the text of the next term in the input, up to and including its `.`, or nothing at
+    // the end of the input; text after the term is kept for the next read
+    pub(crate) fn read_text(&mut self) -> io::Result<Option<String>> {
+        loop {
+            if let Some(end) = term_end(&self.pending) {
+                return Ok(Some(self.pending.drain(..end).collect()));
+            }
+
+            let mut line = String::new();
+            let n = self.current_input.read_line(&mut line)?;
+
+            if n == 0 {
+                let rest = std::mem::take(&mut self.pending);
+                return Ok(if is_layout(&rest) { None } else { Some(rest) });
+            }
+
+            self.pending.push_str(&line);
+        }
+    }
+
+    // the next JSON value in the input, or nothing at the end of the input; as with
+    // read_text, text after the value is kept for the next read
+    pub(crate) fn read_json(&mut self) -> io::Result<Result<Option<Term>, JsonError>> {
+        loop {
+            match json::read_json(&self.pending, false) {
+                Ok(Some((t, end))) => {
+                    self.pending.drain(..end);
+                    return Ok(Ok(Some(t)));
+                }
+                Ok(None) => (),
+                Err(e) => {
+                    self.pending.clear();
+                    return Ok(Err(e));
+                }
+            }
+
+            let mut line = String::new();
+            let n = self.current_input.read_line(&mut line)?;
+
+            if n == 0 {
+                let rest = std::mem::take(&mut self.pending);
+                if is_layout(&rest) {
+                    return Ok(Ok(None));
+                }
+
+                return Ok(json::read_json(&rest, true).map(|read| read.map(|(t, _)| t)));
+            }
+
+            self.pending.push_str(&line);
+        }
+    }
+
+    // find the next solution of a query, backtracking into the previous one if there was
+    // one; an error that is not caught abandons the query
+    pub(crate) fn next_solution(&mut self, q: &mut Query) -> Result<bool, Term> {
+        self.run(q, None).map(|run| run == Run::Solution)
+    }
+
+    // run a query on to its next solution, or only until it has called a number of goals
+    // more, to pause there; a paused query carries on from where it was the next time
+    pub(crate) fn run(&mut self, q: &mut Query, inferences: Option<u64>) -> Result<Run, Term> {
+        // the clock only runs while the query does
+        if !q.started {
+            q.time_left = self.budget.time;
+        }
+        q.deadline = q.time_left.map(|time| Instant::now() + time);
+        q.pause = inferences.map(|n| q.inferences.saturating_add(n));
+
+        let run = self.resume(q);
+        q.time_left = q
+            .deadline
+            .map(|deadline| deadline.saturating_duration_since(Instant::now()));
+        run
+    }
+
+    // run a query started by a goal of another, such as an engine's, on the budget of
+    // the other: the goals it calls count toward those the other may call, and it stops
+    // at the other's deadline, or pauses where the other would
+    pub(crate) fn run_within(&mut self, outer: &mut Query, q: &mut Query) -> Result<Run, Term> {
+        q.inferences = outer.inferences;
+        q.deadline = outer.deadline;
+        q.pause = outer.pause;
+
+        let run = self.resume(q);
+        outer.inferences = q.inferences;
+        run
+    }
+
+    // whether a query run for a goal of another has a solution, found on the budget of
+    // the other but carrying on past its pause, for a goal that can't be left half done,
+    // such as portray/1 writing a term for print/1
+    pub(crate) fn solve_within(&mut self, outer: &mut Query, q: &mut Query) -> Result<bool, Term> {
+        let pause = outer.pause.take();
+        let run = self.run_within(outer, q);
+        outer.pause = pause;
+        Ok(run? == Run::Solution)
+    }
+
+    fn resume(&mut self, q: &mut Query) -> Result<Run, Term> {
+        if q.paused {
+            q.paused = false;
+        } else if q.started && !self.backtrack(q) {
+            return Ok(Run::Failed);
+        }
+
+        q.started = true;
+
+        loop {
+            if q.pause.is_some_and(|at| q.inferences >= at) && !q.goals.is_empty() {
+                q.paused = true;
+                return Ok(Run::Paused);
+            }
+
+            if let Some(threshold) = self.gc.threshold {
+                if q.env.len() >= q.collected + threshold {
+                    self.collect_garbage(q);
+                }
+            }
+
+            let goal = match q.goals.pop() {
+                None => return Ok(Run::Solution),
+                Some(Goal::Call(goal)) => q.env.deref(&goal),
+                Some(Goal::Catch(_)) => continue,
+                Some(Goal::Exit(frame)) => {
+                    let goal = q.env.substitute_term(&frame.goal);
+                    let deterministic = q.choicepoints.len() <= frame.choicepoints;
+
+                    // failing a goal that has exited drops what it left to backtrack into
+                    if self.port(Port::Exit, &frame, &goal, deterministic) == Action::Fail {
+                        self.port(Port::Fail, &frame, &frame.goal, false);
+                        q.choicepoints.truncate(frame.choicepoints);
+
+                        if !self.backtrack(q) {
+                            return Ok(Run::Failed);
+                        }
+                    }
+                    continue;
+                }
+            };
+
+            let mut action = Action::Continue;
+            if self.is_watched() {
+                let goal = q.env.substitute_term(&goal);
+                let frame = q.push_frame(goal.clone());
+                action = self.port(Port::Call, &frame, &goal, false);
+            }
+
+            q.occurs_check = self.flags.occurs_check;
+            q.inferences += 1;
+
+            let result = match (self.over_budget(q), goal) {
+                _ if action == Action::Fail => Ok(false),
+                (Some(exceeded), _) => Err(q.error(resource_error(exceeded))),
+                (None, Term::Atom(a)) => self.call(q, a),
+                (None, Term::Const(Const(name))) => self.call(q, Atom::new(&name, vec![])),
+                (None, Term::Var(_)) => Err(q.error(instantiation_error())),
+                (None, t) => Err(q.error(type_error("callable", t))),
+            };
+
+            match q.exception.take().map_or(result, Err) {
+                Ok(true) => (),
+                Ok(false) => {
+                    if !self.backtrack(q) {
+                        return Ok(Run::Failed);
+                    }
+                }
+                Err(ball) => q.recover(ball)?,
+            }
+        }
+    }
+
+    // backtrack into the last choicepoint of a query, telling a port listener and the
+    // debugger of the goals that fail on the way and of the goal that is tried again
+    fn backtrack(&mut self, q: &mut Query) -> bool {
+        if !self.is_watched() {
+            return q.backtrack();
+        }
+
+        loop {
+            let before = q.open_frames();
+            let resumed = q.backtrack();
+            // with nothing left to backtrack into, every goal still running fails
+            let after = if resumed { q.open_frames() } else { Vec::new() };
+
+            for frame in before.iter().rev() {
+                if !after.iter().any(|f| f.id == frame.id) {
+                    self.port(Port::Fail, frame, &frame.goal, false);
+                }
+            }
+
+            // the goal is shown as it was called, not as its next clause has bound it;
+            // failing it drops the rest of its alternatives
+            match after.last() {
+                Some(frame) if self.port(Port::Redo, frame, &frame.goal, false) == Action::Fail => {
+                    q.choicepoints.truncate(frame.choicepoints);
+                }
+                _ => return resumed,
+            }
+        }
+    }
+
+    // whether goals are followed through their ports, by a port listener, the profiler
+    // or the debugger
+    fn is_watched(&self) -> bool {
+        self.listener.is_some() || self.profiler.is_some() || self.debugger.is_on()
+    }
+
+    // tell the port listener, the profiler and the debugger that a goal is at a port,
+    // and give what the debugger was told to do with it; the debugger shows the goal on
+    // the current output and reads commands from the current input
+    fn port(&mut self, port: Port, frame: &Frame, goal: &Term, deterministic: bool) -> Action {
+        let depth = frame.depth;
+
+        if let Some(listener) = &mut self.listener {
+            listener.port(port, goal, depth, deterministic);
+        }
+
+        if let Some(profiler) = &mut self.profiler {
+            profiler.port(port, frame.id, goal);
+        }
+
+        if !self.debugger.stops_at(goal, depth) {
+            return Action::Continue;
+        }
+
+        let goal = Writer::new(&self.ops).quoted(true).write(goal);
+        let prompt = format!("{:>6}: ({}) {} ? ", format!("{:?}", port), depth, goal);
+
+        loop {
+            // the debugger is only shown, so losing a prompt to a broken stream is not an
+            // error
+            let _ = self.current_output.write(prompt.as_bytes());
+            let _ = self.current_output.flush();
+
+            let mut line = String::new();
+            let command = match self.current_input.read_line(&mut line) {
+                Ok(0) | Err(_) => "l",
+                Ok(_) => line.trim(),
+            };
+
+            match self.debugger.command(port, depth, command) {
+                Some(action) => return action,
+                None => {
+                    let _ = self.current_output.write(debugger::HELP.as_bytes());
+                }
+            }
+        }
+    }
+
+    // the resource a query has used up, if any
+    fn over_budget(&self, q: &Query) -> Option<&'static str> {
+        if let Some(limit) = self.budget.inferences {
+            if q.inferences > limit {
+                return Some("inferences");
+            }
+        }
+
+        match q.deadline {
+            Some(deadline) if Instant::now() >= deadline => Some("time"),
+            _ => None,
+        }
+    }
+
+    // the goals still to run and those backtracking would run are the roots; the trail
+    // is trimmed first, so that what it would put back keeps no more alive than it must
+    pub(crate) fn collect_garbage(&mut self, q: &mut Query) {
+        q.trim_trail();
+
+        let mut roots = Vec::new();
+        let stacks = Some(&q.goals).into_iter();
+        for goal in stacks
+            .chain(q.choicepoints.iter().map(|c| &c.goals))
+            .flatten()
+        {
+            match goal {
+                Goal::Call(t) => roots.extend(t.variables().into_iter().cloned()),
+                Goal::Catch(catch) => {
+                    roots.extend(catch.catcher.variables().into_iter().cloned());
+                    roots.extend(catch.recovery.variables().into_iter().cloned());
+                }
+                Goal::Exit(frame) => roots.extend(frame.goal.variables().into_iter().cloned()),
+            }
+        }
+
+        for choicepoint in &q.choicepoints {
+            match &choicepoint.alternative {
+                Alternative::Clauses(goal, _) => {
+                    for t in &goal.args {
+                        roots.extend(t.variables().into_iter().cloned());
+                    }
+                }
+                Alternative::Goal(t) => roots.extend(t.variables().into_iter().cloned()),
+            }
+        }
+
+        self.gc_stats.collections += 1;
+        self.gc_stats.freed += q.env.collect_garbage(roots);
+        self.gc_stats.live = q.env.len();
+        q.collected = q.env.len();
+    }
+
+    fn call(&mut self, q: &mut Query, goal: Atom) -> Result<bool, Term> {
+        let key = Key::of(&goal);
+
+        if let Some(f) = self.foreign.get(&key).cloned() {
+            return self.call_foreign(q, f, goal, None);
+        }
+
+        match self.builtins.get(&key) {
+            // throw/1 raises its ball as it is, where other builtins only give the formal
+            // part of an error
+            Some(&builtin) if key.0 == "throw" && key.1 == 1 => builtin(self, q, &goal.args),
+            Some(&builtin) => builtin(self, q, &goal.args).map_err(|formal| {
+                let context = vec![indicator(&key.0, key.1), q.fresh_var()];
+                Term::compound("error", vec![formal, Term::compound("context", context)])
+            }),
+            None if self.db.segment(&key).is_none() => {
+                let culprit = indicator(&key.0, key.1);
+
+                match self.flags.unknown {
+                    Unknown::Error => {
+                        let formal = Term::compound(
+                            "existence_error",
+                            vec![Term::atom("procedure"), culprit.clone()],
+                        );
+                        Err(Term::compound("error", vec![formal, culprit]))
+                    }
+                    // the warning goes where the machine's output goes, so an embedder
+                    // sees it; losing it to a broken stream is not an error
+                    Unknown::Warning => {
+                        let warning = format!("Warning: unknown procedure {}\n", key);
+                        let _ = self.current_output.write(warning.as_bytes());
+                        let _ = self.current_output.flush();
+                        Ok(false)
+                    }
+                    Unknown::Fail => Ok(false),
+                }
+            }
+            None => {
+                // clauses are tried in source order, by popping from the end
+                let first = goal.args.first().map(|t| q.env.deref(t));
+                let clauses = self.db.matching(&goal, first.as_ref());
+                let clauses = clauses.into_iter().rev().cloned().collect();
+                Ok(q.resolve(goal, clauses))
+            }
+        }
+    }
+
+    // call a foreign predicate, first with no state and then, on backtracking, with the
+    // state it left for its next solution
+    fn call_foreign(
+        &mut self,
+        q: &mut Query,
+        f: ForeignPredicate,
+        goal: Atom,
+        state: Option<Term>,
+    ) -> Result<bool, Term> {
+        let mark = q.env.mark();
+
+        match f(self, &mut Args::new(q, &goal.args, state)) {
+            Ok(Foreign::Fail) => Ok(false),
+            Ok(Foreign::Succeed) => Ok(true),
+            Ok(Foreign::Redo(state)) => {
+                let redo = Term::compound("$foreign_redo", vec![state, Term::Atom(goal)]);
+                q.push_alternative_from(mark, redo);
+                Ok(true)
+            }
+            Err(formal) => {
+                let context = vec![indicator(&goal.name.0, goal.arity), q.fresh_var()];
+                Err(Term::compound(
+                    "error",
+                    vec![formal, Term::compound("context", context)],
+                ))
+            }
+        }
+    }
+
+    // backtrack into a foreign predicate, for the $foreign_redo/2 goal it left behind
+    pub(crate) fn redo_foreign(
+        &mut self,
+        q: &mut Query,
+        state: &Term,
+        goal: &Term,
+    ) -> Result<bool, Term> {
+        let goal = match goal {
+            Term::Atom(a) => a.clone(),
+            _ => return Ok(false),
+        };
+
+        match self.foreign.get(&Key::of(&goal)).cloned() {
+            Some(f) => self.call_foreign(q, f, goal, Some(state.clone())),
+            None => Ok(false),
+        }
+    }
+}
+
+impl MachineBuilder {
+    pub fn double_quotes(mut self, double_quotes: DoubleQuotes) -> Self {
+        self.flags.double_quotes = double_quotes;
+        self
+    }
+
+    pub fn unknown(mut self, unknown: Unknown) -> Self {
+        self.flags.unknown = unknown;
+        self
+    }
+
+    pub fn occurs_check(mut self, occurs_check: OccursCheck) -> Self {
+        self.flags.occurs_check = occurs_check;
+        self
+    }
+
+    // collect garbage every `threshold` new bindings; bindings are the only memory a
+    // query grows, so this is what bounds it
+    pub fn gc_threshold(mut self, threshold: usize) -> Self {
+        self.gc.threshold = Some(threshold);
+        self
+    }
+
+    pub fn max_inferences(mut self, inferences: u64) -> Self {
+        self.budget.inferences = Some(inferences);
+        self
+    }
+
+    pub fn time_limit(mut self, time: Duration) -> Self {
+        self.budget.time = Some(time);
+        self
+    }
+
+    pub fn output(mut self, output: Box<dyn Write + Send>) -> Self {
+        self.output = Some(output);
+        self
+    }
+
+    pub fn input(mut self, input: Box<dyn BufRead + Send>) -> Self {
+        self.input = Some(input);
+        self
+    }
+
+    pub fn build(self) -> Machine {
+        let mut machine = Machine::new();
+        machine.flags = self.flags;
+        machine.gc = self.gc;
+        machine.budget = self.budget;
+
+        if let Some(output) = self.output {
+            machine.set_output(output);
+        }
+
+        if let Some(input) = self.input {
+            machine.set_input(input);
+        }
+
+        machine
+    }
+}
+
+impl<'m> Solutions<'m> {
+    // the error that ended the solutions, if one did
+    pub fn exception(&self) -> Option<&Term> {
+        self.exception.as_ref().filter(|ball| !is_halt(ball))
+    }
+
+    // whether the solutions were ended by halt/0
+    pub fn halted(&self) -> bool {
+        self.exception.as_ref().is_some_and(is_halt)
+    }
+
+    // whether there is anything left to backtrack into for another solution
+    pub fn has_more(&self) -> bool {
+        self.exception.is_none() && (!self.query.started || self.query.has_alternatives())
+    }
+
+    // find the next solution, keeping the error that ends the solutions if one does
+    fn advance(&mut self) -> bool {
+        if self.exception.is_some() {
+            return false;
+        }
+
+        match self.machine.next_solution(&mut self.query) {
+            Ok(found) => found,
+            Err(ball) => {
+                self.exception = Some(ball);
+                false
+            }
+        }
+    }
+}
+
+impl<'m> Iterator for Solutions<'m> {
+    type Item = Solution;
+
+    fn next(&mut self) -> Option<Solution> {
+        if !self.advance() {
+            return None;
+        }
+
+        let env = &self.query.env;
+        let bindings = self
+            .vars
+            .iter()
+            .map(|x| (x.0.clone(), env.substitute_term(&Term::Var(x.clone()))))
+            .collect();
+
+        Some(Solution { bindings })
+    }
+}
+
+impl<'m, T> SolutionsOf<'m, T> {
+    // the error that ended the solutions, if one did
+    pub fn exception(&self) -> Option<&Term> {
+        self.solutions.exception()
+    }
+
+    pub fn halted(&self) -> bool {
+        self.solutions.halted()
+    }
+}
+
+impl<'m, T: FromTerm> Iterator for SolutionsOf<'m, T> {
+    type Item = Result<T, ConversionError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if !self.solutions.advance() {
+            return None;
+        }
+
+        let value = self.solutions.query.env.substitute_term(&self.template);
+        Some(T::from_term(&value))
+    }
+}
+
+impl Solution {
+    // the value of a variable of the query, by name
+    pub fn term(&self, name: &str) -> Option<&Term> {
+        self.bindings
+            .iter()
+            .find(|(x, _)| x == name)
+            .map(|(_, t)| t)
+    }
+
+    // the value of a variable of the query as a Rust value, e.g. `get::<Vec<i64>>("Xs")`
+    pub fn get<T: FromTerm>(&self, name: &str) -> Result<T, ConversionError> {
+        match self.term(name) {
+            Some(t) => T::from_term(t),
+            None => Err(ConversionError::NoVariable(String::from(name))),
+        }
+    }
+
+    pub fn bindings(&self) -> &[(String, Term)] {
+        &self.bindings
+    }
+
+    // the solution as the toplevel shows it, e.g. `X = f(a), Y = b`, leaving out the
+    // variables still unbound, or `true` if there are none
+    pub fn answer(&self, ops: &OpTable) -> String {
+        let writer = Writer::new(ops).quoted(true);
+        let answer: Vec<_> = self
+            .bindings
+            .iter()
+            .filter(|(x, t)| *t != Term::Var(Var::new(x, 0)))
+            .map(|(x, t)| format!("{} = {}", x, writer.write_priority(t, 699)))
+            .collect();
+
+        if answer.is_empty() {
+            String::from("true")
+        } else {
+            answer.join(", ")
+        }
+    }
+}
+
+impl Query {
+    // a query whose goals are in stack order, as read by the clause parser
+    pub(crate) fn new(goals: Clause) -> Self {
+        Query {
+            env: Environment::new(),
+            goals: goals
+                .into_iter()
+                .map(|a| Goal::Call(cut_to(a.into(), 0)))
+                .collect(),
+            choicepoints: Vec::new(),
+            depth: 0,
+            started: false,
+            occurs_check: OccursCheck::default(),
+            exception: None,
+            collected: 0,
+            inferences: 0,
+            deadline: None,
+            time_left: None,
+            frames: 0,
+            pause: None,
+            paused: false,
+            bags: HashMap::new(),
+            next_bag: 0,
+        }
+    }
+
+    pub(crate) fn has_alternatives(&self) -> bool {
+        !self.choicepoints.is_empty()
+    }
+
+    pub(crate) fn fresh_var(&mut self) -> Term {
+        self.depth += 1;
+        Term::Var(Var::new("_", self.depth))
+    }
+
+    // start a goal that a port listener is told about, marking where it will end
+    fn push_frame(&mut self, goal: Term) -> Frame {
+        self.frames += 1;
+        let frame = Frame {
+            id: self.frames,
+            goal,
+            depth: self.open_frames().len(),
+            choicepoints: self.choicepoints.len(),
+        };
+
+        self.goals.push(Goal::Exit(Box::new(frame.clone())));
+        frame
+    }
+
+    // the goals a port listener was told were called and are still running, outermost
+    // first
+    fn open_frames(&self) -> Vec<Frame> {
+        let frames = self.goals.iter().filter_map(|goal| match goal {
+            Goal::Exit(frame) => Some((**frame).clone()),
+            _ => None,
+        });
+        frames.collect()
+    }
+
+    pub(crate) fn push_goal(&mut self, goal: Term) {
+        self.goals.push(Goal::Call(goal));
+    }
+
+    // run a goal as call/1 does, where a cut only cuts the choicepoints the goal left
+    pub(crate) fn push_call(&mut self, goal: Term) {
+        let goal = cut_to(goal, self.choicepoints.len());
+        self.push_goal(goal);
+    }
+
+    // drop the choicepoints left since there were a number of them, for a cut
+    pub(crate) fn cut(&mut self, choicepoints: usize) {
+        self.choicepoints.truncate(choicepoints);
+    }
+
+    pub(crate) fn choicepoints(&self) -> usize {
+        self.choicepoints.len()
+    }
+
+    pub(crate) fn open_bag(&mut self) -> usize {
+        self.next_bag += 1;
+        self.bags.insert(self.next_bag, Vec::new());
+        self.next_bag
+    }
+
+    pub(crate) fn add_to_bag(&mut self, bag: usize, item: Term) {
+        self.bags.entry(bag).or_default().push(item);
+    }
+
+    pub(crate) fn close_bag(&mut self, bag: usize) -> Vec<Term> {
+        self.bags.remove(&bag).unwrap_or_default()
+    }
+
+    // an error raised outside of any builtin, without a context
+    fn error(&mut self, formal: Term) -> Term {
+        Term::compound("error", vec![formal, self.fresh_var()])
+    }
+
+    // run a goal that hands errors raised while it runs to the recovery of the first
+    // catcher that unifies with them
+    pub(crate) fn push_catch(&mut self, goal: Term, catcher: Term, recovery: Term) {
+        self.goals.push(Goal::Catch(Box::new(Catch {
+            catcher,
+            recovery,
+            trail: self.env.mark(),
+            choicepoints: self.choicepoints.len(),
+        })));
+        self.push_call(goal);
+    }
+
+    // unwind to the innermost catch/3 still running whose catcher unifies with an error,
+    // and run its recovery instead, or give the error back if there is none
+    fn recover(&mut self, ball: Term) -> Result<(), Term> {
+        if is_halt(&ball) {
+            return Err(ball);
+        }
+
+        while let Some(i) = self.goals.iter().rposition(|g| matches!(g, Goal::Catch(_))) {
+            let catch = match self.goals.split_off(i).into_iter().next() {
+                Some(Goal::Catch(catch)) => catch,
+                _ => unreachable!(),
+            };
+
+            self.env.undo_to(catch.trail);
+            self.choicepoints.truncate(catch.choicepoints);
+
+            if self.unify(&catch.catcher, &ball) {
+                self.push_call(catch.recovery);
+                return Ok(());
+            }
+        }
+
+        Err(ball)
+    }
+
+    // forget what the trail holds from before the oldest choicepoint or catch, in the
+    // goals now or in those backtracking would run, as nothing can undo it any more
+    fn trim_trail(&mut self) {
+        let stacks = Some(&self.goals).into_iter();
+        let goals = stacks
+            .chain(self.choicepoints.iter().map(|c| &c.goals))
+            .flatten();
+        let catches = goals.filter_map(|goal| match goal {
+            Goal::Catch(catch) => Some(catch.trail),
+            _ => None,
+        });
+
+        let oldest = self
+            .choicepoints
+            .iter()
+            .map(|c| c.trail)
+            .chain(catches)
+            .min();
+        self.env
+            .forget_before(oldest.unwrap_or_else(|| self.env.mark()));
+    }
+
+    // a copy of a stored term with its variables renamed apart from all others
+    pub(crate) fn rename(&mut self, t: &Term) -> Term {
+        self.depth += 1;
+        renumber_term(self.depth, t)
+    }
+
+    // leave a goal to be run instead of the rest of the current one on backtracking
+    pub(crate) fn push_alternative(&mut self, goal: Term) {
+        self.push_alternative_from(self.env.mark(), goal);
+    }
+
+    // an alternative that starts over from bindings the query had earlier
+    pub(crate) fn push_alternative_from(&mut self, trail: usize, goal: Term) {
+        self.choicepoints.push(Choicepoint {
+            alternative: Alternative::Goal(goal),
+            trail,
+            goals: self.goals.clone(),
+        });
+    }
+
+    pub(crate) fn unify(&mut self, t1: &Term, t2: &Term) -> bool {
+        let mark = self.env.mark();
+        let result = self.env.unify(t1, t2);
+
+        if self.unified(mark, result) {
+            self.wake();
+            return true;
+        }
+
+        false
+    }
+
+    // run the hooks of the attributed variables bound by the last unification before any
+    // other goal, as `Module:attr_unify_hook(Value, Other)`; an attributed variable bound
+    // to a plain variable hands its attributes over instead
+    fn wake(&mut self) {
+        for (x, attributes) in self.env.take_bound_attributes() {
+            match self.env.deref(&Term::Var(x)) {
+                Term::Var(y) if !self.env.is_attributed(&y) => {
+                    self.env.set_attributes(y, attributes)
+                }
+                other => {
+                    for (module, value) in attributes.into_iter().rev() {
+                        let hook = Term::compound("attr_unify_hook", vec![value, other.clone()]);
+                        self.push_goal(Term::compound(":", vec![Term::atom(&module), hook]));
+                    }
+                }
+            }
+        }
+    }
+
+    // whether a unification succeeded, undoing what it bound back to a mark if it failed
+    // and noting the error to raise if it failed the occurs check when that should be an
+    // error
+    fn unified(&mut self, mark: usize, result: Result<(), UnifyErr>) -> bool {
+        let err = match result {
+            Ok(()) => return true,
+            Err(err) => err,
+        };
+
+        self.env.undo_to(mark);
+        if let UnifyErr::Cyclic(x, t) = err {
+            if self.occurs_check == OccursCheck::Error {
+                let formal = Term::compound("occurs_check", vec![Term::Var(x), t]);
+                let ball = self.error(formal);
+                self.exception.get_or_insert(ball);
+            }
+        }
+
+        false
+    }
+
+    // try the clauses of a predicate in turn, leaving a choicepoint for the rest; a cut
+    // in the body of a clause drops that choicepoint and those its goals left
+    fn resolve(&mut self, goal: Atom, mut clauses: Vec<Arc<Assertion>>) -> bool {
+        self.depth += 1;
+        let n = self.depth;
+        let height = self.choicepoints.len();
+        let mark = self.env.mark();
+
+        while let Some(assertion) = clauses.pop() {
+            let result = self
+                .env
+                .unify_atoms(&goal, &renumber_atom(n, &assertion.head));
+            if self.unified(mark, result) {
+                if !clauses.is_empty() {
+                    self.choicepoints.push(Choicepoint {
+                        alternative: Alternative::Clauses(goal, clauses),
+                        trail: mark,
+                        goals: self.goals.clone(),
+                    });
+                }
+
+                for a in assertion.clause.iter().rev() {
+                    self.push_goal(cut_to(renumber_atom(n, a).into(), height));
+                }
+                self.wake();
+
+                return true;
+            }
+        }
+
+        false
+    }
+
+    fn backtrack(&mut self) -> bool {
+        while let Some(Choicepoint {
+            alternative,
+            trail,
+            goals,
+        }) = self.choicepoints.pop()
+        {
+            self.env.undo_to(trail);
+            self.goals = goals;
+
+            match alternative {
+                Alternative::Clauses(goal, clauses) => {
+                    if self.resolve(goal, clauses) {
+                        return true;
+                    }
+                }
+                Alternative::Goal(goal) => {
+                    self.push_goal(goal);
+                    return true;
+                }
+            }
+        }
+
+        false
+    }
+}
+
+// a goal with each cut it makes itself, rather than in a goal it calls, made a cut back to
+// a number of choicepoints: those in conjunctions, disjunctions and the branches of
+// if-then-else, but not in a condition, whose cut is local to it
+fn cut_to(goal: Term, choicepoints: usize) -> Term {
+    match goal {
+        Term::Const(Const(ref name)) if name == "!" => {
+            Term::compound("$cut", vec![Term::int(choicepoints as i64)])
+        }
+        Term::Atom(a) if a.arity == 2 && [",", ";", "->"].contains(&a.name.0.as_str()) => {
+            let (name, mut args) = a.into_parts();
+            let right = cut_to(args.pop().unwrap(), choicepoints);
+            let mut left = args.pop().unwrap();
+            if name.0 != "->" {
+                left = cut_to(left, choicepoints);
+            }
+
+            Term::compound(&name.0, vec![left, right])
+        }
+        goal => goal,
+    }
+}
+
+fn is_op_directive(goal: &Term) -> bool {
+    matches!(goal, Term::Atom(a) if a.name.0 == "op" && a.arity == 3)
+}
+
+fn is_initialization(goal: &Term) -> bool {
+    matches!(goal, Term::Atom(a) if a.name.0 == "initialization" && a.arity == 1)
+}
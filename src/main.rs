@@ -1,52 +1,327 @@
-use bfg_prolog::ast;
-use bfg_prolog::ast::{Assertion, Atom, Clause, Const, Term};
-use bfg_prolog::solve_toplevel;
-use lalrpop_util::lalrpop_mod;
-use std::fs::read_to_string;
+use bfg_prolog::ast::{Clause, Const, Term};
+use bfg_prolog::database::Segment;
+use bfg_prolog::lexer::{Lexer, ParseError};
+use bfg_prolog::machine::Machine;
+use bfg_prolog::writer::Writer;
+use bfg_prolog::{answer_query, parser, run_goal, WamError};
 use std::io::Write;
 
-lalrpop_mod!(pub parser);
+const USAGE: &str = "usage: bfg-prolog [run FILE... [-g GOAL]]";
+
+// the arguments of `run`: the files to consult, in order, and the goal to run then, if
+// one was given
+#[derive(Debug, PartialEq)]
+struct Run {
+    files: Vec<String>,
+    goal: Option<String>,
+}
 
 fn main() {
-    let mut source = Vec::new();
+    let args: Vec<String> = std::env::args().skip(1).collect();
+
+    match args.split_first() {
+        None => toplevel(),
+        Some((command, args)) if command == "run" => match parse_run(args) {
+            Ok(run) => std::process::exit(run_files(&run)),
+            Err(e) => {
+                eprintln!("{}\n{}", e, USAGE);
+                std::process::exit(2);
+            }
+        },
+        Some(_) => {
+            eprintln!("{}", USAGE);
+            std::process::exit(2);
+        }
+    }
+}
+
+fn parse_run(args: &[String]) -> Result<Run, String> {
+    let mut files = Vec::new();
+    let mut goal = None;
+    let mut args = args.iter();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-g" => match args.next() {
+                Some(g) => goal = Some(g.clone()),
+                None => return Err(String::from("-g needs a goal")),
+            },
+            option if option.starts_with('-') => {
+                return Err(format!("unknown option {}", option));
+            }
+            file => files.push(String::from(file)),
+        }
+    }
+
+    if files.is_empty() {
+        return Err(String::from("no files to run"));
+    }
+
+    Ok(Run { files, goal })
+}
+
+// consult the files and run the goal, giving the exit status: 0 if the goal succeeded,
+// 1 if it failed, and 2 if it raised an error or a file could not be loaded. Without a
+// goal, the one a file gave by `initialization(Goal, main)` is run, or else main/0,
+// unless a file already ran its own goals once loaded with `initialization(Goal)`
+fn run_files(run: &Run) -> i32 {
+    let mut machine = Machine::new();
+    let mut main = None;
+    let mut initialized = false;
+
+    for path in &run.files {
+        match machine.consult_file(path) {
+            Ok(consulted) => {
+                for warning in consulted.warnings {
+                    eprintln!("Warning: {}: {}", path, warning);
+                }
+                main = consulted.main.or(main);
+                initialized |= !consulted.initialized.is_empty();
+            }
+            Err(WamError::Halt) => return 0,
+            Err(e) => {
+                eprintln!("Error: {}: {}", path, error_text(&machine, &e));
+                return 2;
+            }
+        }
+    }
+
+    let (goal, solved) = match (&run.goal, main) {
+        (Some(goal), _) => {
+            let goal = goal.trim_end();
+            let query = if goal.ends_with('.') {
+                String::from(goal)
+            } else {
+                format!("{}.", goal)
+            };
+
+            (String::from(goal), machine.solve(&query))
+        }
+        (None, Some(main)) => (main.to_string(), machine.solve_term(main)),
+        (None, None) if initialized => return 0,
+        (None, None) => (String::from("main"), machine.solve("main.")),
+    };
+
+    let mut solutions = match solved {
+        Ok(solutions) => solutions,
+        Err(e) => {
+            eprintln!("Error: {}", error_text(&machine, &e));
+            return 2;
+        }
+    };
+
+    if solutions.next().is_some() || solutions.halted() {
+        return 0;
+    }
+
+    match solutions.exception().cloned() {
+        Some(ball) => {
+            drop(solutions);
+            eprintln!(
+                "Error: {}",
+                error_text(&machine, &WamError::Execution(ball))
+            );
+            2
+        }
+        None => {
+            eprintln!("Warning: goal failed: {}", goal);
+            1
+        }
+    }
+}
+
+// an error as it is reported, with any ball written with the machine's operators
+fn error_text(machine: &Machine, e: &WamError) -> String {
+    match e {
+        WamError::Execution(ball) => format!(
+            "uncaught exception: {}",
+            Writer::new(&machine.ops).quoted(true).write(ball)
+        ),
+        e => e.to_string(),
+    }
+}
+
+fn toplevel() {
+    let mut machine = Machine::new();
     let consult_const = Const::new("consult");
 
     loop {
         print!("?- ");
-        std::io::stdout().flush().expect("Could not flush stdout");
+        let _ = std::io::stdout().flush();
 
         let mut input_buffer = String::new();
-        std::io::stdin()
-            .read_line(&mut input_buffer)
-            .expect("error reading input");
+        let read = match std::io::stdin().read_line(&mut input_buffer) {
+            Ok(read) => read,
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                0
+            }
+        };
 
-        let query = parse_query(&input_buffer);
+        if read == 0 {
+            println!();
+            break;
+        }
 
-        if query.len() == 1 && query[0].name == consult_const && query[0].arity == 1 {
-            if let Term::Atom(Atom { name: Const(p), .. }) = &query[0].args[0] {
-                source = read_source_code(p);
-                solve_toplevel(true, &source, (&query[1..]).to_vec());
+        let query = match parse_query(&machine, &input_buffer) {
+            Ok(query) => query,
+            Err(e) => {
+                eprintln!("{}", e);
+                continue;
+            }
+        };
+        machine.db.clear(Segment::Query);
+
+        let query = if query.len() == 1 && query[0].name == consult_const && query[0].arity == 1 {
+            match &query[0].args[0] {
+                Term::Const(Const(p)) => {
+                    consult(&mut machine, p);
+                    query[1..].to_vec()
+                }
+                _ => continue,
             }
         } else {
-            solve_toplevel(true, &source, query);
+            query
+        };
+
+        let stdin = std::io::stdin();
+        let answered = answer_query(
+            &mut machine,
+            query,
+            &mut stdin.lock(),
+            &mut std::io::stdout(),
+        );
+        match answered {
+            Ok(()) => (),
+            Err(WamError::Halt) => break,
+            Err(e) => eprintln!("Error: {}", e),
         }
     }
 }
 
-fn read_source_code(path: &str) -> Vec<Assertion> {
-    let s = read_to_string(String::from(path)).unwrap();
-    let mut source = parse_code(&s);
-    source.reverse();
+fn consult(machine: &mut Machine, path: &str) {
+    let consulted = match machine.consult_file(path) {
+        Ok(consulted) => consulted,
+        Err(WamError::Halt) => std::process::exit(0),
+        Err(e) => {
+            eprintln!("Error: {}: {}", path, e);
+            return;
+        }
+    };
+
+    for warning in consulted.warnings {
+        eprintln!("Warning: {}: {}", path, warning);
+    }
 
-    source
+    if let Some(goal) = consulted.main {
+        let status = match run_goal(machine, goal) {
+            Ok(true) => 0,
+            Ok(false) => 1,
+            Err(WamError::Halt) => 0,
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                1
+            }
+        };
+        std::process::exit(status);
+    }
 }
 
-fn parse_code(code: &str) -> Vec<Assertion> {
-    let code_parser = parser::CodeParser::new();
-    code_parser.parse(code).unwrap()
+fn parse_query(machine: &Machine, query: &str) -> Result<Clause, ParseError> {
+    let clause_parser = parser::ClauseParser::new();
+    let lexer = Lexer::new(query).double_quotes(machine.flags.double_quotes);
+    clause_parser
+        .parse(&machine.ops, lexer)
+        .map_err(|e| ParseError::new(query, e))
 }
 
-fn parse_query(query: &str) -> Clause {
-    let clause_parser = parser::ClauseParser::new();
-    clause_parser.parse(query).unwrap()
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|&arg| String::from(arg)).collect()
+    }
+
+    #[test]
+    fn test_parse_run_1_succeeds() {
+        assert_eq!(
+            parse_run(&args(&["a.pl", "-g", "go(1)", "b.pl"])),
+            Ok(Run {
+                files: args(&["a.pl", "b.pl"]),
+                goal: Some(String::from("go(1)")),
+            })
+        );
+        assert_eq!(parse_run(&args(&["a.pl"])).unwrap().goal, None);
+    }
+
+    // a program written to a file of its own, for run_files to consult
+    fn program(name: &str, code: &str) -> String {
+        let path = std::env::temp_dir().join(format!("bfg-prolog-{}.pl", name));
+        std::fs::write(&path, code).unwrap();
+        path.to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn test_run_files_1_succeeds() {
+        let path = program("main", ":- initialization(go, main).\ngo.\nmain :- fail.\n");
+        let run = |goal: Option<&str>| {
+            run_files(&Run {
+                files: vec![path.clone()],
+                goal: goal.map(String::from),
+            })
+        };
+
+        assert_eq!(run(None), 0);
+        assert_eq!(run(Some("main")), 1);
+        assert_eq!(run(Some("throw(oops)")), 2);
+    }
+
+    #[test]
+    fn test_run_files_2_succeeds() {
+        // main/0 only succeeds the first time, so running it again would fail
+        let code = ":- dynamic(ran/0).\n:- initialization(main).\nmain :- \\+ ran, assertz(ran).\n";
+        let run = Run {
+            files: vec![program("initialization", code)],
+            goal: None,
+        };
+
+        assert_eq!(run_files(&run), 0);
+    }
+
+    #[test]
+    fn test_run_files_1_fails() {
+        let path = program("fails", ":- initialization(go, main).\ngo :- fail.\n");
+        let run = Run {
+            files: vec![path],
+            goal: None,
+        };
+
+        assert_eq!(run_files(&run), 1);
+    }
+
+    #[test]
+    fn test_error_text_1_succeeds() {
+        let mut machine = Machine::new();
+        let ball = machine
+            .solve("catch(X is foo + 1, E, true).")
+            .unwrap()
+            .next()
+            .unwrap()
+            .term("E")
+            .cloned()
+            .unwrap();
+
+        assert_eq!(
+            error_text(&machine, &WamError::Execution(ball)),
+            "uncaught exception: error(type_error(evaluable,foo/0),context((is)/2,_1))"
+        );
+    }
+
+    #[test]
+    fn test_parse_run_1_fails() {
+        assert!(parse_run(&args(&[])).is_err());
+        assert!(parse_run(&args(&["a.pl", "-g"])).is_err());
+        assert!(parse_run(&args(&["a.pl", "-x"])).is_err());
+    }
 }
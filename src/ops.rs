@@ -0,0 +1,386 @@
+use crate::ast::{Atom, Const, Number, Term};
+use crate::lexer::SyntaxError;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum OpType {
+    Xfx,
+    Xfy,
+    Yfx,
+    Fy,
+    Fx,
+    Xf,
+    Yf,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct OpDef {
+    pub priority: usize,
+    pub op_type: OpType,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OpTable {
+    prefix: HashMap<String, OpDef>,
+    infix: HashMap<String, OpDef>,
+    postfix: HashMap<String, OpDef>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Item {
+    Name(String, usize, usize),
+    // an integer is kept as its digits until it is known whether a `-` negates it
+    Int(u64, usize, usize),
+    Term(Term, usize, usize),
+    // a name directly followed by `(`: the arguments of a compound, or the bracketed
+    // operand of an infix operator, which only its place among the items tells apart
+    Call(String, Vec<Vec<Item>>, usize, usize),
+}
+
+const ISO_OPS: &[(usize, OpType, &str)] = &[
+    (1200, OpType::Xfx, ":-"),
+    (1200, OpType::Xfx, "-->"),
+    (1200, OpType::Fx, ":-"),
+    (1200, OpType::Fx, "?-"),
+    (1150, OpType::Fx, "dynamic"),
+    (1150, OpType::Fx, "discontiguous"),
+    (1150, OpType::Fx, "initialization"),
+    (1150, OpType::Fx, "multifile"),
+    (1100, OpType::Xfy, ";"),
+    (1100, OpType::Xfy, "|"),
+    (1050, OpType::Xfy, "->"),
+    (1050, OpType::Xfy, "*->"),
+    (1000, OpType::Xfy, ","),
+    (900, OpType::Fy, "\\+"),
+    (700, OpType::Xfx, "="),
+    (700, OpType::Xfx, "\\="),
+    (700, OpType::Xfx, "=="),
+    (700, OpType::Xfx, "\\=="),
+    (700, OpType::Xfx, "@<"),
+    (700, OpType::Xfx, "@>"),
+    (700, OpType::Xfx, "@=<"),
+    (700, OpType::Xfx, "@>="),
+    (700, OpType::Xfx, "=.."),
+    (700, OpType::Xfx, "is"),
+    (700, OpType::Xfx, "=:="),
+    (700, OpType::Xfx, "=\\="),
+    (700, OpType::Xfx, "<"),
+    (700, OpType::Xfx, ">"),
+    (700, OpType::Xfx, "=<"),
+    (700, OpType::Xfx, ">="),
+    (600, OpType::Xfy, ":"),
+    (500, OpType::Yfx, "+"),
+    (500, OpType::Yfx, "-"),
+    (500, OpType::Yfx, "/\\"),
+    (500, OpType::Yfx, "\\/"),
+    (500, OpType::Yfx, "xor"),
+    (400, OpType::Yfx, "*"),
+    (400, OpType::Yfx, "/"),
+    (400, OpType::Yfx, "//"),
+    (400, OpType::Yfx, "rem"),
+    (400, OpType::Yfx, "mod"),
+    (400, OpType::Yfx, "div"),
+    (400, OpType::Yfx, "<<"),
+    (400, OpType::Yfx, ">>"),
+    (200, OpType::Xfx, "**"),
+    (200, OpType::Xfy, "^"),
+    (200, OpType::Fy, "-"),
+    (200, OpType::Fy, "+"),
+    (200, OpType::Fy, "\\"),
+];
+
+impl OpType {
+    pub fn is_prefix(self) -> bool {
+        matches!(self, OpType::Fy | OpType::Fx)
+    }
+
+    pub fn is_postfix(self) -> bool {
+        matches!(self, OpType::Xf | OpType::Yf)
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "xfx" => Some(OpType::Xfx),
+            "xfy" => Some(OpType::Xfy),
+            "yfx" => Some(OpType::Yfx),
+            "fy" => Some(OpType::Fy),
+            "fx" => Some(OpType::Fx),
+            "xf" => Some(OpType::Xf),
+            "yf" => Some(OpType::Yf),
+            _ => None,
+        }
+    }
+}
+
+impl OpDef {
+    // maximum priorities of the (left, right) arguments
+    pub fn arg_priorities(self) -> (usize, usize) {
+        let p = self.priority;
+
+        match self.op_type {
+            OpType::Xfx => (p - 1, p - 1),
+            OpType::Xfy => (p - 1, p),
+            OpType::Yfx => (p, p - 1),
+            OpType::Fy => (0, p),
+            OpType::Fx => (0, p - 1),
+            OpType::Xf => (p - 1, 0),
+            OpType::Yf => (p, 0),
+        }
+    }
+}
+
+impl Default for OpTable {
+    fn default() -> Self {
+        let mut ops = OpTable::new();
+
+        for &(priority, op_type, name) in ISO_OPS {
+            ops.add(priority, op_type, name);
+        }
+
+        ops
+    }
+}
+
+impl OpTable {
+    pub fn new() -> Self {
+        OpTable {
+            prefix: HashMap::new(),
+            infix: HashMap::new(),
+            postfix: HashMap::new(),
+        }
+    }
+
+    // a priority of 0 removes the definition, as with op/3
+    pub fn add(&mut self, priority: usize, op_type: OpType, name: &str) {
+        let table = if op_type.is_prefix() {
+            &mut self.prefix
+        } else if op_type.is_postfix() {
+            &mut self.postfix
+        } else {
+            &mut self.infix
+        };
+
+        if priority == 0 {
+            table.remove(name);
+        } else {
+            table.insert(String::from(name), OpDef { priority, op_type });
+        }
+    }
+
+    pub fn prefix(&self, name: &str) -> Option<OpDef> {
+        self.prefix.get(name).cloned()
+    }
+
+    pub fn infix(&self, name: &str) -> Option<OpDef> {
+        self.infix.get(name).cloned()
+    }
+
+    pub fn postfix(&self, name: &str) -> Option<OpDef> {
+        self.postfix.get(name).cloned()
+    }
+
+    pub fn is_op(&self, name: &str) -> bool {
+        self.prefix.contains_key(name)
+            || self.infix.contains_key(name)
+            || self.postfix.contains_key(name)
+    }
+
+    pub fn resolve(&self, items: Vec<Item>, max: usize) -> Result<Term, SyntaxError> {
+        let end = items.last().map(Item::end).unwrap_or(0);
+        let mut resolver = Resolver {
+            ops: self,
+            items,
+            pos: 0,
+            end,
+        };
+
+        let (t, _) = resolver.parse(max)?;
+
+        match resolver.items.get(resolver.pos) {
+            None => Ok(t),
+            Some(Item::Name(name, start, _)) if self.infix(name).is_some() => {
+                Err(SyntaxError::OperatorClash(*start))
+            }
+            Some(item) => Err(SyntaxError::OperatorExpected(item.start())),
+        }
+    }
+
+    // the term in brackets after an infix operator written right before them, as in
+    // `a-(b, c)`, whose commas were taken for those between arguments
+    fn bracketed(&self, args: Vec<Vec<Item>>) -> Result<Term, SyntaxError> {
+        let mut items = Vec::new();
+
+        for seq in args {
+            if let Some(last) = items.last().map(Item::end) {
+                items.push(Item::Name(String::from(","), last, last));
+            }
+            items.extend(seq);
+        }
+
+        self.resolve(items, 1200)
+    }
+}
+
+impl Item {
+    fn start(&self) -> usize {
+        match self {
+            Item::Name(_, start, _)
+            | Item::Int(_, start, _)
+            | Item::Term(_, start, _)
+            | Item::Call(_, _, start, _) => *start,
+        }
+    }
+
+    fn end(&self) -> usize {
+        match self {
+            Item::Name(_, _, end)
+            | Item::Int(_, _, end)
+            | Item::Term(_, _, end)
+            | Item::Call(_, _, _, end) => *end,
+        }
+    }
+}
+
+struct Resolver<'a> {
+    ops: &'a OpTable,
+    items: Vec<Item>,
+    pos: usize,
+    end: usize,
+}
+
+impl<'a> Resolver<'a> {
+    fn parse(&mut self, max: usize) -> Result<(Term, usize), SyntaxError> {
+        let (mut left, mut left_priority) = self.parse_primary(max)?;
+
+        loop {
+            let name = match self.items.get(self.pos) {
+                Some(Item::Name(name, _, _)) => name.clone(),
+                Some(Item::Call(name, args, _, _)) if self.ops.infix(name).is_some() => {
+                    let def = self.ops.infix(name).unwrap();
+                    let (left_max, _) = def.arg_priorities();
+
+                    if def.priority > max || left_priority > left_max {
+                        break;
+                    }
+
+                    let right = self.ops.bracketed(args.clone())?;
+                    left = compound(name, vec![left, right]);
+                    left_priority = def.priority;
+                    self.pos += 1;
+                    continue;
+                }
+                _ => break,
+            };
+
+            if let Some(def) = self.ops.infix(&name) {
+                let (left_max, right_max) = def.arg_priorities();
+
+                if def.priority <= max && left_priority <= left_max {
+                    self.pos += 1;
+                    let (right, _) = self.parse(right_max)?;
+                    left = compound(&name, vec![left, right]);
+                    left_priority = def.priority;
+                    continue;
+                }
+            }
+
+            if let Some(def) = self.ops.postfix(&name) {
+                let (left_max, _) = def.arg_priorities();
+
+                if def.priority <= max && left_priority <= left_max {
+                    self.pos += 1;
+                    left = compound(&name, vec![left]);
+                    left_priority = def.priority;
+                    continue;
+                }
+            }
+
+            break;
+        }
+
+        Ok((left, left_priority))
+    }
+
+    fn parse_primary(&mut self, max: usize) -> Result<(Term, usize), SyntaxError> {
+        let item = match self.items.get(self.pos) {
+            None => return Err(SyntaxError::UnexpectedEnd(self.end)),
+            Some(item) => item.clone(),
+        };
+
+        self.pos += 1;
+
+        match item {
+            Item::Term(t, _, _) => Ok((t, 0)),
+            Item::Call(name, args, _, _) => {
+                let args = args
+                    .into_iter()
+                    .map(|items| self.ops.resolve(items, 999))
+                    .collect::<Result<_, _>>()?;
+                Ok((compound(&name, args), 0))
+            }
+            Item::Int(n, start, _) => match i64::try_from(n) {
+                Ok(i) => Ok((Term::Number(Number::Int(i)), 0)),
+                Err(_) => Err(SyntaxError::IntegerOverflow(start)),
+            },
+            Item::Name(name, _, end) => {
+                if let Some(n) = self.negative_literal(&name, end) {
+                    return Ok((n, 0));
+                }
+
+                if let Some(def) = self.ops.prefix(&name) {
+                    if self.starts_operand() {
+                        let priority = if def.priority > max {
+                            999
+                        } else {
+                            def.priority
+                        };
+                        let (_, arg_max) = def.arg_priorities();
+                        let (arg, _) = self.parse(arg_max.min(priority))?;
+
+                        return Ok((compound(&name, vec![arg]), priority));
+                    }
+                }
+
+                Ok((Term::Const(Const::new(&name)), 0))
+            }
+        }
+    }
+
+    // a `-` directly followed by a numeric literal denotes a negative number
+    fn negative_literal(&mut self, name: &str, end: usize) -> Option<Term> {
+        if name != "-" {
+            return None;
+        }
+
+        let n = match self.items.get(self.pos) {
+            // the magnitude of i64::MIN wraps to itself
+            Some(Item::Int(n, start, _)) if *start == end => {
+                Number::Int((*n as i64).wrapping_neg())
+            }
+            Some(Item::Term(Term::Number(Number::Float(x)), start, _)) if *start == end => {
+                Number::Float(-x)
+            }
+            _ => return None,
+        };
+
+        self.pos += 1;
+        Some(Term::Number(n))
+    }
+
+    // whether the item after a prefix operator can be its argument
+    fn starts_operand(&self) -> bool {
+        match self.items.get(self.pos) {
+            None => false,
+            Some(Item::Int(_, _, _)) | Some(Item::Term(_, _, _)) | Some(Item::Call(..)) => true,
+            Some(Item::Name(name, _, _)) => {
+                self.ops.prefix(name).is_some()
+                    || (self.ops.infix(name).is_none() && self.ops.postfix(name).is_none())
+            }
+        }
+    }
+}
+
+fn compound(name: &str, args: Vec<Term>) -> Term {
+    Term::Atom(Atom::new(name, args))
+}
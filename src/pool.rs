@@ -0,0 +1,79 @@
+use crate::machine::{Machine, Solution};
+use crate::WamError;
+use std::panic;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+
+// runs independent queries against one program on several threads; each query gets a
+// machine of its own, forked from the pool's, so the clauses are shared while what a
+// query asserts or retracts is seen by no other query
+pub struct QueryPool {
+    machine: Mutex<Machine>,
+    threads: usize,
+}
+
+impl QueryPool {
+    pub fn new(machine: Machine, threads: usize) -> Self {
+        QueryPool {
+            machine: Mutex::new(machine),
+            threads: threads.max(1),
+        }
+    }
+
+    // every solution of each query, in the order the queries were given
+    pub fn run(&self, queries: &[&str]) -> Vec<Result<Vec<Solution>, WamError>> {
+        let next = AtomicUsize::new(0);
+        let mut results: Vec<_> = queries.iter().map(|_| None).collect();
+
+        thread::scope(|scope| {
+            let workers: Vec<_> = (0..self.threads.min(queries.len()))
+                .map(|_| {
+                    scope.spawn(|| {
+                        let mut done = Vec::new();
+
+                        loop {
+                            let i = next.fetch_add(1, Ordering::Relaxed);
+                            match queries.get(i) {
+                                Some(query) => done.push((i, solve_all(&mut self.fork(), query))),
+                                None => break,
+                            }
+                        }
+
+                        done
+                    })
+                })
+                .collect();
+
+            for worker in workers {
+                let done = worker.join().unwrap_or_else(|e| panic::resume_unwind(e));
+                for (i, result) in done {
+                    results[i] = Some(result);
+                }
+            }
+        });
+
+        results.into_iter().flatten().collect()
+    }
+
+    fn fork(&self) -> Machine {
+        // a query that panicked leaves the pool's machine as it was, since queries only
+        // ever run on forks of it
+        let machine = self.machine.lock().unwrap_or_else(|e| e.into_inner());
+        machine.fork()
+    }
+}
+
+fn solve_all(machine: &mut Machine, query: &str) -> Result<Vec<Solution>, WamError> {
+    let mut solutions = machine.solve(query)?;
+    let found = solutions.by_ref().collect();
+
+    if solutions.halted() {
+        return Err(WamError::Halt);
+    }
+
+    match solutions.exception() {
+        Some(ball) => Err(WamError::Execution(ball.clone())),
+        None => Ok(found),
+    }
+}
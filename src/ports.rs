@@ -0,0 +1,18 @@
+use crate::ast::Term;
+
+// the points of the box model at which a debugger sees a goal: entering it, leaving it
+// with a solution, coming back into it for another, and leaving it with none
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Port {
+    Call,
+    Exit,
+    Redo,
+    Fail,
+}
+
+// told of every goal passing through a port while a machine runs, for tracers and
+// profilers; `depth` counts the goals still running that the goal was called from, and
+// `deterministic` tells at exit whether the goal left nothing to backtrack into
+pub trait PortListener: Send {
+    fn port(&mut self, port: Port, goal: &Term, depth: usize, deterministic: bool);
+}
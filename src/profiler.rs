@@ -0,0 +1,98 @@
+use crate::ast::Term;
+use crate::database::Key;
+use crate::ports::Port;
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::time::{Duration, Instant};
+
+// what profile/1 found: for each predicate called, how many of its goals went through
+// each port, and how long they ran from their call or redo to their exit or failure;
+// the time of a goal includes that of the goals it called
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Profile {
+    pub predicates: HashMap<Key, PredicateProfile>,
+}
+
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub struct PredicateProfile {
+    pub calls: u64,
+    pub redos: u64,
+    pub exits: u64,
+    pub fails: u64,
+    pub time: Duration,
+}
+
+// a profile being taken, with when each goal still running was last entered
+#[derive(Debug, Default)]
+pub(crate) struct Profiler {
+    profile: Profile,
+    entered: HashMap<usize, Instant>,
+}
+
+impl Profiler {
+    // count a goal at a port, given the frame it runs in; the machine's own predicates,
+    // named with a `$`, are left out
+    pub(crate) fn port(&mut self, port: Port, frame: usize, goal: &Term) {
+        let key = match goal {
+            Term::Atom(a) => Key::of(a),
+            Term::Const(c) => Key::new(&c.0, 0),
+            _ => return,
+        };
+
+        if key.0.starts_with('$') {
+            return;
+        }
+
+        let predicate = self.profile.predicates.entry(key).or_default();
+        match port {
+            Port::Call => predicate.calls += 1,
+            Port::Redo => predicate.redos += 1,
+            Port::Exit => predicate.exits += 1,
+            Port::Fail => predicate.fails += 1,
+        }
+
+        match port {
+            Port::Call | Port::Redo => {
+                self.entered.insert(frame, Instant::now());
+            }
+            Port::Exit | Port::Fail => {
+                if let Some(entered) = self.entered.remove(&frame) {
+                    predicate.time += entered.elapsed();
+                }
+            }
+        }
+    }
+
+    pub(crate) fn finish(self) -> Profile {
+        self.profile
+    }
+}
+
+// a table of the predicates, those that took longest first
+impl Display for Profile {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), std::fmt::Error> {
+        let mut predicates: Vec<_> = self.predicates.iter().collect();
+        predicates.sort_by(|(k1, p1), (k2, p2)| p2.time.cmp(&p1.time).then(k1.cmp(k2)));
+
+        writeln!(
+            f,
+            "{:<24} {:>8} {:>8} {:>8} {:>8} {:>10}",
+            "Predicate", "Calls", "Redos", "Exits", "Fails", "Time"
+        )?;
+
+        for (key, p) in predicates {
+            writeln!(
+                f,
+                "{:<24} {:>8} {:>8} {:>8} {:>8} {:>9.3}s",
+                key.to_string(),
+                p.calls,
+                p.redos,
+                p.exits,
+                p.fails,
+                p.time.as_secs_f64()
+            )?;
+        }
+
+        Ok(())
+    }
+}
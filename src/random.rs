@@ -0,0 +1,90 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// the random numbers of a machine, from a splitmix64 generator: seeded from the clock
+// unless set_random/1 gives a seed, so that a test can replay the same numbers
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) struct Random(u64);
+
+impl Default for Random {
+    fn default() -> Self {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_nanos() as u64);
+
+        Random(now)
+    }
+}
+
+impl Random {
+    pub(crate) fn seeded(seed: u64) -> Self {
+        Random(seed)
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    // a float from 0.0 up to but not including 1.0
+    pub(crate) fn float(&mut self) -> f64 {
+        (self.next() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    // an integer from 0 below n, with no bias toward the low ones
+    pub(crate) fn below(&mut self, n: u64) -> u64 {
+        let limit = u64::MAX - u64::MAX % n;
+
+        loop {
+            let x = self.next();
+            if x < limit {
+                return x % n;
+            }
+        }
+    }
+
+    // an integer from low to high, both included
+    pub(crate) fn between(&mut self, low: i64, high: i64) -> i64 {
+        let span = high.wrapping_sub(low) as u64;
+
+        match span.checked_add(1) {
+            Some(n) => low.wrapping_add(self.below(n) as i64),
+            None => self.next() as i64,
+        }
+    }
+
+    pub(crate) fn shuffle<T>(&mut self, items: &mut [T]) {
+        for i in (1..items.len()).rev() {
+            let j = self.below(i as u64 + 1) as usize;
+            items.swap(i, j);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_random_1_succeeds() {
+        let mut random = Random::seeded(42);
+        let mut again = Random::seeded(42);
+        assert_eq!(random.between(1, 6), again.between(1, 6));
+
+        for _ in 0..1000 {
+            let f = random.float();
+            assert!((0.0..1.0).contains(&f));
+            assert!((-3..=3).contains(&random.between(-3, 3)));
+        }
+
+        assert_eq!(random.between(i64::MIN, i64::MIN), i64::MIN);
+        random.between(i64::MIN, i64::MAX);
+
+        let mut items: Vec<_> = (0..10).collect();
+        random.shuffle(&mut items);
+        items.sort();
+        assert_eq!(items, (0..10).collect::<Vec<_>>());
+    }
+}
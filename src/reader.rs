@@ -0,0 +1,103 @@
+use crate::ast::{Number, Term, Var};
+use crate::lexer::{DoubleQuotes, Lexer, ParseError, Tok};
+use crate::ops::OpTable;
+use crate::parser::ReadParser;
+use std::convert::TryFrom;
+
+// reads a single term ended by `.`, as read_term/2 does
+#[derive(Debug, Copy, Clone)]
+pub struct Reader<'ops> {
+    ops: &'ops OpTable,
+    double_quotes: DoubleQuotes,
+}
+
+// the term read with its named variables in order of appearance, as with the
+// variable_names and singletons options of read_term/2
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReadTerm {
+    pub term: Term,
+    pub variable_names: Vec<(String, Var)>,
+    pub singletons: Vec<(String, Var)>,
+}
+
+impl<'ops> Reader<'ops> {
+    pub fn new(ops: &'ops OpTable) -> Self {
+        Reader {
+            ops,
+            double_quotes: DoubleQuotes::default(),
+        }
+    }
+
+    pub fn double_quotes(mut self, double_quotes: DoubleQuotes) -> Self {
+        self.double_quotes = double_quotes;
+        self
+    }
+
+    pub fn read_term(&self, input: &str) -> Result<ReadTerm, ParseError> {
+        let lexer = Lexer::new(input).double_quotes(self.double_quotes);
+        let term = ReadParser::new()
+            .parse(self.ops, lexer)
+            .map_err(|e| ParseError::new(input, e))?;
+
+        let mut vars: Vec<(&Var, usize)> = Vec::new();
+        let mut next = vec![&term];
+
+        while let Some(t) = next.pop() {
+            match t {
+                Term::Var(x) if x.is_anonymous() => (),
+                Term::Var(x) => match vars.iter_mut().find(|(y, _)| *y == x) {
+                    Some((_, n)) => *n += 1,
+                    None => vars.push((x, 1)),
+                },
+                Term::Atom(a) => next.extend(a.args.iter().rev()),
+                _ => (),
+            }
+        }
+
+        let named = |&(x, _): &(&Var, usize)| (x.0.clone(), x.clone());
+        let variable_names = vars.iter().map(named).collect();
+        let singletons = vars.iter().filter(|&&(_, n)| n == 1).map(named).collect();
+
+        Ok(ReadTerm {
+            term,
+            variable_names,
+            singletons,
+        })
+    }
+}
+
+// where the first term of some input ends, just after its `.`, if it is complete
+pub(crate) fn term_end(input: &str) -> Option<usize> {
+    for token in Lexer::new(input) {
+        match token {
+            Ok((_, Tok::End, end)) => return Some(end),
+            Ok(_) => (),
+            Err(_) => return None,
+        }
+    }
+
+    None
+}
+
+// whether some input has nothing but layout and comments left in it
+pub(crate) fn is_layout(input: &str) -> bool {
+    Lexer::new(input).next().is_none()
+}
+
+// the number written by some text, as number_codes/2 reads it: optional layout, an
+// optional `-` right before the digits, and nothing after them
+pub(crate) fn parse_number(text: &str) -> Option<Number> {
+    let tokens: Result<Vec<_>, _> = Lexer::new(text).collect();
+
+    match &tokens.ok()?[..] {
+        [(_, Tok::Int(i), _)] => i64::try_from(*i).ok().map(Number::Int),
+        [(_, Tok::Float(f), _)] => Some(Number::Float(*f)),
+        [(_, Tok::Name(minus), l), (r, Tok::Int(i), _)] if minus == "-" && l == r => {
+            Some(Number::Int((*i as i64).wrapping_neg()))
+        }
+        [(_, Tok::Name(minus), l), (r, Tok::Float(f), _)] if minus == "-" && l == r => {
+            Some(Number::Float(-f))
+        }
+        _ => None,
+    }
+}
@@ -0,0 +1,481 @@
+// terms through serde, for applications that store or send prolog data: a term is an
+// enum of `Var`, `Const`, `Compound`, `Int`, `Float`, `String` and `List`, a variable
+// the pair of its name and depth, a compound the pair of its name and arguments, a list
+// the pair of its items and tail, and a predicate key the pair of its name and arity;
+// nothing of a machine is needed to read one back. Lists are written flat, so however
+// long, they nest no deeper than their items
+use crate::ast::{Atom, Const, Number, Term, Var};
+use crate::database::Key;
+use serde::de::{self, Deserialize, Deserializer, EnumAccess, VariantAccess, Visitor};
+use serde::ser::{Serialize, Serializer};
+use std::fmt::{self, Formatter};
+
+const VARIANTS: &[&str] = &["Var", "Const", "Compound", "Int", "Float", "String", "List"];
+
+impl Serialize for Term {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Term::Var(x) => s.serialize_newtype_variant("Term", 0, VARIANTS[0], x),
+            Term::Const(Const(name)) => s.serialize_newtype_variant("Term", 1, VARIANTS[1], name),
+            Term::Atom(a) if a.name.0 == "." && a.arity == 2 => {
+                let list = self.list_items().unwrap();
+                s.serialize_newtype_variant("Term", 6, VARIANTS[6], &list)
+            }
+            Term::Atom(a) => s.serialize_newtype_variant("Term", 2, VARIANTS[2], a),
+            Term::Number(Number::Int(i)) => s.serialize_newtype_variant("Term", 3, VARIANTS[3], i),
+            Term::Number(Number::Float(x)) => {
+                s.serialize_newtype_variant("Term", 4, VARIANTS[4], x)
+            }
+            Term::String(text) => s.serialize_newtype_variant("Term", 5, VARIANTS[5], text),
+        }
+    }
+}
+
+impl Serialize for Var {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        (&self.0, self.1).serialize(s)
+    }
+}
+
+impl Serialize for Atom {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        (&self.name.0, &self.args).serialize(s)
+    }
+}
+
+impl Serialize for Key {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        (&self.0, self.1).serialize(s)
+    }
+}
+
+impl<'de> Deserialize<'de> for Term {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        d.deserialize_enum("Term", VARIANTS, TermVisitor)
+    }
+}
+
+impl<'de> Deserialize<'de> for Var {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        let (name, depth) = <(String, usize)>::deserialize(d)?;
+        Ok(Var(name, depth))
+    }
+}
+
+// the arity is that of the arguments read
+impl<'de> Deserialize<'de> for Atom {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        let (name, args) = <(String, Vec<Term>)>::deserialize(d)?;
+        Ok(Atom::new(&name, args))
+    }
+}
+
+impl<'de> Deserialize<'de> for Key {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        let (name, arity) = <(String, usize)>::deserialize(d)?;
+        Ok(Key(name, arity))
+    }
+}
+
+struct TermVisitor;
+
+impl<'de> Visitor<'de> for TermVisitor {
+    type Value = Term;
+
+    fn expecting(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "a prolog term")
+    }
+
+    fn visit_enum<A: EnumAccess<'de>>(self, data: A) -> Result<Term, A::Error> {
+        let (tag, value): (String, _) = data.variant()?;
+
+        match tag.as_str() {
+            "Var" => Ok(Term::Var(value.newtype_variant()?)),
+            "Const" => Ok(Term::Const(Const(value.newtype_variant()?))),
+            "Compound" => Ok(Term::Atom(value.newtype_variant()?)),
+            "Int" => Ok(Term::Number(Number::Int(value.newtype_variant()?))),
+            "Float" => Ok(Term::Number(Number::Float(value.newtype_variant()?))),
+            "String" => Ok(Term::String(value.newtype_variant()?)),
+            "List" => {
+                let (items, tail) = value.newtype_variant()?;
+                Ok(Term::list(items, tail))
+            }
+            _ => Err(de::Error::unknown_variant(&tag, VARIANTS)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::de::value::{Error, SeqDeserializer, StrDeserializer};
+    use serde::de::{DeserializeSeed, IntoDeserializer};
+    use serde::forward_to_deserialize_any;
+    use serde::ser::{self, Impossible, SerializeSeq, SerializeTuple};
+
+    // as much of serde's data model as terms use, kept in memory to be read back
+    #[derive(Debug, Clone, PartialEq)]
+    enum Value {
+        Str(String),
+        U64(u64),
+        I64(i64),
+        F64(f64),
+        Seq(Vec<Value>),
+        Variant(&'static str, Box<Value>),
+    }
+
+    struct ValueSerializer;
+
+    struct SeqSerializer(Vec<Value>);
+
+    fn unused<T>() -> Result<T, Error> {
+        Err(ser::Error::custom("not used by terms"))
+    }
+
+    impl Serializer for ValueSerializer {
+        type Ok = Value;
+        type Error = Error;
+        type SerializeSeq = SeqSerializer;
+        type SerializeTuple = SeqSerializer;
+        type SerializeTupleStruct = Impossible<Value, Error>;
+        type SerializeTupleVariant = Impossible<Value, Error>;
+        type SerializeMap = Impossible<Value, Error>;
+        type SerializeStruct = Impossible<Value, Error>;
+        type SerializeStructVariant = Impossible<Value, Error>;
+
+        fn serialize_str(self, v: &str) -> Result<Value, Error> {
+            Ok(Value::Str(String::from(v)))
+        }
+
+        fn serialize_u64(self, v: u64) -> Result<Value, Error> {
+            Ok(Value::U64(v))
+        }
+
+        fn serialize_i64(self, v: i64) -> Result<Value, Error> {
+            Ok(Value::I64(v))
+        }
+
+        fn serialize_f64(self, v: f64) -> Result<Value, Error> {
+            Ok(Value::F64(v))
+        }
+
+        fn serialize_newtype_variant<T: ?Sized + Serialize>(
+            self,
+            _: &'static str,
+            _: u32,
+            variant: &'static str,
+            value: &T,
+        ) -> Result<Value, Error> {
+            Ok(Value::Variant(variant, Box::new(value.serialize(self)?)))
+        }
+
+        fn serialize_seq(self, _: Option<usize>) -> Result<SeqSerializer, Error> {
+            Ok(SeqSerializer(Vec::new()))
+        }
+
+        fn serialize_tuple(self, _: usize) -> Result<SeqSerializer, Error> {
+            Ok(SeqSerializer(Vec::new()))
+        }
+
+        fn serialize_bool(self, _: bool) -> Result<Value, Error> {
+            unused()
+        }
+
+        fn serialize_i8(self, _: i8) -> Result<Value, Error> {
+            unused()
+        }
+
+        fn serialize_i16(self, _: i16) -> Result<Value, Error> {
+            unused()
+        }
+
+        fn serialize_i32(self, _: i32) -> Result<Value, Error> {
+            unused()
+        }
+
+        fn serialize_u8(self, _: u8) -> Result<Value, Error> {
+            unused()
+        }
+
+        fn serialize_u16(self, _: u16) -> Result<Value, Error> {
+            unused()
+        }
+
+        fn serialize_u32(self, _: u32) -> Result<Value, Error> {
+            unused()
+        }
+
+        fn serialize_f32(self, _: f32) -> Result<Value, Error> {
+            unused()
+        }
+
+        fn serialize_char(self, _: char) -> Result<Value, Error> {
+            unused()
+        }
+
+        fn serialize_bytes(self, _: &[u8]) -> Result<Value, Error> {
+            unused()
+        }
+
+        fn serialize_none(self) -> Result<Value, Error> {
+            unused()
+        }
+
+        fn serialize_some<T: ?Sized + Serialize>(self, _: &T) -> Result<Value, Error> {
+            unused()
+        }
+
+        fn serialize_unit(self) -> Result<Value, Error> {
+            unused()
+        }
+
+        fn serialize_unit_struct(self, _: &'static str) -> Result<Value, Error> {
+            unused()
+        }
+
+        fn serialize_unit_variant(
+            self,
+            _: &'static str,
+            _: u32,
+            _: &'static str,
+        ) -> Result<Value, Error> {
+            unused()
+        }
+
+        fn serialize_newtype_struct<T: ?Sized + Serialize>(
+            self,
+            _: &'static str,
+            _: &T,
+        ) -> Result<Value, Error> {
+            unused()
+        }
+
+        fn serialize_tuple_struct(
+            self,
+            _: &'static str,
+            _: usize,
+        ) -> Result<Self::SerializeTupleStruct, Error> {
+            unused()
+        }
+
+        fn serialize_tuple_variant(
+            self,
+            _: &'static str,
+            _: u32,
+            _: &'static str,
+            _: usize,
+        ) -> Result<Self::SerializeTupleVariant, Error> {
+            unused()
+        }
+
+        fn serialize_map(self, _: Option<usize>) -> Result<Self::SerializeMap, Error> {
+            unused()
+        }
+
+        fn serialize_struct(
+            self,
+            _: &'static str,
+            _: usize,
+        ) -> Result<Self::SerializeStruct, Error> {
+            unused()
+        }
+
+        fn serialize_struct_variant(
+            self,
+            _: &'static str,
+            _: u32,
+            _: &'static str,
+            _: usize,
+        ) -> Result<Self::SerializeStructVariant, Error> {
+            unused()
+        }
+    }
+
+    impl SerializeSeq for SeqSerializer {
+        type Ok = Value;
+        type Error = Error;
+
+        fn serialize_element<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<(), Error> {
+            self.0.push(value.serialize(ValueSerializer)?);
+            Ok(())
+        }
+
+        fn end(self) -> Result<Value, Error> {
+            Ok(Value::Seq(self.0))
+        }
+    }
+
+    impl SerializeTuple for SeqSerializer {
+        type Ok = Value;
+        type Error = Error;
+
+        fn serialize_element<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<(), Error> {
+            SerializeSeq::serialize_element(self, value)
+        }
+
+        fn end(self) -> Result<Value, Error> {
+            SerializeSeq::end(self)
+        }
+    }
+
+    impl<'de> Deserializer<'de> for Value {
+        type Error = Error;
+
+        fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+            match self {
+                Value::Str(text) => visitor.visit_string(text),
+                Value::U64(n) => visitor.visit_u64(n),
+                Value::I64(i) => visitor.visit_i64(i),
+                Value::F64(x) => visitor.visit_f64(x),
+                Value::Seq(items) => visitor.visit_seq(SeqDeserializer::new(items.into_iter())),
+                Value::Variant(_, _) => visitor.visit_enum(self),
+            }
+        }
+
+        forward_to_deserialize_any! {
+            bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string bytes
+            byte_buf option unit unit_struct newtype_struct seq tuple tuple_struct map
+            struct enum identifier ignored_any
+        }
+    }
+
+    impl<'de> IntoDeserializer<'de, Error> for Value {
+        type Deserializer = Value;
+
+        fn into_deserializer(self) -> Value {
+            self
+        }
+    }
+
+    impl<'de> EnumAccess<'de> for Value {
+        type Error = Error;
+        type Variant = Value;
+
+        fn variant_seed<T: DeserializeSeed<'de>>(
+            self,
+            seed: T,
+        ) -> Result<(T::Value, Value), Error> {
+            match self {
+                Value::Variant(tag, value) => {
+                    let tag: StrDeserializer<Error> = tag.into_deserializer();
+                    Ok((seed.deserialize(tag)?, *value))
+                }
+                _ => Err(de::Error::custom("not a variant")),
+            }
+        }
+    }
+
+    impl<'de> VariantAccess<'de> for Value {
+        type Error = Error;
+
+        fn unit_variant(self) -> Result<(), Error> {
+            Err(de::Error::custom("not used by terms"))
+        }
+
+        fn newtype_variant_seed<T: DeserializeSeed<'de>>(self, seed: T) -> Result<T::Value, Error> {
+            seed.deserialize(self)
+        }
+
+        fn tuple_variant<V: Visitor<'de>>(self, _: usize, _: V) -> Result<V::Value, Error> {
+            Err(de::Error::custom("not used by terms"))
+        }
+
+        fn struct_variant<V: Visitor<'de>>(
+            self,
+            _: &'static [&'static str],
+            _: V,
+        ) -> Result<V::Value, Error> {
+            Err(de::Error::custom("not used by terms"))
+        }
+    }
+
+    fn round_trip<T: Serialize + for<'de> Deserialize<'de>>(t: &T) -> (Value, T) {
+        let value = t.serialize(ValueSerializer).unwrap();
+        (value.clone(), T::deserialize(value).unwrap())
+    }
+
+    fn variant(tag: &'static str, value: Value) -> Value {
+        Value::Variant(tag, Box::new(value))
+    }
+
+    fn text(text: &str) -> Value {
+        Value::Str(String::from(text))
+    }
+
+    #[test]
+    fn test_serde_1_succeeds() {
+        for (t, value) in [
+            (
+                Term::Var(Var::new("X", 2)),
+                variant("Var", Value::Seq(vec![text("X"), Value::U64(2)])),
+            ),
+            (Term::atom("a b"), variant("Const", text("a b"))),
+            (
+                Term::compound("f", vec![Term::atom("a"), Term::int(-1)]),
+                variant(
+                    "Compound",
+                    Value::Seq(vec![
+                        text("f"),
+                        Value::Seq(vec![
+                            variant("Const", text("a")),
+                            variant("Int", Value::I64(-1)),
+                        ]),
+                    ]),
+                ),
+            ),
+            (Term::int(i64::MIN), variant("Int", Value::I64(i64::MIN))),
+            (Term::float(-2.5), variant("Float", Value::F64(-2.5))),
+            (
+                Term::String(String::from("text")),
+                variant("String", text("text")),
+            ),
+            (
+                Term::list(vec![Term::int(1)], Term::Var(Var::new("T", 0))),
+                variant(
+                    "List",
+                    Value::Seq(vec![
+                        Value::Seq(vec![variant("Int", Value::I64(1))]),
+                        variant("Var", Value::Seq(vec![text("T"), Value::U64(0)])),
+                    ]),
+                ),
+            ),
+        ] {
+            assert_eq!(round_trip(&t), (value, t));
+        }
+
+        let key = Key::new("p", 2);
+        let value = Value::Seq(vec![text("p"), Value::U64(2)]);
+        assert_eq!(round_trip(&key), (value, key));
+    }
+
+    #[test]
+    fn test_serde_2_succeeds() {
+        // a long list is written as one flat sequence, rather than a compound per cell
+        let items: Vec<Term> = (0..100_000).map(Term::int).collect();
+        let list = Term::list(items, Term::atom("[]"));
+        let (value, t) = round_trip(&list);
+
+        assert!(matches!(&value, Value::Variant("List", _)));
+        assert_eq!(t, list);
+
+        // a list written as a compound per cell still reads back
+        let cell = Term::compound(".", vec![Term::int(1), Term::atom("[]")]);
+        let value = variant(
+            "Compound",
+            Value::Seq(vec![
+                text("."),
+                Value::Seq(vec![
+                    variant("Int", Value::I64(1)),
+                    variant("Const", text("[]")),
+                ]),
+            ]),
+        );
+        assert_eq!(Term::deserialize(value).unwrap(), cell);
+    }
+
+    #[test]
+    fn test_serde_1_fails() {
+        let tag: StrDeserializer<Error> = "Functor".into_deserializer();
+        assert!(Term::deserialize(tag).is_err());
+    }
+}
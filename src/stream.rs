@@ -0,0 +1,294 @@
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+// whether a stream carries text, for read/1 and write/1 and friends, or raw bytes
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum StreamKind {
+    #[default]
+    Text,
+    Binary,
+}
+
+// a source or sink of the input and output of the machine; the input half works like
+// BufRead, with the bytes available looked at before they are consumed, and a stream
+// that is only one half refuses the other; streams move with the machine between threads
+pub trait Stream: Send {
+    fn kind(&self) -> StreamKind {
+        StreamKind::Text
+    }
+
+    // the next bytes of input, without consuming them; empty at the end of the input
+    fn fill(&mut self) -> io::Result<&[u8]> {
+        Err(io::Error::other("not an input stream"))
+    }
+
+    fn consume(&mut self, _n: usize) {}
+
+    fn write(&mut self, _bytes: &[u8]) -> io::Result<()> {
+        Err(io::Error::other("not an output stream"))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn peek(&mut self) -> io::Result<Option<u8>> {
+        Ok(self.fill()?.first().copied())
+    }
+
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let available = self.fill()?;
+        let n = available.len().min(buf.len());
+        buf[..n].copy_from_slice(&available[..n]);
+        self.consume(n);
+        Ok(n)
+    }
+
+    // append the input up to and including the next newline, giving the bytes read
+    fn read_line(&mut self, line: &mut String) -> io::Result<usize> {
+        let mut bytes = Vec::new();
+
+        loop {
+            let available = self.fill()?;
+            if available.is_empty() {
+                break;
+            }
+
+            match available.iter().position(|&b| b == b'\n') {
+                Some(i) => {
+                    bytes.extend_from_slice(&available[..=i]);
+                    self.consume(i + 1);
+                    break;
+                }
+                None => {
+                    let n = available.len();
+                    bytes.extend_from_slice(available);
+                    self.consume(n);
+                }
+            }
+        }
+
+        let text =
+            String::from_utf8(bytes).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        line.push_str(&text);
+        Ok(text.len())
+    }
+}
+
+// standard input, taken a line at a time so that what the machine does not read is
+// left for the toplevel
+#[derive(Debug, Default)]
+pub struct Stdin {
+    line: Vec<u8>,
+    start: usize,
+}
+
+#[derive(Debug, Default)]
+pub struct Stdout;
+
+// input from a file or any other reader, such as a cursor over text in memory
+pub struct InputStream<R: BufRead> {
+    reader: R,
+    kind: StreamKind,
+}
+
+// output to a file or any other writer
+pub struct OutputStream<W: Write> {
+    writer: W,
+    kind: StreamKind,
+}
+
+// output kept in memory, and shared by its clones so it can be read while the machine
+// still writes to it
+#[derive(Debug, Clone, Default)]
+pub struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+impl Stream for Stdin {
+    fn fill(&mut self) -> io::Result<&[u8]> {
+        if self.start == self.line.len() {
+            self.line.clear();
+            self.start = 0;
+
+            let mut stdin = io::stdin().lock();
+            let available = stdin.fill_buf()?;
+            let n = match available.iter().position(|&b| b == b'\n') {
+                Some(i) => i + 1,
+                None => available.len(),
+            };
+
+            self.line.extend_from_slice(&available[..n]);
+            stdin.consume(n);
+        }
+
+        Ok(&self.line[self.start..])
+    }
+
+    fn consume(&mut self, n: usize) {
+        self.start = (self.start + n).min(self.line.len());
+    }
+}
+
+impl Stream for Stdout {
+    fn write(&mut self, bytes: &[u8]) -> io::Result<()> {
+        io::stdout().write_all(bytes)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        io::stdout().flush()
+    }
+}
+
+impl<R: BufRead + Send> InputStream<R> {
+    pub fn new(reader: R) -> Self {
+        InputStream {
+            reader,
+            kind: StreamKind::Text,
+        }
+    }
+
+    pub fn binary(mut self) -> Self {
+        self.kind = StreamKind::Binary;
+        self
+    }
+}
+
+impl InputStream<BufReader<File>> {
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Ok(InputStream::new(BufReader::new(File::open(path)?)))
+    }
+}
+
+impl<R: BufRead + Send> Stream for InputStream<R> {
+    fn kind(&self) -> StreamKind {
+        self.kind
+    }
+
+    fn fill(&mut self) -> io::Result<&[u8]> {
+        self.reader.fill_buf()
+    }
+
+    fn consume(&mut self, n: usize) {
+        self.reader.consume(n)
+    }
+}
+
+impl<W: Write + Send> OutputStream<W> {
+    pub fn new(writer: W) -> Self {
+        OutputStream {
+            writer,
+            kind: StreamKind::Text,
+        }
+    }
+
+    pub fn binary(mut self) -> Self {
+        self.kind = StreamKind::Binary;
+        self
+    }
+}
+
+impl OutputStream<File> {
+    pub fn create<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Ok(OutputStream::new(File::create(path)?))
+    }
+}
+
+impl<W: Write + Send> Stream for OutputStream<W> {
+    fn kind(&self) -> StreamKind {
+        self.kind
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.writer.write_all(bytes)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+impl Buffer {
+    pub fn new() -> Self {
+        Buffer::default()
+    }
+
+    pub fn bytes(&self) -> Vec<u8> {
+        self.lock().clone()
+    }
+
+    // what was written, with any invalid UTF-8 replaced
+    pub fn text(&self) -> String {
+        String::from_utf8_lossy(&self.lock()).into_owned()
+    }
+
+    // a writer that panicked mid-write leaves the bytes it wrote, which are still wanted
+    fn lock(&self) -> MutexGuard<'_, Vec<u8>> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Stream for Buffer {
+    fn write(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.lock().extend_from_slice(bytes);
+        Ok(())
+    }
+}
+
+impl Write for Buffer {
+    fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+        self.lock().extend_from_slice(bytes);
+        Ok(bytes.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_streams_1_succeeds() {
+        let mut input = InputStream::new(Cursor::new("ab\nc"));
+        let mut line = String::new();
+
+        assert_eq!(input.peek().unwrap(), Some(b'a'));
+        assert_eq!(input.read_line(&mut line).unwrap(), 3);
+        assert_eq!(input.read_line(&mut line).unwrap(), 1);
+        assert_eq!(input.read_line(&mut line).unwrap(), 0);
+        assert_eq!(line, "ab\nc");
+        assert_eq!(input.peek().unwrap(), None);
+
+        let buffer = Buffer::new();
+        let mut output = buffer.clone();
+        Stream::write(&mut output, b"hello").unwrap();
+        assert_eq!(buffer.text(), "hello");
+
+        let path = std::env::temp_dir().join("bfg_prolog_test_streams.txt");
+        let mut file = OutputStream::create(&path).unwrap().binary();
+        Stream::write(&mut file, &[1, 2, 3]).unwrap();
+        Stream::flush(&mut file).unwrap();
+        assert_eq!(file.kind(), StreamKind::Binary);
+
+        let mut file = InputStream::open(&path).unwrap();
+        let mut bytes = [0; 4];
+        assert_eq!(file.read(&mut bytes).unwrap(), 3);
+        assert_eq!(bytes, [1, 2, 3, 0]);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_streams_1_fails() {
+        let mut line = String::new();
+
+        assert!(Stdout.read_line(&mut line).is_err());
+        assert!(InputStream::new(Cursor::new("")).write(b"a").is_err());
+        assert!(InputStream::new(Cursor::new(vec![0xff]))
+            .read_line(&mut line)
+            .is_err());
+    }
+}
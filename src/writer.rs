@@ -0,0 +1,271 @@
+use crate::ast::{Assertion, Atom, Const, Term, Var};
+use crate::lexer::{is_alnum, is_symbol_char};
+use crate::map_vars;
+use crate::ops::{OpDef, OpTable};
+use std::collections::HashMap;
+
+// writes terms back in operator notation, as write/1 and writeq/1 do
+#[derive(Debug, Copy, Clone)]
+pub struct Writer<'ops> {
+    ops: &'ops OpTable,
+    quoted: bool,
+    ignore_ops: bool,
+}
+
+impl<'ops> Writer<'ops> {
+    pub fn new(ops: &'ops OpTable) -> Self {
+        Writer {
+            ops,
+            quoted: false,
+            ignore_ops: false,
+        }
+    }
+
+    pub fn quoted(mut self, quoted: bool) -> Self {
+        self.quoted = quoted;
+        self
+    }
+
+    pub fn ignore_ops(mut self, ignore_ops: bool) -> Self {
+        self.ignore_ops = ignore_ops;
+        self
+    }
+
+    pub fn write(&self, t: &Term) -> String {
+        self.write_priority(t, 1200)
+    }
+
+    // write a term to go where the priority of a term may be at most `max`, bracketing
+    // it if it is an operator term of higher priority
+    pub fn write_priority(&self, t: &Term, max: usize) -> String {
+        let mut out = String::new();
+        self.term(&mut out, t, max, false);
+        out
+    }
+
+    // a clause as listing/1 shows it: its variables named A, B, ... in the order they
+    // appear, or `_` if they appear once, and each goal of its body on a line of its own
+    pub fn clause(&self, clause: &Assertion) -> String {
+        let t = Term::from(clause.clone());
+        let mut occurrences: HashMap<&Var, usize> = HashMap::new();
+        count_vars(&t, &mut occurrences);
+
+        let vars = t.variables();
+        let t = map_vars(&t, &mut |x| {
+            if occurrences[x] == 1 {
+                return Term::Var(Var::new("_", 0));
+            }
+
+            let i = vars
+                .iter()
+                .filter(|&&y| occurrences[y] > 1)
+                .position(|&y| y == x);
+            Term::Var(Var::new(&var_name(i.unwrap()), 0))
+        });
+
+        let (head, body) = match &t {
+            Term::Atom(a) => (&a.args[0], &a.args[1]),
+            _ => unreachable!(),
+        };
+
+        let mut out = self.write_priority(head, 1199);
+        if *body != Term::atom("true") {
+            let mut goals = Vec::new();
+            let mut body = body;
+            while let Term::Atom(Atom { name, args, .. }) = body {
+                if name.0 != "," || args.len() != 2 {
+                    break;
+                }
+                goals.push(&args[0]);
+                body = &args[1];
+            }
+            goals.push(body);
+
+            let goals: Vec<_> = goals.iter().map(|g| self.write_priority(g, 999)).collect();
+            out.push_str(" :-\n    ");
+            out.push_str(&goals.join(",\n    "));
+        }
+
+        out.push_str(".\n");
+        out
+    }
+
+    fn name(&self, name: &str) -> String {
+        if self.quoted {
+            Const::new(name).to_string()
+        } else {
+            String::from(name)
+        }
+    }
+
+    // `operand` is set for the arguments of operators, where an operator atom must be
+    // bracketed to be read back as an atom
+    fn term(&self, out: &mut String, t: &Term, max: usize, operand: bool) {
+        if let Some((items, tail)) = t.list_items() {
+            emit(out, "[");
+            for (i, item) in items.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                self.term(out, item, 999, false);
+            }
+
+            match tail {
+                Term::Const(Const(nil)) if nil == "[]" => (),
+                tail => {
+                    out.push('|');
+                    self.term(out, tail, 999, false);
+                }
+            }
+
+            out.push(']');
+            return;
+        }
+
+        match t {
+            Term::Var(v) => emit(out, &v.to_string()),
+            Term::Number(n) => emit(out, &n.to_string()),
+            Term::String(_) if self.quoted => emit(out, &t.to_string()),
+            Term::String(text) => emit(out, text),
+            Term::Const(Const(name)) if operand && self.ops.is_op(name) => {
+                emit(out, "(");
+                emit(out, &self.name(name));
+                out.push(')');
+            }
+            Term::Const(Const(name)) => emit(out, &self.name(name)),
+            Term::Atom(Atom { name, args, .. }) if name.0 == "{}" && args.len() == 1 => {
+                emit(out, "{");
+                self.term(out, &args[0], 1200, false);
+                out.push('}');
+            }
+            Term::Atom(Atom { name, args, .. }) if !self.ignore_ops => {
+                match (&args[..], self.op(&name.0, args.len())) {
+                    ([arg], Some(def)) if def.op_type.is_prefix() => {
+                        self.bracketed(out, def.priority > max, |out| {
+                            self.prefix(out, &name.0, def, arg)
+                        })
+                    }
+                    ([arg], Some(def)) => self.bracketed(out, def.priority > max, |out| {
+                        let (left, _) = def.arg_priorities();
+                        self.term(out, arg, left, true);
+                        self.operator(out, &name.0);
+                    }),
+                    ([left, right], Some(def)) => self.bracketed(out, def.priority > max, |out| {
+                        let (l, r) = def.arg_priorities();
+                        self.term(out, left, l, true);
+                        self.operator(out, &name.0);
+                        self.right_operand(out, right, r);
+                    }),
+                    _ => self.canonical(out, &name.0, args),
+                }
+            }
+            Term::Atom(Atom { name, args, .. }) => self.canonical(out, &name.0, args),
+        }
+    }
+
+    fn op(&self, name: &str, arity: usize) -> Option<OpDef> {
+        match arity {
+            1 => self.ops.prefix(name).or_else(|| self.ops.postfix(name)),
+            2 => self.ops.infix(name),
+            _ => None,
+        }
+    }
+
+    fn bracketed<F: FnOnce(&mut String)>(&self, out: &mut String, bracket: bool, f: F) {
+        if bracket {
+            emit(out, "(");
+            f(out);
+            out.push(')');
+        } else {
+            f(out);
+        }
+    }
+
+    fn prefix(&self, out: &mut String, name: &str, def: OpDef, arg: &Term) {
+        let (_, right) = def.arg_priorities();
+        let mut operand = String::new();
+        self.term(&mut operand, arg, right, true);
+
+        emit(out, &self.name(name));
+
+        // `- 1` is not the number -1, and `-(a)` would read as a compound
+        let number =
+            (name == "-" || name == "+") && operand.starts_with(|c: char| c.is_ascii_digit());
+        if number || operand.starts_with('(') || name.chars().all(is_alnum) {
+            out.push(' ');
+        }
+
+        emit(out, &operand);
+    }
+
+    fn operator(&self, out: &mut String, name: &str) {
+        if name == "," {
+            out.push(',');
+        } else if name.chars().all(is_alnum) {
+            out.push(' ');
+            out.push_str(&self.name(name));
+            out.push(' ');
+        } else {
+            emit(out, &self.name(name));
+        }
+    }
+
+    fn right_operand(&self, out: &mut String, t: &Term, max: usize) {
+        let mut operand = String::new();
+        self.term(&mut operand, t, max, true);
+
+        if operand.starts_with('(') && !out.ends_with(',') && !out.ends_with(' ') {
+            out.push(' ');
+        }
+
+        emit(out, &operand);
+    }
+
+    fn canonical(&self, out: &mut String, name: &str, args: &[Term]) {
+        emit(out, &self.name(name));
+        out.push('(');
+
+        for (i, arg) in args.iter().enumerate() {
+            if i > 0 {
+                out.push(',');
+            }
+            self.term(out, arg, 999, false);
+        }
+
+        out.push(')');
+    }
+}
+
+fn count_vars<'t>(t: &'t Term, occurrences: &mut HashMap<&'t Var, usize>) {
+    match t {
+        Term::Var(x) => *occurrences.entry(x).or_default() += 1,
+        Term::Atom(a) => a.args.iter().for_each(|t| count_vars(t, occurrences)),
+        _ => (),
+    }
+}
+
+// A to Z, then A1 to Z1, and so on
+fn var_name(i: usize) -> String {
+    let letter = (b'A' + (i % 26) as u8) as char;
+
+    match i / 26 {
+        0 => letter.to_string(),
+        n => format!("{}{}", letter, n),
+    }
+}
+
+// append a token, separating it from the previous one if they would otherwise be read
+// back as a single token
+fn emit(out: &mut String, s: &str) {
+    if let (Some(last), Some(first)) = (out.chars().last(), s.chars().next()) {
+        let glued = (is_symbol_char(last) && is_symbol_char(first))
+            || (is_alnum(last) && is_alnum(first))
+            || (first == '\'' && (last == '\'' || last.is_ascii_digit()));
+
+        if glued {
+            out.push(' ');
+        }
+    }
+
+    out.push_str(s);
+}
@@ -0,0 +1,8 @@
+add(X, Y0, Y) :-
+    Y is Y0 + X.
+
+double(X, Y) :-
+    Y is X * 2.
+
+add(X, Y, Z0, Z) :-
+    Z is Z0 + X * Y.
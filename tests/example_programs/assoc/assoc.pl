@@ -0,0 +1,24 @@
+pairs(0, []).
+pairs(N, [N-N|Pairs]) :-
+    N > 0,
+    M is N - 1,
+    pairs(M, Pairs).
+
+depth(t, 0).
+depth(t(_, _, _, L, R), D) :-
+    depth(L, DL),
+    depth(R, DR),
+    D is max(DL, DR) + 1.
+
+sorted(Pairs, Sorted) :-
+    list_to_assoc(Pairs, A),
+    assoc_to_list(A, Sorted).
+
+lookup(Pairs, K, V) :-
+    list_to_assoc(Pairs, A),
+    get_assoc(K, A, V).
+
+balanced(N, D) :-
+    pairs(N, Pairs),
+    list_to_assoc(Pairs, A),
+    depth(A, D).
@@ -0,0 +1,5 @@
+mymod:attr_unify_hook(Allowed, Y) :-
+    member(Y, Allowed).
+
+domain(X, Allowed) :-
+    put_attr(X, mymod, Allowed).
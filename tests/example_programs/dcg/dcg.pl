@@ -0,0 +1,24 @@
+greeting --> [hello], name.
+
+name --> [world].
+name --> [prolog].
+
+digits([D|Ds]) --> digit(D), digits(Ds).
+digits([D]) --> digit(D).
+
+digit(D) --> [D], { D >= 0'0, D =< 0'9 }.
+
+anbn --> [].
+anbn --> [a], anbn, [b].
+
+abc --> "abc".
+
+ab --> [a] ; [b].
+
+sign(-1) --> [-], !.
+sign(1) --> [].
+
+word([C|Cs]) --> [C], { C \== ' ' }, !, word(Cs).
+word([]) --> [].
+
+a_then_x --> ( [a] -> [x] ; [b] ).
@@ -0,0 +1,9 @@
+:- dynamic(seen/1).
+
+seen(a).
+
+see(X) :-
+    assertz(seen(X)).
+
+see_first(X) :-
+    asserta(seen(X)).
@@ -0,0 +1,10 @@
+:- initialization(ready).
+:- initialization(start, main).
+
+ready :-
+    loaded(basic).
+
+start :-
+    loaded(missing).
+
+loaded(basic).
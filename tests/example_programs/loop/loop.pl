@@ -0,0 +1,10 @@
+count(0).
+count(N) :-
+    N > 0,
+    M is N - 1,
+    count(M).
+
+sum([], 0).
+sum([X|Xs], S) :-
+    sum(Xs, S0),
+    S is S0 + X.
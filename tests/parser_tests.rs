@@ -0,0 +1,552 @@
+use bfg_prolog::ast::{Assertion, Atom, Const, Number, Sentence, Term, Var};
+use bfg_prolog::lexer::{DoubleQuotes, Lexer, ParseError};
+use bfg_prolog::ops::{OpTable, OpType};
+use bfg_prolog::reader::Reader;
+use bfg_prolog::writer::Writer;
+use bfg_prolog::{parser, term};
+
+fn parse_term(ops: &OpTable, term: &str) -> String {
+    let term_parser = parser::TermParser::new();
+    term_parser
+        .parse(ops, Lexer::new(term))
+        .unwrap()
+        .to_string()
+}
+
+fn parse_assertion(code: &str) -> Assertion {
+    let assertion_parser = parser::AssertionParser::new();
+    assertion_parser
+        .parse(&OpTable::default(), Lexer::new(code))
+        .unwrap()
+}
+
+#[test]
+fn test_infix_1_succeeds() {
+    let ops = OpTable::default();
+
+    assert_eq!(parse_term(&ops, "X is Y + 1"), "is(X, +(Y, 1))");
+}
+
+#[test]
+fn test_infix_2_succeeds() {
+    let ops = OpTable::default();
+
+    assert_eq!(parse_term(&ops, "1 + 2 * 3 - 4"), "-(+(1, *(2, 3)), 4)");
+    assert_eq!(parse_term(&ops, "(1 + 2) * 3"), "*(+(1, 2), 3)");
+}
+
+#[test]
+fn test_infix_3_succeeds() {
+    let ops = OpTable::default();
+
+    assert_eq!(parse_term(&ops, "a - b - c"), "-(-(a, b), c)");
+    assert_eq!(parse_term(&ops, "a ^ b ^ c"), "^(a, ^(b, c))");
+    assert_eq!(
+        parse_term(&ops, "a :- b, c ; d -> e"),
+        ":-(a, ;(','(b, c), ->(d, e)))"
+    );
+}
+
+#[test]
+fn test_infix_4_succeeds() {
+    let ops = OpTable::default();
+
+    assert_eq!(parse_term(&ops, "N*(N+1)"), "*(N, +(N, 1))");
+    assert_eq!(parse_term(&ops, "a-(b)"), "-(a, b)");
+    assert_eq!(parse_term(&ops, "1-(-1)"), "-(1, -1)");
+    assert_eq!(parse_term(&ops, "X is 10-(2*3)"), "is(X, -(10, *(2, 3)))");
+    assert_eq!(parse_term(&ops, "(a:-(b))"), ":-(a, b)");
+    assert_eq!(parse_term(&ops, "a-(b, c)"), "-(a, ','(b, c))");
+    assert_eq!(parse_term(&ops, "a = -(1) + -(b)"), "=(a, +(-(1), -(b)))");
+}
+
+#[test]
+#[should_panic]
+fn test_infix_3_fails() {
+    let ops = OpTable::default();
+
+    parse_term(&ops, "a = b = c");
+}
+
+#[test]
+fn test_prefix_1_succeeds() {
+    let ops = OpTable::default();
+
+    assert_eq!(parse_term(&ops, "- a"), "-(a)");
+    assert_eq!(parse_term(&ops, "\\+ a = b"), "\\+(=(a, b))");
+    assert_eq!(parse_term(&ops, "- - a"), "-(-(a))");
+    assert_eq!(parse_term(&ops, "- (1)"), "-(1)");
+}
+
+#[test]
+fn test_prefix_2_succeeds() {
+    let ops = OpTable::default();
+
+    assert_eq!(parse_term(&ops, "f(-, +)"), "f(-, +)");
+    assert_eq!(parse_term(&ops, "X = -"), "=(X, -)");
+    assert_eq!(parse_term(&ops, "[-]"), "[-]");
+}
+
+#[test]
+fn test_op_table_1_succeeds() {
+    let mut ops = OpTable::default();
+    ops.add(700, OpType::Xfx, "===");
+    ops.add(200, OpType::Xf, "done");
+
+    assert_eq!(parse_term(&ops, "a === b done"), "===(a, done(b))");
+}
+
+#[test]
+#[should_panic]
+fn test_op_table_1_fails() {
+    let mut ops = OpTable::default();
+    ops.add(0, OpType::Yfx, "+");
+
+    parse_term(&ops, "a + b");
+}
+
+#[test]
+fn test_list_1_succeeds() {
+    let ops = OpTable::default();
+
+    assert_eq!(parse_term(&ops, "[a, b|T]"), "[a, b|T]");
+    assert_eq!(parse_term(&ops, "[a, (b, c)]"), "[a, ','(b, c)]");
+    assert_eq!(parse_term(&ops, "'.'(a, [])"), "[a]");
+}
+
+#[test]
+fn test_rule_1_succeeds() {
+    let assertion = parse_assertion("a(X) :- b(X), X is 1 + 2.");
+
+    assert_eq!(
+        assertion,
+        Assertion::new(
+            Atom::new("a", vec![Term::Var(Var::new("X", 0))]),
+            vec![
+                Atom::new("b", vec![Term::Var(Var::new("X", 0))]),
+                Atom::new(
+                    "is",
+                    vec![
+                        Term::Var(Var::new("X", 0)),
+                        Term::Atom(Atom::new(
+                            "+",
+                            vec![Term::Number(Number::Int(1)), Term::Number(Number::Int(2)),],
+                        )),
+                    ],
+                ),
+            ],
+        )
+    );
+}
+
+#[test]
+fn test_comments_1_succeeds() {
+    let code = "% the first clause\n\
+                a(X) :- /* a block comment, with a % inside */ b(X).\n\
+                /* comments /* do not nest */\n\
+                b(1). % trailing comment\n\
+                b(2).% no space before the comment";
+
+    let code_parser = parser::CodeParser::new();
+    let code = code_parser
+        .parse(&OpTable::default(), Lexer::new(code))
+        .unwrap();
+
+    assert_eq!(code.len(), 3);
+}
+
+#[test]
+#[should_panic]
+fn test_comments_1_fails() {
+    let code_parser = parser::CodeParser::new();
+
+    code_parser
+        .parse(&OpTable::default(), Lexer::new("a. /* unterminated"))
+        .unwrap();
+}
+
+#[test]
+fn test_negative_numbers_1_succeeds() {
+    let ops = OpTable::default();
+
+    assert_eq!(parse_term(&ops, "temp(-5, -2.5)"), "temp(-5, -2.5)");
+    assert_eq!(parse_term(&ops, "X is 3 - -2"), "is(X, -(3, -2))");
+    assert_eq!(parse_term(&ops, "a-1"), "-(a, 1)");
+    assert_eq!(parse_term(&ops, "a -1"), "-(a, 1)");
+    assert_eq!(parse_term(&ops, "[-1, - 1, -(1)]"), "[-1, -(1), -(1)]");
+}
+
+#[test]
+fn test_negative_numbers_2_succeeds() {
+    let ops = OpTable::default();
+    let term_parser = parser::TermParser::new();
+
+    assert_eq!(
+        term_parser.parse(&ops, Lexer::new("-1")).unwrap(),
+        Term::Number(Number::Int(-1))
+    );
+    assert_eq!(
+        term_parser.parse(&ops, Lexer::new("- 1")).unwrap(),
+        Term::Atom(Atom::new("-", vec![Term::Number(Number::Int(1))]))
+    );
+}
+
+#[test]
+fn test_negative_numbers_3_succeeds() {
+    let ops = OpTable::default();
+    let term_parser = parser::TermParser::new();
+
+    assert_eq!(
+        term_parser
+            .parse(&ops, Lexer::new("-9223372036854775808"))
+            .unwrap(),
+        Term::Number(Number::Int(i64::MIN))
+    );
+    assert_eq!(
+        term_parser
+            .parse(&ops, Lexer::new("9223372036854775807"))
+            .unwrap(),
+        Term::Number(Number::Int(i64::MAX))
+    );
+}
+
+#[test]
+fn test_negative_numbers_3_fails() {
+    let ops = OpTable::default();
+    let term_parser = parser::TermParser::new();
+
+    // only a negative literal reaches the smallest integer
+    for text in &[
+        "9223372036854775808",
+        "- 9223372036854775808",
+        "-9223372036854775809",
+    ] {
+        assert!(term_parser.parse(&ops, Lexer::new(text)).is_err());
+    }
+}
+
+#[test]
+fn test_double_quotes_1_succeeds() {
+    let ops = OpTable::default();
+    let term_parser = parser::TermParser::new();
+    let parse = |mode| {
+        let lexer = Lexer::new("f(\"hi\", \"\", \"a\"\"\\n\")").double_quotes(mode);
+        term_parser.parse(&ops, lexer).unwrap().to_string()
+    };
+
+    assert_eq!(
+        parse(DoubleQuotes::Codes),
+        "f([104, 105], [], [97, 34, 10])"
+    );
+    assert_eq!(
+        parse(DoubleQuotes::Chars),
+        "f([h, i], [], [a, '\"', '\\n'])"
+    );
+    assert_eq!(parse(DoubleQuotes::Atom), "f(hi, '', 'a\"\\n')");
+    assert_eq!(parse(DoubleQuotes::String), "f(\"hi\", \"\", \"a\\\"\\n\")");
+}
+
+#[test]
+fn test_char_codes_1_succeeds() {
+    let ops = OpTable::default();
+
+    assert_eq!(
+        parse_term(&ops, "[0'a, 0' , 0'\\n, 0'\\\\, 0''', 0'', 0'\\x41\\]"),
+        "[97, 32, 10, 92, 39, 39, 65]"
+    );
+    assert_eq!(parse_term(&ops, "X is 0'a + 1"), "is(X, +(97, 1))");
+    assert_eq!(parse_term(&ops, "-0'a"), "-97");
+}
+
+#[test]
+fn test_program_1_succeeds() {
+    let code = ":- initialization(main).\n\
+                p('.'). p(1.5).% a comment right after the end\n\
+                q(X) :- p(X).\n\
+                :- dynamic r/1.";
+
+    let program_parser = parser::ProgramParser::new();
+    let program = program_parser
+        .parse(&OpTable::default(), Lexer::new(code))
+        .unwrap();
+
+    assert_eq!(program.len(), 5);
+    assert_eq!(
+        program[0].1,
+        Sentence::Directive(Term::Atom(Atom::new(
+            "initialization",
+            vec![Term::Const(Const::new("main"))]
+        )))
+    );
+    assert!(matches!(program[2].1, Sentence::Assertion(_)));
+    assert_eq!(
+        program[4].1,
+        Sentence::Directive(Term::Atom(Atom::new(
+            "dynamic",
+            vec![Term::Atom(Atom::new(
+                "/",
+                vec![Term::Const(Const::new("r")), Term::Number(Number::Int(1))]
+            ))]
+        )))
+    );
+}
+
+#[test]
+fn test_dcg_1_succeeds() {
+    let code = "a --> [x], b, {c}.\n\
+                d(X) --> \\+ [X], call(e, X), !.";
+
+    let program_parser = parser::ProgramParser::new();
+    let program = program_parser
+        .parse(&OpTable::default(), Lexer::new(code))
+        .unwrap();
+    let clauses: Vec<String> = program
+        .into_iter()
+        .map(|(_, sentence)| match sentence {
+            Sentence::Assertion(assertion) => Term::from(assertion).to_string(),
+            Sentence::Directive(_) => unreachable!(),
+        })
+        .collect();
+
+    assert_eq!(
+        clauses,
+        [
+            ":-(a(_S1, _S2), ','(=(_S1, [x|_S3]), ','(b(_S3, _S4), ','(c, =(_S4, _S2)))))",
+            ":-(d(X, _S1, _S2), ','(\\+(=(_S1, [X|_S5])), ','(=(_S1, _S3), \
+             ','(call(e, X, _S3, _S4), ','(!, =(_S4, _S2))))))",
+        ]
+    );
+}
+
+#[test]
+#[should_panic]
+fn test_program_1_fails() {
+    let program_parser = parser::ProgramParser::new();
+
+    program_parser
+        .parse(&OpTable::default(), Lexer::new("p(a). p(b)"))
+        .unwrap();
+}
+
+#[test]
+fn test_query_1_succeeds() {
+    let ops = OpTable::default();
+    let clause_parser = parser::ClauseParser::new();
+    let parse = |query| clause_parser.parse(&ops, Lexer::new(query)).unwrap();
+
+    assert_eq!(parse("?- a(X), b."), parse("a(X), b."));
+    assert_eq!(
+        parse("?- X."),
+        vec![Atom::new("call", vec![Term::Var(Var::new("X", 0))])]
+    );
+    // a goal that cannot be called raises its type error when the query runs
+    assert_eq!(parse("?- a, 1."), parse("a, call(1)."));
+}
+
+#[test]
+#[should_panic]
+fn test_query_1_fails() {
+    let clause_parser = parser::ClauseParser::new();
+
+    clause_parser
+        .parse(&OpTable::default(), Lexer::new("?- a :- 1."))
+        .unwrap();
+}
+
+#[test]
+fn test_anonymous_1_succeeds() {
+    let ops = OpTable::default();
+    let term_parser = parser::TermParser::new();
+    let term = term_parser.parse(&ops, Lexer::new("f(_, _, _X)")).unwrap();
+
+    match term {
+        Term::Atom(Atom { ref args, .. }) => match &args[..] {
+            [Term::Var(x), Term::Var(y), Term::Var(z)] => {
+                assert!(x.is_anonymous() && y.is_anonymous() && !z.is_anonymous());
+                assert_ne!(x, y);
+            }
+            _ => panic!("expected three variables"),
+        },
+        _ => panic!("expected a compound term"),
+    }
+}
+
+#[test]
+fn test_parse_error_1_succeeds() {
+    let code = "a.\nb(X :- c.";
+    let program_parser = parser::ProgramParser::new();
+    let e = program_parser
+        .parse(&OpTable::default(), Lexer::new(code))
+        .map_err(|e| ParseError::new(code, e))
+        .unwrap_err();
+
+    assert_eq!((e.line, e.column), (2, 9));
+    assert_eq!(e.lexeme, Some(String::from(".")));
+    assert_eq!(
+        e.to_string(),
+        "syntax error at line 2, column 9: unexpected `.`, expected `)`, `,`"
+    );
+}
+
+#[test]
+fn test_parse_error_2_succeeds() {
+    let parse = |code| {
+        let clause_parser = parser::ClauseParser::new();
+        clause_parser
+            .parse(&OpTable::default(), Lexer::new(code))
+            .map_err(|e| ParseError::new(code, e).to_string())
+            .unwrap_err()
+    };
+
+    assert_eq!(
+        parse("a = b = c."),
+        "syntax error at line 1, column 7: operator priority clash"
+    );
+    assert_eq!(
+        parse("a, 'b"),
+        "syntax error at line 1, column 4: unterminated quoted text"
+    );
+    assert!(parse("a, b")
+        .starts_with("syntax error at line 1, column 5: unexpected end of input, expected"));
+}
+
+fn writeq(ops: &OpTable, term: &str) -> String {
+    let term_parser = parser::TermParser::new();
+    let t = term_parser.parse(ops, Lexer::new(term)).unwrap();
+    let written = Writer::new(ops).quoted(true).write(&t);
+
+    assert_eq!(term_parser.parse(ops, Lexer::new(&written)).unwrap(), t);
+    written
+}
+
+#[test]
+fn test_writeq_1_succeeds() {
+    let ops = OpTable::default();
+
+    assert_eq!(writeq(&ops, "1 + 2 * 3"), "1+2*3");
+    assert_eq!(writeq(&ops, "(1 + 2) * 3"), "(1+2)*3");
+    assert_eq!(writeq(&ops, "a - (b - c)"), "a- (b-c)");
+    assert_eq!(writeq(&ops, "X is Y mod 2"), "X is Y mod 2");
+    assert_eq!(writeq(&ops, "a :- b, c ; \\+ d"), "a:-b,c;\\+d");
+    assert_eq!(writeq(&ops, "f((a, b), (c :- d))"), "f((a,b),(c:-d))");
+}
+
+#[test]
+fn test_writeq_2_succeeds() {
+    let ops = OpTable::default();
+
+    assert_eq!(writeq(&ops, "1 - -1"), "1- -1");
+    assert_eq!(writeq(&ops, "- 1"), "- 1");
+    assert_eq!(writeq(&ops, "-(-1)"), "- -1");
+    assert_eq!(writeq(&ops, "-(-(1))"), "- - 1");
+    assert_eq!(writeq(&ops, "- (a, b)"), "- (a,b)");
+    assert_eq!(writeq(&ops, "- a"), "-a");
+    assert_eq!(writeq(&ops, "f(-, [+])"), "f(-,[+])");
+    assert_eq!(writeq(&ops, "- (-)"), "- (-)");
+    assert_eq!(writeq(&ops, "a = \\+"), "a= (\\+)");
+}
+
+#[test]
+fn test_writeq_3_succeeds() {
+    let ops = OpTable::default();
+
+    assert_eq!(writeq(&ops, "'hello world'"), "'hello world'");
+    assert_eq!(writeq(&ops, "['A', b|'C']"), "['A',b|'C']");
+    assert_eq!(writeq(&ops, "{a, 'b c'}"), "{a,'b c'}");
+    assert_eq!(writeq(&ops, "f('\\n', [], '[]', {})"), "f('\\n',[],[],{})");
+    assert_eq!(writeq(&ops, "'a b' + 'c'"), "'a b'+c");
+    assert_eq!(
+        Writer::new(&ops).write(&Term::Atom(Atom::new(
+            "+",
+            vec![Term::Const(Const::new("a b")), Term::Number(Number::Int(1))]
+        ))),
+        "a b+1"
+    );
+    assert_eq!(
+        Writer::new(&ops)
+            .ignore_ops(true)
+            .write(&Term::Atom(Atom::new(
+                "-",
+                vec![Term::Number(Number::Int(1)), Term::Number(Number::Int(2))]
+            ))),
+        "-(1,2)"
+    );
+}
+
+#[test]
+fn test_term_builder_1_succeeds() {
+    let list = Term::list(
+        vec![Term::atom("a"), Term::int(1), Term::float(2.5)],
+        Term::var("T"),
+    );
+
+    assert_eq!(
+        Term::compound("f", vec![Term::var("X"), list]),
+        term!("f(X, [a, 1, 2.5|T])")
+    );
+    assert_eq!(Term::compound("g", vec![]), term!("g"));
+    assert_eq!(
+        term!("X is 1 + 2"),
+        Term::compound(
+            "is",
+            vec![
+                Term::var("X"),
+                Term::compound("+", vec![Term::int(1), Term::int(2)])
+            ]
+        )
+    );
+}
+
+#[test]
+#[should_panic(expected = "syntax error at line 1, column 3")]
+fn test_term_builder_1_fails() {
+    term!("f(");
+}
+
+#[test]
+fn test_unicode_1_succeeds() {
+    let ops = OpTable::default();
+
+    assert_eq!(
+        parse_term(&ops, "père(Größe, 'ünï cödé ☃', 日本, [0'é])"),
+        "père(Größe, 'ünï cödé ☃', 日本, [233])"
+    );
+    assert_eq!(parse_term(&ops, "Ärger = 'Ärger'"), "=(Ärger, 'Ärger')");
+    assert_eq!(writeq(&ops, "f('☃', été)"), "f('☃',été)");
+
+    let e = parser::TermParser::new()
+        .parse(&ops, Lexer::new("größe(☃)"))
+        .map_err(|e| ParseError::new("größe(☃)", e))
+        .unwrap_err();
+    assert_eq!((e.line, e.column), (1, 7));
+}
+
+#[test]
+fn test_read_term_1_succeeds() {
+    let ops = OpTable::default();
+    let read = Reader::new(&ops)
+        .read_term("foo(X, _Y, _, [X|Z]) :- bar(Z, W).")
+        .unwrap();
+
+    let names = |vars: &[(String, Var)]| {
+        vars.iter()
+            .map(|(name, _)| name.clone())
+            .collect::<Vec<_>>()
+    };
+
+    assert!(read
+        .term
+        .is_variant(&term!("foo(X, _Y, _, [X|Z]) :- bar(Z, W)")));
+    assert_eq!(names(&read.variable_names), ["X", "_Y", "Z", "W"]);
+    assert_eq!(names(&read.singletons), ["_Y", "W"]);
+    assert_eq!(read.variable_names[0].1, Var::new("X", 0));
+}
+
+#[test]
+fn test_read_term_1_fails() {
+    let ops = OpTable::default();
+    let e = Reader::new(&ops).read_term("foo(X)").unwrap_err();
+
+    assert_eq!(e.lexeme, None);
+    assert_eq!((e.line, e.column), (1, 7));
+}
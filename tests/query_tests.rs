@@ -1,24 +1,55 @@
-use bfg_prolog::ast;
-use bfg_prolog::ast::{Assertion, Clause};
-use bfg_prolog::solve_toplevel;
-use lalrpop_util::lalrpop_mod;
+use bfg_prolog::ast::{Atom, Clause, Const, Number, Sentence, Term};
+use bfg_prolog::convert::{ConversionError, ToTerm};
+use bfg_prolog::database::{Key, Segment};
+use bfg_prolog::engine::{Engine, Step};
+use bfg_prolog::flags::OccursCheck;
+use bfg_prolog::foreign::Foreign;
+use bfg_prolog::lexer::{DoubleQuotes, Lexer};
+use bfg_prolog::machine::{Budget, Machine};
+use bfg_prolog::ops::OpTable;
+use bfg_prolog::pool::QueryPool;
+use bfg_prolog::ports::{Port, PortListener};
+use bfg_prolog::stream::{Buffer, InputStream, OutputStream};
+use bfg_prolog::{
+    answer_query, initialization_goals, parser, run_goal, singleton_warnings, solve_toplevel, term,
+    Initialization, WamError,
+};
 use std::fs::read_to_string;
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+fn read_source_code(path: &str) -> Machine {
+    let mut machine = Machine::new();
+    let (assertions, _) = initialization_goals(read_program(path));
+    machine.db.consult(Segment::User, path, assertions).unwrap();
+    machine
+}
 
-lalrpop_mod!(pub parser);
-
-fn read_source_code(path: &str) -> Vec<Assertion> {
+fn read_program(path: &str) -> Vec<(usize, Sentence)> {
     let s = read_to_string(String::from(path)).unwrap();
-    parse_code(&s)
+    parse_program(&s)
 }
 
-fn parse_code(code: &str) -> Vec<Assertion> {
-    let code_parser = parser::CodeParser::new();
-    code_parser.parse(code).unwrap()
+fn parse_program(code: &str) -> Vec<(usize, Sentence)> {
+    let program_parser = parser::ProgramParser::new();
+    program_parser
+        .parse(&OpTable::default(), Lexer::new(code))
+        .unwrap()
 }
 
 fn parse_query(query: &str) -> Clause {
+    parse_query_with(DoubleQuotes::default(), query)
+}
+
+fn parse_query_with(double_quotes: DoubleQuotes, query: &str) -> Clause {
     let clause_parser = parser::ClauseParser::new();
-    clause_parser.parse(query).unwrap()
+    clause_parser
+        .parse(
+            &OpTable::default(),
+            Lexer::new(query).double_quotes(double_quotes),
+        )
+        .unwrap()
 }
 
 fn compare_answers(answers: Vec<String>, expected: &[&str]) {
@@ -28,130 +59,140 @@ fn compare_answers(answers: Vec<String>, expected: &[&str]) {
 
 #[test]
 fn test_basic_1_succeeds() {
-    let source = read_source_code("tests/example_programs/basic/basic.pl");
+    let mut source = read_source_code("tests/example_programs/basic/basic.pl");
     let query = parse_query("unify(X, X).");
 
-    let results = solve_toplevel(false, &source, query);
+    let results = solve_toplevel(false, &mut source, query);
 
-    compare_answers(results, &["X = X1"])
+    compare_answers(results, &["Yes"])
 }
 
 #[test]
 fn test_basic_2_succeeds() {
-    let source = read_source_code("tests/example_programs/basic/basic.pl");
+    let mut source = read_source_code("tests/example_programs/basic/basic.pl");
     let query = parse_query("unify(X, Y).");
 
-    let results = solve_toplevel(false, &source, query);
+    let results = solve_toplevel(false, &mut source, query);
 
-    compare_answers(results, &["X = X1\nY = X1"])
+    compare_answers(results, &["Y = X"])
 }
 
 #[test]
 fn test_basic_3_succeeds() {
-    let source = read_source_code("tests/example_programs/basic/basic.pl");
+    let mut source = read_source_code("tests/example_programs/basic/basic.pl");
     let query = parse_query("unify(a, a).");
 
-    let results = solve_toplevel(false, &source, query);
+    let results = solve_toplevel(false, &mut source, query);
 
     compare_answers(results, &["Yes"])
 }
 
 #[test]
 fn test_basic_3_fails() {
-    let source = read_source_code("tests/example_programs/basic/basic.pl");
+    let mut source = read_source_code("tests/example_programs/basic/basic.pl");
     let query = parse_query("unify(a, b).");
 
-    let results = solve_toplevel(false, &source, query);
+    let results = solve_toplevel(false, &mut source, query);
 
     compare_answers(results, &["No"])
 }
 
+#[test]
+fn test_anonymous_1_succeeds() {
+    let mut source = read_source_code("tests/example_programs/basic/basic.pl");
+    let query = parse_query("unify(f(_, _), f(a, b)), unify(_, X), unify(X, c).");
+
+    let results = solve_toplevel(false, &mut source, query);
+
+    compare_answers(results, &["X = c"])
+}
+
 #[test]
 fn test_basic_4_succeeds() {
-    let source = read_source_code("tests/example_programs/basic/basic.pl");
+    let mut source = read_source_code("tests/example_programs/basic/basic.pl");
     let query = parse_query("unify(X, a).");
 
-    let results = solve_toplevel(false, &source, query);
+    let results = solve_toplevel(false, &mut source, query);
 
     compare_answers(results, &["X = a"])
 }
 
 #[test]
 fn test_basic_5_succeeds() {
-    let source = read_source_code("tests/example_programs/basic/basic.pl");
+    let mut source = read_source_code("tests/example_programs/basic/basic.pl");
     let query = parse_query("member(a, list(a, nil)).");
 
-    let results = solve_toplevel(false, &source, query);
+    let results = solve_toplevel(false, &mut source, query);
 
     compare_answers(results, &["Yes"])
 }
 
 #[test]
 fn test_basic_6_succeeds() {
-    let source = read_source_code("tests/example_programs/basic/basic.pl");
+    let mut source = read_source_code("tests/example_programs/basic/basic.pl");
     let query = parse_query("member(a, list(a, list(b, nil))).");
 
-    let results = solve_toplevel(false, &source, query);
+    let results = solve_toplevel(false, &mut source, query);
 
     compare_answers(results, &["Yes"])
 }
 
 #[test]
 fn test_basic_7_succeeds() {
-    let source = read_source_code("tests/example_programs/basic/basic.pl");
+    let mut source = read_source_code("tests/example_programs/basic/basic.pl");
     let query = parse_query("member(a, list(a, list(b, list(a, nil)))).");
 
-    let results = solve_toplevel(false, &source, query);
+    let results = solve_toplevel(false, &mut source, query);
 
     compare_answers(results, &["Yes", "Yes"])
 }
 
 #[test]
 fn test_basic_7_fails() {
-    let source = read_source_code("tests/example_programs/basic/basic.pl");
+    let mut source = read_source_code("tests/example_programs/basic/basic.pl");
     let query = parse_query("member(c, list(a, list(b, list(a, nil)))).");
 
-    let results = solve_toplevel(false, &source, query);
+    let results = solve_toplevel(false, &mut source, query);
 
     compare_answers(results, &["No"])
 }
 
 #[test]
 fn test_basic_8_succeeds() {
-    let source = read_source_code("tests/example_programs/basic/basic.pl");
+    let mut source = read_source_code("tests/example_programs/basic/basic.pl");
     let query = parse_query("member(X, list(a, list(b, list(a, nil)))).");
 
-    let results = solve_toplevel(false, &source, query);
+    let results = solve_toplevel(false, &mut source, query);
 
     compare_answers(results, &["X = a", "X = b", "X = a"]);
 }
 
 #[test]
 fn test_basic_9_succeeds() {
-    let source = read_source_code("tests/example_programs/basic/basic.pl");
+    let mut source = read_source_code("tests/example_programs/basic/basic.pl");
     let query = parse_query("unify(X, b), member(X, list(a, list(b, list(a, nil)))).");
 
-    let results = solve_toplevel(false, &source, query);
+    let results = solve_toplevel(false, &mut source, query);
 
     compare_answers(results, &["X = b"]);
 }
 
 #[test]
 fn test_basic_10_succeeds() {
-    let source = read_source_code("tests/example_programs/basic/basic.pl");
+    let mut source = read_source_code("tests/example_programs/basic/basic.pl");
     let query = parse_query("unify(X, b), member(X, list(a, list(b, list(b, nil)))).");
 
-    let results = solve_toplevel(false, &source, query);
+    let results = solve_toplevel(false, &mut source, query);
 
     compare_answers(results, &["X = b", "X = b"]);
 }
 
 #[test]
 fn test_basic_11_succeeds() {
-    let source = read_source_code("tests/example_programs/basic/basic.pl");
+    let mut source = read_source_code("tests/example_programs/basic/basic.pl");
     let query = parse_query("append(X, Y, list(a, list(b, nil))).");
 
-    let results = solve_toplevel(false, &source, query);
+    let results = solve_toplevel(false, &mut source, query);
 
     compare_answers(
         results,
@@ -165,92 +206,92 @@ fn test_basic_11_succeeds() {
 
 #[test]
 fn test_basic_12_succeeds() {
-    let source = read_source_code("tests/example_programs/basic/basic.pl");
+    let mut source = read_source_code("tests/example_programs/basic/basic.pl");
     let query = parse_query("append(X, list(Y, list(Z, nil)), list(a, list(b, nil))).");
 
-    let results = solve_toplevel(false, &source, query);
+    let results = solve_toplevel(false, &mut source, query);
 
     compare_answers(results, &["X = nil\nY = a\nZ = b"]);
 }
 
 #[test]
 fn test_basic_12_fails() {
-    let source = read_source_code("tests/example_programs/basic/basic.pl");
+    let mut source = read_source_code("tests/example_programs/basic/basic.pl");
     let query = parse_query("append(X, list(Y, list(q, nil)), list(a, list(b, nil))).");
 
-    let results = solve_toplevel(false, &source, query);
+    let results = solve_toplevel(false, &mut source, query);
 
     compare_answers(results, &["No"]);
 }
 
 #[test]
 fn test_basic_13_fails() {
-    let source = read_source_code("tests/example_programs/basic/basic.pl");
+    let mut source = read_source_code("tests/example_programs/basic/basic.pl");
     let query = parse_query(
         "append(list(a, list(b, list(c, nil))), list(Y, list(Z, nil)), list(a, list(b, nil))).",
     );
 
-    let results = solve_toplevel(false, &source, query);
+    let results = solve_toplevel(false, &mut source, query);
 
     compare_answers(results, &["No"]);
 }
 
 #[test]
 fn test_basic_14_fails() {
-    let source = read_source_code("tests/example_programs/basic/basic.pl");
+    let mut source = read_source_code("tests/example_programs/basic/basic.pl");
     let query = parse_query("append(list(a, nil), list(b, nil), list(a, list(b, nil))).");
 
-    let results = solve_toplevel(false, &source, query);
+    let results = solve_toplevel(false, &mut source, query);
 
     compare_answers(results, &["Yes"]);
 }
 
 #[test]
 fn test_basic_14_succeeds() {
-    let source = read_source_code("tests/example_programs/basic/basic.pl");
+    let mut source = read_source_code("tests/example_programs/basic/basic.pl");
     let query = parse_query("append(list(a, nil), X, list(a, list(b, nil))).");
 
-    let results = solve_toplevel(false, &source, query);
+    let results = solve_toplevel(false, &mut source, query);
 
     compare_answers(results, &["X = list(b, nil)"]);
 }
 
 #[test]
 fn test_basic_15_succeeds() {
-    let source = read_source_code("tests/example_programs/basic/basic.pl");
+    let mut source = read_source_code("tests/example_programs/basic/basic.pl");
     let query = parse_query("unify(p(Z, h(Z, W), f(W)), p(f(X), h(Y, f(a)), Y)).");
 
-    let results = solve_toplevel(false, &source, query);
+    let results = solve_toplevel(false, &mut source, query);
 
     compare_answers(results, &["W = f(a)\nX = f(a)\nY = f(f(a))\nZ = f(f(a))"]);
 }
 
 #[test]
 fn test_basic_16_succeeds() {
-    let source = read_source_code("tests/example_programs/basic/basic.pl");
+    let mut source = read_source_code("tests/example_programs/basic/basic.pl");
     let query = parse_query("unify(f(X, g(X, a)), f(b, Y)).");
 
-    let results = solve_toplevel(false, &source, query);
+    let results = solve_toplevel(false, &mut source, query);
 
     compare_answers(results, &["X = b\nY = g(b, a)"]);
 }
 
 #[test]
 fn test_basic_17_succeeds() {
-    let source = read_source_code("tests/example_programs/basic/basic.pl");
+    let mut source = read_source_code("tests/example_programs/basic/basic.pl");
     let query = parse_query("unify(f(X), X).");
 
-    let results = solve_toplevel(false, &source, query);
+    let results = solve_toplevel(false, &mut source, query);
 
     compare_answers(results, &["No"]);
 }
 
 #[test]
 fn test_the_expanse_program_1_succeeds() {
-    let source = read_source_code("tests/example_programs/the_expanse/the_expanse.pl");
+    let mut source = read_source_code("tests/example_programs/the_expanse/the_expanse.pl");
     let query = parse_query("leader(X).");
 
-    let results = solve_toplevel(false, &source, query);
+    let results = solve_toplevel(false, &mut source, query);
 
     compare_answers(
         results,
@@ -260,20 +301,20 @@ fn test_the_expanse_program_1_succeeds() {
 
 #[test]
 fn test_the_expanse_program_1_fails() {
-    let source = read_source_code("tests/example_programs/the_expanse/the_expanse.pl");
+    let mut source = read_source_code("tests/example_programs/the_expanse/the_expanse.pl");
     let query = parse_query("leader('Amos Burton').");
 
-    let results = solve_toplevel(false, &source, query);
+    let results = solve_toplevel(false, &mut source, query);
 
     compare_answers(results, &["No"])
 }
 
 #[test]
 fn test_the_expanse_program_2_succeeds() {
-    let source = read_source_code("tests/example_programs/the_expanse/the_expanse.pl");
+    let mut source = read_source_code("tests/example_programs/the_expanse/the_expanse.pl");
     let query = parse_query("captain(S, X).");
 
-    let results = solve_toplevel(false, &source, query);
+    let results = solve_toplevel(false, &mut source, query);
 
     compare_answers(
         results,
@@ -286,50 +327,53 @@ fn test_the_expanse_program_2_succeeds() {
 
 #[test]
 fn test_the_expanse_program_2_fails() {
-    let source = read_source_code("tests/example_programs/the_expanse/the_expanse.pl");
+    let mut source = read_source_code("tests/example_programs/the_expanse/the_expanse.pl");
     let query = parse_query("captain(X).");
 
-    let results = solve_toplevel(false, &source, query);
+    let results = solve_toplevel(false, &mut source, query);
 
-    compare_answers(results, &["No"])
+    compare_answers(
+        results,
+        &["Error: error(existence_error(procedure,captain/1),captain/1)"],
+    )
 }
 
 #[test]
 fn test_the_expanse_program_3_succeeds() {
-    let source = read_source_code("tests/example_programs/the_expanse/the_expanse.pl");
+    let mut source = read_source_code("tests/example_programs/the_expanse/the_expanse.pl");
     let query = parse_query("mechanic('Rocinante', X).");
 
-    let results = solve_toplevel(false, &source, query);
+    let results = solve_toplevel(false, &mut source, query);
 
     compare_answers(results, &["X = 'Amos Burton'"])
 }
 
 #[test]
 fn test_the_expanse_program_3_fails() {
-    let source = read_source_code("tests/example_programs/the_expanse/the_expanse.pl");
+    let mut source = read_source_code("tests/example_programs/the_expanse/the_expanse.pl");
     let query = parse_query("mechanic('Rocinante', 'Alex Kamal').");
 
-    let results = solve_toplevel(false, &source, query);
+    let results = solve_toplevel(false, &mut source, query);
 
     compare_answers(results, &["No"])
 }
 
 #[test]
 fn test_the_expanse_program_4_succeeds() {
-    let source = read_source_code("tests/example_programs/the_expanse/the_expanse.pl");
+    let mut source = read_source_code("tests/example_programs/the_expanse/the_expanse.pl");
     let query = parse_query("mechanic(S, 'Amos Burton').");
 
-    let results = solve_toplevel(false, &source, query);
+    let results = solve_toplevel(false, &mut source, query);
 
     compare_answers(results, &["S = 'Canterbury'", "S = 'Rocinante'"])
 }
 
 #[test]
 fn test_the_expanse_program_5_succeeds() {
-    let source = read_source_code("tests/example_programs/the_expanse/the_expanse.pl");
+    let mut source = read_source_code("tests/example_programs/the_expanse/the_expanse.pl");
     let query = parse_query("mechanic(S, 'Amos Burton'), pilot(S, Pilot).");
 
-    let results = solve_toplevel(false, &source, query);
+    let results = solve_toplevel(false, &mut source, query);
 
     compare_answers(
         results,
@@ -339,3 +383,2903 @@ fn test_the_expanse_program_5_succeeds() {
         ],
     )
 }
+
+#[test]
+fn test_initialization_1_succeeds() {
+    let source = read_program("tests/example_programs/initialization/initialization.pl");
+    let (source, goals) = initialization_goals(source);
+
+    assert_eq!(source.len(), 3);
+    assert_eq!(
+        goals,
+        vec![
+            Initialization::Load(Term::Const(Const::new("ready"))),
+            Initialization::Main(Term::Const(Const::new("start"))),
+        ]
+    );
+}
+
+#[test]
+fn test_initialization_2_succeeds() {
+    let source = read_program("tests/example_programs/initialization/initialization.pl");
+    let (source, _) = initialization_goals(source);
+    let mut machine = Machine::new();
+    machine
+        .db
+        .consult(Segment::User, "initialization.pl", source)
+        .unwrap();
+
+    assert!(run_goal(&mut machine, Term::Const(Const::new("ready"))).unwrap());
+    assert!(!run_goal(&mut machine, Term::Const(Const::new("start"))).unwrap());
+    assert!(!run_goal(
+        &mut machine,
+        Term::Atom(Atom::new(
+            "loaded",
+            vec![Term::Const(Const::new("missing"))]
+        ))
+    )
+    .unwrap());
+    assert!(matches!(
+        run_goal(&mut machine, Term::int(1)),
+        Err(WamError::Execution(_))
+    ));
+}
+
+#[test]
+fn test_singletons_1_succeeds() {
+    let code = "length([], 0).\n\
+                length([_|T], N) :- length(T, M), N is M + 1.\n\
+                last([X], Y).\n\
+                first([X|_Rest], X).\n\
+                :- initialization(main(Unused)).\n\
+                oops(Lenght) :-\n    \
+                    length([], Length).";
+
+    let warnings = singleton_warnings(code, &parse_program(code));
+
+    assert_eq!(
+        warnings.iter().map(|w| w.to_string()).collect::<Vec<_>>(),
+        [
+            "singleton variables [X, Y] in clause of last/2 at line 3",
+            "singleton variables [Lenght, Length] in clause of oops/1 at line 6",
+        ]
+    );
+}
+
+#[test]
+fn test_control_1_succeeds() {
+    let mut source = read_source_code("tests/example_programs/basic/basic.pl");
+    let query = parse_query("X = f(Y), true, Y = a.");
+
+    let results = solve_toplevel(false, &mut source, query);
+
+    compare_answers(results, &["X = f(a)\nY = a"]);
+}
+
+#[test]
+fn test_control_1_fails() {
+    let mut source = read_source_code("tests/example_programs/basic/basic.pl");
+
+    for query in &[
+        "fail.",
+        "false.",
+        "true, fail.",
+        "unify(X, a), X = b.",
+        "X = f(X).",
+    ] {
+        let results = solve_toplevel(false, &mut source, parse_query(query));
+
+        compare_answers(results, &["No"]);
+    }
+}
+
+#[test]
+fn test_control_2_succeeds() {
+    let mut source = read_source_code("tests/example_programs/basic/basic.pl");
+    let query = parse_query("member(X, list(a, list(b, nil))), X = b.");
+
+    let results = solve_toplevel(false, &mut source, query);
+
+    compare_answers(results, &["X = b"]);
+}
+
+#[test]
+fn test_arithmetic_1_succeeds() {
+    let mut source = read_source_code("tests/example_programs/basic/basic.pl");
+
+    for (query, answer) in &[
+        ("X is 1 + 2 * 3.", "X = 7"),
+        ("X is 7 / 2.", "X = 3.5"),
+        ("X is 6 / 2.", "X = 3"),
+        ("X is -7 mod 2.", "X = 1"),
+        ("X is -7 // 2.", "X = -3"),
+        ("X is 2 ^ 10.", "X = 1024"),
+        ("Y = 3, X is Y * Y.", "X = 9\nY = 3"),
+        ("1 < 2.0.", "Yes"),
+        ("1 =:= 1.0.", "Yes"),
+        ("X is sqrt(16).", "X = 4.0"),
+        ("X is floor(-2.5).", "X = -3"),
+        ("X is ceiling(2.1) + round(2.5) + truncate(-2.7).", "X = 4"),
+        ("X is float(3) + float_integer_part(2.5).", "X = 5.0"),
+        ("X is float_fractional_part(-2.5).", "X = -0.5"),
+        ("X is exp(log(1)) + sin(0) + cos(0) + tan(0).", "X = 2.0"),
+        ("X is atan(0, 1) + atan2(0, -1) - pi.", "X = 0.0"),
+        ("X is floor(7).", "X = 7"),
+    ] {
+        let results = solve_toplevel(false, &mut source, parse_query(query));
+
+        compare_answers(results, &[answer]);
+    }
+}
+
+#[test]
+fn test_arithmetic_1_fails() {
+    let mut source = read_source_code("tests/example_programs/basic/basic.pl");
+
+    for (query, answer) in &[
+        ("3 is 1 + 1.", "No"),
+        ("2 > 3.", "No"),
+        (
+            "X is foo + 1.",
+            "Error: error(type_error(evaluable,foo/0),context((is)/2,_1))",
+        ),
+        (
+            "X is Y + 1.",
+            "Error: error(instantiation_error,context((is)/2,_1))",
+        ),
+        (
+            "X is 1 / 0.",
+            "Error: error(evaluation_error(zero_divisor),context((is)/2,_1))",
+        ),
+        (
+            "X is sqrt(-1).",
+            "Error: error(evaluation_error(undefined),context((is)/2,_1))",
+        ),
+        (
+            "X is log(0).",
+            "Error: error(evaluation_error(undefined),context((is)/2,_1))",
+        ),
+        (
+            "X is round(1.0e300).",
+            "Error: error(evaluation_error(int_overflow),context((is)/2,_1))",
+        ),
+    ] {
+        let results = solve_toplevel(false, &mut source, parse_query(query));
+
+        compare_answers(results, &[answer]);
+    }
+}
+
+#[test]
+fn test_type_checks_1_succeeds() {
+    let mut source = read_source_code("tests/example_programs/basic/basic.pl");
+
+    for query in &[
+        "var(X).",
+        "X = Y, var(X).",
+        "X = a, nonvar(X).",
+        "atom(foo), atom([]).",
+        "number(1), number(1.5).",
+        "integer(3), float(3.0).",
+        "atomic(a), atomic(1).",
+        "X = f(Y), compound(X).",
+        "callable(a), callable(f(x)).",
+        "is_list([]), is_list([a, b]).",
+        "T = [c], is_list([a, b|T]).",
+    ] {
+        let results = solve_toplevel(false, &mut source, parse_query(query));
+
+        assert_ne!(results, ["No"], "{}", query);
+    }
+}
+
+#[test]
+fn test_type_checks_1_fails() {
+    let mut source = read_source_code("tests/example_programs/basic/basic.pl");
+
+    for query in &[
+        "X = a, var(X).",
+        "nonvar(X).",
+        "atom(1).",
+        "atom(X).",
+        "number(a).",
+        "integer(1.0).",
+        "float(1).",
+        "atomic(f(x)).",
+        "compound(a).",
+        "callable(1).",
+        "is_list([a|_]).",
+        "is_list(foo).",
+    ] {
+        let results = solve_toplevel(false, &mut source, parse_query(query));
+
+        compare_answers(results, &["No"]);
+    }
+}
+
+#[test]
+fn test_term_inspection_1_succeeds() {
+    let mut source = read_source_code("tests/example_programs/basic/basic.pl");
+
+    for (query, answer) in &[
+        ("functor(f(a, b), N, A).", "A = 2\nN = f"),
+        ("functor(foo, N, A).", "A = 0\nN = foo"),
+        ("functor(3, N, A).", "A = 0\nN = 3"),
+        ("functor(T, f, 2), T = f(a, b).", "T = f(a, b)"),
+        ("functor(T, foo, 0).", "T = foo"),
+        ("arg(2, f(a, b, c), X).", "X = b"),
+        ("f(a, b) =.. L.", "L = [f, a, b]"),
+        ("a =.. L.", "L = [a]"),
+        ("T =.. [g, 1, X], X = 2.", "T = g(1, 2)\nX = 2"),
+        ("T =.. [1].", "T = 1"),
+    ] {
+        let results = solve_toplevel(false, &mut source, parse_query(query));
+
+        compare_answers(results, &[answer]);
+    }
+}
+
+#[test]
+fn test_term_inspection_1_fails() {
+    let mut source = read_source_code("tests/example_programs/basic/basic.pl");
+
+    for (query, answer) in &[
+        ("arg(4, f(a, b, c), X).", "No"),
+        ("arg(0, f(a), X).", "No"),
+        ("functor(f(a), g, 1).", "No"),
+        (
+            "functor(T, N, 1).",
+            "Error: error(instantiation_error,context(functor/3,_1))",
+        ),
+        (
+            "functor(T, f(a), 1).",
+            "Error: error(type_error(atomic,f(a)),context(functor/3,_1))",
+        ),
+        (
+            "arg(1, foo, X).",
+            "Error: error(type_error(compound,foo),context(arg/3,_1))",
+        ),
+        (
+            "T =.. [f|_].",
+            "Error: error(instantiation_error,context((=..)/2,_1))",
+        ),
+        (
+            "T =.. [].",
+            "Error: error(domain_error(non_empty_list,[]),context((=..)/2,_1))",
+        ),
+        (
+            "T =.. [1, a].",
+            "Error: error(type_error(atom,1),context((=..)/2,_1))",
+        ),
+    ] {
+        let results = solve_toplevel(false, &mut source, parse_query(query));
+
+        compare_answers(results, &[answer]);
+    }
+}
+
+#[test]
+fn test_dynamic_1_succeeds() {
+    let path = "tests/example_programs/dynamic/dynamic.pl";
+    let (assertions, goals) = initialization_goals(read_program(path));
+    let mut machine = Machine::new();
+    machine.db.consult(Segment::User, path, assertions).unwrap();
+
+    for goal in goals {
+        if let Initialization::Load(goal) = goal {
+            assert!(run_goal(&mut machine, goal).unwrap());
+        }
+    }
+
+    let query = parse_query("see(b), see_first(z), assert((pair(X, Y) :- seen(X), seen(Y))).");
+    compare_answers(solve_toplevel(false, &mut machine, query), &["Yes"]);
+
+    let query = parse_query("seen(X).");
+    let results = solve_toplevel(false, &mut machine, query);
+    compare_answers(results, &["X = z", "X = a", "X = b"]);
+
+    let query = parse_query("pair(b, Y).");
+    let results = solve_toplevel(false, &mut machine, query);
+    compare_answers(results, &["Y = z", "Y = a", "Y = b"]);
+}
+
+#[test]
+fn test_dynamic_1_fails() {
+    let mut source = read_source_code("tests/example_programs/basic/basic.pl");
+
+    for (query, answer) in &[
+        (
+            "assert(unify(a, b)).",
+            "Error: error(permission_error(modify,static_procedure,unify/2),context(assert/1,_1))",
+        ),
+        (
+            "asserta((foo :- 1)).",
+            "Error: error(type_error(callable,(foo:-1)),context(asserta/1,_1))",
+        ),
+        (
+            "assertz(X).",
+            "Error: error(instantiation_error,context(assertz/1,_1))",
+        ),
+    ] {
+        let results = solve_toplevel(false, &mut source, parse_query(query));
+
+        compare_answers(results, &[answer]);
+    }
+}
+
+#[test]
+fn test_dynamic_2_succeeds() {
+    let mut source = read_source_code("tests/example_programs/dynamic/dynamic.pl");
+
+    let query = parse_query("dynamic(seen/1), assert(seen(b)), assert(seen(c)).");
+    compare_answers(solve_toplevel(false, &mut source, query), &["Yes"]);
+
+    let query = parse_query("retract(seen(X)).");
+    let results = solve_toplevel(false, &mut source, query);
+    compare_answers(results, &["X = a", "X = b", "X = c"]);
+
+    let query =
+        parse_query("assert((r(1) :- true)), assert((r(2) :- r(1))), retract((r(X) :- r(Y))).");
+    let results = solve_toplevel(false, &mut source, query);
+    compare_answers(results, &["X = 2\nY = 1"]);
+
+    let query = parse_query("assert(s(1)), assert(s(2)), assert(t(1)), retractall(s(_)), t(X).");
+    let results = solve_toplevel(false, &mut source, query);
+    compare_answers(results, &["X = 1"]);
+
+    let query = parse_query("retractall(u(_)), abolish(t/1).");
+    compare_answers(solve_toplevel(false, &mut source, query), &["Yes"]);
+
+    for query in &["seen(_).", "s(_).", "u(_).", "r(2)."] {
+        let results = solve_toplevel(false, &mut source, parse_query(query));
+        compare_answers(results, &["No"]);
+    }
+
+    let results = solve_toplevel(false, &mut source, parse_query("t(_)."));
+    compare_answers(
+        results,
+        &["Error: error(existence_error(procedure,t/1),t/1)"],
+    );
+}
+
+#[test]
+fn test_dynamic_2_fails() {
+    let mut source = read_source_code("tests/example_programs/basic/basic.pl");
+
+    for (query, answer) in &[
+        ("retract(missing(_)).", "No"),
+        (
+            "retract(unify(_, _)).",
+            "Error: error(permission_error(modify,static_procedure,unify/2),context(retract/1,_1))",
+        ),
+        (
+            "abolish(foo/bar).",
+            "Error: error(type_error(integer,bar),context(abolish/1,_1))",
+        ),
+        (
+            "retractall(1).",
+            "Error: error(type_error(callable,1),context(retractall/1,_1))",
+        ),
+    ] {
+        let results = solve_toplevel(false, &mut source, parse_query(query));
+
+        compare_answers(results, &[answer]);
+    }
+}
+
+#[test]
+fn test_term_order_1_succeeds() {
+    let mut source = read_source_code("tests/example_programs/basic/basic.pl");
+
+    for query in &[
+        "f(X, a) == f(X, a).",
+        "X \\== Y.",
+        "X \\== a.",
+        "X @< 1, 1 @< 1.5, 1.5 @< 2, 2 @< a, a @< b, b @< f(a).",
+        "f(b) @< g(a), g(a) @< f(a, a).",
+        "f(a) @=< f(a), f(b) @>= f(a), f(b) @> f(a).",
+        "compare(<, 1, 2), compare(=, a, a), compare(>, f(b), f(a)).",
+    ] {
+        let results = solve_toplevel(false, &mut source, parse_query(query));
+
+        assert_ne!(results, ["No"], "{}", query);
+    }
+
+    let query = parse_query("compare(O, f(a), f(b)).");
+    let results = solve_toplevel(false, &mut source, query);
+
+    compare_answers(results, &["O = <"]);
+}
+
+#[test]
+fn test_term_order_1_fails() {
+    let mut source = read_source_code("tests/example_programs/basic/basic.pl");
+
+    for (query, answer) in &[
+        ("X == Y.", "No"),
+        ("f(X) == f(a).", "No"),
+        ("a \\== a.", "No"),
+        ("b @< a.", "No"),
+        ("compare(>, 1, 2).", "No"),
+        (
+            "compare(less, 1, 2).",
+            "Error: error(domain_error(order,less),context(compare/3,_1))",
+        ),
+        (
+            "compare(1, 1, 2).",
+            "Error: error(type_error(atom,1),context(compare/3,_1))",
+        ),
+    ] {
+        let results = solve_toplevel(false, &mut source, parse_query(query));
+
+        compare_answers(results, &[answer]);
+    }
+}
+
+#[test]
+fn test_call_1_succeeds() {
+    let mut source = read_source_code("tests/example_programs/basic/basic.pl");
+
+    for (query, answer) in &[
+        ("call(unify(X, a)).", "X = a"),
+        ("G = unify(X), call(G, b).", "G = unify(b)\nX = b"),
+        ("call(append, nil, list(a, nil), Xs).", "Xs = list(a, nil)"),
+        (
+            "G = (X = 1, Y is X + 1), call(G).",
+            "G = ','(=(1, 1), is(2, +(1, 1)))\nX = 1\nY = 2",
+        ),
+        ("G = true, G.", "G = true"),
+    ] {
+        let results = solve_toplevel(false, &mut source, parse_query(query));
+
+        compare_answers(results, &[answer]);
+    }
+
+    let query = parse_query("call(member(X), list(a, list(b, nil))).");
+    let results = solve_toplevel(false, &mut source, query);
+
+    compare_answers(results, &["X = a", "X = b"]);
+}
+
+#[test]
+fn test_call_1_fails() {
+    let mut source = read_source_code("tests/example_programs/basic/basic.pl");
+
+    for (query, answer) in &[
+        ("call(fail).", "No"),
+        ("call(unify(a), b).", "No"),
+        (
+            "call(G).",
+            "Error: error(instantiation_error,context(call/1,_1))",
+        ),
+        (
+            "call(1, a).",
+            "Error: error(type_error(callable,1),context(call/2,_1))",
+        ),
+        ("G.", "Error: error(instantiation_error,context(call/1,_1))"),
+        (
+            "3.",
+            "Error: error(type_error(callable,3),context(call/1,_1))",
+        ),
+        (
+            "X = 1, X.",
+            "Error: error(type_error(callable,1),context(call/1,_1))",
+        ),
+    ] {
+        let results = solve_toplevel(false, &mut source, parse_query(query));
+
+        compare_answers(results, &[answer]);
+    }
+}
+
+#[test]
+fn test_output_1_succeeds() {
+    let mut source = read_source_code("tests/example_programs/basic/basic.pl");
+    let output = Buffer::new();
+    source.set_output(Box::new(output.clone()));
+
+    let query = parse_query(
+        "X = 'hello world', write(X), nl, print(X), nl, writeln(f(-1, [a|T])), \
+         writeq(['A'|1+2]), nl, write_canonical(1+'b c').",
+    );
+    let results = solve_toplevel(false, &mut source, query);
+
+    assert_eq!(results.len(), 1);
+    assert_eq!(
+        output.text(),
+        "hello world\n'hello world'\nf(-1,[a|T])\n['A'|1+2]\n+(1,'b c')"
+    );
+}
+
+#[test]
+fn test_input_1_succeeds() {
+    let mut source = read_source_code("tests/example_programs/basic/basic.pl");
+    let input = "foo(X, Y, X).\n% a comment\n'hello'(\n  [1, 2]). bar(_, Z).\n";
+    source.set_input(Box::new(io::Cursor::new(input)));
+
+    let query = parse_query("read(T), T = foo(a, b, A).");
+    compare_answers(
+        solve_toplevel(false, &mut source, query),
+        &["A = a\nT = foo(a, b, a)"],
+    );
+
+    let query = parse_query("read(T).");
+    compare_answers(
+        solve_toplevel(false, &mut source, query),
+        &["T = hello([1, 2])"],
+    );
+
+    let query = parse_query("read_term(T, [variable_names(Vs), singletons(Ss)]), Vs = [_ = a].");
+    compare_answers(
+        solve_toplevel(false, &mut source, query),
+        &["Ss = [=('Z', a)]\nT = bar(_1, a)\nVs = [=('Z', a)]"],
+    );
+
+    let query = parse_query("read(T).");
+    compare_answers(
+        solve_toplevel(false, &mut source, query),
+        &["T = end_of_file"],
+    );
+}
+
+#[test]
+fn test_input_1_fails() {
+    let mut source = read_source_code("tests/example_programs/basic/basic.pl");
+    source.set_input(Box::new(io::Cursor::new("foo(. bar.")));
+
+    let query = parse_query("read(T).");
+    let results = solve_toplevel(false, &mut source, query);
+
+    assert!(results[0].starts_with("Error: error(syntax_error("));
+
+    let query = parse_query("read_term(T, [foo]).");
+    compare_answers(
+        solve_toplevel(false, &mut source, query),
+        &["Error: error(domain_error(read_option,foo),context(read_term/2,_1))"],
+    );
+}
+
+#[test]
+fn test_reflection_1_succeeds() {
+    let mut source = read_source_code("tests/example_programs/basic/basic.pl");
+
+    let query = parse_query("clause(member(X, Y), B).");
+    compare_answers(
+        solve_toplevel(false, &mut source, query),
+        &[
+            "B = true\nY = list(X, _Rest1)",
+            "B = member(X, Rest2)\nY = list(_Y2, Rest2)",
+        ],
+    );
+
+    let query = parse_query("clause(unify(a, b), true).");
+    compare_answers(solve_toplevel(false, &mut source, query), &["No"]);
+
+    let query = parse_query("current_predicate(P).");
+    compare_answers(
+        solve_toplevel(false, &mut source, query),
+        &["P = /(append, 3)", "P = /(member, 2)", "P = /(unify, 2)"],
+    );
+
+    let query = parse_query("current_predicate(member/A).");
+    compare_answers(solve_toplevel(false, &mut source, query), &["A = 2"]);
+
+    for query in &[
+        "current_predicate(call/1).",
+        "current_predicate(missing/_).",
+    ] {
+        let results = solve_toplevel(false, &mut source, parse_query(query));
+        compare_answers(results, &["No"]);
+    }
+}
+
+#[test]
+fn test_reflection_1_fails() {
+    let mut source = read_source_code("tests/example_programs/basic/basic.pl");
+
+    for (query, answer) in &[
+        (
+            "clause(X, true).",
+            "Error: error(instantiation_error,context(clause/2,_1))",
+        ),
+        (
+            "clause(atom(_), B).",
+            "Error: error(permission_error(access,private_procedure,atom/1),context(clause/2,_1))",
+        ),
+        (
+            "current_predicate(foo).",
+            "Error: error(type_error(predicate_indicator,foo),context(current_predicate/1,_1))",
+        ),
+    ] {
+        let results = solve_toplevel(false, &mut source, parse_query(query));
+
+        compare_answers(results, &[answer]);
+    }
+}
+
+#[test]
+fn test_sort_1_succeeds() {
+    let mut source = read_source_code("tests/example_programs/basic/basic.pl");
+
+    for (query, answer) in &[
+        ("sort([c, a, b, a], L).", "L = [a, b, c]"),
+        ("msort([c, a, b, a], L).", "L = [a, a, b, c]"),
+        (
+            "sort([f(a), 2, a, X, 1.0, 1, \"s\"], L).",
+            "L = [X, 1.0, 1, 2, a, f(a), [115]]",
+        ),
+        ("sort([1, 1.0], L).", "L = [1.0, 1]"),
+        (
+            "keysort([b-1, a-2, b-0, a-1], L).",
+            "L = [-(a, 2), -(a, 1), -(b, 1), -(b, 0)]",
+        ),
+        ("X = b, sort([X, a], [A|_]).", "A = a\nX = b"),
+    ] {
+        let results = solve_toplevel(false, &mut source, parse_query(query));
+
+        compare_answers(results, &[answer]);
+    }
+}
+
+#[test]
+fn test_sort_1_fails() {
+    let mut source = read_source_code("tests/example_programs/basic/basic.pl");
+
+    for (query, answer) in &[
+        ("sort([b, a], [b, a]).", "No"),
+        (
+            "sort([a|_], L).",
+            "Error: error(instantiation_error,context(sort/2,_1))",
+        ),
+        (
+            "msort([a], foo).",
+            "Error: error(type_error(list,foo),context(msort/2,_1))",
+        ),
+        (
+            "keysort([a-1, b], L).",
+            "Error: error(type_error(pair,b),context(keysort/2,_1))",
+        ),
+    ] {
+        let results = solve_toplevel(false, &mut source, parse_query(query));
+
+        compare_answers(results, &[answer]);
+    }
+}
+
+#[test]
+fn test_number_text_1_succeeds() {
+    let mut source = read_source_code("tests/example_programs/basic/basic.pl");
+
+    for (query, answer) in &[
+        ("number_codes(X, \" 42\").", "X = 42"),
+        ("number_codes(-1.5, X).", "X = [45, 49, 46, 53]"),
+        ("number_codes(12, X).", "X = [49, 50]"),
+        ("number_chars(X, ['-', '3']).", "X = -3"),
+        ("number_chars(2.5, X).", "X = ['2', ., '5']"),
+        ("number_chars(X, ['0', '''', a]).", "X = 97"),
+        ("atom_number('12.5e1', X).", "X = 125.0"),
+        ("atom_number(A, 7).", "A = '7'"),
+        ("atom_number(foo, X).", "No"),
+        (
+            "atom_number('-9223372036854775808', X).",
+            "X = -9223372036854775808",
+        ),
+        ("X = -9223372036854775808.", "X = -9223372036854775808"),
+        ("char_code(a, X).", "X = 97"),
+        ("char_code(X, 0'b).", "X = b"),
+    ] {
+        let results = solve_toplevel(false, &mut source, parse_query(query));
+
+        compare_answers(results, &[answer]);
+    }
+}
+
+#[test]
+fn test_number_text_1_fails() {
+    let mut source = read_source_code("tests/example_programs/basic/basic.pl");
+
+    for (query, answer) in &[
+        (
+            "number_codes(X, \"3x\").",
+            "Error: error(syntax_error(illegal_number),context(number_codes/2,_1))",
+        ),
+        (
+            "number_chars(X, ['-', ' ', '1']).",
+            "Error: error(syntax_error(illegal_number),context(number_chars/2,_1))",
+        ),
+        (
+            "number_codes(X, Y).",
+            "Error: error(instantiation_error,context(number_codes/2,_1))",
+        ),
+        (
+            "number_chars(a, X).",
+            "Error: error(type_error(number,a),context(number_chars/2,_1))",
+        ),
+        (
+            "char_code(ab, X).",
+            "Error: error(type_error(character,ab),context(char_code/2,_1))",
+        ),
+        (
+            "char_code(X, -1).",
+            "Error: error(representation_error(character_code),context(char_code/2,_1))",
+        ),
+    ] {
+        let results = solve_toplevel(false, &mut source, parse_query(query));
+
+        compare_answers(results, &[answer]);
+    }
+}
+
+#[test]
+fn test_term_variables_1_succeeds() {
+    let mut source = read_source_code("tests/example_programs/basic/basic.pl");
+
+    for (query, answer) in &[
+        ("ground(f(a, [1])).", "Yes"),
+        ("X = a, ground(f(X)).", "X = a"),
+        ("term_variables(f(X, g(Y, X), _Z), Vs).", "Vs = [X, Y, _Z]"),
+        (
+            "X = f(Y), term_variables([X, Z], Vs).",
+            "Vs = [Y, Z]\nX = f(Y)",
+        ),
+        ("term_variables(a, Vs).", "Vs = []"),
+    ] {
+        let results = solve_toplevel(false, &mut source, parse_query(query));
+
+        compare_answers(results, &[answer]);
+    }
+
+    for query in &["ground(f(_)).", "term_variables(f(X), [])."] {
+        let results = solve_toplevel(false, &mut source, parse_query(query));
+
+        compare_answers(results, &["No"]);
+    }
+}
+
+#[test]
+fn test_flags_1_succeeds() {
+    let mut source = read_source_code("tests/example_programs/basic/basic.pl");
+
+    for (query, answer) in &[
+        ("current_prolog_flag(bounded, X).", "X = true"),
+        (
+            "current_prolog_flag(max_integer, X).",
+            "X = 9223372036854775807",
+        ),
+        (
+            "set_prolog_flag(double_quotes, atom), current_prolog_flag(double_quotes, X).",
+            "X = atom",
+        ),
+        (
+            "set_prolog_flag(unknown, error), missing.",
+            "Error: error(existence_error(procedure,missing/0),missing/0)",
+        ),
+        (
+            "set_prolog_flag(occurs_check, error), X = f(X).",
+            "Error: error(occurs_check(X,f(X)),_1)",
+        ),
+    ] {
+        let results = solve_toplevel(false, &mut source, parse_query(query));
+
+        compare_answers(results, &[answer]);
+    }
+
+    let query = parse_query("current_prolog_flag(F, _).");
+    assert_eq!(solve_toplevel(false, &mut source, query).len(), 6);
+}
+
+#[test]
+fn test_flags_2_succeeds() {
+    let mut machine = Machine::new();
+    let output = Buffer::new();
+    machine.set_output(Box::new(output.clone()));
+
+    let mut solutions = machine
+        .solve("set_prolog_flag(unknown, warning), missing.")
+        .unwrap();
+    assert!(solutions.next().is_none());
+    assert_eq!(output.text(), "Warning: unknown procedure missing/0\n");
+}
+
+#[test]
+fn test_flags_1_fails() {
+    let mut source = read_source_code("tests/example_programs/basic/basic.pl");
+
+    for (query, answer) in &[
+        ("current_prolog_flag(bounded, false).", "No"),
+        (
+            "set_prolog_flag(bounded, false).",
+            "Error: error(permission_error(modify,flag,bounded),context(set_prolog_flag/2,_1))",
+        ),
+        (
+            "set_prolog_flag(unknown, maybe).",
+            "Error: error(domain_error(flag_value,unknown+maybe),context(set_prolog_flag/2,_1))",
+        ),
+        (
+            "current_prolog_flag(missing, X).",
+            "Error: error(domain_error(prolog_flag,missing),context(current_prolog_flag/2,_1))",
+        ),
+    ] {
+        let results = solve_toplevel(false, &mut source, parse_query(query));
+
+        compare_answers(results, &[answer]);
+    }
+}
+
+#[test]
+fn test_catch_1_succeeds() {
+    let mut source = read_source_code("tests/example_programs/basic/basic.pl");
+
+    for (query, answer) in &[
+        ("catch(throw(oops), E, true).", "E = oops"),
+        (
+            "catch(X is 1 / 0, error(E, _), true).",
+            "E = evaluation_error(zero_divisor)",
+        ),
+        (
+            "catch(missing, error(existence_error(procedure, PI), _), true).",
+            "PI = /(missing, 0)",
+        ),
+        ("catch(G, error(E, _), true).", "E = instantiation_error"),
+        (
+            "catch(call(1), error(type_error(T, C), _), true).",
+            "C = 1\nT = callable",
+        ),
+        ("catch((X = a, throw(f(X))), f(Y), true).", "Y = a"),
+        ("catch(catch(throw(b), a, fail), B, true).", "B = b"),
+        ("catch(member(X, list(a, list(b, nil))), _, true).", "X = a"),
+        ("catch(true, _, fail), throw(late).", "Error: late"),
+    ] {
+        let results = solve_toplevel(false, &mut source, parse_query(query));
+
+        compare_answers(results[..1].to_vec(), &[answer]);
+    }
+}
+
+#[test]
+fn test_catch_1_fails() {
+    let mut source = read_source_code("tests/example_programs/basic/basic.pl");
+
+    for (query, answer) in &[
+        ("catch(fail, _, true).", "No"),
+        ("catch(throw(a), b, true).", "Error: a"),
+        ("catch(throw(a), a, fail).", "No"),
+        ("throw(_).", "Error: error(instantiation_error,_1)"),
+        (
+            "X = 1, X.",
+            "Error: error(type_error(callable,1),context(call/1,_1))",
+        ),
+        (
+            "G = (true, 1), call(G).",
+            "Error: error(type_error(callable,1),_1)",
+        ),
+    ] {
+        let results = solve_toplevel(false, &mut source, parse_query(query));
+
+        compare_answers(results, &[answer]);
+    }
+}
+
+#[test]
+fn test_halt_1_succeeds() {
+    let mut machine = Machine::new();
+
+    // halt/0 ends the query past any catch/3, leaving the host running
+    let mut solutions = machine.solve("catch(halt, _, true).").unwrap();
+    assert!(solutions.next().is_none());
+    assert!(solutions.halted());
+    assert_eq!(solutions.exception(), None);
+
+    assert!(matches!(
+        run_goal(&mut machine, term!("halt")),
+        Err(WamError::Halt)
+    ));
+    assert!(matches!(
+        machine.consult_str("p.\n:- halt.\n"),
+        Err(WamError::Halt)
+    ));
+
+    let mut output = Vec::new();
+    let mut input = io::Cursor::new("");
+    let answered = answer_query(&mut machine, parse_query("halt."), &mut input, &mut output);
+    assert!(matches!(answered, Err(WamError::Halt)));
+}
+
+#[test]
+fn test_garbage_collection_1_succeeds() {
+    let mut source = read_source_code("tests/example_programs/loop/loop.pl");
+    source.gc.threshold = Some(100);
+
+    let query = parse_query("count(2000), sum([1, 2, 3], S).");
+    let results = solve_toplevel(false, &mut source, query);
+
+    compare_answers(results, &["S = 6"]);
+    assert!(source.gc_stats.collections > 10);
+    assert!(source.gc_stats.freed > 3000);
+    assert!(source.gc_stats.live < 100);
+
+    let query = parse_query("X = f(Y), count(10), garbage_collect, Y = a.");
+    let results = solve_toplevel(false, &mut source, query);
+
+    compare_answers(results, &["X = f(a)\nY = a"]);
+}
+
+#[test]
+fn test_garbage_collection_2_succeeds() {
+    let mut source = read_source_code("tests/example_programs/loop/loop.pl");
+    source.gc.threshold = Some(100);
+
+    // bindings made before a choicepoint are kept through collections for backtracking
+    // to go back to
+    let query = parse_query("X = f(Y), between(1, 3, Y), count(500), Z = Y.");
+    let results = solve_toplevel(false, &mut source, query);
+
+    compare_answers(
+        results,
+        &[
+            "X = f(1)\nY = 1\nZ = 1",
+            "X = f(2)\nY = 2\nZ = 2",
+            "X = f(3)\nY = 3\nZ = 3",
+        ],
+    );
+    assert!(source.gc_stats.live < 200);
+}
+
+#[test]
+fn test_backtracking_1_succeeds() {
+    let mut machine = Machine::builder()
+        .time_limit(Duration::from_secs(20))
+        .build();
+    machine
+        .consult_str("mk(0, []).\nmk(N, [N|T]) :- N > 0, M is N - 1, mk(M, T).\n")
+        .unwrap();
+
+    // backtracking only undoes what was bound since the choicepoint it goes back to, so
+    // a recursion leaving a choicepoint at each step takes time in proportion to its depth
+    let mut solutions = machine.solve("mk(50000, [N|_]).").unwrap();
+    assert_eq!(solutions.next().unwrap().get::<i64>("N"), Ok(50000));
+    assert!(solutions.next().is_none());
+    assert!(solutions.exception().is_none());
+}
+
+#[test]
+fn test_lists_1_succeeds() {
+    let mut machine = Machine::new();
+
+    for (query, answers) in &[
+        ("append(X, [c], [a, b, c]).", vec!["X = [a, b]"]),
+        ("member(X, [a, b]).", vec!["X = a", "X = b"]),
+        ("memberchk(X, [a, b]).", vec!["X = a"]),
+        ("reverse([a, b, c], X).", vec!["X = [c, b, a]"]),
+        ("nth0(1, [a, b, c], X).", vec!["X = b"]),
+        ("nth1(I, [a, b, c], c).", vec!["I = 3"]),
+        ("last([a, b, c], X).", vec!["X = c"]),
+        ("select(b, [a, b, c], X).", vec!["X = [a, c]"]),
+        ("delete([a, b, c, a], a, X).", vec!["X = [b, c]"]),
+        ("exclude(atom, [a, 1, b, 2], X).", vec!["X = [1, 2]"]),
+        ("include(atom, [a, 1, b, 2], X).", vec!["X = [a, b]"]),
+        (
+            "partition(integer, [a, 1, b, 2], I, E).",
+            vec!["E = [a, b]\nI = [1, 2]"],
+        ),
+        // a goal with more than one solution is only tried once for each item
+        (
+            "include(member(a), [[a, a], [b]], L).",
+            vec!["L = [[a, a]]"],
+        ),
+        ("exclude(member(a), [[a, a], [b]], L).", vec!["L = [[b]]"]),
+        (
+            "partition(member(a), [[a, a], [b]], I, E).",
+            vec!["E = [[b]]\nI = [[a, a]]"],
+        ),
+    ] {
+        let results = solve_toplevel(false, &mut machine, parse_query(query));
+
+        compare_answers(results, answers);
+    }
+}
+
+#[test]
+fn test_lists_1_fails() {
+    let mut machine = Machine::new();
+
+    for (query, answer) in &[
+        ("member(c, [a, b]).", "No"),
+        ("memberchk(a, [b, c]).", "No"),
+        ("last([], X).", "No"),
+        ("nth0(3, [a, b, c], X).", "No"),
+        ("\\+ member(a, [a]).", "No"),
+        (
+            "\\+ call(_).",
+            "Error: error(instantiation_error,context(call/1,_1))",
+        ),
+    ] {
+        let results = solve_toplevel(false, &mut machine, parse_query(query));
+
+        compare_answers(results, &[answer]);
+    }
+
+    // a program's own definitions take the place of the library's
+    let mut source = read_source_code("tests/example_programs/basic/basic.pl");
+    let query = parse_query("member(a, [a]).");
+    compare_answers(solve_toplevel(false, &mut source, query), &["No"]);
+}
+
+#[test]
+fn test_lists_2_succeeds() {
+    let mut machine = Machine::new();
+
+    // a list far longer than the stack is deep is built, unified, copied, compared,
+    // sorted, stored and dropped without recursing down its tail
+    let query = "findall(x, between(1, 100000, _), L), L = [_|_], L == L, \
+                 msort(L, [x|_]), assertz(long(L)), long(M), M == L.";
+    assert_eq!(machine.solve(query).unwrap().count(), 1);
+
+    let query = "findall(_, between(1, 100000, _), L), L = [_|_], L \\== [].";
+    assert_eq!(machine.solve(query).unwrap().count(), 1);
+}
+
+#[test]
+fn test_apply_1_succeeds() {
+    let mut machine = read_source_code("tests/example_programs/apply/apply.pl");
+
+    for (query, answer) in &[
+        ("maplist(atom, [a, b]).", "Yes"),
+        ("L = [A, B], maplist(=(x), L).", "A = x\nB = x\nL = [x, x]"),
+        ("maplist(double, [1, 2, 3], X).", "X = [2, 4, 6]"),
+        (
+            "maplist(functor, [f(a), g], Ns, As).",
+            "As = [1, 0]\nNs = [f, g]",
+        ),
+        ("foldl(add, [1, 2, 3], 0, S).", "S = 6"),
+        ("foldl(add, [1, 2], [3, 4], 0, S).", "S = 11"),
+    ] {
+        let results = solve_toplevel(false, &mut machine, parse_query(query));
+
+        compare_answers(results, &[answer]);
+    }
+}
+
+#[test]
+fn test_apply_1_fails() {
+    let mut machine = read_source_code("tests/example_programs/apply/apply.pl");
+
+    for (query, answer) in &[
+        ("maplist(atom, [a, 1]).", "No"),
+        ("maplist(=, [a], [a, b]).", "No"),
+        (
+            "maplist(double, X, [2]).",
+            "Error: error(instantiation_error,context((is)/2,_3))",
+        ),
+    ] {
+        let results = solve_toplevel(false, &mut machine, parse_query(query));
+
+        compare_answers(results, &[answer]);
+    }
+}
+
+#[test]
+fn test_assoc_1_succeeds() {
+    let mut source = read_source_code("tests/example_programs/assoc/assoc.pl");
+
+    for (query, answer) in &[
+        ("empty_assoc(A).", "A = t"),
+        (
+            "sorted([c-3, a-1, b-2], X).",
+            "X = [-(a, 1), -(b, 2), -(c, 3)]",
+        ),
+        ("lookup([c-3, a-1, b-2], b, X).", "X = 2"),
+        ("lookup([a-1, a-2], a, X).", "X = 2"),
+        ("balanced(15, D).", "D = 4"),
+        ("balanced(31, D).", "D = 5"),
+        (
+            "list_to_assoc([b-2, a-1], A), put_assoc(c, A, 3, B), assoc_to_keys(B, K), \
+             assoc_to_values(B, V).",
+            "A = t(b, 2, >, t(a, 1, =, t, t), t)\n\
+             B = t(b, 2, =, t(a, 1, =, t, t), t(c, 3, =, t, t))\n\
+             K = [a, b, c]\n\
+             V = [1, 2, 3]",
+        ),
+    ] {
+        let results = solve_toplevel(false, &mut source, parse_query(query));
+
+        compare_answers(results, &[answer]);
+    }
+}
+
+#[test]
+fn test_assoc_1_fails() {
+    let mut source = read_source_code("tests/example_programs/assoc/assoc.pl");
+
+    for (query, answer) in &[
+        ("lookup([a-1, b-2], c, X).", "No"),
+        ("empty_assoc(A), get_assoc(a, A, X).", "No"),
+        ("lookup([a-1], a, 2).", "No"),
+    ] {
+        let results = solve_toplevel(false, &mut source, parse_query(query));
+
+        compare_answers(results, &[answer]);
+    }
+}
+
+#[test]
+fn test_pairs_1_succeeds() {
+    let mut machine = Machine::new();
+
+    for (query, answer) in &[
+        (
+            "pairs_keys_values(P, [a, b], [1, 2]).",
+            "P = [-(a, 1), -(b, 2)]",
+        ),
+        (
+            "pairs_keys_values([a-1, b-2], K, V).",
+            "K = [a, b]\nV = [1, 2]",
+        ),
+        (
+            "keysort([b-1, a-2, b-0], S), pairs_keys(S, K).",
+            "K = [a, b, b]\nS = [-(a, 2), -(b, 1), -(b, 0)]",
+        ),
+        ("pairs_values([a-1, b-2], V).", "V = [1, 2]"),
+    ] {
+        let results = solve_toplevel(false, &mut machine, parse_query(query));
+
+        compare_answers(results, &[answer]);
+    }
+}
+
+#[test]
+fn test_pairs_1_fails() {
+    let mut machine = Machine::new();
+
+    for (query, answer) in &[
+        ("pairs_keys([a-1, b], K).", "No"),
+        ("pairs_keys_values(P, [a], [1, 2]).", "No"),
+    ] {
+        let results = solve_toplevel(false, &mut machine, parse_query(query));
+
+        compare_answers(results, &[answer]);
+    }
+}
+
+#[test]
+fn test_aggregate_all_1_succeeds() {
+    let mut machine = Machine::new();
+
+    for (query, answer) in &[
+        ("findall(X, member(X, [a, b]), L).", "L = [a, b]"),
+        ("aggregate_all(count, member(_, [a, b, c]), N).", "N = 3"),
+        ("aggregate_all(count, fail, N).", "N = 0"),
+        (
+            "aggregate_all(sum(X * 2), member(X, [1, 2, 3]), S).",
+            "S = 12",
+        ),
+        ("aggregate_all(sum(X), fail, S).", "S = 0"),
+        (
+            "aggregate_all(max(X), member(X, [1, 3.0, 2]), M).",
+            "M = 3.0",
+        ),
+        ("aggregate_all(min(X), member(X, [2, 1, 3]), M).", "M = 1"),
+        (
+            "aggregate_all(bag(X), member(X, [c, a, c]), B).",
+            "B = [c, a, c]",
+        ),
+        (
+            "aggregate_all(set(X), member(X, [c, a, c]), S).",
+            "S = [a, c]",
+        ),
+    ] {
+        let results = solve_toplevel(false, &mut machine, parse_query(query));
+
+        compare_answers(results, &[answer]);
+    }
+}
+
+#[test]
+fn test_aggregate_all_1_fails() {
+    let mut machine = Machine::new();
+
+    for (query, answer) in &[
+        ("aggregate_all(max(X), fail, M).", "No"),
+        ("aggregate_all(count, member(_, [a]), 2).", "No"),
+        (
+            "aggregate_all(foo, true, R).",
+            "Error: error(domain_error(aggregate_spec,foo),context(aggregate_all/3,_1))",
+        ),
+        (
+            "aggregate_all(sum(X), member(X, [1, a]), S).",
+            "Error: error(type_error(evaluable,a/0),context(aggregate_all/3,_9))",
+        ),
+        (
+            "findall(X, call(_), L).",
+            "Error: error(instantiation_error,context(call/1,_1))",
+        ),
+    ] {
+        let results = solve_toplevel(false, &mut machine, parse_query(query));
+
+        compare_answers(results, &[answer]);
+    }
+}
+
+#[test]
+fn test_strings_1_succeeds() {
+    let mut machine = Machine::new();
+
+    for (query, answer) in &[
+        ("string(\"abc\"), \\+ string(abc), atomic(\"\").", "Yes"),
+        ("string_concat(\"ab\", cd, S).", "S = \"abcd\""),
+        ("string_concat(X, \"c\", \"abc\").", "X = \"ab\""),
+        (
+            "findall(X+Y, string_concat(X, Y, ab), L).",
+            "L = [+(\"\", \"ab\"), +(\"a\", \"b\"), +(\"ab\", \"\")]",
+        ),
+        (
+            "string_chars(S, [h, i]), string_codes(S, C).",
+            "C = [104, 105]\nS = \"hi\"",
+        ),
+        (
+            "string_to_atom(S, abc), string_to_atom(\"x\", A).",
+            "A = x\nS = \"abc\"",
+        ),
+        (
+            "number_string(N, \" 42\"), number_string(1.5, S).",
+            "N = 42\nS = \"1.5\"",
+        ),
+        (
+            "split_string(\"a.b.c\", \".\", \"\", L).",
+            "L = [\"a\", \"b\", \"c\"]",
+        ),
+        (
+            "split_string(\"/home//jan///nice/path\", \"/\", \"\", L).",
+            "L = [\"\", \"home\", \"\", \"jan\", \"\", \"\", \"nice\", \"path\"]",
+        ),
+        (
+            "split_string(\"  a b  \", \"\", \" \", L).",
+            "L = [\"a b\"]",
+        ),
+        ("msort([f(x), \"s\", b, 1], L).", "L = [1, b, \"s\", f(x)]"),
+    ] {
+        let query = parse_query_with(DoubleQuotes::String, query);
+        let results = solve_toplevel(false, &mut machine, query);
+
+        compare_answers(results, &[answer]);
+    }
+}
+
+#[test]
+fn test_strings_1_fails() {
+    let mut machine = Machine::new();
+
+    for (query, answer) in &[
+        ("\"abc\" = abc.", "No"),
+        ("string_concat(\"a\", X, \"bc\").", "No"),
+        (
+            "string_concat(X, Y, Z).",
+            "Error: error(instantiation_error,context(string_concat/3,_1))",
+        ),
+        (
+            "number_string(N, \"4x\").",
+            "Error: error(syntax_error(illegal_number),context(number_string/2,_1))",
+        ),
+    ] {
+        let query = parse_query_with(DoubleQuotes::String, query);
+        let results = solve_toplevel(false, &mut machine, query);
+
+        compare_answers(results, &[answer]);
+    }
+}
+
+#[test]
+fn test_control_3_succeeds() {
+    let mut machine = Machine::new();
+    machine
+        .consult_str(
+            "first(X, L) :- member(X, L), !.\n\
+             max(X, Y, X) :- X >= Y, !.\n\
+             max(_, Y, Y).\n\
+             sign(X, S) :- ( X < 0 -> S = minus ; X > 0 -> S = plus ; S = zero ).\n\
+             local(X) :- call((member(X, [a, b]), !)) ; X = c.\n",
+        )
+        .unwrap();
+
+    for (query, answers) in &[
+        ("X = 1 ; X = 2.", vec!["X = 1", "X = 2"]),
+        ("(fail ; X = 2).", vec!["X = 2"]),
+        ("(X = 1 -> Y = a ; Y = b).", vec!["X = 1\nY = a"]),
+        ("(fail -> Y = a ; Y = b).", vec!["Y = b"]),
+        ("(member(X, [1, 2, 3]) -> true).", vec!["X = 1"]),
+        ("member(X, [1, 2]), !.", vec!["X = 1"]),
+        ("first(X, [a, b]).", vec!["X = a"]),
+        ("max(3, 1, M).", vec!["M = 3"]),
+        ("max(1, 3, M).", vec!["M = 3"]),
+        ("sign(-2, S).", vec!["S = minus"]),
+        ("sign(0, S).", vec!["S = zero"]),
+        ("local(X).", vec!["X = a", "X = c"]),
+        ("G = !, (G ; true).", vec!["G = !", "G = !"]),
+        // a cut in the condition of an if-then-else is local to it
+        (
+            "((member(X, [1, 2]), !, X > 1) -> Y = y ; Y = n).",
+            vec!["Y = n"],
+        ),
+    ] {
+        let results = solve_toplevel(false, &mut machine, parse_query(query));
+
+        compare_answers(results, answers);
+    }
+}
+
+#[test]
+fn test_control_3_fails() {
+    let mut machine = Machine::new();
+
+    for (query, answer) in &[
+        ("fail ; fail.", "No"),
+        ("(true -> fail ; true).", "No"),
+        ("(fail -> true).", "No"),
+        ("call((!, fail ; true)).", "No"),
+        ("(X = 1 ; X = 2), !, X = 2.", "No"),
+        ("(X ; true).", "Error: error(instantiation_error,_1)"),
+    ] {
+        let results = solve_toplevel(false, &mut machine, parse_query(query));
+
+        compare_answers(results, &[answer]);
+    }
+}
+
+#[test]
+fn test_phrase_1_succeeds() {
+    let mut machine = read_source_code("tests/example_programs/dcg/dcg.pl");
+
+    for (query, answers) in &[
+        ("phrase(greeting, [hello, world]).", vec!["Yes"]),
+        (
+            "phrase(greeting, [hello, X]).",
+            vec!["X = world", "X = prolog"],
+        ),
+        (
+            "phrase(digits(Ds), \"12\", R).",
+            vec!["Ds = [49, 50]\nR = []", "Ds = [49]\nR = [50]"],
+        ),
+        ("phrase(anbn, [a, a, b, b]).", vec!["Yes"]),
+        ("phrase(abc, L).", vec!["L = [97, 98, 99]"]),
+        ("phrase(([a], {X = 1}, [b]), [a, b]).", vec!["X = 1"]),
+        ("phrase([a], [a, b], R).", vec!["R = [b]"]),
+        ("phrase(ab, [b]).", vec!["Yes"]),
+        ("phrase(sign(S), [-, 1], R).", vec!["R = [1]\nS = -1"]),
+        (
+            "phrase(word(W), [h, i, ' ', x], R).",
+            vec!["R = [' ', x]\nW = [h, i]"],
+        ),
+        ("phrase(a_then_x, [a, x]).", vec!["Yes"]),
+        ("phrase(a_then_x, [b]).", vec!["Yes"]),
+    ] {
+        let results = solve_toplevel(false, &mut machine, parse_query(query));
+
+        compare_answers(results, answers);
+    }
+}
+
+#[test]
+fn test_phrase_1_fails() {
+    let mut machine = read_source_code("tests/example_programs/dcg/dcg.pl");
+
+    for (query, answer) in &[
+        ("phrase(greeting, [hello]).", "No"),
+        ("phrase(anbn, [a, b, b]).", "No"),
+        ("phrase(ab, [c]).", "No"),
+        ("phrase(a_then_x, [a, b]).", "No"),
+        (
+            "phrase(X, [a]).",
+            "Error: error(instantiation_error,context(phrase/2,_1))",
+        ),
+        (
+            "phrase(1, [a]).",
+            "Error: error(type_error(callable,1),context(phrase/2,_1))",
+        ),
+        (
+            "phrase(greeting, foo).",
+            "Error: error(type_error(list,foo),context(phrase/2,_1))",
+        ),
+    ] {
+        let results = solve_toplevel(false, &mut machine, parse_query(query));
+
+        compare_answers(results, &[answer]);
+    }
+}
+
+#[test]
+fn test_coroutining_1_succeeds() {
+    let mut machine = read_source_code("tests/example_programs/coroutining/coroutining.pl");
+    let output = Buffer::new();
+    machine.set_output(Box::new(output.clone()));
+
+    for (query, answers) in &[
+        (
+            "put_attr(X, m, 1), get_attr(X, m, V), attvar(X).",
+            vec!["V = 1"],
+        ),
+        (
+            "put_attr(X, m, 1), del_attr(X, m), \\+ attvar(X).",
+            vec!["Yes"],
+        ),
+        ("freeze(X, Y = b), X = a.", vec!["X = a\nY = b"]),
+        ("freeze(X, Y = b), Z = X.", vec!["Z = X"]),
+        ("dif(X, a), X = b.", vec!["X = b"]),
+        ("dif(f(X, Y), f(a, b)), X = a, Y = c.", vec!["X = a\nY = c"]),
+        (
+            "when((nonvar(X) ; nonvar(Y)), write(w)), X = 1, Y = 2.",
+            vec!["X = 1\nY = 2"],
+        ),
+        (
+            "when(ground(f(X, Y)), write(g)), X = 1, write(x), Y = 2.",
+            vec!["X = 1\nY = 2"],
+        ),
+        ("domain(X, [a, b]), X = b.", vec!["X = b"]),
+    ] {
+        let results = solve_toplevel(false, &mut machine, parse_query(query));
+
+        compare_answers(results, answers);
+    }
+
+    assert_eq!(output.text(), "wxg");
+}
+
+#[test]
+fn test_coroutining_1_fails() {
+    let mut machine = read_source_code("tests/example_programs/coroutining/coroutining.pl");
+
+    for (query, answer) in &[
+        ("freeze(X, fail), X = 1.", "No"),
+        ("dif(X, a), X = a.", "No"),
+        ("dif(f(X, Y), f(a, b)), X = a, Y = b.", "No"),
+        ("dif(X, Y), X = Z, Z = Y.", "No"),
+        ("dif(a, a).", "No"),
+        ("domain(X, [a, b]), X = c.", "No"),
+        (
+            "put_attr(a, m, 1).",
+            "Error: error(uninstantiation_error(a),context(put_attr/3,_1))",
+        ),
+        (
+            "when(foo, true).",
+            "Error: error(domain_error(when_condition,foo),context(when/2,_2))",
+        ),
+    ] {
+        let results = solve_toplevel(false, &mut machine, parse_query(query));
+
+        compare_answers(results, &[answer]);
+    }
+}
+
+#[test]
+fn test_solutions_1_succeeds() {
+    let mut machine = Machine::new();
+
+    let solutions: Vec<_> = machine
+        .solve("member(X, [a, f(Y), 1]), _ = X.")
+        .unwrap()
+        .collect();
+
+    assert_eq!(solutions.len(), 3);
+    assert_eq!(solutions[0].term("X"), Some(&Term::atom("a")));
+    assert_eq!(solutions[1].bindings()[0].0, "X");
+    assert_eq!(solutions[1].bindings()[1].0, "Y");
+    assert_eq!(solutions[2].term("X"), Some(&Term::int(1)));
+    assert_eq!(solutions[2].term("Z"), None);
+
+    let solutions: Vec<_> = machine.solve("X = 1 ; X = 2.").unwrap().collect();
+    assert_eq!(solutions.len(), 2);
+    assert_eq!(solutions[1].term("X"), Some(&Term::int(2)));
+
+    let mut solutions = machine.solve("append(X, Y, [a]).").unwrap();
+    let first = solutions.next().unwrap();
+    assert_eq!(first.term("X"), Some(&Term::atom("[]")));
+    assert_eq!(solutions.next().unwrap().term("Y"), Some(&Term::atom("[]")));
+    assert!(solutions.next().is_none());
+    assert!(solutions.next().is_none());
+}
+
+#[test]
+fn test_solutions_1_fails() {
+    let mut machine = Machine::new();
+
+    assert!(matches!(
+        machine.solve("member(X, [a, b]"),
+        Err(WamError::Parse(_))
+    ));
+    assert_eq!(machine.solve("member(c, [a, b]).").unwrap().count(), 0);
+}
+
+#[test]
+fn test_solution_get_1_succeeds() {
+    let mut machine = Machine::new();
+    let solution = machine
+        .solve("X = 42, Y is 1 / 4, Z = 'hello world', B = true, L = [[1], [2, 3]], T = f(_).")
+        .unwrap()
+        .next()
+        .unwrap();
+
+    assert_eq!(solution.get::<i64>("X"), Ok(42));
+    assert_eq!(solution.get::<f64>("Y"), Ok(0.25));
+    assert_eq!(solution.get::<String>("Z"), Ok(String::from("hello world")));
+    assert_eq!(solution.get::<bool>("B"), Ok(true));
+    assert_eq!(
+        solution.get::<Vec<Vec<i64>>>("L"),
+        Ok(vec![vec![1], vec![2, 3]])
+    );
+    assert!(matches!(solution.get::<Term>("T"), Ok(Term::Atom(_))));
+}
+
+#[test]
+fn test_solution_get_1_fails() {
+    let mut machine = Machine::new();
+    let solution = machine.solve("X = a, Y = [1|_].").unwrap().next().unwrap();
+
+    assert_eq!(
+        solution.get::<i64>("Z"),
+        Err(ConversionError::NoVariable(String::from("Z")))
+    );
+    assert_eq!(
+        solution.get::<i64>("X").unwrap_err().to_string(),
+        "expected integer, found a"
+    );
+    assert!(solution.get::<Vec<i64>>("Y").is_err());
+}
+
+#[test]
+fn test_consult_1_succeeds() {
+    let mut machine = Machine::new();
+    let consulted = machine
+        .consult_file("tests/example_programs/initialization/initialization.pl")
+        .unwrap();
+
+    assert!(consulted.warnings.is_empty());
+    assert_eq!(consulted.main, Some(Term::atom("start")));
+    assert_eq!(machine.solve("loaded(X).").unwrap().count(), 1);
+
+    let consulted = machine
+        .consult_str("p(X, Y) :- q(X).\n:- p(a, b).\n\n:- throw(oops).\nq(a).\n")
+        .unwrap();
+    let warnings: Vec<_> = consulted.warnings.iter().map(|w| w.to_string()).collect();
+
+    assert_eq!(
+        warnings,
+        [
+            "singleton variables [Y] in clause of p/2 at line 1",
+            "goal (directive) raised oops at line 4: throw(oops)"
+        ]
+    );
+    assert_eq!(consulted.main, None);
+    assert_eq!(machine.solve("p(a, _).").unwrap().count(), 1);
+}
+
+#[test]
+fn test_consult_1_fails() {
+    let mut machine = Machine::new();
+
+    assert!(matches!(
+        machine.consult_str("p(a).\np(b"),
+        Err(WamError::Parse(_))
+    ));
+    assert!(matches!(
+        machine.consult_str("p(a).\natom(b)."),
+        Err(WamError::Compile(_))
+    ));
+    assert!(matches!(
+        machine.consult_file("tests/example_programs/missing.pl"),
+        Err(WamError::Resource(_))
+    ));
+    assert_eq!(machine.solve("p(_).").unwrap().count(), 0);
+
+    let consulted = machine.consult_str("p(a).\n:- p(b).").unwrap();
+    assert_eq!(
+        consulted.warnings[0].to_string(),
+        "goal (directive) failed at line 2: p(b)"
+    );
+}
+
+#[test]
+fn test_op_1_succeeds() {
+    let mut machine = Machine::new();
+    let buffer = Buffer::new();
+    machine.set_current_output(Box::new(buffer.clone()));
+
+    let consulted = machine
+        .consult_str(
+            ":- op(700, xfx, ===>).\n\
+             :- op(200, xfy, [and, or]).\n\
+             rule(a and b ===> c).\n\
+             :- rule(_ ===> c).\n",
+        )
+        .unwrap();
+    assert!(consulted.warnings.is_empty());
+
+    for query in &[
+        "rule(X ===> Y), X =.. L, writeq(L), nl, op(0, xfx, ===>).",
+        "rule(R), writeq(R), nl, op(100, fy, ~).",
+        "T = ~ ~ a, T = ~(U), writeq(T-U), nl.",
+    ] {
+        assert_eq!(machine.solve(query).unwrap().count(), 1);
+    }
+    assert_eq!(buffer.text(), "[and,a,b]\n===>(a and b,c)\n~ ~a- ~a\n");
+}
+
+#[test]
+fn test_op_1_fails() {
+    let mut machine = Machine::new();
+
+    for (query, error) in &[
+        ("op(P, xfx, foo).", "instantiation_error"),
+        (
+            "op(1201, xfx, foo).",
+            "domain_error(operator_priority, 1201)",
+        ),
+        (
+            "op(700, xxx, foo).",
+            "domain_error(operator_specifier, xxx)",
+        ),
+        ("op(700, xfx, [foo, 1]).", "type_error(atom, 1)"),
+        (
+            "op(700, xfx, ',').",
+            "permission_error(modify, operator, ',')",
+        ),
+        (
+            "op(700, xfx, '|').",
+            "permission_error(modify, operator, '|')",
+        ),
+    ] {
+        let mut solutions = machine.solve(query).unwrap();
+        assert!(solutions.next().is_none());
+        let ball = solutions.exception().unwrap();
+        assert!(term!(&format!("error({}, _)", error)).subsumes(ball));
+    }
+
+    // operators defined by a program that does not load are taken back
+    let error = machine
+        .consult_str(":- op(700, xfx, ===>).\np(a ===> b).\np(a ===>).\n")
+        .unwrap_err();
+    assert!(matches!(&error, WamError::Parse(e) if e.line == 3 && e.column == 9));
+    assert!(machine.solve("X = (a ===> b).").is_err());
+}
+
+#[test]
+fn test_builder_1_succeeds() {
+    let output = Buffer::new();
+    let mut machine = Machine::builder()
+        .double_quotes(DoubleQuotes::Chars)
+        .occurs_check(OccursCheck::Error)
+        .gc_threshold(100)
+        .output(Box::new(output.clone()))
+        .input(Box::new(io::Cursor::new("hello.\n")))
+        .build();
+
+    let solution = machine
+        .solve("X = \"ab\", read(Y), write(Y), current_prolog_flag(occurs_check, F).")
+        .unwrap()
+        .next()
+        .unwrap();
+
+    assert_eq!(
+        solution.get::<Vec<String>>("X"),
+        Ok(vec![String::from("a"), String::from("b")])
+    );
+    assert_eq!(solution.get::<String>("F"), Ok(String::from("error")));
+    assert_eq!(output.text(), "hello");
+    assert_eq!(machine.gc.threshold, Some(100));
+}
+
+#[test]
+fn test_foreign_1_succeeds() {
+    let mut machine = Machine::new();
+    machine.register("host", 1, |_, args| {
+        Ok(if args.unify(0, Term::atom("localhost")) {
+            Foreign::Succeed
+        } else {
+            Foreign::Fail
+        })
+    });
+    // upto(N, X): X is each of 1..=N in turn
+    machine.register("upto", 2, |_, args| {
+        let n: i64 = args.read(0)?;
+        let x = match args.state() {
+            Some(Term::Number(Number::Int(x))) => *x + 1,
+            _ => 1,
+        };
+
+        if x > n || !args.unify(1, x) {
+            Ok(Foreign::Fail)
+        } else {
+            Ok(Foreign::Redo(Term::int(x)))
+        }
+    });
+
+    let solution = machine.solve("host(H).").unwrap().next().unwrap();
+    assert_eq!(solution.get::<String>("H"), Ok(String::from("localhost")));
+    assert_eq!(machine.solve("host(remote).").unwrap().count(), 0);
+
+    let xs: Vec<i64> = machine
+        .solve("upto(3, X).")
+        .unwrap()
+        .map(|s| s.get("X").unwrap())
+        .collect();
+    assert_eq!(xs, [1, 2, 3]);
+
+    let solution = machine
+        .solve("findall(X-Y, (upto(2, X), upto(X, Y)), L).")
+        .unwrap()
+        .next()
+        .unwrap();
+    assert_eq!(solution.term("L"), Some(&term!("[1-1, 2-1, 2-2]")));
+}
+
+#[test]
+fn test_foreign_1_fails() {
+    let mut machine = Machine::new();
+    machine.register("upto", 2, |_, args| {
+        let n: i64 = args.read(0)?;
+        Ok(if args.unify(1, n) {
+            Foreign::Succeed
+        } else {
+            Foreign::Fail
+        })
+    });
+
+    let mut solutions = machine.solve("upto(a, X).").unwrap();
+    assert!(solutions.next().is_none());
+    let ball = solutions.exception().unwrap();
+    assert!(term!("error(type_error(integer, a), context(upto/2, _))").subsumes(ball));
+    assert!(matches!(
+        machine.consult_str("upto(1, 1)."),
+        Err(WamError::Compile(_))
+    ));
+}
+
+#[test]
+fn test_solve_term_1_succeeds() {
+    let mut machine = Machine::new();
+
+    let goal = Term::compound(
+        "append",
+        vec![Term::var("X"), Term::var("Y"), vec![1, 2].to_term()],
+    );
+    let splits: Vec<(Vec<i64>, Vec<i64>)> = machine
+        .solve_term(goal)
+        .unwrap()
+        .map(|s| (s.get("X").unwrap(), s.get("Y").unwrap()))
+        .collect();
+    assert_eq!(
+        splits,
+        [
+            (vec![], vec![1, 2]),
+            (vec![1], vec![2]),
+            (vec![1, 2], vec![])
+        ]
+    );
+
+    let goal = term!("(member(X, [a, b]), X @> a)");
+    let solution = machine.solve_term(goal).unwrap().next().unwrap();
+    assert_eq!(solution.get::<String>("X"), Ok(String::from("b")));
+
+    let solution = machine
+        .solve_call("msort", &[vec![3, 1].to_term(), Term::var("N")])
+        .unwrap()
+        .next()
+        .unwrap();
+    assert_eq!(solution.get::<Vec<i64>>("N"), Ok(vec![1, 3]));
+    assert_eq!(machine.solve_call::<Term>("true", &[]).unwrap().count(), 1);
+}
+
+#[test]
+fn test_solve_term_1_fails() {
+    let mut machine = Machine::new();
+
+    assert!(matches!(
+        machine.solve_term(Term::int(1)),
+        Err(WamError::Execution(_))
+    ));
+    assert_eq!(machine.solve_call("member", &[3, 4]).unwrap().count(), 0);
+}
+
+#[test]
+fn test_current_output_1_succeeds() {
+    let mut machine = Machine::new();
+    let buffer = Buffer::new();
+    machine.set_current_output(Box::new(buffer.clone()));
+    machine.set_current_input(Box::new(InputStream::new(io::Cursor::new("f(X, Y).\n"))));
+
+    let query = parse_query("read(T), writeq(T), nl.");
+    solve_toplevel(false, &mut machine, query);
+
+    assert_eq!(buffer.text(), "f(_1,_2)\n");
+}
+
+#[test]
+fn test_current_output_1_fails() {
+    let mut machine = Machine::new();
+    machine.set_current_output(Box::new(OutputStream::new(Vec::new()).binary()));
+    machine.set_current_input(Box::new(InputStream::new(io::Cursor::new("a.")).binary()));
+
+    let query = parse_query("catch(write(a), error(E, _), true).");
+    let results = solve_toplevel(false, &mut machine, query);
+    compare_answers(
+        results,
+        &["E = permission_error(output, binary_stream, user_output)"],
+    );
+
+    let query = parse_query("catch(read(_), error(E, _), true).");
+    let results = solve_toplevel(false, &mut machine, query);
+    compare_answers(
+        results,
+        &["E = permission_error(input, binary_stream, user_input)"],
+    );
+}
+
+#[test]
+fn test_query_pool_1_succeeds() {
+    fn send<T: Send>(_: &T) {}
+
+    let mut machine = Machine::new();
+    machine
+        .consult_str(":- dynamic(seen/1).\nnat(z).\nnat(s(X)) :- nat(X).\n")
+        .unwrap();
+    send(&machine);
+
+    let pool = QueryPool::new(machine, 3);
+    let queries = [
+        "nat(s(s(z))).",
+        "assertz(seen(a)), seen(X).",
+        "seen(X).",
+        "member(X, [1, 2, 3]).",
+        "X is 2 ** 10.",
+    ];
+    let results = pool.run(&queries);
+
+    assert_eq!(results.len(), 5);
+    assert_eq!(results[0].as_ref().unwrap().len(), 1);
+    assert_eq!(
+        results[1].as_ref().unwrap()[0].get::<String>("X"),
+        Ok(String::from("a"))
+    );
+    assert!(results[2].as_ref().unwrap().is_empty());
+    assert_eq!(results[3].as_ref().unwrap().len(), 3);
+    assert_eq!(results[4].as_ref().unwrap()[0].get::<f64>("X"), Ok(1024.0));
+}
+
+#[test]
+fn test_query_pool_1_fails() {
+    let pool = QueryPool::new(Machine::new(), 2);
+    let results = pool.run(&["undefined_predicate.", "p(", "fail."]);
+
+    assert!(matches!(results[0], Err(WamError::Execution(_))));
+    assert!(matches!(results[1], Err(WamError::Parse(_))));
+    assert!(results[2].as_ref().unwrap().is_empty());
+    assert!(pool.run(&[]).is_empty());
+}
+
+#[test]
+fn test_budget_1_succeeds() {
+    let mut machine = Machine::builder().max_inferences(1000).build();
+    machine
+        .consult_str("count(0).\ncount(N) :- N > 0, M is N - 1, count(M).\n")
+        .unwrap();
+
+    assert_eq!(machine.solve("count(100).").unwrap().count(), 1);
+    // the budget is for each query, not the machine
+    assert_eq!(machine.solve("count(100).").unwrap().count(), 1);
+
+    machine.budget = Budget::default();
+    assert_eq!(machine.solve("count(500).").unwrap().count(), 1);
+}
+
+#[test]
+fn test_budget_1_fails() {
+    let mut machine = Machine::builder().max_inferences(1000).build();
+    machine.consult_str("loop :- loop.\n").unwrap();
+
+    let mut solutions = machine.solve("loop.").unwrap();
+    assert!(solutions.next().is_none());
+    let ball = solutions.exception().unwrap();
+    assert!(term!("error(resource_error(inferences), _)").subsumes(ball));
+
+    let mut solutions = machine.solve("catch(loop, _, loop).").unwrap();
+    assert!(solutions.next().is_none());
+    assert!(solutions.exception().is_some());
+
+    machine.budget = Budget {
+        inferences: None,
+        time: Some(Duration::from_millis(20)),
+    };
+    let mut solutions = machine.solve("loop.").unwrap();
+    assert!(solutions.next().is_none());
+    let ball = solutions.exception().unwrap();
+    assert!(term!("error(resource_error(time), _)").subsumes(ball));
+}
+
+#[test]
+fn test_budget_2_fails() {
+    let mut machine = Machine::builder().max_inferences(1000).build();
+    machine
+        .consult_str(
+            "n(X) :- between(1, 30, X).\n\
+             count(0).\n\
+             count(N) :- N > 0, M is N - 1, count(M).\n",
+        )
+        .unwrap();
+
+    // each of these stays within the budget on its own, but not taken together with
+    // the query that runs it
+    assert_eq!(machine.solve("count(300).").unwrap().count(), 1);
+
+    for query in &[
+        "findall(_, (n(_), findall(_, n(_), _)), _).",
+        "forall(n(_), aggregate_all(count, n(_), _)).",
+        "\\+ (n(_), \\+ \\+ count(100), fail).",
+        "engine_create(x, count(300), E), engine_create(x, count(300), F), \
+         engine_next(E, _), engine_next(F, _).",
+    ] {
+        let mut solutions = machine.solve(query).unwrap();
+        assert!(solutions.next().is_none(), "{}", query);
+        let ball = solutions.exception().unwrap();
+        assert!(term!("error(resource_error(inferences), _)").subsumes(ball));
+    }
+}
+
+type Seen = (Port, usize, Term, bool);
+
+#[derive(Clone, Default)]
+struct Ports(Arc<Mutex<Vec<Seen>>>);
+
+impl PortListener for Ports {
+    fn port(&mut self, port: Port, goal: &Term, depth: usize, deterministic: bool) {
+        let seen = (port, depth, goal.clone(), deterministic);
+        self.0.lock().unwrap().push(seen);
+    }
+}
+
+impl Ports {
+    // the ports seen, with the goals equal up to the names of their variables
+    fn assert_seen(&self, expected: &[(Port, usize, &str, bool)]) {
+        let seen = self.0.lock().unwrap();
+        assert_eq!(seen.len(), expected.len(), "{:?}", seen);
+
+        for ((port, depth, goal, det), (e_port, e_depth, e_goal, e_det)) in
+            seen.iter().zip(expected)
+        {
+            let e_goal = term!(*e_goal);
+            assert_eq!((port, depth, det), (e_port, e_depth, e_det), "{}", goal);
+            assert!(e_goal.subsumes(goal) && goal.subsumes(&e_goal), "{}", goal);
+        }
+    }
+}
+
+#[test]
+fn test_ports_1_succeeds() {
+    let mut machine = Machine::new();
+    machine
+        .consult_str("p(a).\np(b).\nq(X) :- p(X).\n")
+        .unwrap();
+
+    let ports = Ports::default();
+    machine.set_port_listener(Some(Box::new(ports.clone())));
+    assert_eq!(machine.solve("q(X).").unwrap().count(), 2);
+    ports.assert_seen(&[
+        (Port::Call, 0, "q(_)", false),
+        (Port::Call, 1, "p(_)", false),
+        (Port::Exit, 1, "p(a)", false),
+        (Port::Exit, 0, "q(a)", false),
+        (Port::Redo, 1, "p(_)", false),
+        (Port::Exit, 1, "p(b)", true),
+        (Port::Exit, 0, "q(b)", true),
+    ]);
+
+    machine.set_port_listener(None);
+    assert_eq!(machine.solve("q(X).").unwrap().count(), 2);
+    assert_eq!(ports.0.lock().unwrap().len(), 7);
+}
+
+#[test]
+fn test_ports_1_fails() {
+    let mut machine = Machine::new();
+    machine
+        .consult_str("p(a).\nq(X) :- p(X), X = b.\n")
+        .unwrap();
+
+    let ports = Ports::default();
+    machine.set_port_listener(Some(Box::new(ports.clone())));
+    assert_eq!(machine.solve("q(X).").unwrap().count(), 0);
+    ports.assert_seen(&[
+        (Port::Call, 0, "q(_)", false),
+        (Port::Call, 1, "p(_)", false),
+        (Port::Exit, 1, "p(a)", true),
+        (Port::Call, 1, "a = b", false),
+        (Port::Fail, 1, "a = b", false),
+        (Port::Fail, 0, "q(_)", false),
+    ]);
+}
+
+#[test]
+fn test_global_variables_1_succeeds() {
+    let mut machine = Machine::new();
+
+    let query = "nb_setval(n, 0), \\+ (member(_, [a, b, c]), nb_getval(n, N), M is N + 1, \
+                 nb_setval(n, M), fail), nb_getval(n, C).";
+    let solution = machine.solve(query).unwrap().next().unwrap();
+    assert_eq!(solution.get::<i64>("C"), Ok(3));
+
+    // kept from one query to the next
+    let solution = machine.solve("nb_getval(n, C).").unwrap().next().unwrap();
+    assert_eq!(solution.get::<i64>("C"), Ok(3));
+
+    let query = "b_setval(v, 1), \\+ (b_setval(v, 2), fail), b_getval(v, X), \
+                 b_setval(w, f(Y)), Y = a, b_getval(w, W).";
+    let solution = machine.solve(query).unwrap().next().unwrap();
+    assert_eq!(solution.get::<i64>("X"), Ok(1));
+    assert_eq!(solution.get::<Term>("W"), Ok(term!("f(a)")));
+
+    // nb_setval/2 copies its value, where b_setval/2 does not
+    let query = "nb_setval(k, g(Z)), Z = b, nb_getval(k, K).";
+    let solution = machine.solve(query).unwrap().next().unwrap();
+    assert!(
+        matches!(solution.get::<Term>("K"), Ok(Term::Atom(a)) if matches!(a.args[0], Term::Var(_)))
+    );
+}
+
+#[test]
+fn test_global_variables_1_fails() {
+    let mut machine = Machine::new();
+
+    assert_eq!(machine.solve("b_setval(v, 1).").unwrap().count(), 1);
+    let mut solutions = machine.solve("b_getval(v, X).").unwrap();
+    assert!(solutions.next().is_none());
+    let ball = solutions.exception().unwrap();
+    assert!(term!("error(existence_error(variable, v), _)").subsumes(ball));
+
+    let mut solutions = machine.solve("nb_setval(X, 1).").unwrap();
+    assert!(solutions.next().is_none());
+    let ball = solutions.exception().unwrap();
+    assert!(term!("error(instantiation_error, context(nb_setval/2, _))").subsumes(ball));
+
+    assert_eq!(
+        machine
+            .solve("nb_setval(n, 1), nb_getval(n, 2).")
+            .unwrap()
+            .count(),
+        0
+    );
+}
+
+#[test]
+fn test_json_1_succeeds() {
+    let mut machine = Machine::new();
+    let buffer = Buffer::new();
+    machine.set_current_output(Box::new(buffer.clone()));
+    let input = "{\"name\": \"ada\", \"tags\": [1, 2.5, true]}\n[] 7\n";
+    machine.set_current_input(Box::new(InputStream::new(io::Cursor::new(input))));
+
+    let query = "json_read(user_input, A), json_read(user_input, B), json_read(user_input, C), \
+                 json_read(user_input, D).";
+    let solution = machine.solve(query).unwrap().next().unwrap();
+    assert_eq!(
+        solution.get::<Term>("A"),
+        Ok(Term::compound(
+            "json",
+            vec![Term::list(
+                vec![
+                    Term::compound("=", vec![Term::atom("name"), Term::string("ada")]),
+                    term!("tags = [1, 2.5, true]"),
+                ],
+                Term::atom("[]"),
+            )],
+        ))
+    );
+    assert_eq!(solution.get::<Vec<i64>>("B"), Ok(vec![]));
+    assert_eq!(solution.get::<i64>("C"), Ok(7));
+    assert_eq!(solution.term("D"), Some(&term!("end_of_file")));
+
+    let query = "json_write(user_output, json([a=[x, 'Y z'], b-null])).";
+    assert_eq!(machine.solve(query).unwrap().count(), 1);
+    assert_eq!(buffer.text(), "{\"a\":[\"x\",\"Y z\"],\"b\":null}");
+}
+
+#[test]
+fn test_json_1_fails() {
+    let mut machine = Machine::new();
+    machine.set_current_output(Box::new(Buffer::new()));
+    machine.set_current_input(Box::new(InputStream::new(io::Cursor::new("[1,\n]"))));
+
+    let query = parse_query("catch(json_read(user_input, _), error(E, _), true).");
+    let results = solve_toplevel(false, &mut machine, query);
+    assert!(results[0].contains("E = syntax_error("), "{:?}", results);
+
+    let cases = [
+        (
+            "json_write(user_output, f(x))",
+            "E = type_error(json_term, f(x))",
+        ),
+        ("json_write(user_output, [X])", "E = instantiation_error"),
+        (
+            "json_write(user_error, 1)",
+            "E = existence_error(stream, user_error)",
+        ),
+        ("json_read(S, _)", "E = instantiation_error"),
+    ];
+    for (goal, error) in cases.iter() {
+        let query = parse_query(&format!("catch({}, error(E, _), true).", goal));
+        let results = solve_toplevel(false, &mut machine, query);
+        compare_answers(results, &[error]);
+    }
+}
+
+fn answer(machine: &mut Machine, query: &str, input: &str) -> String {
+    let mut output = Vec::new();
+    let mut input = io::Cursor::new(input);
+    answer_query(machine, parse_query(query), &mut input, &mut output).unwrap();
+    String::from_utf8(output).unwrap()
+}
+
+#[test]
+fn test_answer_query_1_succeeds() {
+    let mut machine = Machine::new();
+    machine
+        .consult_str("p(a, f(b)).\np('C', 1 + 2).\n")
+        .unwrap();
+
+    assert_eq!(
+        answer(&mut machine, "p(X, Y).", ";\n"),
+        "X = a, Y = f(b) X = 'C', Y = 1+2.\n"
+    );
+    assert_eq!(answer(&mut machine, "p(X, _).", ".\n"), "X = a .\n");
+    assert_eq!(answer(&mut machine, "p(X, _).", ""), "X = a .\n");
+    assert_eq!(
+        answer(&mut machine, "p(X, _).", "x\n\n"),
+        "X = a unknown action x (; for more, . to stop) .\n"
+    );
+
+    // a second line that is no action stops the query, and the lines after it are left
+    let mut output = Vec::new();
+    let mut input = io::Cursor::new("x\np(Y, _).\n;\n");
+    answer_query(
+        &mut machine,
+        parse_query("p(X, _)."),
+        &mut input,
+        &mut output,
+    )
+    .unwrap();
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        "X = a unknown action x (; for more, . to stop) .\n"
+    );
+    let mut rest = String::new();
+    io::Read::read_to_string(&mut input, &mut rest).unwrap();
+    assert_eq!(rest, ";\n");
+    assert_eq!(
+        answer(&mut machine, "X = (a :- b), Y = Z.", ""),
+        "X = (a:-b), Y = Z.\n"
+    );
+    assert_eq!(answer(&mut machine, "p('C', _).", ""), "true.\n");
+}
+
+#[test]
+fn test_answer_query_1_fails() {
+    let mut machine = Machine::new();
+    machine.consult_str("p(a).\np(b).\n").unwrap();
+
+    assert_eq!(answer(&mut machine, "p(c).", ""), "false.\n");
+    assert_eq!(answer(&mut machine, "p(X), X = b.", ";\n"), "X = b.\n");
+    assert_eq!(
+        answer(&mut machine, "X is foo.", ""),
+        "Error: error(type_error(evaluable,foo/0),context((is)/2,_1))\n"
+    );
+}
+
+fn debug(machine: &mut Machine, query: &str, commands: &str) -> (usize, String) {
+    let buffer = Buffer::new();
+    machine.set_current_output(Box::new(buffer.clone()));
+    machine.set_current_input(Box::new(InputStream::new(io::Cursor::new(String::from(
+        commands,
+    )))));
+
+    let found = machine.solve(query).unwrap().count();
+    (found, buffer.text())
+}
+
+#[test]
+fn test_debugger_1_succeeds() {
+    let mut machine = Machine::new();
+    machine
+        .consult_str("p(X) :- q(X).\nq(a).\nq(b).\nr(X) :- p(X).\n")
+        .unwrap();
+
+    let (found, shown) = debug(&mut machine, "trace, p(X), notrace.", "\n\n\n\n\n");
+    assert_eq!(found, 2);
+    assert_eq!(
+        shown.split(" ? ").collect::<Vec<_>>(),
+        [
+            "  Call: (0) p(X)",
+            "  Call: (1) q(X)",
+            "  Exit: (1) q(a)",
+            "  Exit: (0) p(a)",
+            "  Call: (0) notrace",
+            ""
+        ]
+    );
+
+    // skip over the goals called by r/1 to its exit, then leap out
+    let (found, shown) = debug(&mut machine, "trace, r(X), notrace.", "s\nl\n");
+    assert_eq!(found, 2);
+    assert_eq!(shown, "  Call: (0) r(X) ?   Exit: (0) r(a) ? ");
+
+    // stop only at the spy point
+    let (found, shown) = debug(&mut machine, "spy(q/1), r(X), nospy(q).", "l\nl\n");
+    assert_eq!(found, 2);
+    assert_eq!(shown, "  Call: (2) q(X) ?   Exit: (2) q(a) ? ");
+}
+
+#[test]
+fn test_debugger_1_fails() {
+    let mut machine = Machine::new();
+    machine.consult_str("q(a).\nq(b).\n").unwrap();
+
+    let (found, shown) = debug(&mut machine, "trace, q(X), notrace.", "f\nn\n");
+    assert_eq!(found, 0);
+    assert_eq!(shown, "  Call: (0) q(X) ?   Fail: (0) q(X) ? ");
+
+    // failing q(a) at its exit leaves no q(b) to try
+    let (found, shown) = debug(&mut machine, "trace, q(X), notrace.", "\n?\nf\nn\n");
+    assert_eq!(found, 0);
+    assert!(shown.contains("Exit: (0) q(a) ? c: creep"), "{}", shown);
+
+    let mut solutions = machine.solve("spy(_).").unwrap();
+    assert!(solutions.next().is_none());
+    let ball = solutions.exception().unwrap();
+    assert!(term!("error(instantiation_error, context(spy/1, _))").subsumes(ball));
+}
+
+#[test]
+fn test_listing_1_succeeds() {
+    let mut machine = Machine::new();
+    machine
+        .consult_str(
+            ":- dynamic(count/1).\ncount(0).\n\
+             len([], 0).\nlen([_|T], N) :- len(T, M), N is M + 1.\n\
+             'odd name'(X, Y, Z) :- X = Y, \\+ Z = (a :- b).\n",
+        )
+        .unwrap();
+
+    assert_eq!(
+        machine.listing_of(&Key::new("len", 2)),
+        "len([],0).\nlen([_|A],B) :-\n    len(A,C),\n    B is C+1.\n\n"
+    );
+    assert_eq!(
+        machine.listing(),
+        ":- dynamic count/1.\n\ncount(0).\n\n\
+         len([],0).\nlen([_|A],B) :-\n    len(A,C),\n    B is C+1.\n\n\
+         'odd name'(A,B,C) :-\n    A=B,\n    \\+C= (a:-b).\n\n"
+    );
+
+    let buffer = Buffer::new();
+    machine.set_current_output(Box::new(buffer.clone()));
+    let query = "assertz(count(1)), listing(count), listing(len/2), listing(none).";
+    assert_eq!(machine.solve(query).unwrap().count(), 1);
+    assert_eq!(
+        buffer.text(),
+        ":- dynamic count/1.\n\ncount(0).\ncount(1).\n\n\
+         len([],0).\nlen([_|A],B) :-\n    len(A,C),\n    B is C+1.\n\n"
+    );
+}
+
+#[test]
+fn test_listing_1_fails() {
+    let mut machine = Machine::new();
+
+    let mut solutions = machine.solve("listing(f(x)).").unwrap();
+    assert!(solutions.next().is_none());
+    let ball = solutions.exception().unwrap();
+    assert!(term!("error(type_error(predicate_indicator, f(x)), _)").subsumes(ball));
+    assert_eq!(machine.listing_of(&Key::new("none", 0)), "");
+}
+
+#[test]
+fn test_profile_1_succeeds() {
+    let mut machine = Machine::new();
+    machine
+        .consult_str("len([], 0).\nlen([_|T], N) :- len(T, M), N is M + 1.\nitem(a).\nitem(b).\n")
+        .unwrap();
+
+    let buffer = Buffer::new();
+    machine.set_current_output(Box::new(buffer.clone()));
+    let mut solutions = machine.solve("profile(len([a, b], N)).").unwrap();
+    assert_eq!(solutions.next().unwrap().term("N"), Some(&term!("2")));
+    drop(solutions);
+
+    let profile = machine.profile().unwrap();
+    let len = profile.predicates[&Key::new("len", 2)];
+    assert_eq!((len.calls, len.exits), (3, 3));
+    assert_eq!(profile.predicates[&Key::new("is", 2)].calls, 2);
+    assert!(buffer.text().starts_with("Predicate"));
+    assert!(buffer.text().contains("len/2"));
+
+    assert_eq!(
+        machine.solve("profile(item(X)), X = b.").unwrap().count(),
+        1
+    );
+    let item = machine.profile().unwrap().predicates[&Key::new("item", 1)];
+    assert_eq!((item.calls, item.exits, item.redos), (1, 1, 0));
+}
+
+#[test]
+fn test_profile_1_fails() {
+    let mut machine = Machine::new();
+    machine.consult_str("p :- q.\nq :- fail.\n").unwrap();
+    machine.set_current_output(Box::new(Buffer::new()));
+
+    assert_eq!(machine.solve("profile(p).").unwrap().count(), 0);
+    let profile = machine.profile().unwrap();
+    assert_eq!(profile.predicates[&Key::new("p", 0)].fails, 1);
+    assert_eq!(profile.predicates[&Key::new("q", 0)].fails, 1);
+
+    let mut solutions = machine.solve("profile(throw(oops)).").unwrap();
+    assert!(solutions.next().is_none());
+    assert_eq!(solutions.exception(), Some(&term!("oops")));
+    drop(solutions);
+    assert!(machine
+        .profile()
+        .unwrap()
+        .predicates
+        .contains_key(&Key::new("throw", 1)));
+}
+
+#[test]
+fn test_first_argument_index_1_succeeds() {
+    let mut machine = Machine::new();
+    let facts: String = (0..20000)
+        .map(|i| format!("square({}, {}).\n", i, i * i))
+        .collect();
+    machine.consult_str(&facts).unwrap();
+    machine
+        .consult_str("sum(0, []).\nsum(N, [X|Xs]) :- sum(M, Xs), N is M + X.\n")
+        .unwrap();
+
+    let mut solutions = machine
+        .solve("square(12345, S), square(R, 400), sum(T, [1, 2, 3]).")
+        .unwrap();
+    let solution = solutions.next().unwrap();
+    assert_eq!(solution.term("S"), Some(&term!("152399025")));
+    assert_eq!(solution.term("R"), Some(&term!("20")));
+    assert_eq!(solution.term("T"), Some(&term!("6")));
+    assert!(solutions.next().is_none());
+}
+
+#[test]
+fn test_random_1_succeeds() {
+    let mut machine = Machine::new();
+    let query = "set_random(seed(7)), random(F), random_between(1, 6, D), \
+                 random_member(M, [a, b, c]), random_permutation([1, 2, 3, 4], P), \
+                 msort(P, [1, 2, 3, 4]).";
+
+    let first = machine.solve(query).unwrap().next().unwrap();
+    let again = machine.solve(query).unwrap().next().unwrap();
+    assert_eq!(first, again);
+
+    let f: f64 = first.get("F").unwrap();
+    let d: i64 = first.get("D").unwrap();
+    assert!((0.0..1.0).contains(&f));
+    assert!((1..=6).contains(&d));
+    assert!(["a", "b", "c"].contains(&first.get::<String>("M").unwrap().as_str()));
+
+    let query = "random_between(3, 3, X), set_random(seed(random)).";
+    let solution = machine.solve(query).unwrap().next().unwrap();
+    assert_eq!(solution.term("X"), Some(&term!("3")));
+}
+
+#[test]
+fn test_random_1_fails() {
+    let mut machine = Machine::new();
+    assert_eq!(
+        machine.solve("random_between(2, 1, _).").unwrap().count(),
+        0
+    );
+    assert_eq!(machine.solve("random_member(_, []).").unwrap().count(), 0);
+
+    for (query, error) in &[
+        ("random_between(a, 2, _).", "type_error(integer, a)"),
+        ("random_member(_, _).", "instantiation_error"),
+        ("set_random(seed(a)).", "type_error(integer, a)"),
+        ("set_random(foo).", "domain_error(set_random_option, foo)"),
+    ] {
+        let mut solutions = machine.solve(query).unwrap();
+        assert!(solutions.next().is_none());
+        let ball = solutions.exception().unwrap();
+        assert!(term!(&format!("error({}, _)", error)).subsumes(ball));
+    }
+}
+
+#[test]
+fn test_format_1_succeeds() {
+    let mut machine = Machine::new();
+    let buffer = Buffer::new();
+    machine.set_current_output(Box::new(buffer.clone()));
+
+    let query = "format('~w~t~10|~t~D~12+~n', [apples, 12345]), \
+                 format(\"~a: ~2f~n\", [pi, 3.14159]), \
+                 format('~q~n', 'A b'), format('done~n').";
+    assert_eq!(machine.solve(query).unwrap().count(), 1);
+    assert_eq!(
+        buffer.text(),
+        "apples          12,345\npi: 3.14\n'A b'\ndone\n"
+    );
+}
+
+#[test]
+fn test_format_1_fails() {
+    let mut machine = Machine::new();
+
+    for (query, error) in &[
+        ("format(F, []).", "instantiation_error"),
+        ("format('~d', [a]).", "type_error(integer, a)"),
+        ("format('~w ~w', [a]).", "format('not enough arguments')"),
+        ("format('~z', [a]).", "format('unknown directive ~z')"),
+    ] {
+        let mut solutions = machine.solve(query).unwrap();
+        assert!(solutions.next().is_none());
+        let ball = solutions.exception().unwrap();
+        assert!(term!(&format!("error({}, _)", error)).subsumes(ball));
+    }
+}
+
+#[test]
+fn test_print_1_succeeds() {
+    let mut machine = Machine::new();
+    let buffer = Buffer::new();
+    machine.set_current_output(Box::new(buffer.clone()));
+    machine
+        .consult_str("portray(point(X, Y)) :- format('<~w,~w>', [X, Y]).\n")
+        .unwrap();
+
+    let query = "print(point(1, 2)), print(' '), print(f('A')), nl, \
+                 format('~p and ~p~n', [point(1, 2), 'A']), \
+                 format('~p~t~10|x~n', [point(3, 4)]).";
+    assert_eq!(machine.solve(query).unwrap().count(), 1);
+    assert_eq!(
+        buffer.text(),
+        "<1,2>' 'f('A')\n<1,2> and 'A'\n<3,4>     x\n"
+    );
+}
+
+#[test]
+fn test_print_1_fails() {
+    let mut machine = Machine::new();
+    let buffer = Buffer::new();
+    machine.set_current_output(Box::new(buffer.clone()));
+    machine
+        .consult_str("portray(_) :- write(ignored), fail.\n")
+        .unwrap();
+
+    assert_eq!(
+        machine
+            .solve("print('A'), format('~p', [b]).")
+            .unwrap()
+            .count(),
+        1
+    );
+    assert_eq!(buffer.text(), "'A'b");
+}
+
+#[test]
+fn test_setof_1_succeeds() {
+    let mut machine = Machine::new();
+    machine
+        .consult_str("p(1, a).\np(2, b).\np(1, c).\np(3, a).\np(1, a).\n")
+        .unwrap();
+
+    for (query, answers) in &[
+        ("bagof(X, p(X, Y), L).", &["[1, 3, 1]", "[2]", "[1]"][..]),
+        ("setof(X, p(X, Y), L).", &["[1, 3]", "[2]", "[1]"][..]),
+        ("setof(X, Y^p(X, Y), L).", &["[1, 2, 3]"][..]),
+        (
+            "bagof(X-Y, p(X, Y), L).",
+            &["[1-a, 2-b, 1-c, 3-a, 1-a]"][..],
+        ),
+        (
+            "setof(Y-Xs, setof(X, p(X, Y), Xs), L).",
+            &["[a-[1, 3], b-[2], c-[1]]"][..],
+        ),
+    ] {
+        let solutions: Vec<_> = machine
+            .solve(query)
+            .unwrap()
+            .map(|solution| solution.term("L").cloned())
+            .collect();
+        let answers: Vec<_> = answers.iter().map(|&answer| Some(term!(answer))).collect();
+        assert_eq!(solutions, answers);
+    }
+
+    let mut solutions = machine.solve("bagof(X, p(X, Y), L).").unwrap();
+    let witnesses: Vec<_> = (&mut solutions)
+        .map(|solution| solution.term("Y").cloned())
+        .collect();
+    assert_eq!(
+        witnesses,
+        [Some(term!("a")), Some(term!("b")), Some(term!("c"))]
+    );
+}
+
+#[test]
+fn test_setof_1_fails() {
+    let mut machine = Machine::new();
+
+    assert_eq!(machine.solve("bagof(X, fail, L).").unwrap().count(), 0);
+    assert_eq!(
+        machine
+            .solve("setof(X, member(X, []), L).")
+            .unwrap()
+            .count(),
+        0
+    );
+
+    for (query, error) in &[
+        ("bagof(X, G, L).", "instantiation_error"),
+        ("setof(X, Y^G, L).", "instantiation_error"),
+        ("setof(X, 1, L).", "type_error(callable, _)"),
+        ("bagof(X, true, foo).", "type_error(list, foo)"),
+    ] {
+        let mut solutions = machine.solve(query).unwrap();
+        assert!(solutions.next().is_none());
+        let ball = solutions.exception().unwrap();
+        assert!(term!(&format!("error({}, _)", error)).subsumes(ball));
+    }
+}
+
+// compare/3, msort/2, sort/2 and setof/3 all put terms in the standard order
+#[test]
+fn test_standard_order_1_succeeds() {
+    let mut machine = Machine::new();
+    assert_eq!(
+        machine
+            .solve("set_prolog_flag(double_quotes, string).")
+            .unwrap()
+            .count(),
+        1
+    );
+
+    for (smaller, larger) in &[
+        ("1.0", "1"),
+        ("1", "1.5"),
+        ("0.5", "1"),
+        ("2", "3.0"),
+        ("-1", "0.0"),
+        ("-0.5", "0"),
+        ("9007199254740992.0", "9007199254740993"),
+        ("9007199254740993", "9007199254740994.0"),
+        ("1", "a"),
+        ("'B'", "a"),
+        ("a", "b"),
+        ("z", "\"a\""),
+        ("\"a\"", "\"b\""),
+        ("\"z\"", "a(z)"),
+        ("z(a)", "a(a, a)"),
+        ("f(a)", "g(a)"),
+        ("f(1.0)", "f(1)"),
+        ("f(a, b)", "f(b, a)"),
+        ("[a]", "f(a, b)"),
+    ] {
+        let query = format!(
+            "A = {0}, B = {1}, \
+             compare(O1, A, B), O1 == (<), compare(O2, B, A), O2 == (>), \
+             msort([B, A, B], M), M == [A, B, B], \
+             sort([B, A, B], S), S == [A, B], \
+             setof(X, member(X, [B, A, B]), T), T == [A, B].",
+            smaller, larger
+        );
+        assert_eq!(machine.solve(&query).unwrap().count(), 1, "{}", query);
+    }
+}
+
+#[test]
+fn test_sort_4_succeeds() {
+    let mut machine = Machine::new();
+
+    for (query, sorted) in &[
+        ("sort(0, @<, [c, a, b, a], L).", "[a, b, c]"),
+        ("sort(0, @>=, [c, a, b, a], L).", "[c, b, a, a]"),
+        (
+            "sort(1, @<, [f(2, a), f(1, b), f(2, c)], L).",
+            "[f(1, b), f(2, a)]",
+        ),
+        (
+            "sort(1, @=<, [f(2, a), f(1, b), f(2, c)], L).",
+            "[f(1, b), f(2, a), f(2, c)]",
+        ),
+        (
+            "sort(2, @>, [p(x, 1), p(y, 3), p(z, 2), p(w, 3)], L).",
+            "[p(y, 3), p(z, 2), p(x, 1)]",
+        ),
+        ("sort(1, @>=, [], L).", "[]"),
+    ] {
+        let solution = machine.solve(query).unwrap().next().unwrap();
+        assert_eq!(solution.term("L"), Some(&term!(sorted)));
+    }
+}
+
+#[test]
+fn test_sort_4_fails() {
+    let mut machine = Machine::new();
+    assert_eq!(
+        machine
+            .solve("sort(0, @<, [b, a], [b, a]).")
+            .unwrap()
+            .count(),
+        0
+    );
+
+    for (query, error) in &[
+        ("sort(K, @<, [a], _).", "instantiation_error"),
+        (
+            "sort(-1, @<, [a], _).",
+            "domain_error(not_less_than_zero, -1)",
+        ),
+        ("sort(0, <, [a], _).", "domain_error(order, <)"),
+        ("sort(1, @<, [a], _).", "type_error(compound, a)"),
+        ("sort(2, @<, [f(a)], _).", "existence_error(key, 2)"),
+        ("sort(0, @<, [a|_], _).", "instantiation_error"),
+    ] {
+        let mut solutions = machine.solve(query).unwrap();
+        assert!(solutions.next().is_none());
+        let ball = solutions.exception().unwrap();
+        assert!(term!(&format!("error({}, _)", error)).subsumes(ball));
+    }
+}
+
+#[test]
+fn test_numeric_lists_1_succeeds() {
+    let mut machine = Machine::new();
+
+    for (query, answer) in &[
+        ("numlist(1, 5, X).", "[1, 2, 3, 4, 5]"),
+        ("numlist(3, 3, X).", "[3]"),
+        ("sum_list([1, 2.5, 3], X).", "6.5"),
+        ("sum_list([], X).", "0"),
+        ("max_list([3, 1, 4, 1, 5], X).", "5"),
+        ("min_list([3, 1, 4, 1.0, 5], X).", "1"),
+        ("numlist(1, 100, L), sum_list(L, X).", "5050"),
+    ] {
+        let solution = machine.solve(query).unwrap().next().unwrap();
+        assert_eq!(solution.term("X"), Some(&term!(answer)));
+    }
+}
+
+#[test]
+fn test_numeric_lists_1_fails() {
+    let mut machine = Machine::new();
+    assert_eq!(machine.solve("numlist(5, 1, _).").unwrap().count(), 0);
+    assert_eq!(machine.solve("max_list([], _).").unwrap().count(), 0);
+    assert_eq!(machine.solve("numlist(1, 3, [1, 2]).").unwrap().count(), 0);
+
+    for (query, error) in &[
+        ("numlist(a, 3, _).", "type_error(integer, a)"),
+        ("numlist(1, _, _).", "instantiation_error"),
+        ("sum_list([1, a], _).", "type_error(evaluable, a/0)"),
+    ] {
+        let mut solutions = machine.solve(query).unwrap();
+        assert!(solutions.next().is_none());
+        let ball = solutions.exception().unwrap();
+        assert!(term!(&format!("error({}, _)", error)).subsumes(ball));
+    }
+}
+
+#[test]
+fn test_between_1_succeeds() {
+    let mut machine = Machine::new();
+
+    let found: Vec<_> = machine
+        .solve("between(1, 3, X).")
+        .unwrap()
+        .map(|s| s.get::<i64>("X").unwrap())
+        .collect();
+    assert_eq!(found, vec![1, 2, 3]);
+    assert_eq!(
+        machine
+            .solve("between(1, 3, 3), between(0, inf, 7).")
+            .unwrap()
+            .count(),
+        1
+    );
+
+    // an endless generator is only run as far as the values taken from it
+    let squares: Vec<i64> = machine
+        .iter_solutions_of(term!("Y"), term!("(between(1, inf, X), Y is X * X)"))
+        .unwrap()
+        .take(5)
+        .map(Result::unwrap)
+        .collect();
+    assert_eq!(squares, vec![1, 4, 9, 16, 25]);
+
+    let pairs: Vec<(String, i64)> = machine
+        .iter_solutions_of(term!("K-V"), term!("member(K-V, [a-1, b-2])"))
+        .unwrap()
+        .collect::<Result<_, _>>()
+        .unwrap();
+    assert_eq!(pairs, vec![(String::from("a"), 1), (String::from("b"), 2)]);
+}
+
+#[test]
+fn test_between_1_fails() {
+    let mut machine = Machine::new();
+    assert_eq!(machine.solve("between(3, 1, _).").unwrap().count(), 0);
+    assert_eq!(machine.solve("between(1, 3, 4).").unwrap().count(), 0);
+
+    let mut solutions = machine.solve("between(1, a, _).").unwrap();
+    assert!(solutions.next().is_none());
+    let ball = solutions.exception().unwrap();
+    assert!(term!("error(type_error(integer, a), _)").subsumes(ball));
+
+    machine
+        .consult_str("v(a).\nv(_) :- throw(oops).\n")
+        .unwrap();
+    let mut values = machine
+        .iter_solutions_of::<i64>(term!("X"), term!("v(X)"))
+        .unwrap();
+    assert!(values.next().unwrap().is_err());
+    assert!(values.next().is_none());
+    assert_eq!(values.exception(), Some(&term!("oops")));
+}
+
+#[test]
+fn test_forall_1_succeeds() {
+    let mut machine = Machine::new();
+    machine.consult_str("p(1).\np(2).\np(3).\n").unwrap();
+
+    let query = "forall(p(X), X > 0), forall(member(X, []), fail), \
+                 forall(p(X), (Y = X, integer(Y))), var(X), var(Y).";
+    assert_eq!(machine.solve(query).unwrap().count(), 1);
+}
+
+#[test]
+fn test_forall_1_fails() {
+    let mut machine = Machine::new();
+    machine.consult_str("p(1).\np(2).\np(3).\n").unwrap();
+
+    assert_eq!(machine.solve("forall(p(X), X < 3).").unwrap().count(), 0);
+
+    let mut solutions = machine.solve("forall(p(X), Y > X).").unwrap();
+    assert!(solutions.next().is_none());
+    let ball = solutions.exception().unwrap();
+    assert!(term!("error(instantiation_error, _)").subsumes(ball));
+}
+
+#[test]
+fn test_engines_1_succeeds() {
+    let mut machine = Machine::new();
+
+    let query = "engine_create(X, member(X, [a, b]), E), engine_next(E, A), engine_next(E, B), \
+                 \\+ engine_next(E, _), engine_destroy(E).";
+    let solution = machine.solve(query).unwrap().next().unwrap();
+    assert_eq!(solution.term("A"), Some(&term!("a")));
+    assert_eq!(solution.term("B"), Some(&term!("b")));
+
+    // an engine answers each term posted to it when backtracked into
+    let query = "engine_create(Y, (between(1, inf, _), engine_fetch(X), Y is X * 2), E), \
+                 engine_post(E, 20, A), engine_post(E, 1, B).";
+    let solution = machine.solve(query).unwrap().next().unwrap();
+    assert_eq!(solution.term("A"), Some(&term!("40")));
+    assert_eq!(solution.term("B"), Some(&term!("2")));
+
+    // and from Rust, an engine is pulled while the machine runs other queries
+    let mut engine = Engine::new(term!("X"), term!("between(1, inf, X)"));
+    assert_eq!(engine.next(&mut machine), Ok(Some(term!("1"))));
+    assert_eq!(machine.solve("true.").unwrap().count(), 1);
+    assert_eq!(engine.next(&mut machine), Ok(Some(term!("2"))));
+}
+
+#[test]
+fn test_engines_1_fails() {
+    let mut machine = Machine::new();
+
+    for (query, error) in &[
+        ("engine_next(E, _).", "instantiation_error"),
+        ("engine_next(foo, _).", "type_error(engine, foo)"),
+        (
+            "engine_create(X, true, E), engine_destroy(E), engine_next(E, _).",
+            "existence_error(engine, '$engine'(_))",
+        ),
+        (
+            "engine_create(X, engine_fetch(X), E), engine_next(E, _).",
+            "existence_error(term, posted)",
+        ),
+        (
+            "engine_create(X, true, E), engine_post(E, a), engine_post(E, b).",
+            "permission_error(post_to, engine, '$engine'(_))",
+        ),
+    ] {
+        let mut solutions = machine.solve(query).unwrap();
+        assert!(solutions.next().is_none());
+        let ball = solutions.exception().unwrap();
+        assert!(term!(&format!("error({}, _)", error)).subsumes(ball));
+    }
+
+    let mut solutions = machine
+        .solve("engine_create(X, throw(oops), E), engine_next(E, _).")
+        .unwrap();
+    assert!(solutions.next().is_none());
+    assert_eq!(solutions.exception(), Some(&term!("oops")));
+
+    let mut engine = Engine::new(term!("X"), term!("fail"));
+    assert_eq!(engine.next(&mut machine), Ok(None));
+    assert!(engine.post(term!("a")));
+    assert!(!engine.post(term!("b")));
+}
+
+#[test]
+fn test_suspend_1_succeeds() {
+    let mut machine = Machine::new();
+    machine
+        .consult_str("count(N, N). count(N, M) :- N1 is N + 1, count(N1, M).")
+        .unwrap();
+
+    // a long run is sliced up, with the machine free for other queries in between
+    let mut q = machine.start(term!("count(0, 1000)"));
+    let mut paused = 0;
+    let answer = loop {
+        match q.run_for(&mut machine, 100).unwrap() {
+            Step::Paused => paused += 1,
+            step => break step,
+        }
+        assert_eq!(machine.solve("true.").unwrap().count(), 1);
+    };
+    assert_eq!(answer, Step::Answer(term!("count(0, 1000)")));
+    assert!(paused > 10);
+
+    // a paused run is carried on to its next answer
+    let mut q = machine.start(term!("count(0, X)"));
+    assert_eq!(q.run_for(&mut machine, 0), Ok(Step::Paused));
+    assert_eq!(q.next(&mut machine), Ok(Some(term!("count(0, 0)"))));
+    assert_eq!(q.run_for(&mut machine, 1), Ok(Step::Paused));
+    assert_eq!(q.next(&mut machine), Ok(Some(term!("count(0, 1)"))));
+}
+
+#[test]
+fn test_suspend_1_fails() {
+    let mut machine = Machine::new();
+
+    let mut q = machine.start(term!("fail"));
+    assert_eq!(q.run_for(&mut machine, 10), Ok(Step::Done));
+    assert_eq!(q.run_for(&mut machine, 10), Ok(Step::Done));
+
+    let mut q = machine.start(term!("throw(oops)"));
+    assert_eq!(q.run_for(&mut machine, 10), Err(term!("oops")));
+    assert_eq!(q.run_for(&mut machine, 10), Ok(Step::Done));
+}
+
+#[test]
+fn test_suspend_2_succeeds() {
+    let mut machine = Machine::new();
+    machine
+        .consult_str("count(N, N). count(N, M) :- N1 is N + 1, count(N1, M).")
+        .unwrap();
+
+    // the goals of findall, negation and engines are sliced up with the query's own
+    for goal in &[
+        "findall(x, between(1, 1000, _), L)",
+        "\\+ (between(1, 1000, _), fail)",
+        "engine_create(x, count(0, 1000), E), engine_next(E, x)",
+    ] {
+        let mut q = machine.start(term!(*goal));
+        let mut paused = 0;
+        let answer = loop {
+            match q.run_for(&mut machine, 10).unwrap() {
+                Step::Paused => paused += 1,
+                step => break step,
+            }
+        };
+        assert!(matches!(answer, Step::Answer(_)), "{}", goal);
+        assert!(paused > 50, "{}", goal);
+    }
+
+    // time spent paused is not counted against the query
+    machine.budget = Budget {
+        inferences: None,
+        time: Some(Duration::from_millis(100)),
+    };
+    let mut q = machine.start(term!("count(0, 100)"));
+    assert_eq!(q.run_for(&mut machine, 10), Ok(Step::Paused));
+    std::thread::sleep(Duration::from_millis(200));
+    assert_eq!(q.next(&mut machine), Ok(Some(term!("count(0, 100)"))));
+}